@@ -0,0 +1,64 @@
+//! # Import Casbin models into oso
+//!
+//! Convert a Casbin model (`model.conf`) and its policy (`policy.csv`) into
+//! equivalent Polar rules and facts, so that an application can move from
+//! Casbin to oso incrementally.
+//!
+//! For a model with `p = sub, obj, act`, `g = _, _` and
+//! `r = sub, obj, act`, the generated policy contains:
+//!
+//! - a `casbin_p(sub, obj, act)` fact for every `p` policy line and a
+//!   `casbin_g(user, role)` fact for every `g` line;
+//! - `casbin_g_has_role(user, role)`, the transitive closure of `casbin_g`,
+//!   which implements `g()` in matchers;
+//! - `casbin_allow(sub, obj, act)`, implementing the matcher and policy effect;
+//! - since the request is `sub, obj, act`, also
+//!   `allow(sub, act, obj) if casbin_allow(sub, obj, act)`, so that
+//!   `Oso::is_allowed(actor, action, resource)` works as usual.
+//!
+//! Matchers may use `&&`, `||`, `!`, comparisons, role functions and
+//! `keyMatch`. Attribute access like `r.sub.Owner` is translated to a
+//! Polar attribute lookup. The supported policy effects are
+//! `some(where (p.eft == allow))`, `!some(where (p.eft == deny))` and
+//! `some(where (p.eft == allow)) && !some(where (p.eft == deny))`.
+
+mod matcher;
+mod model;
+
+use std::fs;
+
+use oso::Oso;
+
+pub use model::Model;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Oso(#[from] oso::OsoError),
+    #[error("invalid model: {0}")]
+    Model(String),
+    #[error("invalid policy line {line}: {message}")]
+    Policy { line: usize, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Convert a Casbin model and policy to Polar source.
+pub fn to_polar(model: &str, policy: &str) -> Result<String> {
+    Model::parse(model)?.to_polar(policy)
+}
+
+/// Convert a Casbin model and policy and load the result into `oso`.
+pub fn load_str(oso: &mut Oso, model: &str, policy: &str) -> Result<()> {
+    let polar = to_polar(model, policy)?;
+    Ok(oso.load_str(&polar)?)
+}
+
+/// Like [`load_str`], reading the model and policy from files.
+pub fn load_files(oso: &mut Oso, model: &str, policy: &str) -> Result<()> {
+    let model = fs::read_to_string(model)?;
+    let policy = fs::read_to_string(policy)?;
+    load_str(oso, &model, &policy)
+}
@@ -0,0 +1,130 @@
+//! Translation of Casbin matcher expressions to Polar.
+
+use std::collections::BTreeMap;
+
+use crate::model::string;
+use crate::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A possibly dotted name, e.g. `r.sub.Owner` or `keyMatch`.
+    Name(String),
+    String(String),
+    Number(String),
+    Operator(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenize(matcher: &str) -> Result<Vec<Token>> {
+    const OPERATORS: &[&str] = &["&&", "||", "==", "!=", "<=", ">=", "!", "<", ">"];
+
+    let mut tokens = vec![];
+    let mut rest = matcher.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Operator(*op));
+            op.len()
+        } else if c == '(' || c == ')' || c == ',' {
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => Token::Comma,
+            });
+            1
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..].find(c).ok_or_else(|| {
+                Error::Model(format!("unterminated string in matcher `{}`", matcher))
+            })?;
+            tokens.push(Token::String(rest[1..end + 1].to_string()));
+            end + 2
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(rest[..len].to_string()));
+            len
+        } else if c.is_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            return Err(Error::Model(format!(
+                "unexpected `{}` in matcher `{}`",
+                c, matcher
+            )));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Translate `matcher` to a Polar expression over the variables
+/// `r_<field>` for request fields and `p_<field>` for policy fields.
+pub fn translate(
+    matcher: &str,
+    request: &[String],
+    policy: &[String],
+    roles: &BTreeMap<String, usize>,
+) -> Result<String> {
+    let unsupported = |what: String| Err(Error::Model(format!("unsupported {} in matcher", what)));
+
+    let tokens = tokenize(matcher)?;
+    let mut polar = String::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let next = tokens.get(i + 1);
+        let translated = match &tokens[i] {
+            Token::Name(name) if next == Some(&Token::Open) => {
+                // Consume the parenthesis along with the function name.
+                i += 1;
+                if roles.contains_key(name) {
+                    format!("casbin_{}_has_role(", name)
+                } else if name == "keyMatch" {
+                    "Condition.StringLike(".to_string()
+                } else {
+                    return unsupported(format!("function `{}`", name));
+                }
+            }
+            Token::Name(name) => {
+                let mut parts = name.splitn(3, '.');
+                let head = parts.next().unwrap();
+                match (head, parts.next(), parts.next()) {
+                    ("true", None, None) | ("false", None, None) => name.clone(),
+                    ("r", Some(field), attributes) | ("p", Some(field), attributes) => {
+                        let fields = if head == "r" { request } else { policy };
+                        if !fields.iter().any(|f| f == field) {
+                            return Err(Error::Model(format!(
+                                "`{}` is not defined in the model",
+                                name
+                            )));
+                        }
+                        match attributes {
+                            Some(attributes) => format!("{}_{}.{}", head, field, attributes),
+                            None => format!("{}_{}", head, field),
+                        }
+                    }
+                    _ => return unsupported(format!("name `{}`", name)),
+                }
+            }
+            Token::String(s) => string(s),
+            Token::Number(n) => n.clone(),
+            Token::Operator("&&") => "and".to_string(),
+            Token::Operator("||") => "or".to_string(),
+            Token::Operator("!") => "not".to_string(),
+            Token::Operator(op) => op.to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+            Token::Comma => ",".to_string(),
+        };
+        if !(polar.is_empty() || polar.ends_with('(') || translated == ")" || translated == ",") {
+            polar.push(' ');
+        }
+        polar.push_str(&translated);
+        i += 1;
+    }
+    Ok(polar)
+}
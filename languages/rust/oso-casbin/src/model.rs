@@ -0,0 +1,244 @@
+//! Parsing of Casbin model and policy files, and generation of Polar.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::{matcher, Error, Result};
+
+/// How matching policy lines combine into a decision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Effect {
+    /// `some(where (p.eft == allow))`
+    AllowOverride,
+    /// `!some(where (p.eft == deny))`
+    DenyOverride,
+    /// `some(where (p.eft == allow)) && !some(where (p.eft == deny))`
+    AllowAndDeny,
+}
+
+/// A parsed Casbin model.
+#[derive(Clone, Debug)]
+pub struct Model {
+    request: Vec<String>,
+    policy: Vec<String>,
+    /// Map from role definition names (e.g. `g`) to the number of arguments.
+    roles: BTreeMap<String, usize>,
+    effect: Effect,
+    matcher: String,
+}
+
+impl Model {
+    pub fn parse(conf: &str) -> Result<Self> {
+        let mut sections: HashMap<String, BTreeMap<String, String>> = HashMap::new();
+        let mut section = String::new();
+        let mut continued = String::new();
+        for line in conf.lines() {
+            let line = line.trim();
+            if let Some(line) = line.strip_suffix('\\') {
+                continued.push_str(line.trim());
+                continued.push(' ');
+                continue;
+            }
+            let line = std::mem::take(&mut continued) + line;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            let i = line
+                .find('=')
+                .ok_or_else(|| Error::Model(format!("expected `key = value`, got `{}`", line)))?;
+            sections.entry(section.clone()).or_default().insert(
+                line[..i].trim().to_string(),
+                line[i + 1..].trim().to_string(),
+            );
+        }
+
+        let get = |section: &str, key: &str| {
+            sections
+                .get(section)
+                .and_then(|entries| entries.get(key))
+                .ok_or_else(|| Error::Model(format!("missing `{}` in [{}]", key, section)))
+        };
+        let fields = |value: &str| -> Vec<String> {
+            value.split(',').map(|f| f.trim().to_string()).collect()
+        };
+
+        let roles = sections
+            .get("role_definition")
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|(name, value)| {
+                        let arity = fields(value).len();
+                        if arity == 2 || arity == 3 {
+                            Ok((name.clone(), arity))
+                        } else {
+                            Err(Error::Model(format!(
+                                "role definition `{}` must have 2 or 3 arguments",
+                                name
+                            )))
+                        }
+                    })
+                    .collect::<Result<_>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let effect: String = get("policy_effect", "e")?
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        let effect = match effect.as_str() {
+            "some(where(p.eft==allow))" => Effect::AllowOverride,
+            "!some(where(p.eft==deny))" => Effect::DenyOverride,
+            "some(where(p.eft==allow))&&!some(where(p.eft==deny))" => Effect::AllowAndDeny,
+            _ => {
+                return Err(Error::Model(format!(
+                    "unsupported policy effect `{}`",
+                    get("policy_effect", "e")?
+                )))
+            }
+        };
+
+        Ok(Self {
+            request: fields(get("request_definition", "r")?),
+            policy: fields(get("policy_definition", "p")?),
+            roles,
+            effect,
+            matcher: get("matchers", "m")?.clone(),
+        })
+    }
+
+    /// Generate Polar rules implementing the model, and facts for `policy`.
+    pub fn to_polar(&self, policy: &str) -> Result<String> {
+        let mut polar = String::new();
+
+        for (i, line) in policy.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| Error::Policy {
+                line: i + 1,
+                message,
+            };
+            let mut values = split_csv(line);
+            let ptype = values.remove(0);
+            let arity = if ptype == "p" {
+                self.policy.len()
+            } else if let Some(arity) = self.roles.get(&ptype) {
+                *arity
+            } else {
+                return Err(invalid(format!("unknown policy type `{}`", ptype)));
+            };
+            if values.len() > arity || (ptype != "p" && values.len() != arity) {
+                return Err(invalid(format!(
+                    "expected {} values for `{}`, got {}",
+                    arity,
+                    ptype,
+                    values.len()
+                )));
+            }
+            if ptype == "p" {
+                // Missing trailing values are empty, except for
+                // the effect, which defaults to `allow`.
+                for field in &self.policy[values.len()..] {
+                    values.push(if field == "eft" { "allow" } else { "" }.to_string());
+                }
+            }
+            let values: Vec<String> = values.iter().map(|v| string(v)).collect();
+            writeln!(polar, "casbin_{}({});", ptype, values.join(", ")).unwrap();
+        }
+
+        for (name, arity) in &self.roles {
+            if *arity == 2 {
+                writeln!(
+                    polar,
+                    "casbin_{name}_has_role(user, user);\n\
+                     casbin_{name}_has_role(user, role) if\n    \
+                     casbin_{name}(user, parent) and casbin_{name}_has_role(parent, role);",
+                    name = name
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    polar,
+                    "casbin_{name}_has_role(user, user, _domain);\n\
+                     casbin_{name}_has_role(user, role, domain) if\n    \
+                     casbin_{name}(user, parent, domain) and \
+                     casbin_{name}_has_role(parent, role, domain);",
+                    name = name
+                )
+                .unwrap();
+            }
+        }
+
+        let request: Vec<String> = self.request.iter().map(|f| format!("r_{}", f)).collect();
+        let policy: Vec<String> = self.policy.iter().map(|f| format!("p_{}", f)).collect();
+        let effect = if self.policy.iter().any(|f| f == "eft") {
+            "eft = p_eft"
+        } else {
+            "eft = \"allow\""
+        };
+        let matcher = matcher::translate(&self.matcher, &self.request, &self.policy, &self.roles)?;
+        writeln!(
+            polar,
+            "casbin_match({}, eft) if\n    casbin_p({}) and {} and ({});",
+            request.join(", "),
+            policy.join(", "),
+            effect,
+            matcher
+        )
+        .unwrap();
+
+        let request = request.join(", ");
+        let allow = format!("casbin_match({}, \"allow\")", request);
+        let deny = format!("not casbin_match({}, \"deny\")", request);
+        let body = match self.effect {
+            Effect::AllowOverride => allow,
+            Effect::DenyOverride => deny,
+            Effect::AllowAndDeny => format!("{} and {}", allow, deny),
+        };
+        writeln!(polar, "casbin_allow({}) if {};", request, body).unwrap();
+
+        if self.request == ["sub", "obj", "act"] {
+            writeln!(
+                polar,
+                "allow(sub, act, obj) if casbin_allow(sub, obj, act);"
+            )
+            .unwrap();
+        }
+
+        Ok(polar)
+    }
+}
+
+/// Split a policy line into its comma-separated values, which
+/// may be enclosed in double quotes to contain commas.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut values = vec![];
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(std::mem::take(&mut value).trim().to_string()),
+            c => value.push(c),
+        }
+    }
+    values.push(value.trim().to_string());
+    values
+}
+
+/// A Polar string literal.
+pub(crate) fn string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
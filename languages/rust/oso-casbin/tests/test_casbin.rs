@@ -0,0 +1,92 @@
+use oso::Oso;
+
+const RBAC_MODEL: &str = r#"
+[request_definition]
+r = sub, obj, act
+
+[policy_definition]
+p = sub, obj, act
+
+[role_definition]
+g = _, _
+
+[policy_effect]
+e = some(where (p.eft == allow))
+
+[matchers]
+m = g(r.sub, p.sub) && keyMatch(r.obj, p.obj) && r.act == p.act
+"#;
+
+const RBAC_POLICY: &str = r#"
+p, alice, data1, read
+p, data_admin, data2/*, read
+p, data_admin, data2/*, write
+g, bob, data_admin
+g, carol, bob
+"#;
+
+#[test]
+fn test_rbac() {
+    let mut oso = Oso::new();
+    oso_casbin::load_str(&mut oso, RBAC_MODEL, RBAC_POLICY).unwrap();
+
+    assert!(oso.is_allowed("alice", "read", "data1").unwrap());
+    assert!(!oso.is_allowed("alice", "write", "data1").unwrap());
+    assert!(!oso.is_allowed("alice", "read", "data2/x").unwrap());
+    assert!(oso.is_allowed("bob", "write", "data2/x").unwrap());
+    assert!(oso.is_allowed("carol", "read", "data2/y").unwrap());
+    assert!(!oso.is_allowed("carol", "read", "data1").unwrap());
+}
+
+#[test]
+fn test_deny_override() {
+    let model = r#"
+[request_definition]
+r = sub, obj, act
+
+[policy_definition]
+p = sub, obj, act, eft
+
+[policy_effect]
+e = some(where (p.eft == allow)) && !some(where (p.eft == deny))
+
+[matchers]
+m = (r.sub == p.sub || p.sub == "*") && r.obj == p.obj && r.act == p.act
+"#;
+    let policy = r#"
+p, *, report, read
+p, "mallory", report, read, deny
+p, alice, report, write
+"#;
+
+    let mut oso = Oso::new();
+    oso_casbin::load_str(&mut oso, model, policy).unwrap();
+
+    assert!(oso.is_allowed("alice", "read", "report").unwrap());
+    assert!(oso.is_allowed("alice", "write", "report").unwrap());
+    assert!(!oso.is_allowed("mallory", "read", "report").unwrap());
+    assert!(!oso.is_allowed("bob", "write", "report").unwrap());
+}
+
+#[test]
+fn test_generated_polar() {
+    let polar = oso_casbin::to_polar(RBAC_MODEL, "p, alice, data1, read\ng, bob, alice").unwrap();
+    assert!(polar.contains(r#"casbin_p("alice", "data1", "read");"#));
+    assert!(polar.contains(r#"casbin_g("bob", "alice");"#));
+    assert!(polar.contains(
+        "casbin_g_has_role(r_sub, p_sub) and Condition.StringLike(r_obj, p_obj) and r_act == p_act"
+    ));
+}
+
+#[test]
+fn test_unsupported() {
+    let model = RBAC_MODEL.replace("keyMatch", "regexMatch");
+    let err = oso_casbin::to_polar(&model, "").unwrap_err();
+    assert!(err.to_string().contains("regexMatch"));
+
+    let model = RBAC_MODEL.replace("some(where (p.eft == allow))", "priority(p.eft) || deny");
+    assert!(oso_casbin::to_polar(&model, "").is_err());
+
+    let err = oso_casbin::to_polar(RBAC_MODEL, "p, alice, data1, read, extra").unwrap_err();
+    assert!(matches!(err, oso_casbin::Error::Policy { line: 1, .. }));
+}
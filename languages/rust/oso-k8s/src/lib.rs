@@ -0,0 +1,13 @@
+//! # oso Kubernetes admission webhooks
+//!
+//! Serve a Polar policy as a validating or mutating admission webhook:
+//! deserialize an `AdmissionReview`, evaluate it with [`Webhook`], and
+//! send back the resulting review.
+
+mod review;
+mod webhook;
+
+pub use review::{
+    AdmissionRequest, AdmissionResponse, AdmissionReview, GroupVersionKind, Status, UserInfo,
+};
+pub use webhook::{Error, KubeObject, Result, Webhook};
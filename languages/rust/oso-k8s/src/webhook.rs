@@ -0,0 +1,281 @@
+//! Evaluate admission reviews against a Polar policy.
+//!
+//! For each request the webhook queries, in order:
+//!
+//! - `deny(request, object, reason)`: any result rejects the request with `reason`.
+//! - `allow(request, object)`: at least one result is required to admit the request.
+//! - `patch(request, object, op, path, value)`: each result is a JSON patch operation
+//!   applied to the admitted object.
+//! - `warn(request, object, message)`: each result is returned to the client as a warning.
+//!
+//! The request is passed to Polar as a dictionary. The object, and the old object of
+//! the request, are instances of the class registered for their kind with
+//! [`Webhook::register_kind`], so rules can dispatch on the resource type with a class
+//! specializer, e.g. `allow(_, obj: Deployment)`. The fields of the object are its
+//! attributes, converted from JSON as with the `json` feature of `oso`: `null` becomes
+//! `nil`. Requests without an object, such as deletions, pass `nil` as the object.
+
+use std::collections::HashMap;
+
+use oso::{Class, Oso, PolarValue, ToPolar};
+
+use crate::review::{
+    AdmissionRequest, AdmissionResponse, AdmissionReview, GroupVersionKind, Status,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Oso(#[from] oso::OsoError),
+    #[error("admission review has no request")]
+    MissingRequest,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A Kubernetes object, an instance of the class registered for its kind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KubeObject {
+    pub kind: GroupVersionKind,
+    pub object: serde_json::Value,
+}
+
+pub struct Webhook {
+    oso: Oso,
+    /// Map from resource types to the classes of their objects.
+    kinds: HashMap<GroupVersionKind, Class>,
+    /// Whether requests for kinds that were never registered are admitted.
+    allow_unregistered: bool,
+}
+
+impl Webhook {
+    pub fn new(oso: Oso) -> Self {
+        Self {
+            oso,
+            kinds: HashMap::new(),
+            allow_unregistered: true,
+        }
+    }
+
+    /// Evaluate requests for `gvk`, registering the class `name` for its
+    /// objects.
+    pub fn register_kind(&mut self, gvk: GroupVersionKind, name: &str) -> Result<&mut Self> {
+        let kind = gvk.clone();
+        let class = Class::<KubeObject>::new()
+            .name(name)
+            .set_instance_check(move |object| object.kind == kind)
+            .set_attribute_fallback(|object, field| object.object.get(field).cloned())
+            .with_equality_check()
+            .build();
+        self.oso.register_class(class.clone())?;
+        self.kinds.insert(gvk, class);
+        Ok(self)
+    }
+
+    /// Set whether requests for unregistered kinds are admitted without
+    /// evaluating the policy. Defaults to `true`.
+    pub fn allow_unregistered(&mut self, allow: bool) -> &mut Self {
+        self.allow_unregistered = allow;
+        self
+    }
+
+    pub fn oso(&mut self) -> &mut Oso {
+        &mut self.oso
+    }
+
+    /// Handle the raw body of an `AdmissionReview` request, returning
+    /// the serialized review to send back to the API server.
+    pub fn review_json(&mut self, body: &[u8]) -> Result<Vec<u8>> {
+        let review: AdmissionReview = serde_json::from_slice(body)?;
+        let review = self.review(review)?;
+        Ok(serde_json::to_vec(&review)?)
+    }
+
+    pub fn review(&mut self, review: AdmissionReview) -> Result<AdmissionReview> {
+        let request = review.request.ok_or(Error::MissingRequest)?;
+        let response = self.admit(&request)?;
+        Ok(AdmissionReview::from_response(response))
+    }
+
+    pub fn admit(&mut self, request: &AdmissionRequest) -> Result<AdmissionResponse> {
+        let mut response = AdmissionResponse {
+            uid: request.uid.clone(),
+            ..Default::default()
+        };
+
+        let class = match self.kinds.get(&request.kind) {
+            Some(class) => class.clone(),
+            None => {
+                response.allowed = self.allow_unregistered;
+                if !response.allowed {
+                    response.status = Some(Status {
+                        code: Some(403),
+                        message: format!(
+                            "kind {} is not handled by this webhook",
+                            request.kind.kind
+                        ),
+                    });
+                }
+                return Ok(response);
+            }
+        };
+
+        let req = request_to_polar(request, &class);
+        let object = object_to_polar(request, request.object.as_ref(), &class);
+
+        let reason = PolarValue::Variable("reason".to_string());
+        let mut denials = self
+            .oso
+            .query_rule("deny", vec![&req as &dyn ToPolar, &object, &reason])?;
+        if let Some(result) = denials.next() {
+            let message = result?
+                .get_typed::<String>("reason")
+                .unwrap_or_else(|_| "denied by policy".to_string());
+            response.status = Some(Status {
+                code: Some(403),
+                message,
+            });
+            return Ok(response);
+        }
+
+        let mut allows = self
+            .oso
+            .query_rule("allow", vec![&req as &dyn ToPolar, &object])?;
+        match allows.next() {
+            Some(result) => {
+                result?;
+            }
+            None => {
+                response.status = Some(Status {
+                    code: Some(403),
+                    message: "not allowed by policy".to_string(),
+                });
+                return Ok(response);
+            }
+        }
+        response.allowed = true;
+
+        let message = PolarValue::Variable("message".to_string());
+        for result in self
+            .oso
+            .query_rule("warn", vec![&req as &dyn ToPolar, &object, &message])?
+        {
+            response.warnings.push(result?.get_typed("message")?);
+        }
+
+        let op = PolarValue::Variable("op".to_string());
+        let path = PolarValue::Variable("path".to_string());
+        let value = PolarValue::Variable("value".to_string());
+        let mut patch = vec![];
+        for result in self.oso.query_rule(
+            "patch",
+            vec![&req as &dyn ToPolar, &object, &op, &path, &value],
+        )? {
+            let result = result?;
+            let mut operation = serde_json::Map::new();
+            operation.insert("op".to_string(), result.get_typed::<String>("op")?.into());
+            operation.insert(
+                "path".to_string(),
+                result.get_typed::<String>("path")?.into(),
+            );
+            match result.get("value")? {
+                None | Some(PolarValue::Variable(_)) => {}
+                Some(_) => {
+                    operation.insert("value".to_string(), result.get_typed("value")?);
+                }
+            }
+            patch.push(serde_json::Value::Object(operation));
+        }
+        if !patch.is_empty() {
+            let patch = serde_json::to_vec(&patch)?;
+            response.patch = Some(base64::encode(&patch));
+            response.patch_type = Some("JSONPatch".to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+/// An instance of `class` for `object`, or `nil` if there is none.
+fn object_to_polar(
+    request: &AdmissionRequest,
+    object: Option<&serde_json::Value>,
+    class: &Class,
+) -> PolarValue {
+    match object {
+        Some(object) => PolarValue::Instance(class.cast_to_instance(KubeObject {
+            kind: request.kind.clone(),
+            object: object.clone(),
+        })),
+        None => PolarValue::nil(),
+    }
+}
+
+fn request_to_polar(request: &AdmissionRequest, class: &Class) -> PolarValue {
+    let mut kind = HashMap::new();
+    kind.insert(
+        "group".to_string(),
+        PolarValue::String(request.kind.group.clone()),
+    );
+    kind.insert(
+        "version".to_string(),
+        PolarValue::String(request.kind.version.clone()),
+    );
+    kind.insert(
+        "kind".to_string(),
+        PolarValue::String(request.kind.kind.clone()),
+    );
+
+    let mut user = HashMap::new();
+    user.insert(
+        "username".to_string(),
+        PolarValue::String(request.user_info.username.clone()),
+    );
+    user.insert(
+        "uid".to_string(),
+        PolarValue::String(request.user_info.uid.clone()),
+    );
+    user.insert(
+        "groups".to_string(),
+        PolarValue::List(
+            request
+                .user_info
+                .groups
+                .iter()
+                .cloned()
+                .map(PolarValue::String)
+                .collect(),
+        ),
+    );
+
+    let mut fields = HashMap::new();
+    fields.insert("uid".to_string(), PolarValue::String(request.uid.clone()));
+    fields.insert("kind".to_string(), PolarValue::Map(kind));
+    fields.insert(
+        "operation".to_string(),
+        PolarValue::String(request.operation.clone()),
+    );
+    fields.insert("user".to_string(), PolarValue::Map(user));
+    fields.insert(
+        "dry_run".to_string(),
+        PolarValue::Bool(request.dry_run.unwrap_or(false)),
+    );
+    if let Some(name) = &request.name {
+        fields.insert("name".to_string(), PolarValue::String(name.clone()));
+    }
+    if let Some(namespace) = &request.namespace {
+        fields.insert(
+            "namespace".to_string(),
+            PolarValue::String(namespace.clone()),
+        );
+    }
+    if request.old_object.is_some() {
+        fields.insert(
+            "old_object".to_string(),
+            object_to_polar(request, request.old_object.as_ref(), class),
+        );
+    }
+    PolarValue::Map(fields)
+}
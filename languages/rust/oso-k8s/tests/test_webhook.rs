@@ -0,0 +1,141 @@
+use oso::Oso;
+use oso_k8s::{AdmissionReview, GroupVersionKind, Webhook};
+
+fn review(kind: &str, namespace: &str, replicas: i64) -> AdmissionReview {
+    serde_json::from_value(serde_json::json!({
+        "apiVersion": "admission.k8s.io/v1",
+        "kind": "AdmissionReview",
+        "request": {
+            "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+            "kind": {"group": "apps", "version": "v1", "kind": kind},
+            "namespace": namespace,
+            "operation": "CREATE",
+            "userInfo": {"username": "alice", "groups": ["dev"]},
+            "object": {
+                "metadata": {"name": "web", "labels": null},
+                "spec": {"replicas": replicas}
+            }
+        }
+    }))
+    .unwrap()
+}
+
+fn webhook(policy: &str) -> Webhook {
+    let mut webhook = Webhook::new(Oso::new());
+    webhook
+        .register_kind(
+            GroupVersionKind::new("apps", "v1", "Deployment"),
+            "Deployment",
+        )
+        .unwrap()
+        .register_kind(
+            GroupVersionKind::new("apps", "v1", "StatefulSet"),
+            "StatefulSet",
+        )
+        .unwrap();
+    webhook.oso().load_str(policy).unwrap();
+    webhook
+}
+
+#[test]
+fn test_validate() {
+    let mut webhook = webhook(
+        r#"allow(request, _: Deployment) if "dev" in request.user.groups;
+           deny(request, _, "kube-system is off limits") if request.namespace = "kube-system";"#,
+    );
+
+    let response = webhook
+        .review(review("Deployment", "default", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(response.allowed);
+    assert_eq!(response.uid, "705ab4f5-6393-11e8-b7cc-42010a800002");
+
+    // Objects of other registered kinds are instances of their own class.
+    let response = webhook
+        .review(review("StatefulSet", "default", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(!response.allowed);
+
+    let response = webhook
+        .review(review("Deployment", "kube-system", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(!response.allowed);
+    assert_eq!(
+        response.status.unwrap().message,
+        "kube-system is off limits"
+    );
+
+    // Unregistered kinds are admitted by default.
+    let response = webhook
+        .review(review("DaemonSet", "kube-system", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(response.allowed);
+    webhook.allow_unregistered(false);
+    let response = webhook
+        .review(review("DaemonSet", "default", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(!response.allowed);
+}
+
+#[test]
+fn test_mutate() {
+    let mut webhook = webhook(
+        r#"allow(_, _);
+           patch(_, object, "replace", "/spec/replicas", 3) if object.spec.replicas > 3;
+           warn(_, object, "replicas capped at 3") if object.spec.replicas > 3;"#,
+    );
+
+    let response = webhook
+        .review(review("Deployment", "default", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(response.allowed);
+    assert!(response.patch.is_none());
+
+    let response = webhook
+        .review(review("Deployment", "default", 10))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(response.allowed);
+    assert_eq!(response.warnings, vec!["replicas capped at 3".to_string()]);
+    assert_eq!(response.patch_type.as_deref(), Some("JSONPatch"));
+    let patch: serde_json::Value =
+        serde_json::from_slice(&base64::decode(response.patch.unwrap()).unwrap()).unwrap();
+    assert_eq!(
+        patch,
+        serde_json::json!([{"op": "replace", "path": "/spec/replicas", "value": 3}])
+    );
+}
+
+#[test]
+fn test_null_fields() {
+    let mut webhook = webhook(
+        r#"allow(_, object: Deployment) if object.metadata.labels = nil;
+           patch(_, _: Deployment, "add", "/metadata/labels", nil);"#,
+    );
+
+    let response = webhook
+        .review(review("Deployment", "default", 1))
+        .unwrap()
+        .response
+        .unwrap();
+    assert!(response.allowed);
+    let patch: serde_json::Value =
+        serde_json::from_slice(&base64::decode(response.patch.unwrap()).unwrap()).unwrap();
+    assert_eq!(
+        patch,
+        serde_json::json!([{"op": "add", "path": "/metadata/labels", "value": null}])
+    );
+}
@@ -0,0 +1,125 @@
+//! # Policy-as-code for infrastructure plans
+//!
+//! Evaluate compliance rules written in Polar over a JSON document, such as
+//! the output of `terraform show -json`.
+//!
+//! The document is split into resources:
+//!
+//! - a Terraform plan contributes one resource per entry in `resource_changes`,
+//!   with the fields `address`, `type`, `name`, `provider`, `mode`, `actions`,
+//!   `before` and `after`;
+//! - a top-level JSON array contributes one resource per element;
+//! - any other document is a single resource.
+//!
+//! Every resource is checked against the `violation(resource, message)` rule, and
+//! each result is reported as a [`Violation`]. The whole document is available to
+//! rules as the `document` constant.
+//!
+//! JSON values convert to Polar as with the `json` feature of `oso`: objects
+//! become dictionaries, arrays lists, and `null` becomes `nil`.
+
+use oso::{Oso, PolarValue, ToPolar};
+use serde::Serialize;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Oso(#[from] oso::OsoError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A resource that failed a compliance rule.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Violation {
+    /// The Terraform address of the resource, or its index in the document.
+    pub address: String,
+    pub message: String,
+}
+
+pub struct PlanChecker {
+    oso: Oso,
+}
+
+impl PlanChecker {
+    pub fn new(oso: Oso) -> Self {
+        Self { oso }
+    }
+
+    pub fn oso(&mut self) -> &mut Oso {
+        &mut self.oso
+    }
+
+    pub fn check_str(&mut self, document: &str) -> Result<Vec<Violation>> {
+        let document: serde_json::Value = serde_json::from_str(document)?;
+        self.check(&document)
+    }
+
+    pub fn check(&mut self, document: &serde_json::Value) -> Result<Vec<Violation>> {
+        self.oso.register_constant("document", document)?;
+
+        let message = PolarValue::Variable("message".to_string());
+        let mut violations = vec![];
+        for (address, resource) in resources(document) {
+            for result in self
+                .oso
+                .query_rule("violation", vec![&resource as &dyn ToPolar, &message])?
+            {
+                violations.push(Violation {
+                    address: address.clone(),
+                    message: result?.get_typed("message")?,
+                });
+            }
+        }
+        Ok(violations)
+    }
+}
+
+/// Split `document` into `(address, resource)` pairs.
+fn resources(document: &serde_json::Value) -> Vec<(String, serde_json::Value)> {
+    if let Some(changes) = document
+        .get("resource_changes")
+        .and_then(serde_json::Value::as_array)
+    {
+        changes
+            .iter()
+            .enumerate()
+            .map(|(i, change)| {
+                let address = change
+                    .get("address")
+                    .and_then(serde_json::Value::as_str)
+                    .map_or_else(|| i.to_string(), str::to_string);
+                (address, resource_change(change))
+            })
+            .collect()
+    } else if let Some(elements) = document.as_array() {
+        elements
+            .iter()
+            .enumerate()
+            .map(|(i, element)| (i.to_string(), element.clone()))
+            .collect()
+    } else {
+        vec![("0".to_string(), document.clone())]
+    }
+}
+
+/// Flatten a Terraform `resource_changes` entry so that the planned
+/// actions and states are top-level fields of the resource.
+fn resource_change(change: &serde_json::Value) -> serde_json::Value {
+    let mut fields = match change {
+        serde_json::Value::Object(fields) => fields.clone(),
+        _ => serde_json::Map::new(),
+    };
+    if let Some(serde_json::Value::Object(mut planned)) = fields.remove("change") {
+        for field in &["actions", "before", "after"] {
+            if let Some(value) = planned.remove(*field) {
+                fields.insert(field.to_string(), value);
+            }
+        }
+    }
+    serde_json::Value::Object(fields)
+}
@@ -0,0 +1,41 @@
+//! Check a JSON plan against Polar compliance rules.
+//!
+//! Usage: `oso-plan <policy.polar>... <plan.json>`
+//!
+//! Prints the violations as a JSON array, and exits with status 1
+//! if there were any.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use oso::Oso;
+use oso_plan::PlanChecker;
+
+fn run(args: &[String]) -> oso_plan::Result<bool> {
+    let (plan, policies) = args.split_last().expect("checked by caller");
+    let mut oso = Oso::new();
+    for policy in policies {
+        oso.load_file(policy)?;
+    }
+    let document = fs::read_to_string(plan)?;
+    let violations = PlanChecker::new(oso).check_str(&document)?;
+    println!("{}", serde_json::to_string_pretty(&violations)?);
+    Ok(violations.is_empty())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: oso-plan <policy.polar>... <plan.json>");
+        process::exit(2);
+    }
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use oso::Oso;
+use oso_plan::{PlanChecker, Violation};
+
+fn checker(policy: &str) -> PlanChecker {
+    let mut oso = Oso::new();
+    oso.load_str(policy).unwrap();
+    PlanChecker::new(oso)
+}
+
+#[test]
+fn test_terraform_plan() {
+    let mut checker = checker(
+        r#"violation(r, "buckets must not be public") if
+               r.type = "aws_s3_bucket" and r.after.acl = "public-read";
+           violation(r, "resources must not be deleted") if "delete" in r.actions;"#,
+    );
+    let plan = r#"{
+        "format_version": "0.1",
+        "resource_changes": [
+            {
+                "address": "aws_s3_bucket.logs",
+                "type": "aws_s3_bucket",
+                "name": "logs",
+                "change": {"actions": ["create"], "before": null, "after": {"acl": "private"}}
+            },
+            {
+                "address": "aws_s3_bucket.site",
+                "type": "aws_s3_bucket",
+                "name": "site",
+                "change": {"actions": ["update"], "before": {"acl": "private"}, "after": {"acl": "public-read"}}
+            },
+            {
+                "address": "aws_instance.web",
+                "type": "aws_instance",
+                "name": "web",
+                "change": {"actions": ["delete"], "before": {"ami": "ami-123"}, "after": null}
+            }
+        ]
+    }"#;
+    assert_eq!(
+        checker.check_str(plan).unwrap(),
+        vec![
+            Violation {
+                address: "aws_s3_bucket.site".to_string(),
+                message: "buckets must not be public".to_string(),
+            },
+            Violation {
+                address: "aws_instance.web".to_string(),
+                message: "resources must not be deleted".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_json_document() {
+    let mut checker = checker(
+        r#"violation(user, "admins need mfa") if user.admin = true and not user.mfa = true;
+           violation(_, "no admins allowed") if document matches {max_admins: 0};"#,
+    );
+    assert_eq!(
+        checker
+            .check_str(r#"[{"admin": true, "mfa": true}, {"admin": true, "mfa": false}]"#)
+            .unwrap(),
+        vec![Violation {
+            address: "1".to_string(),
+            message: "admins need mfa".to_string(),
+        }]
+    );
+    assert!(checker
+        .check_str(r#"{"admin": false, "max_admins": 3}"#)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        checker
+            .check_str(r#"{"admin": false, "max_admins": 0}"#)
+            .unwrap(),
+        vec![Violation {
+            address: "0".to_string(),
+            message: "no admins allowed".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_null_is_nil() {
+    let mut checker = checker(
+        r#"violation(r, "resources need an owner") if r.owner = nil;
+           violation(r, "tags must be set") if nil in r.tags;"#,
+    );
+    assert_eq!(
+        checker
+            .check_str(r#"[{"owner": null, "tags": ["a"]}, {"owner": "ops", "tags": [null]}]"#)
+            .unwrap(),
+        vec![
+            Violation {
+                address: "0".to_string(),
+                message: "resources need an owner".to_string(),
+            },
+            Violation {
+                address: "1".to_string(),
+                message: "tags must be set".to_string(),
+            },
+        ]
+    );
+}
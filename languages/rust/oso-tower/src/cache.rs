@@ -0,0 +1,161 @@
+//! A shared cache of authorization decisions.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use oso::ExpiringMap;
+
+use crate::Decision;
+
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Where a [`DecisionCache`] keeps its decisions.
+///
+/// Decisions are stored with the version of the policy they were made with,
+/// and only returned for the same version, so that services sharing a store
+/// never see decisions of a policy they haven't loaded. A store that fails,
+/// e.g. because it lost its connection, should act as if it was empty, so
+/// that the decision is made again.
+pub trait DecisionStore: Send + Sync {
+    fn get(&self, version: &str, decision: &Decision) -> Option<bool>;
+
+    fn insert(&self, version: &str, decision: Decision, allowed: bool, ttl: Duration);
+
+    /// Forget all decisions about `actor`, of any policy version.
+    fn invalidate_actor(&self, actor: &str);
+
+    /// Forget all decisions.
+    fn clear(&self);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Decisions kept in memory, for a single process.
+#[derive(Debug)]
+pub struct MemoryStore {
+    entries: Mutex<ExpiringMap<(String, Decision), bool>>,
+}
+
+impl MemoryStore {
+    /// Keep at most `capacity` decisions.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(ExpiringMap::new(capacity)),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl DecisionStore for MemoryStore {
+    fn get(&self, version: &str, decision: &Decision) -> Option<bool> {
+        let key = (version.to_string(), decision.clone());
+        self.entries.lock().unwrap().get(&key)
+    }
+
+    fn insert(&self, version: &str, decision: Decision, allowed: bool, ttl: Duration) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((version.to_string(), decision), allowed, ttl);
+    }
+
+    fn invalidate_actor(&self, actor: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|(_, decision), _| decision.actor != actor);
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// Decisions and when they expire.
+#[derive(Clone)]
+pub struct DecisionCache {
+    ttl: Duration,
+    version: Arc<RwLock<String>>,
+    store: Arc<dyn DecisionStore>,
+}
+
+impl fmt::Debug for DecisionCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecisionCache")
+            .field("ttl", &self.ttl)
+            .field("version", &*self.version.read().unwrap())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl DecisionCache {
+    /// Cache at most 10,000 decisions for `ttl` in memory. Use
+    /// [`with_store`](DecisionCache::with_store) with a
+    /// [`MemoryStore`] of another capacity to keep more or fewer.
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_store(ttl, MemoryStore::default())
+    }
+
+    /// Cache decisions for `ttl` in `store`, e.g. a
+    /// [`RedisStore`](crate::RedisStore) shared by several services.
+    pub fn with_store(ttl: Duration, store: impl DecisionStore + 'static) -> Self {
+        Self {
+            ttl,
+            version: Arc::new(RwLock::new(String::new())),
+            store: Arc::new(store),
+        }
+    }
+
+    /// Use the decisions made with the policy `version` from now on, e.g.
+    /// after the policy was reloaded. Decisions of other versions are kept
+    /// in the store until they expire, for services that still use them.
+    pub fn set_policy_version(&self, version: &str) {
+        *self.version.write().unwrap() = version.to_string();
+    }
+
+    pub fn policy_version(&self) -> String {
+        self.version.read().unwrap().clone()
+    }
+
+    pub fn get(&self, decision: &Decision) -> Option<bool> {
+        self.store.get(&self.version.read().unwrap(), decision)
+    }
+
+    pub fn insert(&self, decision: Decision, allowed: bool) {
+        self.store
+            .insert(&self.version.read().unwrap(), decision, allowed, self.ttl);
+    }
+
+    /// Forget all decisions about `actor`, e.g. after its roles changed.
+    pub fn invalidate_actor(&self, actor: &str) {
+        self.store.invalidate_actor(actor);
+    }
+
+    /// Forget all decisions, e.g. after the policy was reloaded.
+    pub fn clear(&self) {
+        self.store.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,53 @@
+//! # oso authorization for tower services
+//!
+//! [`AuthorizeLayer`] wraps an HTTP service so that every request is
+//! checked against `allow(actor, action, resource)` before it reaches the
+//! service. An extractor maps each request to a [`Decision`], typically
+//! the authenticated user, the route and the id of the requested resource.
+//! Requests with no decision are rejected with `401 Unauthorized`, denied
+//! requests with `403 Forbidden`.
+//!
+//! Decisions are cached in a [`DecisionCache`] for a fixed time to live.
+//! The cache is shared by all clones of the layer and its services, so an
+//! application can keep a handle to it, e.g. to invalidate the decisions
+//! about an actor whose roles changed. Decisions are only used with the
+//! [policy version](oso::Oso::policy_version) they were made with, so
+//! reloading the policy makes them again.
+//!
+//! Decisions are kept in memory by default. With the `redis` feature, a
+//! [`RedisStore`] shares them, and their invalidation, between services.
+
+mod cache;
+#[cfg(feature = "redis")]
+mod redis_store;
+mod service;
+
+pub use cache::{DecisionCache, DecisionStore, MemoryStore};
+#[cfg(feature = "redis")]
+pub use redis_store::RedisStore;
+pub use service::{Authorize, AuthorizeLayer};
+
+/// The arguments of an `allow` query, identifying a decision.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Decision {
+    pub actor: String,
+    pub action: String,
+    pub resource: String,
+}
+
+impl Decision {
+    pub fn new(actor: &str, action: &str, resource: &str) -> Self {
+        Self {
+            actor: actor.to_string(),
+            action: action.to_string(),
+            resource: resource.to_string(),
+        }
+    }
+
+    /// A decision whose action is the route of `request`,
+    /// e.g. `GET /documents`.
+    pub fn for_route<B>(request: &http::Request<B>, actor: &str, resource: &str) -> Self {
+        let action = format!("{} {}", request.method(), request.uri().path());
+        Self::new(actor, &action, resource)
+    }
+}
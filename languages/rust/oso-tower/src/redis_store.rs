@@ -0,0 +1,126 @@
+//! Decisions kept in Redis, shared by several services.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use redis::{Commands, Connection, RedisResult};
+
+use crate::{Decision, DecisionStore};
+
+/// Decisions kept in Redis, so that services scaled horizontally share
+/// decisions and their invalidation.
+///
+/// Keys start with a prefix, `oso` by default, followed by `:decision:`
+/// for decisions and `:actor:` for the sets of decisions about an actor.
+/// Redis errors are logged and otherwise treated as cache misses.
+pub struct RedisStore {
+    prefix: String,
+    connection: Mutex<Connection>,
+}
+
+impl RedisStore {
+    /// Connect to the Redis server at `url`, e.g. `redis://127.0.0.1/`.
+    pub fn open(url: &str) -> RedisResult<Self> {
+        let connection = redis::Client::open(url)?.get_connection()?;
+        Ok(Self {
+            prefix: String::from("oso"),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Start keys with `prefix`, e.g. to share a server between
+    /// applications. It must not contain glob characters.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    fn decision_key(&self, version: &str, decision: &Decision) -> String {
+        format!(
+            "{}:decision:{:?}:{:?}:{:?}:{:?}",
+            self.prefix, version, decision.actor, decision.action, decision.resource
+        )
+    }
+
+    fn actor_key(&self, actor: &str) -> String {
+        format!("{}:actor:{:?}", self.prefix, actor)
+    }
+
+    fn keys(&self, connection: &mut Connection, pattern: &str) -> RedisResult<Vec<String>> {
+        Ok(connection.scan_match::<_, String>(pattern)?.collect())
+    }
+
+    fn run<T>(
+        &self,
+        operation: &str,
+        f: impl FnOnce(&mut Connection) -> RedisResult<T>,
+    ) -> Option<T> {
+        let mut connection = self.connection.lock().unwrap();
+        match f(&mut *connection) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                tracing::warn!(error = %e, "decision cache {} failed", operation);
+                None
+            }
+        }
+    }
+}
+
+impl DecisionStore for RedisStore {
+    fn get(&self, version: &str, decision: &Decision) -> Option<bool> {
+        let key = self.decision_key(version, decision);
+        self.run("get", |connection| connection.get::<_, Option<bool>>(key))
+            .flatten()
+    }
+
+    fn insert(&self, version: &str, decision: Decision, allowed: bool, ttl: Duration) {
+        let key = self.decision_key(version, &decision);
+        let actor_key = self.actor_key(&decision.actor);
+        let ttl = ttl.as_millis() as u64;
+        self.run("insert", |connection| {
+            redis::pipe()
+                .atomic()
+                .cmd("SET")
+                .arg(&key)
+                .arg(allowed)
+                .arg("PX")
+                .arg(ttl)
+                .ignore()
+                .cmd("SADD")
+                .arg(&actor_key)
+                .arg(&key)
+                .ignore()
+                .cmd("PEXPIRE")
+                .arg(&actor_key)
+                .arg(ttl)
+                .ignore()
+                .query::<()>(connection)
+        });
+    }
+
+    fn invalidate_actor(&self, actor: &str) {
+        let actor_key = self.actor_key(actor);
+        self.run("invalidation", |connection| {
+            let mut keys: Vec<String> = connection.smembers(&actor_key)?;
+            keys.push(actor_key);
+            connection.del::<_, ()>(keys)
+        });
+    }
+
+    fn clear(&self) {
+        let pattern = format!("{}:*", self.prefix);
+        self.run("invalidation", |connection| {
+            let keys = self.keys(connection, &pattern)?;
+            if keys.is_empty() {
+                return Ok(());
+            }
+            connection.del::<_, ()>(keys)
+        });
+    }
+
+    fn len(&self) -> usize {
+        let pattern = format!("{}:decision:*", self.prefix);
+        self.run("count", |connection| self.keys(connection, &pattern))
+            .map_or(0, |keys| keys.len())
+    }
+}
@@ -0,0 +1,127 @@
+//! The authorization layer and service.
+
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_util::future::{ready, Either, Ready};
+use http::{Request, Response, StatusCode};
+use oso::Oso;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{Decision, DecisionCache};
+
+/// Applies [`Authorize`] to services.
+pub struct AuthorizeLayer<F> {
+    oso: Oso,
+    cache: DecisionCache,
+    extract: Arc<F>,
+}
+
+impl<F> AuthorizeLayer<F> {
+    /// Authorize requests with `oso`, mapping them to decisions with
+    /// `extract` and caching the results in `cache`.
+    pub fn new(oso: Oso, cache: DecisionCache, extract: F) -> Self {
+        Self {
+            oso,
+            cache,
+            extract: Arc::new(extract),
+        }
+    }
+
+    pub fn cache(&self) -> &DecisionCache {
+        &self.cache
+    }
+}
+
+impl<F> Clone for AuthorizeLayer<F> {
+    fn clone(&self) -> Self {
+        Self {
+            oso: self.oso.clone(),
+            cache: self.cache.clone(),
+            extract: self.extract.clone(),
+        }
+    }
+}
+
+impl<S, F> Layer<S> for AuthorizeLayer<F> {
+    type Service = Authorize<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authorize {
+            inner,
+            oso: self.oso.clone(),
+            cache: self.cache.clone(),
+            extract: self.extract.clone(),
+        }
+    }
+}
+
+/// A service that only passes on requests allowed by the policy.
+pub struct Authorize<S, F> {
+    inner: S,
+    oso: Oso,
+    cache: DecisionCache,
+    extract: Arc<F>,
+}
+
+impl<S: Clone, F> Clone for Authorize<S, F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            oso: self.oso.clone(),
+            cache: self.cache.clone(),
+            extract: self.extract.clone(),
+        }
+    }
+}
+
+impl<S, F> Authorize<S, F> {
+    fn is_allowed(&mut self, decision: Decision) -> oso::Result<bool> {
+        self.cache.set_policy_version(&self.oso.policy_version());
+        if let Some(allowed) = self.cache.get(&decision) {
+            return Ok(allowed);
+        }
+        let allowed = self.oso.is_allowed(
+            decision.actor.clone(),
+            decision.action.clone(),
+            decision.resource.clone(),
+        )?;
+        self.cache.insert(decision, allowed);
+        Ok(allowed)
+    }
+}
+
+fn reject<B: Default>(status: StatusCode) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = status;
+    response
+}
+
+impl<S, F, ReqBody, ResBody> Service<Request<ReqBody>> for Authorize<S, F>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    F: Fn(&Request<ReqBody>) -> Option<Decision>,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<S::Response, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let status = match (self.extract)(&request).map(|decision| self.is_allowed(decision)) {
+            Some(Ok(true)) => return Either::Left(self.inner.call(request)),
+            Some(Ok(false)) => StatusCode::FORBIDDEN,
+            Some(Err(e)) => {
+                tracing::error!(error = %e, "authorization failed");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            None => StatusCode::UNAUTHORIZED,
+        };
+        Either::Right(ready(Ok(reject(status))))
+    }
+}
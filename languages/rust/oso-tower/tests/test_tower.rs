@@ -0,0 +1,130 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use http::{Request, Response, StatusCode};
+use oso::Oso;
+use oso_tower::{AuthorizeLayer, Decision, DecisionCache, DecisionStore, MemoryStore};
+use tower::{service_fn, Layer, ServiceExt};
+
+fn request(user: Option<&str>, path: &str) -> Request<()> {
+    let mut request = Request::get(path);
+    if let Some(user) = user {
+        request = request.header("x-user", user);
+    }
+    request.body(()).unwrap()
+}
+
+fn extract(request: &Request<()>) -> Option<Decision> {
+    let user = request.headers().get("x-user")?.to_str().ok()?;
+    let id = request.uri().path().rsplit('/').next()?;
+    Some(Decision::for_route(request, user, id))
+}
+
+#[test]
+fn test_authorize() {
+    let mut oso = Oso::new();
+    oso.load_str(r#"allow("alice", "GET /documents/1", "1");"#)
+        .unwrap();
+    let layer = AuthorizeLayer::new(
+        oso.clone(),
+        DecisionCache::new(Duration::from_secs(60)),
+        extract,
+    );
+    let status = |user, path| {
+        let service = layer.layer(service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(String::from("ok")))
+        }));
+        block_on(service.oneshot(request(user, path)))
+            .unwrap()
+            .status()
+    };
+
+    assert_eq!(status(Some("alice"), "/documents/1"), StatusCode::OK);
+    assert_eq!(status(Some("alice"), "/documents/2"), StatusCode::FORBIDDEN);
+    assert_eq!(status(Some("bob"), "/documents/1"), StatusCode::FORBIDDEN);
+    assert_eq!(status(None, "/documents/1"), StatusCode::UNAUTHORIZED);
+    assert_eq!(layer.cache().len(), 3);
+
+    // Decisions are made again after the policy is reloaded.
+    let version = layer.cache().policy_version();
+    oso.load_str(r#"allow("bob", "GET /documents/1", "1");"#)
+        .unwrap();
+    assert_eq!(status(Some("bob"), "/documents/1"), StatusCode::OK);
+    assert_ne!(layer.cache().policy_version(), version);
+    assert_eq!(layer.cache().len(), 4);
+    layer.cache().invalidate_actor("bob");
+    assert_eq!(layer.cache().len(), 2);
+
+    layer.cache().clear();
+    assert!(layer.cache().is_empty());
+}
+
+#[test]
+fn test_cache_expiry() {
+    let cache = DecisionCache::with_store(Duration::from_secs(0), MemoryStore::new(2));
+    let decision = Decision::new("alice", "read", "1");
+    cache.insert(decision.clone(), true);
+    assert_eq!(cache.get(&decision), None);
+
+    let cache = DecisionCache::with_store(Duration::from_secs(60), MemoryStore::new(2));
+    cache.insert(Decision::new("alice", "read", "1"), true);
+    cache.insert(Decision::new("alice", "read", "2"), false);
+    assert_eq!(cache.get(&Decision::new("alice", "read", "2")), Some(false));
+    // A full cache of live decisions is emptied to make room.
+    cache.insert(Decision::new("alice", "read", "3"), true);
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_policy_versions() {
+    let cache = DecisionCache::new(Duration::from_secs(60));
+    let decision = Decision::new("alice", "read", "1");
+    cache.set_policy_version("v1");
+    cache.insert(decision.clone(), true);
+    assert_eq!(cache.get(&decision), Some(true));
+
+    // Decisions of other versions are not used, but kept.
+    cache.set_policy_version("v2");
+    assert_eq!(cache.get(&decision), None);
+    cache.insert(decision.clone(), false);
+    assert_eq!(cache.get(&decision), Some(false));
+    assert_eq!(cache.len(), 2);
+    cache.set_policy_version("v1");
+    assert_eq!(cache.get(&decision), Some(true));
+
+    cache.invalidate_actor("alice");
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_custom_store() {
+    use std::sync::{Arc, Mutex};
+
+    /// A store that records the versions it was asked about.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl DecisionStore for Recorder {
+        fn get(&self, version: &str, _: &Decision) -> Option<bool> {
+            self.0.lock().unwrap().push(version.to_string());
+            None
+        }
+
+        fn insert(&self, _: &str, _: Decision, _: bool, _: Duration) {}
+
+        fn invalidate_actor(&self, _: &str) {}
+
+        fn clear(&self) {}
+
+        fn len(&self) -> usize {
+            0
+        }
+    }
+
+    let recorder = Recorder::default();
+    let cache = DecisionCache::with_store(Duration::from_secs(60), recorder.clone());
+    cache.set_policy_version("v1");
+    assert_eq!(cache.get(&Decision::new("alice", "read", "1")), None);
+    assert_eq!(*recorder.0.lock().unwrap(), vec![String::from("v1")]);
+}
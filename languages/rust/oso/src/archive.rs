@@ -0,0 +1,55 @@
+//! Load policies bundled as a zip or tar archive, without unpacking it to
+//! disk.
+
+use std::io::{Cursor, Read};
+
+use crate::Oso;
+
+/// Read the `.polar` files in the zip or tar archive `bytes`, in archive
+/// order, as pairs of their path in the archive and their contents.
+fn polar_files(bytes: &[u8]) -> crate::Result<Vec<(String, String)>> {
+    let mut files = vec![];
+    if bytes.starts_with(b"PK\x03\x04") {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(std::io::Error::from)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(std::io::Error::from)?;
+            if file.name().ends_with(".polar") {
+                let name = file.name().to_string();
+                let mut src = String::new();
+                file.read_to_string(&mut src)?;
+                files.push((name, src));
+            }
+        }
+    } else {
+        let mut archive = tar::Archive::new(bytes);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            if entry.header().entry_type().is_file() && name.ends_with(".polar") {
+                let mut src = String::new();
+                entry.read_to_string(&mut src)?;
+                files.push((name, src));
+            }
+        }
+    }
+    Ok(files)
+}
+
+impl Oso {
+    /// Load the `.polar` files in a zip or tar archive, in archive order,
+    /// e.g. policies bundled as a single artifact in object storage. Other
+    /// files in the archive are ignored. Errors name the path of the file
+    /// in the archive.
+    pub fn load_archive(&mut self, bytes: &[u8]) -> crate::Result<()> {
+        let files = polar_files(bytes)?;
+        let conflicts = self.rule_conflicts();
+        let migrations = self.attribute_migrations();
+        for (name, src) in files {
+            self.inner.load(&src, Some(name))?;
+            self.record_policy(&src);
+        }
+        self.warn_conflicts(&conflicts);
+        self.warn_migrations(&migrations);
+        self.check_inline_queries()
+    }
+}
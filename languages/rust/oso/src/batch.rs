@@ -0,0 +1,114 @@
+//! Evaluate authorization over Arrow record batches, for enforcing
+//! row-level security in analytics pipelines.
+//!
+//! Each row is passed to the `allow(actor, action, row)` rule as a
+//! dictionary from column names to values. Null values are omitted
+//! from the dictionary.
+
+use std::collections::HashMap;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array,
+    Int8Array, LargeStringArray, StringArray, UInt16Array, UInt32Array, UInt8Array,
+};
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+
+use crate::{Oso, PolarValue, ToPolar};
+
+type Column<'a> = Box<dyn Fn(usize) -> PolarValue + 'a>;
+
+impl Oso {
+    /// Evaluate `allow(actor, action, row)` for every row of `batch`,
+    /// returning a mask of the authorized rows.
+    pub fn authorize_batch<Actor, Action>(
+        &mut self,
+        actor: Actor,
+        action: Action,
+        batch: &RecordBatch,
+    ) -> crate::Result<BooleanArray>
+    where
+        Actor: ToPolar,
+        Action: ToPolar,
+    {
+        let schema = batch.schema();
+        let columns = schema
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .map(|(field, array)| Ok((field.name().clone(), column(field.name(), array)?)))
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let mut mask = Vec::with_capacity(batch.num_rows());
+        for row in 0..batch.num_rows() {
+            let fields: HashMap<String, PolarValue> = columns
+                .iter()
+                .zip(batch.columns())
+                .filter(|(_, array)| array.is_valid(row))
+                .map(|((name, value), _)| (name.clone(), value(row)))
+                .collect();
+            let row = PolarValue::Map(fields);
+            let args: Vec<&dyn ToPolar> = vec![&actor, &action, &row];
+            let allowed = match self.query_rule("allow", args)?.next() {
+                Some(result) => result.map(|_| true)?,
+                None => false,
+            };
+            mask.push(allowed);
+        }
+        Ok(BooleanArray::from(mask))
+    }
+
+    /// Return the rows of `batch` for which `allow(actor, action, row)` holds.
+    pub fn filter_batch<Actor, Action>(
+        &mut self,
+        actor: Actor,
+        action: Action,
+        batch: &RecordBatch,
+    ) -> crate::Result<RecordBatch>
+    where
+        Actor: ToPolar,
+        Action: ToPolar,
+    {
+        let mask = self.authorize_batch(actor, action, batch)?;
+        Ok(arrow::compute::filter_record_batch(batch, &mask)?)
+    }
+}
+
+/// Build an accessor converting the values of `array` to Polar.
+fn column<'a>(name: &str, array: &'a ArrayRef) -> crate::Result<Column<'a>> {
+    macro_rules! values {
+        ($array:ty, $variant:ident, $convert:path) => {{
+            let array = array.as_any().downcast_ref::<$array>().unwrap();
+            Box::new(move |row| PolarValue::$variant($convert(array.value(row))))
+        }};
+    }
+
+    let column: Column = match array.data_type() {
+        DataType::Boolean => values!(BooleanArray, Bool, std::convert::identity),
+        DataType::Int8 => values!(Int8Array, Integer, i64::from),
+        DataType::Int16 => values!(Int16Array, Integer, i64::from),
+        DataType::Int32 => values!(Int32Array, Integer, i64::from),
+        DataType::Int64 => values!(Int64Array, Integer, std::convert::identity),
+        DataType::UInt8 => values!(UInt8Array, Integer, i64::from),
+        DataType::UInt16 => values!(UInt16Array, Integer, i64::from),
+        DataType::UInt32 => values!(UInt32Array, Integer, i64::from),
+        DataType::Float32 => values!(Float32Array, Float, f64::from),
+        DataType::Float64 => values!(Float64Array, Float, std::convert::identity),
+        DataType::Utf8 => {
+            let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+            Box::new(move |row| PolarValue::String(array.value(row).to_string()))
+        }
+        DataType::LargeUtf8 => {
+            let array = array.as_any().downcast_ref::<LargeStringArray>().unwrap();
+            Box::new(move |row| PolarValue::String(array.value(row).to_string()))
+        }
+        data_type => {
+            return lazy_error!(
+                "column `{}` has type {:?}, which cannot be converted to Polar",
+                name,
+                data_type
+            )
+        }
+    };
+    Ok(column)
+}
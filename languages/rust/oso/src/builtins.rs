@@ -4,7 +4,7 @@ use polar_core::terms::{Symbol, Value};
 
 use std::collections::HashMap;
 
-use crate::Class;
+use crate::{Class, Instance, PolarValue};
 
 fn boolean() -> Class<bool> {
     Class::<bool>::with_default().name("Boolean")
@@ -32,14 +32,100 @@ fn string() -> Class<String> {
         .add_method("ends_with", |s: &String, pat: String| s.ends_with(&pat))
 }
 
+/// `uuid::Uuid`, created with `Uuid.parse(s)`.
+#[cfg(feature = "uuid")]
+fn uuid() -> Class<uuid::Uuid> {
+    Class::<uuid::Uuid>::new()
+        .name("Uuid")
+        .with_equality_check()
+        .with_comparison_check()
+        .add_class_method("parse", |s: String| uuid::Uuid::parse_str(&s))
+        .add_class_method("nil", uuid::Uuid::nil)
+        .add_method("to_string", |u: &uuid::Uuid| u.to_string())
+}
+
+#[cfg(feature = "uuid")]
+impl crate::HostClass for uuid::Uuid {}
+
+/// `rust_decimal::Decimal`, created with `Decimal.parse(s)` or
+/// `Decimal.from_int(i)`. Decimals compare with other decimals only, so
+/// policies compare them to decimal literals, e.g.
+/// `amount <= Decimal.parse("1000.00")`.
+#[cfg(feature = "rust_decimal")]
+fn decimal() -> Class<rust_decimal::Decimal> {
+    use rust_decimal::Decimal;
+
+    Class::<Decimal>::new()
+        .name("Decimal")
+        .with_equality_check()
+        .with_comparison_check()
+        .add_class_method("parse", |s: String| s.parse::<Decimal>())
+        .add_class_method("from_int", |i: i64| -> Decimal { i.into() })
+        .add_method("add", |a: &Decimal, b: Decimal| a.checked_add(b))
+        .add_method("sub", |a: &Decimal, b: Decimal| a.checked_sub(b))
+        .add_method("mul", |a: &Decimal, b: Decimal| a.checked_mul(b))
+        .add_method("round", |d: &Decimal, dp: u32| d.round_dp(dp))
+        .add_method("to_string", |d: &Decimal| d.to_string())
+}
+
+#[cfg(feature = "rust_decimal")]
+impl crate::HostClass for rust_decimal::Decimal {}
+
+/// `regex::Regex`, created with `Regex.compile(pattern)` or registered by
+/// the application with `Oso::register_pattern`. Regexes are equal when
+/// their patterns are.
+#[cfg(feature = "regex")]
+fn regex() -> Class<regex::Regex> {
+    use regex::Regex;
+
+    Class::<Regex>::new()
+        .name("Regex")
+        .set_equality_check(|a: &Regex, b: &Regex| a.as_str() == b.as_str())
+        .add_class_method("compile", |pattern: String| Regex::new(&pattern))
+        .add_class_method("escape", |s: String| regex::escape(&s))
+        .add_method("matches", |r: &Regex, s: String| r.is_match(&s))
+        .add_method("find", |r: &Regex, s: String| {
+            r.find(&s).map(|m| m.as_str().to_string())
+        })
+        .add_method("to_string", |r: &Regex| r.as_str().to_string())
+}
+
+#[cfg(feature = "regex")]
+impl crate::HostClass for regex::Regex {}
+
+/// The class of `nil`, which is represented as `Option::<PolarValue>::None`.
+pub fn nil() -> Class<Option<PolarValue>> {
+    Class::<Option<PolarValue>>::new()
+        .name("Nil")
+        .with_equality_check()
+}
+
+/// The value of the `nil` constant.
+pub fn nil_instance() -> Instance {
+    nil().build().cast_to_instance(Option::<PolarValue>::None)
+}
+
 /// Returns the builtin types, the name, class, and instance
 pub fn classes() -> Vec<Class> {
-    vec![
+    let mut classes = vec![
         boolean().erase_type(),
         integer().erase_type(),
         float().erase_type(),
         list().erase_type(),
         dictionary().erase_type(),
         string().erase_type(),
-    ]
+        nil().erase_type(),
+        crate::conditions::class().erase_type(),
+        crate::rollout::class().erase_type(),
+    ];
+    classes.extend(crate::net::classes());
+    #[cfg(feature = "chrono")]
+    classes.extend(crate::datetime::classes());
+    #[cfg(feature = "uuid")]
+    classes.push(uuid().erase_type());
+    #[cfg(feature = "rust_decimal")]
+    classes.push(decimal().erase_type());
+    #[cfg(feature = "regex")]
+    classes.push(regex().erase_type());
+    classes
 }
@@ -0,0 +1,266 @@
+//! Answer `is_allowed` for simple `allow` rules without the virtual
+//! machine.
+//!
+//! Policies often spend most checks in a few rules that only compare
+//! attributes of the resource with literals and the actor. With compiled
+//! rules enabled, the `allow` rules for an action and a resource class are
+//! translated into a filter once (see the `filter` module) and turned into
+//! a closure reading the attributes of the resource directly. Rules that
+//! can't be translated, and checks the closure can't answer, e.g. for an
+//! actor that is an instance or attributes that aren't strings, numbers or
+//! booleans, fall back to the virtual machine.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use polar_core::terms::*;
+
+use crate::context::QueryContext;
+use crate::filter::{Comparison, Filter, Operand};
+use crate::host::{Host, Instance, ToPolarResults};
+use crate::{Oso, ToPolar};
+
+/// Looks up an attribute of the resource.
+type Lookup<'a> = dyn FnMut(&str) -> Option<Value> + 'a;
+
+/// Decides whether the actor is allowed, or `None` to ask the virtual
+/// machine instead.
+pub(crate) type CompiledRule = Arc<dyn Fn(&Value, &mut Lookup) -> Option<bool> + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct CompiledRules {
+    enabled: bool,
+    /// The policy version and host generation the rules were compiled for.
+    version: (String, u64),
+    /// By resource class and action, `None` for rules that can't be
+    /// compiled.
+    rules: HashMap<(String, String), Option<CompiledRule>>,
+}
+
+impl Oso {
+    /// Experimental: answer [`is_allowed`](Oso::is_allowed) without the
+    /// virtual machine when the `allow` rules for the action and the class
+    /// of the resource only compare attributes of the resource with
+    /// literals and the actor, e.g.
+    ///
+    /// ```polar
+    /// allow(_, "read", doc: Document) if doc.public = true;
+    /// allow(actor, "edit", doc: Document) if doc.owner = actor;
+    /// ```
+    ///
+    /// Other checks use the virtual machine as usual.
+    pub fn set_compiled_rules(&mut self, enabled: bool) {
+        let mut compiled = self.compiled.write().unwrap();
+        compiled.enabled = enabled;
+        compiled.rules.clear();
+    }
+
+    /// Decide whether `actor` may perform `action` on `resource` with the
+    /// compiled rules, if they are enabled and can.
+    pub(crate) fn compiled_is_allowed(
+        &self,
+        actor: &Term,
+        action: &Term,
+        resource: &Term,
+    ) -> Option<bool> {
+        if !self.compiled.read().unwrap().enabled {
+            return None;
+        }
+        let (action, id) = match (action.value(), resource.value()) {
+            (Value::String(action), Value::ExternalInstance(instance)) => {
+                (action, instance.instance_id)
+            }
+            _ => return None,
+        };
+        let instance = self.host.lock().unwrap().get_instance(id)?.clone();
+        let rule = self.compiled_rule(&instance.name, action, resource)?;
+
+        let mut host = self.host.lock().unwrap();
+        let context = QueryContext::new();
+        let allowed = rule(actor.value(), &mut |name| {
+            attribute(&instance, name, &mut host, &context)
+        });
+        tracing::debug!(allowed = ?allowed, class = %instance.name, action = %action, "compiled");
+        allowed
+    }
+
+    fn compiled_rule(&self, class: &str, action: &str, resource: &Term) -> Option<CompiledRule> {
+        let version = (
+            self.policy_version(),
+            self.host.lock().unwrap().generation(),
+        );
+        let key = (class.to_string(), action.to_string());
+        {
+            let compiled = self.compiled.read().unwrap();
+            if compiled.version == version {
+                if let Some(rule) = compiled.rules.get(&key) {
+                    return rule.clone();
+                }
+            }
+        }
+
+        let rule = if self.other_rules_apply(class, action, resource) {
+            None
+        } else {
+            self.allow_filter(class, action, "compiled rules")
+                .ok()
+                .map(compile)
+        };
+        let mut compiled = self.compiled.write().unwrap();
+        if compiled.version != version {
+            compiled.version = version;
+            compiled.rules.clear();
+        }
+        compiled.rules.insert(key, rule.clone());
+        rule
+    }
+
+    /// Whether `allow` rules specialized on another class than `class`
+    /// apply to `resource`, e.g. on a class it inherits from. They are
+    /// left out of its filter.
+    fn other_rules_apply(&self, class: &str, action: &str, resource: &Term) -> bool {
+        let host = self.host.lock().unwrap();
+        let kb = self.inner.kb.read().unwrap();
+        let rules = match kb.rules.get(&Symbol("allow".to_string())) {
+            Some(generic_rule) => generic_rule.get_applicable_rules(&vec![
+                Term::new_temporary(Value::Variable(Symbol("actor".to_string()))),
+                Term::new_temporary(Value::String(action.to_string())),
+                resource.clone(),
+            ]),
+            None => return false,
+        };
+        rules
+            .iter()
+            .filter(|rule| rule.params.len() == 3)
+            .any(
+                |rule| match rule.params[2].specializer.as_ref().map(Term::value) {
+                    None => false,
+                    Some(Value::Pattern(Pattern::Instance(InstanceLiteral { tag, .. }))) => {
+                        tag.0 != class && host.isa(resource.clone(), tag).unwrap_or(true)
+                    }
+                    Some(_) => true,
+                },
+            )
+    }
+}
+
+/// Look up the attribute `name` of `instance`, if it has exactly one value
+/// that is a string, number or boolean.
+fn attribute(
+    instance: &Instance,
+    name: &str,
+    host: &mut Host,
+    context: &QueryContext,
+) -> Option<Value> {
+    let (method, receiver) = instance.find_method(name, false, host)?;
+    let results = method.invoke(receiver, vec![], None, host, context).ok()?;
+    let mut results = results.to_polar_results();
+    let value = results.next()?.ok()?.try_to_polar(host).ok()?;
+    if results.next().is_some() {
+        return None;
+    }
+    match value.value() {
+        Value::Number(_) | Value::String(_) | Value::Boolean(_) => Some(value.value().clone()),
+        _ => None,
+    }
+}
+
+fn compile(filter: Filter) -> CompiledRule {
+    match filter {
+        Filter::Bool(b) => Arc::new(move |_, _| Some(b)),
+        Filter::And(filters) => {
+            let filters: Vec<_> = filters.into_iter().map(compile).collect();
+            Arc::new(move |actor, lookup| {
+                for filter in &filters {
+                    if !filter(actor, lookup)? {
+                        return Some(false);
+                    }
+                }
+                Some(true)
+            })
+        }
+        Filter::Or(filters) => {
+            let filters: Vec<_> = filters.into_iter().map(compile).collect();
+            Arc::new(move |actor, lookup| {
+                for filter in &filters {
+                    if filter(actor, lookup)? {
+                        return Some(true);
+                    }
+                }
+                Some(false)
+            })
+        }
+        Filter::Not(filter) => {
+            let filter = compile(*filter);
+            Arc::new(move |actor, lookup| filter(actor, lookup).map(|b| !b))
+        }
+        Filter::Compare(left, comparison, right) => Arc::new(move |actor, lookup| {
+            let left = operand(&left, actor, lookup)?;
+            let right = operand(&right, actor, lookup)?;
+            compare(&left, comparison, &right)
+        }),
+        Filter::In(item, values) => Arc::new(move |actor, lookup| {
+            let item = operand(&item, actor, lookup)?;
+            for value in &values {
+                if compare(&item, Comparison::Eq, &operand(value, actor, lookup)?)? {
+                    return Some(true);
+                }
+            }
+            Some(false)
+        }),
+        Filter::ActorIs(value, filter) => {
+            let filter = compile(*filter);
+            Arc::new(move |actor, lookup| {
+                let value = operand(&value, actor, lookup)?;
+                if compare(actor_value(actor)?, Comparison::Eq, &value)? {
+                    filter(actor, lookup)
+                } else {
+                    Some(false)
+                }
+            })
+        }
+    }
+}
+
+/// The actor, if it can be compared without the host.
+fn actor_value(actor: &Value) -> Option<&Value> {
+    match actor {
+        Value::Number(_) | Value::String(_) | Value::Boolean(_) => Some(actor),
+        _ => None,
+    }
+}
+
+fn operand(operand: &Operand, actor: &Value, lookup: &mut Lookup) -> Option<Value> {
+    match operand {
+        Operand::Actor => actor_value(actor).cloned(),
+        Operand::Tenant => None,
+        Operand::Column(name) => lookup(name),
+        Operand::Literal(value) => Some(value.clone()),
+    }
+}
+
+/// Compare two strings, numbers or booleans like Polar does, or `None` for
+/// values of different types. Integers and floats aren't compared with each
+/// other, nor `NaN`, since Polar may be set to reject them.
+fn compare(left: &Value, comparison: Comparison, right: &Value) -> Option<bool> {
+    let ordering = match (left, right) {
+        (Value::Number(Numeric::Integer(left)), Value::Number(Numeric::Integer(right))) => {
+            Some(left.cmp(right))
+        }
+        (Value::Number(Numeric::Float(left)), Value::Number(Numeric::Float(right))) => {
+            left.partial_cmp(right)
+        }
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        (Value::Boolean(left), Value::Boolean(right)) => Some(left.cmp(right)),
+        _ => None,
+    }?;
+    Some(match comparison {
+        Comparison::Eq => ordering == Ordering::Equal,
+        Comparison::Neq => ordering != Ordering::Equal,
+        Comparison::Lt => ordering == Ordering::Less,
+        Comparison::Leq => ordering != Ordering::Greater,
+        Comparison::Gt => ordering == Ordering::Greater,
+        Comparison::Geq => ordering != Ordering::Less,
+    })
+}
@@ -0,0 +1,196 @@
+//! Condition operators familiar from cloud IAM policies.
+//!
+//! These are registered as class methods on the builtin `Condition` class,
+//! so an IAM condition like `{"StringLike": {"s3:prefix": "home/*"}}` can be
+//! written in Polar as `Condition.StringLike(request.prefix, "home/*")`.
+
+use std::net::IpAddr;
+
+use crate::errors::TypeError;
+use crate::{Class, Network, PolarValue};
+
+#[derive(Clone, Default)]
+pub struct Condition;
+
+pub fn class() -> Class<Condition> {
+    Class::<Condition>::new()
+        .name("Condition")
+        .add_class_method("StringEquals", |a: String, b: String| a == b)
+        .add_class_method("StringNotEquals", |a: String, b: String| a != b)
+        .add_class_method("StringEqualsIgnoreCase", |a: String, b: String| {
+            a.to_lowercase() == b.to_lowercase()
+        })
+        .add_class_method("StringNotEqualsIgnoreCase", |a: String, b: String| {
+            a.to_lowercase() != b.to_lowercase()
+        })
+        .add_class_method("StringLike", |value: String, pattern: String| {
+            string_like(&value, &pattern)
+        })
+        .add_class_method("StringNotLike", |value: String, pattern: String| {
+            !string_like(&value, &pattern)
+        })
+        .add_class_method(
+            "NumericEquals",
+            numeric(|a, b| (a - b).abs() < f64::EPSILON),
+        )
+        .add_class_method(
+            "NumericNotEquals",
+            numeric(|a, b| (a - b).abs() >= f64::EPSILON),
+        )
+        .add_class_method("NumericLessThan", numeric(|a, b| a < b))
+        .add_class_method("NumericLessThanEquals", numeric(|a, b| a <= b))
+        .add_class_method("NumericGreaterThan", numeric(|a, b| a > b))
+        .add_class_method("NumericGreaterThanEquals", numeric(|a, b| a >= b))
+        .add_class_method("DateEquals", date(|a, b| (a - b).abs() < f64::EPSILON))
+        .add_class_method("DateNotEquals", date(|a, b| (a - b).abs() >= f64::EPSILON))
+        .add_class_method("DateLessThan", date(|a, b| a < b))
+        .add_class_method("DateLessThanEquals", date(|a, b| a <= b))
+        .add_class_method("DateGreaterThan", date(|a, b| a > b))
+        .add_class_method("DateGreaterThanEquals", date(|a, b| a >= b))
+        .add_class_method("Bool", |value: bool, expected: bool| value == expected)
+        .add_class_method("IpAddress", |ip: String, network: String| {
+            ip_address(&ip, &network)
+        })
+        .add_class_method("NotIpAddress", |ip: String, network: String| {
+            ip_address(&ip, &network).map(|matches| !matches)
+        })
+}
+
+fn numeric(
+    op: fn(f64, f64) -> bool,
+) -> impl Fn(PolarValue, PolarValue) -> crate::Result<bool> + Send + Sync + 'static {
+    move |a, b| Ok(op(number(&a)?, number(&b)?))
+}
+
+fn date(
+    op: fn(f64, f64) -> bool,
+) -> impl Fn(PolarValue, PolarValue) -> crate::Result<bool> + Send + Sync + 'static {
+    move |a, b| Ok(op(timestamp(&a)?, timestamp(&b)?))
+}
+
+fn number(value: &PolarValue) -> crate::Result<f64> {
+    match value {
+        PolarValue::Integer(i) => Ok(*i as f64),
+        PolarValue::Float(f) => Ok(*f),
+        // IAM condition values are frequently strings.
+        PolarValue::String(s) => s.parse().map_err(|_| number_expected()),
+        _ => Err(number_expected()),
+    }
+}
+
+fn number_expected() -> crate::OsoError {
+    TypeError {
+        expected: String::from("number"),
+    }
+    .user()
+}
+
+/// Interpret `value` as seconds since the Unix epoch, either directly
+/// or by parsing an ISO 8601 date or date-time string.
+fn timestamp(value: &PolarValue) -> crate::Result<f64> {
+    let timestamp = match value {
+        PolarValue::Integer(i) => Some(*i as f64),
+        PolarValue::Float(f) => Some(*f),
+        PolarValue::String(s) => parse_iso8601(s),
+        _ => None,
+    };
+    timestamp.ok_or_else(|| {
+        TypeError {
+            expected: String::from("ISO 8601 date"),
+        }
+        .user()
+    })
+}
+
+fn parse_iso8601(s: &str) -> Option<f64> {
+    let (date, time) = match s.find('T') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, ""),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (clock, offset) = if let Some(clock) = time.strip_suffix('Z') {
+        (clock, 0.0)
+    } else if let Some(i) = time.rfind(|c| c == '+' || c == '-') {
+        let sign = if time[i..].starts_with('-') {
+            -1.0
+        } else {
+            1.0
+        };
+        let mut offset = time[i + 1..].splitn(2, ':');
+        let hours: f64 = offset.next()?.parse().ok()?;
+        let minutes: f64 = offset.next().unwrap_or("0").parse().ok()?;
+        (&time[..i], sign * (hours * 3600.0 + minutes * 60.0))
+    } else {
+        (time, 0.0)
+    };
+
+    let mut seconds = 0.0;
+    if !clock.is_empty() {
+        let mut hms = clock.splitn(3, ':');
+        let hours: f64 = hms.next()?.parse().ok()?;
+        let minutes: f64 = hms.next()?.parse().ok()?;
+        let secs: f64 = hms.next().unwrap_or("0").parse().ok()?;
+        seconds = hours * 3600.0 + minutes * 60.0 + secs;
+    }
+
+    Some(days_from_civil(year, month, day) as f64 * 86400.0 + seconds - offset)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Match `value` against a pattern where `*` matches any
+/// sequence of characters and `?` matches any single character.
+fn string_like(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut v, mut p) = (0, 0);
+    // Position in the pattern of the last `*`, and the position in
+    // the value it is currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            v += 1;
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            star = Some((star_p, star_v + 1));
+            p = star_p + 1;
+            v = star_v + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Check whether `ip` is in `network`, given in CIDR notation
+/// (a bare address is treated as a single host network).
+fn ip_address(ip: &str, network: &str) -> crate::Result<bool> {
+    let invalid = |expected: &str| {
+        TypeError {
+            expected: expected.to_string(),
+        }
+        .user()
+    };
+    let ip: IpAddr = ip.parse().map_err(|_| invalid("IP address"))?;
+    let network: Network = network.parse().map_err(|_| invalid("CIDR network"))?;
+    Ok(network.contains(&ip))
+}
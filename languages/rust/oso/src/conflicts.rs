@@ -0,0 +1,126 @@
+//! Detect rules whose relative order decides the outcome of a query.
+//!
+//! Polar tries the rules of a query in order of specificity, and in source
+//! order when their specializers are the same. A rule that contains a `cut`
+//! stops the rules after it from being tried, so when it overlaps with a
+//! later rule with the same specializers, the later rule is silently
+//! shadowed, and reordering the source changes the result.
+
+use std::fmt;
+
+use polar_core::formatting::ToPolarString;
+use polar_core::rules::{Parameter, Rule};
+use polar_core::terms::*;
+
+use crate::Oso;
+
+/// A pair of rules where the first, which contains a `cut`, may shadow the
+/// second.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleConflict {
+    /// The name of the rules.
+    pub rule: String,
+    /// The rule tried first.
+    pub shadowing: String,
+    /// The rule it may shadow.
+    pub shadowed: String,
+    /// How to make the outcome independent of the order of the rules.
+    pub suggestion: String,
+}
+
+impl fmt::Display for RuleConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` may shadow `{}`: {}",
+            self.shadowing, self.shadowed, self.suggestion
+        )
+    }
+}
+
+impl Oso {
+    /// Find pairs of overlapping rules whose order changes the outcome of
+    /// queries. Conflicts are also logged as warnings when a policy is
+    /// loaded.
+    pub fn rule_conflicts(&self) -> Vec<RuleConflict> {
+        let kb = self.inner.kb.read().unwrap();
+        let mut names: Vec<_> = kb.rules.keys().collect();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut conflicts = vec![];
+        for name in names {
+            let rules = kb.rules[name].rules();
+            for (i, first) in rules.iter().enumerate() {
+                if !has_cut(&first.body) {
+                    continue;
+                }
+                for second in &rules[i + 1..] {
+                    if let Some(conflict) = conflict(first, second) {
+                        conflicts.push(conflict);
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Log the conflicts that are not in `known`.
+    pub(crate) fn warn_conflicts(&self, known: &[RuleConflict]) {
+        for conflict in self.rule_conflicts() {
+            if !known.contains(&conflict) {
+                tracing::warn!(rule = %conflict.rule, "rule conflict: {}", conflict);
+            }
+        }
+    }
+}
+
+fn conflict(first: &Rule, second: &Rule) -> Option<RuleConflict> {
+    if first.params.len() != second.params.len() {
+        return None;
+    }
+    let pairs = || first.params.iter().zip(second.params.iter());
+    // Rules with different specializers are ordered by specificity, and
+    // rules that cannot match the same arguments don't interact.
+    if pairs().any(|(a, b)| specializer(a) != specializer(b) || disjoint(a, b)) {
+        return None;
+    }
+
+    let narrower = pairs().any(|(a, b)| is_variable(a) && !is_variable(b))
+        && pairs().all(|(a, b)| is_variable(a) || !is_variable(b));
+    let suggestion = if narrower {
+        "move the narrower rule first, or give it a more specific specializer"
+    } else {
+        "give the rules different specializers, or remove the `cut`"
+    };
+    Some(RuleConflict {
+        rule: first.name.0.clone(),
+        shadowing: first.to_polar(),
+        shadowed: second.to_polar(),
+        suggestion: suggestion.to_string(),
+    })
+}
+
+fn specializer(param: &Parameter) -> Option<&Value> {
+    param.specializer.as_ref().map(Term::value)
+}
+
+fn is_variable(param: &Parameter) -> bool {
+    matches!(param.parameter.value(), Value::Variable(_))
+}
+
+/// Whether no argument can match both parameters.
+fn disjoint(a: &Parameter, b: &Parameter) -> bool {
+    let (a, b) = (a.parameter.value(), b.parameter.value());
+    a.is_ground() && b.is_ground() && a != b
+}
+
+fn has_cut(term: &Term) -> bool {
+    match term.value() {
+        Value::Expression(Operation {
+            operator: Operator::Cut,
+            ..
+        }) => true,
+        Value::Expression(Operation { args, .. }) => args.iter().any(has_cut),
+        _ => false,
+    }
+}
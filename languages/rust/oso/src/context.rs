@@ -0,0 +1,91 @@
+//! The context of a query, passed to the host methods that ask for it.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use polar_core::terms::Symbol;
+
+use crate::host::Host;
+use crate::Class;
+
+static NEXT_QUERY_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a method registered with
+/// [`Class::add_context_method`](crate::Class::add_context_method) knows
+/// about the query calling it, e.g. to cache results per query or to scope
+/// them to a tenant.
+pub struct Context<'a> {
+    query: &'a QueryContext,
+    host: &'a Host,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(query: &'a QueryContext, host: &'a Host) -> Self {
+        Self { query, host }
+    }
+
+    /// A number identifying the query, unique in the process.
+    pub fn query_id(&self) -> u64 {
+        self.query.id
+    }
+
+    /// The value of type `T` attached to the query with
+    /// [`Query::with_context`](crate::Query::with_context), if any.
+    pub fn data<T: 'static>(&self) -> Option<&T> {
+        self.query.data.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// The results of the query requested by its caller with
+    /// [`Query::offset`](crate::Query::offset) and
+    /// [`Query::limit`](crate::Query::limit), e.g. for an iterator method
+    /// to fetch only the rows needed from a database.
+    pub fn page(&self) -> Page {
+        self.query.page
+    }
+
+    /// The class registered as `name`.
+    pub fn class(&self, name: &str) -> Option<&Class> {
+        self.host.get_class(&Symbol(name.to_string()))
+    }
+}
+
+/// The range of results requested from a query, as a hint for the methods
+/// it calls. Fetching `end()` rows is enough when each row produces at most
+/// one result, and skipping `offset` of them when each produces exactly
+/// one; the query applies the range to its results either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Page {
+    /// The number of results skipped.
+    pub offset: usize,
+    /// The most results returned after them, if limited.
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    /// The number of results needed, counting the skipped ones, if limited.
+    pub fn end(&self) -> Option<usize> {
+        self.limit.map(|limit| self.offset + limit)
+    }
+}
+
+/// The context of a query, from which a [`Context`] is made for each call.
+#[derive(Clone)]
+pub(crate) struct QueryContext {
+    pub id: u64,
+    /// Application data attached to the query, by type.
+    pub data: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    /// The results requested from the query.
+    pub page: Page,
+}
+
+impl QueryContext {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_QUERY_ID.fetch_add(1, Ordering::Relaxed),
+            data: HashMap::new(),
+            page: Page::default(),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Translate `allow` rules into polars expressions, so that the same
+//! row-level filters that guard the serving path can be applied to
+//! DataFrames.
+//!
+//! See the `filter` module for the rules that can be translated. The actor is
+//! a string, number or boolean, typically a user id; the resource's
+//! attributes are the DataFrame's columns.
+
+use polar_core::terms::*;
+use polars::lazy::dsl::{col, lit, Expr};
+
+use crate::filter::{Comparison, Filter, Operand};
+use crate::{Oso, PolarValue};
+
+impl Oso {
+    /// Translate the `allow` rules for `actor` performing `action` on
+    /// instances of `class` into an expression selecting the authorized
+    /// rows, e.g. for `df.lazy().filter(expr)`. Fails for classes with a
+    /// [tenant key](crate::Class::tenant_key), which need
+    /// [`polars_tenant_filter`](Oso::polars_tenant_filter).
+    pub fn polars_filter(
+        &self,
+        actor: &PolarValue,
+        action: &str,
+        class: &str,
+    ) -> crate::Result<Expr> {
+        let actor = literal(actor, "actor")?;
+        let filter = self.data_filter(class, action, "polars")?;
+        expr(&filter, &actor, None)
+    }
+
+    /// Like [`polars_filter`](Oso::polars_filter), only selecting the rows
+    /// of `tenant` for classes with a [tenant key](crate::Class::tenant_key).
+    pub fn polars_tenant_filter(
+        &self,
+        actor: &PolarValue,
+        tenant: &PolarValue,
+        action: &str,
+        class: &str,
+    ) -> crate::Result<Expr> {
+        let actor = literal(actor, "actor")?;
+        let tenant = literal(tenant, "tenant")?;
+        let filter = self.data_filter(class, action, "polars")?;
+        expr(&filter, &actor, Some(&tenant))
+    }
+}
+
+fn literal(value: &PolarValue, name: &str) -> crate::Result<Expr> {
+    match value {
+        PolarValue::Integer(i) => Ok(lit(*i)),
+        PolarValue::Float(f) => Ok(lit(*f)),
+        PolarValue::Bool(b) => Ok(lit(*b)),
+        PolarValue::String(s) => Ok(lit(s.as_str())),
+        _ => lazy_error!("{} `{:?}` cannot be expressed in polars", name, value),
+    }
+}
+
+fn expr(filter: &Filter, actor: &Expr, tenant: Option<&Expr>) -> crate::Result<Expr> {
+    let exprs = |filters: &[Filter]| {
+        filters
+            .iter()
+            .map(|f| expr(f, actor, tenant))
+            .collect::<crate::Result<Vec<_>>>()
+    };
+    let operand = |o: &Operand| operand(o, actor, tenant);
+    Ok(match filter {
+        Filter::Bool(b) => lit(*b),
+        Filter::And(filters) => exprs(filters)?.into_iter().fold(lit(true), Expr::and),
+        Filter::Or(filters) => exprs(filters)?.into_iter().fold(lit(false), Expr::or),
+        Filter::Not(filter) => expr(filter, actor, tenant)?.not(),
+        Filter::Compare(left, comparison, right) => {
+            let (left, right) = (operand(left)?, operand(right)?);
+            match comparison {
+                Comparison::Eq => left.eq(right),
+                Comparison::Neq => left.neq(right),
+                Comparison::Lt => left.lt(right),
+                Comparison::Leq => left.lt_eq(right),
+                Comparison::Gt => left.gt(right),
+                Comparison::Geq => left.gt_eq(right),
+            }
+        }
+        Filter::In(value, values) => {
+            let mut any = lit(false);
+            for v in values {
+                any = any.or(operand(value)?.eq(operand(v)?));
+            }
+            any
+        }
+        Filter::ActorIs(value, filter) => actor
+            .clone()
+            .eq(operand(value)?)
+            .and(expr(filter, actor, tenant)?),
+    })
+}
+
+fn operand(operand: &Operand, actor: &Expr, tenant: Option<&Expr>) -> crate::Result<Expr> {
+    Ok(match operand {
+        Operand::Actor => actor.clone(),
+        Operand::Tenant => match tenant {
+            Some(tenant) => tenant.clone(),
+            None => {
+                return lazy_error!(
+                    "the class has a tenant key, use `polars_tenant_filter` to filter it"
+                )
+            }
+        },
+        Operand::Column(column) => col(column),
+        Operand::Literal(Value::Number(Numeric::Integer(i))) => lit(*i),
+        Operand::Literal(Value::Number(Numeric::Float(f))) => lit(*f),
+        Operand::Literal(Value::Boolean(b)) => lit(*b),
+        Operand::Literal(Value::String(s)) => lit(s.as_str()),
+        Operand::Literal(value) => unreachable!("`{:?}` is not a literal", value),
+    })
+}
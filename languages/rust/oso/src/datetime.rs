@@ -0,0 +1,83 @@
+//! Builtin date and time classes, backed by `chrono`.
+//!
+//! - `Datetime`: a `DateTime<Utc>`, created with `Datetime.now()`,
+//!   `Datetime.parse("2020-08-01T12:00:00Z")` or `Datetime.from_timestamp(secs)`.
+//! - `Date`: a `NaiveDate`, created with `Date.today()`,
+//!   `Date.parse("2020-08-01")` or `Date.from_ymd(2020, 8, 1)`, which
+//!   returns `nil` for invalid dates.
+//! - `Duration`: a `chrono::Duration`, created with `Duration.seconds(n)`,
+//!   `Duration.minutes(n)`, `Duration.hours(n)` or `Duration.days(n)`.
+//!
+//! Values of the same class can be compared with `==`, `!=`, `<`, `<=`,
+//! `>` and `>=`.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+
+use crate::{Class, HostClass};
+
+impl HostClass for DateTime<Utc> {}
+impl HostClass for NaiveDate {}
+impl HostClass for Duration {}
+
+fn datetime() -> Class<DateTime<Utc>> {
+    Class::<DateTime<Utc>>::new()
+        .name("Datetime")
+        .with_equality_check()
+        .with_comparison_check()
+        .add_class_method("now", Utc::now)
+        .add_class_method("parse", |s: String| {
+            DateTime::parse_from_rfc3339(&s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| format!("invalid datetime `{}`: {}", s, e))
+        })
+        .add_class_method("from_timestamp", |secs: i64| {
+            DateTime::<Utc>::from_utc(chrono::NaiveDateTime::from_timestamp(secs, 0), Utc)
+        })
+        .add_method("timestamp", |dt: &DateTime<Utc>| dt.timestamp())
+        .add_method("date", |dt: &DateTime<Utc>| dt.naive_utc().date())
+        .add_method("add", |dt: &DateTime<Utc>, d: Duration| *dt + d)
+        .add_method("sub", |dt: &DateTime<Utc>, d: Duration| *dt - d)
+        .add_method("since", |dt: &DateTime<Utc>, other: DateTime<Utc>| {
+            dt.signed_duration_since(other)
+        })
+        .add_method("to_string", |dt: &DateTime<Utc>| dt.to_rfc3339())
+}
+
+fn date() -> Class<NaiveDate> {
+    Class::<NaiveDate>::new()
+        .name("Date")
+        .with_equality_check()
+        .with_comparison_check()
+        .add_class_method("today", || Utc::today().naive_utc())
+        .add_class_method("from_ymd", NaiveDate::from_ymd_opt)
+        .add_class_method("parse", |s: String| {
+            NaiveDate::parse_from_str(&s, "%Y-%m-%d")
+                .map_err(|e| format!("invalid date `{}`: {}", s, e))
+        })
+        .add_attribute_getter("year", |d: &NaiveDate| d.year())
+        .add_attribute_getter("month", |d: &NaiveDate| d.month())
+        .add_attribute_getter("day", |d: &NaiveDate| d.day())
+        .add_method("add", |date: &NaiveDate, d: Duration| *date + d)
+        .add_method("sub", |date: &NaiveDate, d: Duration| *date - d)
+        .add_method("to_string", |d: &NaiveDate| d.to_string())
+}
+
+fn duration() -> Class<Duration> {
+    Class::<Duration>::new()
+        .name("Duration")
+        .with_equality_check()
+        .with_comparison_check()
+        .add_class_method("seconds", Duration::seconds)
+        .add_class_method("minutes", Duration::minutes)
+        .add_class_method("hours", Duration::hours)
+        .add_class_method("days", Duration::days)
+        .add_method("num_seconds", |d: &Duration| d.num_seconds())
+}
+
+pub fn classes() -> Vec<Class> {
+    vec![
+        datetime().erase_type(),
+        date().erase_type(),
+        duration().erase_type(),
+    ]
+}
@@ -0,0 +1,211 @@
+//! Caching the decisions of `is_allowed` and `authorize`.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use polar_core::terms::*;
+
+use crate::errors::ForbiddenError;
+use crate::host::{Host, LiveQuery};
+use crate::{Oso, ToPolar};
+
+/// The decision of `authorize`, which is also that of `is_allowed` unless
+/// a `deny` rule gave a reason.
+pub(crate) type Decision = Result<(), ForbiddenError>;
+
+/// A check, by the identities of its arguments.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct DecisionKey {
+    /// `"is_allowed"` or `"authorize"`.
+    check: &'static str,
+    actor: String,
+    action: String,
+    resource: String,
+}
+
+pub(crate) struct DecisionCache {
+    ttl: Duration,
+    /// The policy version and host generation the decisions were made with.
+    version: (String, u64),
+    entries: ExpiringMap<DecisionKey, Decision>,
+}
+
+/// A map of at most `capacity` entries that expire, shared by the decision
+/// caches of Oso and its integrations.
+///
+/// When the map is full, inserting drops the expired entries, and all
+/// entries if that is not enough.
+#[derive(Debug)]
+pub struct ExpiringMap<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, Instant)>,
+}
+
+impl<K: Hash + Eq, V: Clone> ExpiringMap<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The value of `key`, unless it expired.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        match self.entries.get(key) {
+            Some((value, expires)) if *expires > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Insert `value` for `ttl`.
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration) {
+        let now = Instant::now();
+        if self.entries.len() >= self.capacity {
+            self.entries.retain(|_, (_, expires)| *expires > now);
+            if self.entries.len() >= self.capacity {
+                self.entries.clear();
+            }
+        }
+        self.entries.insert(key, (value, now + ttl));
+    }
+
+    /// Keep only the entries for which `f` returns `true`.
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|key, (value, _)| f(key, value));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The number of entries, including expired ones that have not been
+    /// looked up since.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Oso {
+    /// Cache the decisions of [`is_allowed`](Oso::is_allowed) and
+    /// [`authorize`](Oso::authorize) for `ttl`, keeping at most `capacity`
+    /// of them, for services that make the same checks many times a
+    /// second. Replaces the decisions cached before.
+    ///
+    /// Checks are identified by their actor, action and resource. Strings,
+    /// numbers and booleans are identified by their value, and instances
+    /// by the identity of their class, set with
+    /// [`Class::set_identity`](crate::Class::set_identity). Checks of other
+    /// values are not cached. Decisions are forgotten when the policy or
+    /// the classes change, and can be forgotten earlier with
+    /// [`invalidate_decisions`](Oso::invalidate_decisions), e.g. when the
+    /// data the policy reads changed.
+    pub fn set_decision_cache(&mut self, ttl: Duration, capacity: usize) {
+        *self.decisions.lock().unwrap() = Some(DecisionCache {
+            ttl,
+            version: Default::default(),
+            entries: ExpiringMap::new(capacity),
+        });
+    }
+
+    /// Forget all cached decisions.
+    pub fn invalidate_decisions(&self) {
+        if let Some(cache) = self.decisions.lock().unwrap().as_mut() {
+            cache.entries.clear();
+        }
+    }
+
+    /// Forget the cached decisions about `value` as the actor or the
+    /// resource, e.g. after a user's roles or a document's owner changed.
+    pub fn invalidate_decisions_about<V: ToPolar>(&self, value: V) -> crate::Result<()> {
+        let live = LiveQuery::new(&self.host);
+        let value = value.try_to_polar(&mut live.host())?;
+        let key = identity(&value, &live.host());
+        if let (Some(cache), Some(key)) = (self.decisions.lock().unwrap().as_mut(), key) {
+            cache
+                .entries
+                .retain(|decision, _| decision.actor != key && decision.resource != key);
+        }
+        Ok(())
+    }
+
+    /// The number of decisions cached, including expired ones that have not
+    /// been looked up since.
+    pub fn cached_decisions(&self) -> usize {
+        self.decisions
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |cache| cache.entries.len())
+    }
+
+    /// The key of the `check` of `args`, if decisions are cached and the
+    /// arguments can be identified.
+    pub(crate) fn decision_key(
+        &self,
+        check: &'static str,
+        args: &[Term],
+        live: &LiveQuery,
+    ) -> Option<DecisionKey> {
+        if self.decisions.lock().unwrap().is_none() {
+            return None;
+        }
+        let host = live.host();
+        Some(DecisionKey {
+            check,
+            actor: identity(&args[0], &host)?,
+            action: identity(&args[1], &host)?,
+            resource: identity(&args[2], &host)?,
+        })
+    }
+
+    pub(crate) fn cached_decision(&self, key: &DecisionKey) -> Option<Decision> {
+        let version = self.decision_version();
+        let mut decisions = self.decisions.lock().unwrap();
+        let cache = decisions.as_mut()?;
+        if cache.version != version {
+            cache.version = version;
+            cache.entries.clear();
+            return None;
+        }
+        cache.entries.get(key)
+    }
+
+    pub(crate) fn cache_decision(&self, key: DecisionKey, decision: Decision) {
+        let version = self.decision_version();
+        let mut decisions = self.decisions.lock().unwrap();
+        let cache = match decisions.as_mut() {
+            Some(cache) if cache.version == version => cache,
+            _ => return,
+        };
+        cache.entries.insert(key, decision, cache.ttl);
+    }
+
+    fn decision_version(&self) -> (String, u64) {
+        (
+            self.policy_version(),
+            self.host.lock().unwrap().generation(),
+        )
+    }
+}
+
+/// Identify `term` by its value if it is a string, number or boolean, and
+/// by its class and the identity set for it if it is an instance.
+fn identity(term: &Term, host: &Host) -> Option<String> {
+    match term.value() {
+        Value::Number(_) | Value::String(_) | Value::Boolean(_) => Some(term.to_polar()),
+        Value::ExternalInstance(ExternalInstance { instance_id, .. }) => {
+            let instance = host.get_instance(*instance_id)?;
+            Some(format!("{}:{}", instance.name, instance.identity()?))
+        }
+        _ => None,
+    }
+}
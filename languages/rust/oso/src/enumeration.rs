@@ -0,0 +1,288 @@
+//! Enumerating every result of a rule with bounded memory, e.g. for audit
+//! jobs listing each actor and resource a rule relates.
+//!
+//! [`Oso::enumerate`] passes results to a sink as the virtual machine finds
+//! them instead of collecting them. Results can be deduplicated by the
+//! fingerprints of their bindings; once more fingerprints than fit in the
+//! memory budget are seen, they are written to sorted files, which are
+//! searched for the fingerprints of later results and removed afterwards.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+use polar_core::terms::{ExternalInstance, Term, ToPolarString, Value};
+
+use crate::{Oso, ResultSet, ToPolar};
+
+/// The most fingerprints of results kept in memory by default.
+const DEFAULT_MAX_IN_MEMORY: usize = 1_000_000;
+
+/// The size of a fingerprint in a spill file.
+const FINGERPRINT_SIZE: u64 = 16;
+
+/// Numbers the spill files of this process.
+static NEXT_SPILL: AtomicU64 = AtomicU64::new(0);
+
+/// How [`Oso::enumerate`] passes on results.
+#[derive(Clone, Debug)]
+pub struct Enumeration {
+    dedup: bool,
+    max_in_memory: usize,
+    spill_dir: Option<PathBuf>,
+}
+
+impl Default for Enumeration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Enumeration {
+    /// Pass on every result, including duplicates.
+    pub fn new() -> Self {
+        Self {
+            dedup: false,
+            max_in_memory: DEFAULT_MAX_IN_MEMORY,
+            spill_dir: None,
+        }
+    }
+
+    /// Skip results with the same bindings as an earlier result, e.g. of a
+    /// pair related by several rules.
+    pub fn dedup(mut self) -> Self {
+        self.dedup = true;
+        self
+    }
+
+    /// Keep the fingerprints of at most `max` results in memory, 16 bytes
+    /// each, and spill more to files. Defaults to 1,000,000.
+    pub fn max_in_memory(mut self, max: usize) -> Self {
+        self.max_in_memory = max.max(1);
+        self
+    }
+
+    /// Write spill files to `dir` instead of the temporary directory of the
+    /// system.
+    pub fn spill_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.spill_dir = Some(dir.into());
+        self
+    }
+}
+
+/// What an enumeration passed on and skipped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EnumerationStats {
+    /// The results passed to the sink.
+    pub results: usize,
+    /// The results skipped as duplicates.
+    pub duplicates: usize,
+    /// The files fingerprints were spilled to.
+    pub spills: usize,
+}
+
+impl Oso {
+    /// Query the rule `name` with `args`, like [`query_rule`](Oso::query_rule),
+    /// and pass each result to `sink` as it is found, without keeping it.
+    /// Stops at the first error of the query or the sink.
+    ///
+    /// ```
+    /// # use oso::{Enumeration, Oso, PolarValue, ToPolar};
+    /// let mut oso = Oso::new();
+    /// oso.load_str(r#"can_read("alice", "readme"); can_read("alice", "readme");"#)
+    ///     .unwrap();
+    /// let user = PolarValue::Variable("user".to_string());
+    /// let doc = PolarValue::Variable("doc".to_string());
+    /// let mut pairs = vec![];
+    /// let stats = oso
+    ///     .enumerate(
+    ///         "can_read",
+    ///         vec![&user as &dyn ToPolar, &doc],
+    ///         &Enumeration::new().dedup(),
+    ///         |result| {
+    ///             let user: String = result.get_typed("user")?;
+    ///             pairs.push((user, result.get_typed::<String>("doc")?));
+    ///             Ok(())
+    ///         },
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(pairs, vec![("alice".to_string(), "readme".to_string())]);
+    /// assert_eq!(stats.duplicates, 1);
+    /// ```
+    pub fn enumerate<'a, F>(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = &'a dyn ToPolar>,
+        enumeration: &Enumeration,
+        mut sink: F,
+    ) -> crate::Result<EnumerationStats>
+    where
+        F: FnMut(ResultSet) -> crate::Result<()>,
+    {
+        let mut seen = if enumeration.dedup {
+            Some(Seen::new(enumeration))
+        } else {
+            None
+        };
+        let mut stats = EnumerationStats::default();
+        for result in self.query_rule(name, args)? {
+            let result = result?;
+            if let Some(seen) = &mut seen {
+                if !seen.insert(fingerprint(&result))? {
+                    stats.duplicates += 1;
+                    continue;
+                }
+            }
+            sink(result)?;
+            stats.results += 1;
+        }
+        stats.spills = seen.map_or(0, |seen| seen.spills.len());
+        Ok(stats)
+    }
+}
+
+/// A fingerprint of the bindings of `result`.
+fn fingerprint(result: &ResultSet) -> u128 {
+    let mut bindings: Vec<_> = result
+        .bindings
+        .iter()
+        .map(|(var, value)| (var.0.as_str(), key(value)))
+        .collect();
+    bindings.sort();
+    let hash = |seed: u64| {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        bindings.hash(&mut hasher);
+        hasher.finish()
+    };
+    (u128::from(hash(0)) << 64) | u128::from(hash(1))
+}
+
+/// The representation of `term` that results are fingerprinted by.
+/// Instances are identified by their ids, since the reprs of distinct
+/// instances may be the same.
+fn key(term: &Term) -> String {
+    match term.value() {
+        Value::ExternalInstance(ExternalInstance { instance_id, .. }) => {
+            format!("^{{id: {}}}", instance_id)
+        }
+        Value::List(terms) => {
+            let terms: Vec<String> = terms.iter().map(key).collect();
+            format!("[{}]", terms.join(", "))
+        }
+        Value::Dictionary(dict) => {
+            let fields: Vec<String> = dict
+                .fields
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k.0, key(v)))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        _ => term.to_polar(),
+    }
+}
+
+/// The fingerprints of the results seen so far.
+struct Seen {
+    memory: HashSet<u128>,
+    max_in_memory: usize,
+    dir: PathBuf,
+    spills: Vec<Spill>,
+}
+
+impl Seen {
+    fn new(enumeration: &Enumeration) -> Self {
+        Self {
+            memory: HashSet::new(),
+            max_in_memory: enumeration.max_in_memory,
+            dir: enumeration
+                .spill_dir
+                .clone()
+                .unwrap_or_else(std::env::temp_dir),
+            spills: vec![],
+        }
+    }
+
+    /// Add `fingerprint`, returning whether it is new.
+    fn insert(&mut self, fingerprint: u128) -> io::Result<bool> {
+        if self.memory.contains(&fingerprint) {
+            return Ok(false);
+        }
+        for spill in &mut self.spills {
+            if spill.contains(fingerprint)? {
+                return Ok(false);
+            }
+        }
+        if self.memory.len() >= self.max_in_memory {
+            let path = self.dir.join(format!(
+                "oso-enumerate-{}-{}",
+                std::process::id(),
+                NEXT_SPILL.fetch_add(1, AtomicOrdering::Relaxed)
+            ));
+            let fingerprints = self.memory.drain().collect();
+            self.spills.push(Spill::write(path, fingerprints)?);
+        }
+        self.memory.insert(fingerprint);
+        Ok(true)
+    }
+}
+
+/// A file of sorted fingerprints, removed when dropped.
+struct Spill {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl Spill {
+    fn write(path: PathBuf, mut fingerprints: Vec<u128>) -> io::Result<Self> {
+        fingerprints.sort_unstable();
+        let file = File::create(&path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            for fingerprint in &fingerprints {
+                writer.write_all(&fingerprint.to_be_bytes())?;
+            }
+            writer.flush()?;
+            File::open(&path)
+        });
+        match file {
+            Ok(file) => Ok(Self {
+                path,
+                file,
+                len: fingerprints.len() as u64,
+            }),
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether the file holds `fingerprint`, by binary search.
+    fn contains(&mut self, fingerprint: u128) -> io::Result<bool> {
+        let (mut low, mut high) = (0, self.len);
+        let mut buf = [0; FINGERPRINT_SIZE as usize];
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.file.seek(SeekFrom::Start(mid * FINGERPRINT_SIZE))?;
+            self.file.read_exact(&mut buf)?;
+            match u128::from_be_bytes(buf).cmp(&fingerprint) {
+                Ordering::Equal => return Ok(true),
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -9,10 +9,29 @@ pub enum OsoError {
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Polar(#[from] polar_core::error::PolarError),
+    #[cfg(feature = "arrow")]
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
     #[error("failed to convert type from Polar")]
     FromPolar,
+    #[error("integer {value} is out of range for `{target}`")]
+    IntegerOverflow { value: String, target: String },
     #[error("policy files must end in .polar")]
     IncorrectFileType,
+    #[error("query was cancelled")]
+    Cancelled,
+    #[error("query deadline exceeded")]
+    DeadlineExceeded,
+    #[error("query used about {used} bytes, more than its memory budget of {limit} bytes")]
+    ResourceExhausted { used: usize, limit: usize },
+    #[error("instance {id} is not cached, it may have been evicted")]
+    MissingInstance { id: u64 },
+    #[error("invalid decision token: {reason}")]
+    InvalidDecisionToken { reason: String },
+    #[error(transparent)]
+    Forbidden(#[from] ForbiddenError),
+    #[error(transparent)]
+    TypeMismatch(#[from] TypeMismatchError),
 
     #[error("Invariant error: {source}")]
     InvariantError {
@@ -41,6 +60,51 @@ pub enum OsoError {
     Custom { message: String },
 }
 
+/// A request was not authorized by `Oso::authorize`. Requests denied by a
+/// `deny(actor, action, resource, reason)` rule carry its reason, which is
+/// either a message string or a dictionary with `code` and `message` keys.
+#[derive(Error, Clone, Debug, Default, PartialEq)]
+#[error("forbidden{}", .message.as_ref().map(|m| format!(": {}", m)).unwrap_or_default())]
+pub struct ForbiddenError {
+    /// A machine-readable reason code, e.g. `"quota_exceeded"`.
+    pub code: Option<String>,
+    /// A user-presentable explanation.
+    pub message: Option<String>,
+}
+
+impl ForbiddenError {
+    pub(crate) fn from_reason(reason: Option<crate::PolarValue>) -> Self {
+        use crate::PolarValue;
+
+        let string = |value: Option<&PolarValue>| match value {
+            Some(PolarValue::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        match reason {
+            Some(PolarValue::String(message)) => Self {
+                code: None,
+                message: Some(message),
+            },
+            Some(PolarValue::Map(fields)) => Self {
+                code: string(fields.get("code")),
+                message: string(fields.get("message")),
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// An [`Instance`](crate::Instance) was converted to a type it is not an
+/// instance of.
+#[derive(Error, Clone, Debug, PartialEq)]
+#[error("expected an instance of `{expected}`, got an instance of `{actual}`")]
+pub struct TypeMismatchError {
+    /// The type the instance was converted to.
+    pub expected: String,
+    /// The name of the class of the instance.
+    pub actual: String,
+}
+
 /// These are conditions that should never occur, and indicate a bug in oso.
 #[derive(Error, Debug)]
 pub enum InvariantError {
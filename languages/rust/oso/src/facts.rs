@@ -0,0 +1,246 @@
+//! Facts asserted at runtime, and read-your-writes consistency between the
+//! replicas of a service.
+//!
+//! Facts are appended to a [`FactStore`], which replicas can share, and are
+//! loaded into the policy of each replica when it syncs with the store.
+//! [`Oso::assert_fact`] returns a [`ConsistencyToken`] for the fact. A
+//! service that passes the token along with a later request can check it
+//! with [`Oso::is_allowed_after`] on any replica: the replica syncs until it
+//! has loaded the fact, or reports the check as
+//! [`Consistency::PotentiallyStale`] if the store hasn't caught up yet, e.g.
+//! because it reads from a lagging database replica.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::{Oso, PolarValue, ToPolar};
+
+/// A fact, e.g. `has_role("alice", "admin")`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fact {
+    pub name: String,
+    /// Strings, integers, booleans, or lists of them.
+    pub args: Vec<PolarValue>,
+}
+
+/// Where asserted facts are kept.
+///
+/// Versions count the facts appended to the store, so that a replica that
+/// has loaded the facts up to a version only needs the ones after it.
+pub trait FactStore: Send + Sync {
+    /// Append `fact`, returning the version of the store that includes it.
+    fn append(&self, fact: Fact) -> crate::Result<u64>;
+
+    /// The facts appended after `version`, in order, and the version they
+    /// bring a replica to. A store may return fewer facts than were
+    /// appended, e.g. if it reads from a replica of its database.
+    fn since(&self, version: u64) -> crate::Result<(Vec<Fact>, u64)>;
+}
+
+/// Facts kept in memory, e.g. shared by several `Oso` in one process.
+#[derive(Debug, Default)]
+pub struct MemoryFactStore {
+    facts: Mutex<Vec<Fact>>,
+}
+
+impl FactStore for MemoryFactStore {
+    fn append(&self, fact: Fact) -> crate::Result<u64> {
+        let mut facts = self.facts.lock().unwrap();
+        facts.push(fact);
+        Ok(facts.len() as u64)
+    }
+
+    fn since(&self, version: u64) -> crate::Result<(Vec<Fact>, u64)> {
+        let facts = self.facts.lock().unwrap();
+        let start = (version as usize).min(facts.len());
+        Ok((facts[start..].to_vec(), facts.len() as u64))
+    }
+}
+
+/// The version of the fact store that includes an asserted fact. Formats
+/// to and parses from a string, to pass along with requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConsistencyToken(u64);
+
+impl fmt::Display for ConsistencyToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ConsistencyToken {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ConsistencyToken)
+    }
+}
+
+/// Whether a check saw the facts asserted before its consistency token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    Consistent,
+    /// The fact store had not caught up to the token, so the check may
+    /// have missed some of the facts.
+    PotentiallyStale,
+}
+
+/// The fact store of an `Oso`, and the version of it loaded.
+pub(crate) struct FactSync {
+    store: Arc<dyn FactStore>,
+    version: u64,
+}
+
+impl Default for FactSync {
+    fn default() -> Self {
+        Self {
+            store: Arc::new(MemoryFactStore::default()),
+            version: 0,
+        }
+    }
+}
+
+impl Oso {
+    /// Keep the facts asserted with [`assert_fact`](Oso::assert_fact) in
+    /// `store`, e.g. a store shared by the replicas of a service, instead
+    /// of in memory. The facts in the store are loaded on the next sync.
+    pub fn set_fact_store(&self, store: Arc<dyn FactStore>) {
+        *self.facts.lock().unwrap() = FactSync { store, version: 0 };
+    }
+
+    /// Add the fact `name(args)` to the fact store, and load the facts of
+    /// the store, including it. Pass the returned token to
+    /// [`is_allowed_after`](Oso::is_allowed_after) for checks that must see
+    /// the fact.
+    ///
+    /// ```
+    /// # use oso::{Oso, PolarValue};
+    /// let mut oso = Oso::new();
+    /// oso.load_str("allow(user, action, _) if grant(user, action);")?;
+    /// let token = oso.assert_fact(
+    ///     "grant",
+    ///     vec![PolarValue::from("alice"), PolarValue::from("read")],
+    /// )?;
+    /// assert!(oso.is_allowed_after(token, "alice", "read", "doc")?.0);
+    /// # Ok::<(), oso::OsoError>(())
+    /// ```
+    pub fn assert_fact(
+        &mut self,
+        name: &str,
+        args: Vec<PolarValue>,
+    ) -> crate::Result<ConsistencyToken> {
+        let fact = Fact {
+            name: name.to_string(),
+            args,
+        };
+        // Reject facts that can't be loaded before storing them.
+        fact_source(&fact)?;
+        let store = self.facts.lock().unwrap().store.clone();
+        let version = store.append(fact)?;
+        self.sync_facts()?;
+        Ok(ConsistencyToken(version))
+    }
+
+    /// Load the facts added to the fact store since the last sync, e.g. by
+    /// other replicas, returning the version loaded.
+    pub fn sync_facts(&mut self) -> crate::Result<ConsistencyToken> {
+        // Hold the lock while loading, so that facts are loaded once.
+        let facts = self.facts.clone();
+        let mut sync = facts.lock().unwrap();
+        let (new_facts, version) = sync.store.since(sync.version)?;
+        let src = new_facts
+            .iter()
+            .map(fact_source)
+            .collect::<crate::Result<String>>()?;
+        if !src.is_empty() {
+            self.load_str(&src)?;
+        }
+        sync.version = sync.version.max(version);
+        Ok(ConsistencyToken(sync.version))
+    }
+
+    /// Whether the facts asserted before `token` are loaded, syncing with
+    /// the fact store if they aren't yet.
+    pub fn ensure_consistency(&mut self, token: ConsistencyToken) -> crate::Result<Consistency> {
+        if self.facts.lock().unwrap().version >= token.0 {
+            return Ok(Consistency::Consistent);
+        }
+        if self.sync_facts()? >= token {
+            Ok(Consistency::Consistent)
+        } else {
+            Ok(Consistency::PotentiallyStale)
+        }
+    }
+
+    /// Like [`is_allowed`](Oso::is_allowed), after loading the facts
+    /// asserted before `token`. Also returns whether the check saw them.
+    pub fn is_allowed_after<Actor, Action, Resource>(
+        &mut self,
+        token: ConsistencyToken,
+        actor: Actor,
+        action: Action,
+        resource: Resource,
+    ) -> crate::Result<(bool, Consistency)>
+    where
+        Actor: ToPolar,
+        Action: ToPolar,
+        Resource: ToPolar,
+    {
+        let consistency = self.ensure_consistency(token)?;
+        let allowed = self.is_allowed(actor, action, resource)?;
+        Ok((allowed, consistency))
+    }
+}
+
+/// The Polar source of `fact`.
+fn fact_source(fact: &Fact) -> crate::Result<String> {
+    let mut chars = fact.name.chars();
+    let valid = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return lazy_error!("invalid fact name `{}`", fact.name);
+    }
+    let args = fact
+        .args
+        .iter()
+        .map(literal)
+        .collect::<crate::Result<Vec<_>>>()?;
+    Ok(format!("{}({});\n", fact.name, args.join(", ")))
+}
+
+fn literal(value: &PolarValue) -> crate::Result<String> {
+    match value {
+        PolarValue::Integer(i) => Ok(i.to_string()),
+        PolarValue::Bool(b) => Ok(b.to_string()),
+        PolarValue::String(s) => {
+            let mut literal = String::from("\"");
+            for c in s.chars() {
+                match c {
+                    '"' => literal.push_str("\\\""),
+                    '\\' => literal.push_str("\\\\"),
+                    '\n' => literal.push_str("\\n"),
+                    '\r' => literal.push_str("\\r"),
+                    '\t' => literal.push_str("\\t"),
+                    '\0' => literal.push_str("\\0"),
+                    c => literal.push(c),
+                }
+            }
+            literal.push('"');
+            Ok(literal)
+        }
+        PolarValue::List(values) => {
+            let values = values
+                .iter()
+                .map(literal)
+                .collect::<crate::Result<Vec<_>>>()?;
+            Ok(format!("[{}]", values.join(", ")))
+        }
+        value => lazy_error!(
+            "facts can only hold strings, integers, booleans and lists, not {:?}",
+            value
+        ),
+    }
+}
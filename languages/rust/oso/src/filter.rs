@@ -0,0 +1,289 @@
+//! Translate `allow` rules into filters over the attributes of a resource,
+//! shared by the SQL and DataFrame exports.
+//!
+//! Only rules that are fully expressible as filters can be translated. For
+//! the requested action and resource class, every applicable
+//! `allow(actor, action, resource)` rule must have a body built from
+//! conjunctions, disjunctions and negations of comparisons between
+//! attributes of the resource (columns), the actor, and literals.
+
+use std::collections::HashMap;
+
+use polar_core::formatting::ToPolarString;
+use polar_core::rules::Parameter;
+use polar_core::terms::*;
+
+use crate::Oso;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Operand {
+    Actor,
+    /// The tenant of the actor.
+    Tenant,
+    Column(String),
+    /// A string, number or boolean.
+    Literal(Value),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Comparison {
+    Eq,
+    Neq,
+    Lt,
+    Leq,
+    Gt,
+    Geq,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Filter {
+    Bool(bool),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Compare(Operand, Comparison, Operand),
+    In(Operand, Vec<Operand>),
+    /// A rule that only applies to the actor equal to the operand.
+    ActorIs(Operand, Box<Filter>),
+}
+
+impl Oso {
+    /// The filter for the instances of `class` the actor may perform
+    /// `action` on, only selecting the instances of the tenant of the actor
+    /// if `class` has a [tenant key](crate::Class::tenant_key).
+    pub(crate) fn data_filter(
+        &self,
+        class: &str,
+        action: &str,
+        target: &'static str,
+    ) -> crate::Result<Filter> {
+        let tenant_key = self
+            .host
+            .lock()
+            .unwrap()
+            .get_class(&Symbol(class.to_string()))
+            .and_then(|class| class.tenant_column().map(str::to_string));
+        let filter = self.allow_filter(class, action, target)?;
+        Ok(match tenant_key {
+            Some(key) => Filter::And(vec![
+                Filter::Compare(Operand::Column(key), Comparison::Eq, Operand::Tenant),
+                filter,
+            ]),
+            None => filter,
+        })
+    }
+
+    /// Translate the `allow` rules for `action` on instances of `class`
+    /// into a filter. `target` names the output in error messages.
+    pub(crate) fn allow_filter(
+        &self,
+        class: &str,
+        action: &str,
+        target: &'static str,
+    ) -> crate::Result<Filter> {
+        let kb = self.inner.kb.read().unwrap();
+        let rules = match kb.rules.get(&Symbol("allow".to_string())) {
+            Some(generic_rule) => generic_rule.get_applicable_rules(&vec![
+                Term::new_temporary(Value::Variable(Symbol("actor".to_string()))),
+                Term::new_temporary(Value::String(action.to_string())),
+                Term::new_temporary(Value::Variable(Symbol("resource".to_string()))),
+            ]),
+            None => vec![],
+        };
+
+        let mut filters = vec![];
+        for rule in rules {
+            if rule.params.len() != 3 || !applies_to(&rule.params[2], class) {
+                continue;
+            }
+            let mut translator = Translator {
+                variables: HashMap::new(),
+                target,
+            };
+            let mut literals = vec![];
+            for (param, variable) in rule.params.iter().zip(vec![
+                Variable::Actor,
+                Variable::Literal(Value::String(action.to_string())),
+                Variable::Resource,
+            ]) {
+                if let Some(specializer) = &param.specializer {
+                    match specializer.value() {
+                        Value::Pattern(Pattern::Instance(InstanceLiteral { fields, .. }))
+                            if fields.fields.is_empty() && variable.is_resource() => {}
+                        _ => return translator.not_expressible(specializer),
+                    }
+                }
+                match param.parameter.value() {
+                    Value::Variable(name) => {
+                        translator.variables.insert(name.clone(), variable);
+                    }
+                    // The action was matched by the rule index.
+                    _ if matches!(variable, Variable::Literal(_)) => {}
+                    _ if matches!(variable, Variable::Actor) => {
+                        literals.push(param.parameter.clone())
+                    }
+                    _ => return translator.not_expressible(&param.parameter),
+                }
+            }
+            translator.collect_lookups(&rule.body)?;
+            let mut filter = translator.translate(&rule.body)?;
+            for literal in literals {
+                filter = Filter::ActorIs(translator.operand(&literal)?, Box::new(filter));
+            }
+            filters.push(filter);
+        }
+
+        Ok(match filters.len() {
+            0 => Filter::Bool(false),
+            1 => filters.pop().unwrap(),
+            _ => Filter::Or(filters),
+        })
+    }
+}
+
+/// Whether a rule with resource parameter `param` applies to `class`.
+fn applies_to(param: &Parameter, class: &str) -> bool {
+    match param.specializer.as_ref().map(Term::value) {
+        None => true,
+        Some(Value::Pattern(Pattern::Instance(InstanceLiteral { tag, .. }))) => tag.0 == class,
+        Some(_) => false,
+    }
+}
+
+enum Variable {
+    Actor,
+    Resource,
+    Column(String),
+    Literal(Value),
+}
+
+impl Variable {
+    fn is_resource(&self) -> bool {
+        matches!(self, Variable::Resource)
+    }
+}
+
+struct Translator {
+    variables: HashMap<Symbol, Variable>,
+    target: &'static str,
+}
+
+impl Translator {
+    fn not_expressible<T>(&self, term: &Term) -> crate::Result<T> {
+        lazy_error!(
+            "`{}` cannot be expressed in {}",
+            term.to_polar(),
+            self.target
+        )
+    }
+
+    /// Bind the results of attribute lookups on the resource to columns.
+    fn collect_lookups(&mut self, term: &Term) -> crate::Result<()> {
+        if let Value::Expression(Operation { operator, args }) = term.value() {
+            if *operator == Operator::Dot {
+                match (
+                    args[0].value(),
+                    args[1].value(),
+                    args.get(2).map(Term::value),
+                ) {
+                    (
+                        Value::Variable(object),
+                        Value::String(field),
+                        Some(Value::Variable(result)),
+                    ) if matches!(self.variables.get(object), Some(Variable::Resource)) => {
+                        self.variables
+                            .insert(result.clone(), Variable::Column(field.clone()));
+                    }
+                    _ => return self.not_expressible(term),
+                }
+            } else {
+                for arg in args {
+                    self.collect_lookups(arg)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn translate(&self, term: &Term) -> crate::Result<Filter> {
+        let (operator, args) = match term.value() {
+            Value::Boolean(b) => return Ok(Filter::Bool(*b)),
+            Value::Expression(Operation { operator, args }) => (*operator, args),
+            _ => return self.not_expressible(term),
+        };
+        let filter = match operator {
+            // Lookups are bound to columns by `collect_lookups`.
+            Operator::Dot => Filter::Bool(true),
+            Operator::And | Operator::Or => {
+                let args = args
+                    .iter()
+                    .filter(|arg| !is_lookup(arg))
+                    .map(|arg| self.translate(arg))
+                    .collect::<crate::Result<Vec<_>>>()?;
+                if operator == Operator::And {
+                    Filter::And(args)
+                } else {
+                    Filter::Or(args)
+                }
+            }
+            Operator::Not => Filter::Not(Box::new(self.translate(&args[0])?)),
+            Operator::Unify
+            | Operator::Eq
+            | Operator::Neq
+            | Operator::Lt
+            | Operator::Leq
+            | Operator::Gt
+            | Operator::Geq => {
+                let comparison = match operator {
+                    Operator::Unify | Operator::Eq => Comparison::Eq,
+                    Operator::Neq => Comparison::Neq,
+                    Operator::Lt => Comparison::Lt,
+                    Operator::Leq => Comparison::Leq,
+                    Operator::Gt => Comparison::Gt,
+                    _ => Comparison::Geq,
+                };
+                Filter::Compare(self.operand(&args[0])?, comparison, self.operand(&args[1])?)
+            }
+            Operator::In => match args[1].value() {
+                Value::List(values) => Filter::In(
+                    self.operand(&args[0])?,
+                    values
+                        .iter()
+                        .map(|v| self.operand(v))
+                        .collect::<crate::Result<Vec<_>>>()?,
+                ),
+                _ => return self.not_expressible(term),
+            },
+            _ => return self.not_expressible(term),
+        };
+        Ok(filter)
+    }
+
+    fn operand(&self, term: &Term) -> crate::Result<Operand> {
+        let value = match term.value() {
+            Value::Variable(name) => match self.variables.get(name) {
+                Some(Variable::Actor) => return Ok(Operand::Actor),
+                Some(Variable::Column(column)) => return Ok(Operand::Column(column.clone())),
+                Some(Variable::Literal(value)) => value,
+                Some(Variable::Resource) | None => return self.not_expressible(term),
+            },
+            value => value,
+        };
+        match value {
+            Value::Number(_) | Value::Boolean(_) | Value::String(_) => {
+                Ok(Operand::Literal(value.clone()))
+            }
+            _ => self.not_expressible(term),
+        }
+    }
+}
+
+fn is_lookup(term: &Term) -> bool {
+    matches!(
+        term.value(),
+        Value::Expression(Operation {
+            operator: Operator::Dot,
+            ..
+        })
+    )
+}
@@ -0,0 +1,228 @@
+//! Pluggable group membership.
+//!
+//! A [`GroupResolver`] registered with [`Oso::register_group_resolver`] is
+//! available to policies as the `Groups` constant:
+//!
+//! ```polar
+//! allow(user, "read", _) if Groups.member(user.name, "readers");
+//! allow(user, "write", _) if "writers" in Groups.of(user.name);
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::{Class, Oso, PolarValue};
+
+/// Looks up the groups a user belongs to, e.g. in a directory.
+pub trait GroupResolver: Send + Sync {
+    /// All groups `user` is a member of.
+    fn groups(&self, user: &str) -> crate::Result<Vec<String>>;
+
+    /// Whether `user` is a member of `group`. Resolvers that can check
+    /// a single membership more cheaply than listing all groups should
+    /// override this.
+    fn is_member(&self, user: &str, group: &str) -> crate::Result<bool> {
+        Ok(self.groups(user)?.iter().any(|g| g == group))
+    }
+}
+
+/// A shared resolver, e.g. one the application keeps a handle to in
+/// order to update it after registration.
+impl<R: GroupResolver + ?Sized> GroupResolver for Arc<R> {
+    fn groups(&self, user: &str) -> crate::Result<Vec<String>> {
+        (**self).groups(user)
+    }
+
+    fn is_member(&self, user: &str, group: &str) -> crate::Result<bool> {
+        (**self).is_member(user, group)
+    }
+}
+
+/// Groups from a fixed map of users to groups.
+#[derive(Clone, Debug, Default)]
+pub struct StaticGroups {
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl StaticGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_member(mut self, user: &str, group: &str) -> Self {
+        self.groups
+            .entry(user.to_string())
+            .or_default()
+            .push(group.to_string());
+        self
+    }
+}
+
+impl GroupResolver for StaticGroups {
+    fn groups(&self, user: &str) -> crate::Result<Vec<String>> {
+        Ok(self.groups.get(user).cloned().unwrap_or_default())
+    }
+}
+
+/// Groups from the claims of OpenID Connect ID tokens.
+///
+/// The application records the claims of each verified token with
+/// [`OidcClaims::insert`]; groups are then read from the configured claim,
+/// which may be a list of strings or a single space-separated string.
+pub struct OidcClaims {
+    claim: String,
+    subjects: RwLock<HashMap<String, Vec<String>>>,
+}
+
+impl OidcClaims {
+    /// Read groups from the `claim` claim, e.g. `groups`.
+    pub fn new(claim: &str) -> Self {
+        Self {
+            claim: claim.to_string(),
+            subjects: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Record the groups claimed for `subject`.
+    pub fn insert(&self, subject: &str, claims: &HashMap<String, PolarValue>) {
+        let groups = match claims.get(&self.claim) {
+            Some(PolarValue::List(groups)) => groups
+                .iter()
+                .filter_map(|group| match group {
+                    PolarValue::String(group) => Some(group.clone()),
+                    _ => None,
+                })
+                .collect(),
+            Some(PolarValue::String(groups)) => {
+                groups.split_whitespace().map(str::to_string).collect()
+            }
+            _ => vec![],
+        };
+        self.subjects
+            .write()
+            .unwrap()
+            .insert(subject.to_string(), groups);
+    }
+
+    /// Forget the claims of `subject`, e.g. when its session ends.
+    pub fn remove(&self, subject: &str) {
+        self.subjects.write().unwrap().remove(subject);
+    }
+}
+
+impl GroupResolver for OidcClaims {
+    fn groups(&self, subject: &str) -> crate::Result<Vec<String>> {
+        Ok(self
+            .subjects
+            .read()
+            .unwrap()
+            .get(subject)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(feature = "ldap")]
+pub use ldap::LdapGroups;
+
+#[cfg(feature = "ldap")]
+mod ldap {
+    use ldap3::{ldap_escape, LdapConn, Scope, SearchEntry};
+
+    use super::GroupResolver;
+
+    /// Groups from an LDAP directory.
+    ///
+    /// Groups are the entries under the base DN matching the filter, in
+    /// which `{user}` is replaced by the escaped user name. The group name
+    /// is read from the `cn` attribute unless configured otherwise.
+    pub struct LdapGroups {
+        url: String,
+        base: String,
+        filter: String,
+        attribute: String,
+        bind: Option<(String, String)>,
+    }
+
+    impl LdapGroups {
+        pub fn new(url: &str, base: &str, filter: &str) -> Self {
+            Self {
+                url: url.to_string(),
+                base: base.to_string(),
+                filter: filter.to_string(),
+                attribute: "cn".to_string(),
+                bind: None,
+            }
+        }
+
+        pub fn with_attribute(mut self, attribute: &str) -> Self {
+            self.attribute = attribute.to_string();
+            self
+        }
+
+        /// Bind as `dn` before searching.
+        pub fn with_bind(mut self, dn: &str, password: &str) -> Self {
+            self.bind = Some((dn.to_string(), password.to_string()));
+            self
+        }
+    }
+
+    impl GroupResolver for LdapGroups {
+        fn groups(&self, user: &str) -> crate::Result<Vec<String>> {
+            let ldap_error = |e: ldap3::LdapError| crate::OsoError::Custom {
+                message: format!("LDAP error: {}", e),
+            };
+
+            let mut ldap = LdapConn::new(&self.url).map_err(ldap_error)?;
+            if let Some((dn, password)) = &self.bind {
+                ldap.simple_bind(dn, password)
+                    .and_then(|result| result.success())
+                    .map_err(ldap_error)?;
+            }
+            let filter = self.filter.replace("{user}", &ldap_escape(user));
+            let (entries, _) = ldap
+                .search(
+                    &self.base,
+                    Scope::Subtree,
+                    &filter,
+                    vec![self.attribute.as_str()],
+                )
+                .and_then(|result| result.success())
+                .map_err(ldap_error)?;
+            let _ = ldap.unbind();
+
+            Ok(entries
+                .into_iter()
+                .flat_map(|entry| {
+                    SearchEntry::construct(entry)
+                        .attrs
+                        .remove(&self.attribute)
+                        .unwrap_or_default()
+                })
+                .collect())
+        }
+    }
+}
+
+/// The `Groups` constant through which policies query a resolver.
+#[derive(Clone)]
+struct Groups(Arc<dyn GroupResolver>);
+
+impl Oso {
+    /// Make `resolver` available to policies as the `Groups` constant, with
+    /// the methods `Groups.member(user, group)` and `Groups.of(user)`.
+    pub fn register_group_resolver<R>(&mut self, resolver: R) -> crate::Result<()>
+    where
+        R: GroupResolver + 'static,
+    {
+        let class = Class::<Groups>::new()
+            .name("Groups")
+            .add_method("member", |groups: &Groups, user: String, group: String| {
+                groups.0.is_member(&user, &group)
+            })
+            .add_method("of", |groups: &Groups, user: String| groups.0.groups(&user))
+            .build();
+        let groups = class.cast_to_instance(Groups(Arc::new(resolver)));
+        self.register_constant("Groups", &PolarValue::Instance(groups))
+    }
+}
@@ -3,21 +3,48 @@
 use polar_core::terms::{Symbol, Term};
 
 use std::any::{Any, TypeId};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::rc::Rc;
 use std::sync::Arc;
 
-use crate::errors::OsoError;
+use crate::errors::{OsoError, TypeMismatchError};
 use crate::FromPolar;
 
 use super::class_method::{ClassMethod, Constructor, InstanceMethod};
 use super::downcast;
-use super::method::{Function, Method};
+use super::method::{ContextMethod, Function, Method};
 use super::to_polar::ToPolarResults;
 use super::Host;
 
 type ClassMethods = HashMap<Symbol, ClassMethod>;
 type InstanceMethods = HashMap<Symbol, InstanceMethod>;
+type KwargSetters =
+    HashMap<Symbol, Arc<dyn Fn(&mut dyn Any, &Term, &mut Host) -> crate::Result<()> + Send + Sync>>;
+type ParentCast = Arc<dyn Fn(&dyn Any) -> Option<&dyn Any> + Send + Sync>;
+type Coercion = Arc<dyn Fn(&dyn Any) -> Option<Arc<dyn Any + Send + Sync>> + Send + Sync>;
+type AttributeFallback =
+    Arc<dyn Fn(&dyn Any, &str) -> crate::Result<Option<Arc<dyn ToPolarResults>>> + Send + Sync>;
+
+/// Wrap `cast` to get the parent of an instance of `T` from `&dyn Any`.
+fn parent_cast<T, P, F>(cast: F) -> ParentCast
+where
+    T: 'static,
+    P: 'static,
+    F: Fn(&T) -> &P + Send + Sync + 'static,
+{
+    // Closures don't tie the lifetime of their result to their argument
+    // unless they are checked against such a signature.
+    fn constrain<G: Fn(&dyn Any) -> Option<&dyn Any>>(g: G) -> G {
+        g
+    }
+    Arc::new(constrain(move |instance| {
+        instance
+            .downcast_ref::<T>()
+            .map(|instance| cast(instance) as &dyn Any)
+    }))
+}
 
 fn equality_not_supported(
     type_name: String,
@@ -32,18 +59,50 @@ fn equality_not_supported(
     Box::new(eq)
 }
 
+fn comparison_not_supported(
+    type_name: String,
+) -> Box<dyn Fn(&dyn Any, &dyn Any) -> crate::Result<Option<Ordering>> + Send + Sync> {
+    let cmp = move |_: &dyn Any, _: &dyn Any| -> crate::Result<Option<Ordering>> {
+        Err(OsoError::UnsupportedOperation {
+            operation: String::from("comparison"),
+            type_name: type_name.clone(),
+        })
+    };
+
+    Box::new(cmp)
+}
+
+fn containment_not_supported(
+    type_name: String,
+) -> Box<dyn Fn(&dyn Any, &dyn Any) -> crate::Result<bool> + Send + Sync> {
+    let contains = move |_: &dyn Any, _: &dyn Any| -> crate::Result<bool> {
+        Err(OsoError::UnsupportedOperation {
+            operation: String::from("in"),
+            type_name: type_name.clone(),
+        })
+    };
+
+    Box::new(contains)
+}
+
 #[derive(Clone)]
 pub struct Class<T = ()> {
     /// The class name. Defaults to the `std::any::type_name`
     pub name: String,
     /// A wrapped method that constructs an instance of `T` from Polar terms
     pub constructor: Option<Constructor>,
-    /// Methods that return simple attribute lookups on an instance of `T`
-    pub attributes: InstanceMethods,
-    /// Instance methods on `T` that expect Polar terms, and an instance of `&T`
-    pub instance_methods: InstanceMethods,
+    /// Alternative constructors, called like class methods
+    pub constructors: HashMap<Symbol, Constructor>,
+    /// Methods that return simple attribute lookups on an instance of `T`,
+    /// shared with its instances
+    pub attributes: Arc<InstanceMethods>,
+    /// Instance methods on `T` that expect Polar terms, and an instance of
+    /// `&T`, shared with its instances
+    pub instance_methods: Arc<InstanceMethods>,
     /// Class methods on `T`
     pub class_methods: ClassMethods,
+    /// Constants associated with `T`, looked up like attributes of the class
+    pub constants: ClassMethods,
     pub type_id: TypeId,
     /// A method to check whether the supplied argument is in instance of `T`
     instance_check: Arc<dyn Fn(&dyn Any) -> bool + Send + Sync>,
@@ -56,6 +115,47 @@ pub struct Class<T = ()> {
     /// Limitation: Only works on comparisons of the same type.
     equality_check: Arc<dyn Fn(&dyn Any, &dyn Any) -> crate::Result<bool> + Send + Sync>,
 
+    /// A function that orders arguments of this class, for the `<`, `<=`, `>`
+    /// and `>=` operators. Same limitation as `equality_check`.
+    comparison_check:
+        Arc<dyn Fn(&dyn Any, &dyn Any) -> crate::Result<Option<Ordering>> + Send + Sync>,
+
+    /// A function that checks whether an instance of this class contains a
+    /// value, for `value in instance`. The value may be of any class.
+    containment_check: Arc<dyn Fn(&dyn Any, &dyn Any) -> crate::Result<bool> + Send + Sync>,
+
+    /// A function that describes instances of this class in `print` and
+    /// trace output. Instances are shown by their id if it returns `None`.
+    repr: Arc<dyn Fn(&dyn Any) -> Option<String> + Send + Sync>,
+
+    /// A function that looks up attributes that are not registered.
+    attribute_fallback: Option<AttributeFallback>,
+
+    /// Functions that set the fields of a new instance from the keyword
+    /// arguments of `new`, by name.
+    kwargs: KwargSetters,
+
+    /// The types of the classes this class inherits attributes and methods
+    /// from, and functions that get the parent of an instance.
+    parents: Vec<(TypeId, ParentCast)>,
+
+    /// Functions that convert instances of other classes to this class, by
+    /// the type of the other class.
+    coercions: HashMap<TypeId, Coercion>,
+
+    /// The version of the schema of this class.
+    version: Option<u32>,
+
+    /// Attributes removed from this class, by name, and their new name if
+    /// they were renamed.
+    attribute_changes: HashMap<Symbol, Option<String>>,
+    /// The attribute holding the tenant of instances, constrained to the
+    /// tenant of the actor by data filters.
+    tenant_key: Option<String>,
+    /// A function that identifies instances of this class, for caching
+    /// decisions about them.
+    identity: Option<Arc<dyn Fn(&dyn Any) -> Option<String> + Send + Sync>>,
+
     /// A type marker. This is erased when the class is ready to be constructed with
     /// `erase_type`
     ty: std::marker::PhantomData<T>,
@@ -88,12 +188,25 @@ where
         Self {
             name: name.clone(),
             constructor: None,
-            attributes: InstanceMethods::new(),
-            instance_methods: InstanceMethods::new(),
+            constructors: HashMap::new(),
+            attributes: Arc::new(InstanceMethods::new()),
+            instance_methods: Arc::new(InstanceMethods::new()),
             class_methods: ClassMethods::new(),
+            constants: ClassMethods::new(),
             instance_check: Arc::new(|any| any.is::<T>()),
             class_check: Arc::new(|type_id| TypeId::of::<T>() == type_id),
-            equality_check: Arc::from(equality_not_supported(name)),
+            equality_check: Arc::from(equality_not_supported(name.clone())),
+            comparison_check: Arc::from(comparison_not_supported(name.clone())),
+            containment_check: Arc::from(containment_not_supported(name)),
+            repr: Arc::new(|_| None),
+            attribute_fallback: None,
+            kwargs: KwargSetters::new(),
+            parents: vec![],
+            coercions: HashMap::new(),
+            version: None,
+            attribute_changes: HashMap::new(),
+            tenant_key: None,
+            identity: None,
             ty: std::marker::PhantomData,
             type_id: TypeId::of::<T>(),
         }
@@ -101,7 +214,7 @@ where
 
     pub fn with_default() -> Self
     where
-        T: std::default::Default,
+        T: std::default::Default + Send + Sync,
     {
         Self::with_constructor::<_, _>(T::default)
     }
@@ -110,6 +223,7 @@ where
     where
         F: Function<Args, Result = T> + 'static,
         Args: FromPolar + 'static,
+        T: Send + Sync,
     {
         let mut class: Class<T> = Class::new();
         class = class.set_constructor(f);
@@ -120,11 +234,121 @@ where
     where
         F: Function<Args, Result = T> + 'static,
         Args: FromPolar + 'static,
+        T: Send + Sync,
     {
         self.constructor = Some(Constructor::new(f));
         self
     }
 
+    /// Like [`with_constructor`](Class::with_constructor), for a
+    /// constructor that returns a `Result`. An error fails `new` in the
+    /// policy with an application error.
+    pub fn with_fallible_constructor<F, Args, E>(f: F) -> Self
+    where
+        F: Function<Args, Result = Result<T, E>> + 'static,
+        Args: FromPolar + 'static,
+        T: Send + Sync,
+        E: ToString,
+    {
+        Class::new().set_fallible_constructor(f)
+    }
+
+    pub fn set_fallible_constructor<F, Args, E>(mut self, f: F) -> Self
+    where
+        F: Function<Args, Result = Result<T, E>> + 'static,
+        Args: FromPolar + 'static,
+        T: Send + Sync,
+        E: ToString,
+    {
+        self.constructor = Some(Constructor::new_fallible(f));
+        self
+    }
+
+    /// An alternative constructor, called like a class method, e.g.
+    /// `Repo.from_id(1)`, so that instances can be made in different ways
+    /// in the policy.
+    pub fn add_constructor<F, Args>(mut self, name: &str, f: F) -> Self
+    where
+        F: Function<Args, Result = T> + 'static,
+        Args: FromPolar + 'static,
+        T: Send + Sync,
+    {
+        self.constructors
+            .insert(Symbol(name.to_string()), Constructor::new(f));
+        self
+    }
+
+    /// Make instances of this class instances of `Parent` as well, so that
+    /// they match `Parent{}` specializers, and rules specialized on this
+    /// class are preferred over rules specialized on `Parent`. Ancestors
+    /// are not inherited transitively; call this once for each of them.
+    pub fn with_subclass_of<Parent: ?Sized + 'static>(mut self) -> Self {
+        let class_check = self.class_check.clone();
+        self.class_check =
+            Arc::new(move |type_id| TypeId::of::<Parent>() == type_id || class_check(type_id));
+        self
+    }
+
+    /// Make this class a subclass of `Parent`, like
+    /// [`with_subclass_of`](Class::with_subclass_of), and inherit the
+    /// attributes and methods registered on the class of `Parent`. They are
+    /// called on the `Parent` that `cast` returns for an instance, e.g.
+    /// `with_parent(|admin: &Admin| &admin.user)`. Attributes and methods
+    /// registered on this class take precedence over inherited ones.
+    pub fn with_parent<Parent, F>(mut self, cast: F) -> Self
+    where
+        Parent: 'static,
+        F: Fn(&T) -> &Parent + Send + Sync + 'static,
+    {
+        self.parents
+            .push((TypeId::of::<Parent>(), parent_cast(cast)));
+        self.with_subclass_of::<Parent>()
+    }
+
+    /// Accept instances of `Other` where this class is expected: they match
+    /// this class in rules and `matches`, and are converted with `f` when
+    /// passed to a method that takes `T`, e.g.
+    /// `coerces_from(|user: &UserRef| users.get(user.id))`.
+    pub fn coerces_from<Other, F>(mut self, f: F) -> Self
+    where
+        Other: 'static,
+        F: Fn(&Other) -> T + Send + Sync + 'static,
+        T: Send + Sync,
+    {
+        self.coercions.insert(
+            TypeId::of::<Other>(),
+            Arc::new(move |instance| {
+                let other = instance.downcast_ref::<Other>()?;
+                Some(Arc::new(f(other)) as Arc<dyn Any + Send + Sync>)
+            }),
+        );
+        self
+    }
+
+    /// Declare that `T` implements the trait `U`, registered with
+    /// [`Oso::register_trait`](crate::Oso::register_trait), so that
+    /// instances of this class match it, e.g.
+    /// `implements::<dyn Resource, _>(|repo| repo)`. `cast` is not called;
+    /// it checks that `T` implements `U` at compile time.
+    pub fn implements<U, F>(self, _cast: F) -> Self
+    where
+        U: ?Sized + 'static,
+        F: Fn(&T) -> &U,
+    {
+        self.with_subclass_of::<U>()
+    }
+
+    /// Only treat the values of `T` for which `f` returns `true` as
+    /// instances of this class, so that several classes can share a Rust
+    /// type, e.g. one class per kind of JSON document.
+    pub fn set_instance_check<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        self.instance_check = Arc::new(move |any| any.downcast_ref::<T>().map_or(false, &f));
+        self
+    }
+
     pub fn set_equality_check<F>(mut self, f: F) -> Self
     where
         F: Fn(&T, &T) -> bool + Send + Sync + 'static,
@@ -148,13 +372,113 @@ where
         self.set_equality_check(|a, b| PartialEq::eq(a, b))
     }
 
+    pub fn set_comparison_check<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T, &T) -> Option<Ordering> + Send + Sync + 'static,
+    {
+        self.comparison_check = Arc::new(move |a, b| {
+            tracing::trace!("comparison check");
+
+            let a = downcast(a).map_err(|e| e.user())?;
+            let b = downcast(b).map_err(|e| e.user())?;
+
+            Ok((f)(a, b))
+        });
+
+        self
+    }
+
+    pub fn with_comparison_check(self) -> Self
+    where
+        T: PartialOrd<T>,
+    {
+        self.set_comparison_check(|a, b| PartialOrd::partial_cmp(a, b))
+    }
+
+    /// Support `==`, `!=`, `<`, `<=`, `>` and `>=` through the `PartialEq`
+    /// and `PartialOrd` implementations of `T`. Values that `partial_cmp`
+    /// does not order satisfy none of the ordering operators.
+    pub fn with_ordering(self) -> Self
+    where
+        T: PartialOrd<T>,
+    {
+        self.with_equality_check().with_comparison_check()
+    }
+
+    /// Support `value in instance`. `f` receives the value as `&dyn Any`, so
+    /// that values of several classes can be accepted.
+    pub fn set_containment_check<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T, &dyn Any) -> bool + Send + Sync + 'static,
+    {
+        self.containment_check = Arc::new(move |container, item| {
+            tracing::trace!("containment check");
+
+            let container = downcast(container).map_err(|e| e.user())?;
+
+            Ok((f)(container, item))
+        });
+
+        self
+    }
+
+    /// Decide with `f` whether instances used as conditions in rule bodies
+    /// succeed, e.g. `set_truthiness(|name: &OptionalName| name.0.is_some())`
+    /// for `allow(user, _, _) if user.nickname;`. Only used once
+    /// [`Oso::set_instance_truthiness`](crate::Oso::set_instance_truthiness)
+    /// is enabled.
+    pub fn set_truthiness<F>(self, f: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        self.add_method(polar_core::polar::TRUTHINESS_METHOD, move |instance: &T| {
+            f(instance)
+        })
+    }
+
+    /// Describe instances with `f` in `print` and trace output, e.g.
+    /// `set_repr(|repo: &Repo| format!("Repo({})", repo.name))`.
+    pub fn set_repr<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T) -> String + Send + Sync + 'static,
+    {
+        self.repr =
+            Arc::new(move |instance| instance.downcast_ref::<T>().map(|instance| f(instance)));
+        self
+    }
+
+    /// Describe instances with their `Debug` representation.
+    pub fn with_debug_repr(self) -> Self
+    where
+        T: fmt::Debug,
+    {
+        self.set_repr(|instance| format!("{:?}", instance))
+    }
+
+    /// Look up attributes that are not registered with `f`, which is called
+    /// with the instance and the name of the attribute, and returns `None`
+    /// if the instance has no such attribute. This supports instances whose
+    /// fields are only known at runtime, such as JSON documents or database
+    /// rows.
+    pub fn set_attribute_fallback<F, R>(mut self, f: F) -> Self
+    where
+        F: Fn(&T, &str) -> Option<R> + Send + Sync + 'static,
+        R: ToPolarResults + 'static,
+    {
+        self.attribute_fallback = Some(Arc::new(move |instance, name| {
+            let instance = downcast(instance).map_err(|e| e.invariant())?;
+            Ok((f)(instance, name).map(|value| Arc::new(value) as Arc<dyn ToPolarResults>))
+        }));
+        self
+    }
+
     pub fn add_attribute_getter<F, R>(mut self, name: &str, f: F) -> Self
     where
         F: Method<T, Result = R> + 'static,
         R: ToPolarResults + 'static,
         T: 'static,
     {
-        self.attributes
+        Arc::make_mut(&mut self.attributes)
             .insert(Symbol(name.to_string()), InstanceMethod::new(f));
         self
     }
@@ -164,17 +488,143 @@ where
         self
     }
 
+    /// Set the version of the schema of `T`, named in the warnings about
+    /// policies that use attributes removed in it.
+    pub fn set_version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Declare that the attribute `name` of `T` holds the tenant it belongs
+    /// to. Data filters for `T`, e.g. [`Oso::sql_filter`](crate::Oso::sql_filter),
+    /// then only select the instances of the tenant of the actor, whether
+    /// or not the policy checks it.
+    pub fn tenant_key(mut self, name: &str) -> Self {
+        self.tenant_key = Some(name.to_string());
+        self
+    }
+
+    /// Identify instances of `T` with `f`, e.g. by their primary key, so
+    /// that decisions about them can be cached. See
+    /// [`Oso::set_decision_cache`](crate::Oso::set_decision_cache).
+    pub fn set_identity<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T) -> String + Send + Sync + 'static,
+    {
+        self.identity = Some(Arc::new(move |instance| {
+            instance.downcast_ref::<T>().map(|instance| f(instance))
+        }));
+        self
+    }
+
+    /// Record that `T` no longer has the attribute `name`, so that loading
+    /// a policy that uses it logs a warning. See
+    /// [`Oso::attribute_migrations`](crate::Oso::attribute_migrations).
+    pub fn add_removed_attribute(mut self, name: &str) -> Self {
+        self.attribute_changes
+            .insert(Symbol(name.to_string()), None);
+        self
+    }
+
+    /// Record that the attribute `from` of `T` was renamed to `to`, so that
+    /// loading a policy that uses `from` logs a warning suggesting `to`.
+    pub fn add_renamed_attribute(mut self, from: &str, to: &str) -> Self {
+        self.attribute_changes
+            .insert(Symbol(from.to_string()), Some(to.to_string()));
+        self
+    }
+
     pub fn add_method<F, Args, R>(mut self, name: &str, f: F) -> Self
     where
         Args: FromPolar,
         F: Method<T, Args, Result = R> + 'static,
         R: ToPolarResults + 'static,
     {
-        self.instance_methods
+        Arc::make_mut(&mut self.instance_methods)
             .insert(Symbol(name.to_string()), InstanceMethod::new(f));
         self
     }
 
+    /// A method that receives the [`Context`](crate::Context) of the query
+    /// calling it after the instance, and before its other arguments.
+    ///
+    /// ```
+    /// # use oso::{Class, Context};
+    /// #[derive(Clone, Default)]
+    /// struct Repo;
+    ///
+    /// let class = Class::<Repo>::with_default()
+    ///     .add_context_method("query_id", |_: &Repo, context: &Context| context.query_id())
+    ///     .build();
+    /// ```
+    pub fn add_context_method<F, Args, R>(mut self, name: &str, f: F) -> Self
+    where
+        Args: FromPolar,
+        F: ContextMethod<T, Args, Result = R> + 'static,
+        R: ToPolarResults + 'static,
+    {
+        Arc::make_mut(&mut self.instance_methods).insert(
+            Symbol(name.to_string()),
+            InstanceMethod::new_with_context(f),
+        );
+        self
+    }
+
+    /// A method that takes keyword arguments, e.g. `x.f(1, limit: 10)`. They
+    /// are passed as a dictionary in the last argument of `f`, which is
+    /// empty if there are none, and may be converted to e.g. a
+    /// `HashMap<String, PolarValue>`.
+    pub fn add_kwargs_method<F, Args, R>(mut self, name: &str, f: F) -> Self
+    where
+        Args: FromPolar,
+        F: Method<T, Args, Result = R> + 'static,
+        R: ToPolarResults + 'static,
+    {
+        Arc::make_mut(&mut self.instance_methods).insert(
+            Symbol(name.to_string()),
+            InstanceMethod::new(f).with_kwargs(),
+        );
+        self
+    }
+
+    /// Accept the keyword argument `name` in `new`, e.g. `new Foo(bar: 1)`,
+    /// and set it on the instance made by the constructor with `f`.
+    /// Keyword arguments without a setter are errors.
+    ///
+    /// ```
+    /// # use oso::Class;
+    /// #[derive(Clone, Default)]
+    /// struct Foo {
+    ///     bar: i64,
+    ///     baz: String,
+    /// }
+    ///
+    /// let class = Class::<Foo>::with_default()
+    ///     .add_kwarg("bar", |foo: &mut Foo, bar| foo.bar = bar)
+    ///     .add_kwarg("baz", |foo: &mut Foo, baz| foo.baz = baz)
+    ///     .build();
+    /// ```
+    pub fn add_kwarg<F, V>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(&mut T, V) + Send + Sync + 'static,
+        V: FromPolar,
+    {
+        self.kwargs.insert(
+            Symbol(name.to_string()),
+            Arc::new(move |instance, value, host| {
+                let instance = instance.downcast_mut().ok_or_else(|| {
+                    crate::errors::TypeError {
+                        expected: String::from(std::any::type_name::<T>()),
+                    }
+                    .invariant()
+                })?;
+                f(instance, V::from_polar(value, host)?);
+                Ok(())
+            }),
+        );
+        self
+    }
+
     /// A method that returns multiple values. Every element in the iterator returned by the method will
     /// be a separate polar return value.
     pub fn add_iterator_method<F, Args, I>(mut self, name: &str, f: F) -> Self
@@ -182,15 +632,48 @@ where
         Args: FromPolar,
         F: Method<T, Args> + 'static,
         F::Result: IntoIterator<Item = I>,
-        <<F as Method<T, Args>>::Result as IntoIterator>::IntoIter: Sized + Clone + 'static,
+        <<F as Method<T, Args>>::Result as IntoIterator>::IntoIter: Sized + 'static,
         I: ToPolarResults + 'static,
         T: 'static,
     {
-        self.instance_methods
+        Arc::make_mut(&mut self.instance_methods)
             .insert(Symbol(name.to_string()), InstanceMethod::new_iterator(f));
         self
     }
 
+    /// An iterator method that receives the [`Context`](crate::Context)
+    /// of the query calling it, like
+    /// [`add_context_method`](Class::add_context_method), e.g. to fetch
+    /// only the [`page`](crate::Context::page) of results requested.
+    ///
+    /// ```
+    /// # use oso::{Class, Context};
+    /// #[derive(Clone, Default)]
+    /// struct Repo;
+    ///
+    /// let class = Class::<Repo>::with_default()
+    ///     .add_context_iterator_method("issues", |_: &Repo, context: &Context| {
+    ///         let end = context.page().end().unwrap_or(100);
+    ///         (0..end as i64).collect::<Vec<_>>()
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn add_context_iterator_method<F, Args, I>(mut self, name: &str, f: F) -> Self
+    where
+        Args: FromPolar,
+        F: ContextMethod<T, Args> + 'static,
+        F::Result: IntoIterator<Item = I>,
+        <<F as ContextMethod<T, Args>>::Result as IntoIterator>::IntoIter: Sized + 'static,
+        I: ToPolarResults + 'static,
+        T: 'static,
+    {
+        Arc::make_mut(&mut self.instance_methods).insert(
+            Symbol(name.to_string()),
+            InstanceMethod::new_context_iterator(f),
+        );
+        self
+    }
+
     pub fn add_class_method<F, Args, R>(mut self, name: &str, f: F) -> Self
     where
         F: Function<Args, Result = R> + 'static,
@@ -202,6 +685,18 @@ where
         self
     }
 
+    /// A constant associated with the class, available as `Class.NAME`.
+    pub fn add_constant<V>(mut self, name: &str, value: V) -> Self
+    where
+        V: ToPolarResults + Clone + Send + Sync + 'static,
+    {
+        self.constants.insert(
+            Symbol(name.to_string()),
+            ClassMethod::new(move || value.clone()),
+        );
+        self
+    }
+
     /// Erase the generic type parameter
     /// This is done before registering so
     /// that the host can store all of the same type. The generic paramtere
@@ -211,13 +706,26 @@ where
         Class {
             name: self.name,
             constructor: self.constructor,
+            constructors: self.constructors,
             attributes: self.attributes,
             instance_methods: self.instance_methods,
             class_methods: self.class_methods,
+            constants: self.constants,
             instance_check: self.instance_check,
             class_check: self.class_check,
             type_id: self.type_id,
             equality_check: self.equality_check,
+            comparison_check: self.comparison_check,
+            containment_check: self.containment_check,
+            repr: self.repr,
+            attribute_fallback: self.attribute_fallback,
+            kwargs: self.kwargs,
+            parents: self.parents,
+            coercions: self.coercions,
+            version: self.version,
+            attribute_changes: self.attribute_changes,
+            tenant_key: self.tenant_key,
+            identity: self.identity,
             ty: std::marker::PhantomData,
         }
     }
@@ -244,32 +752,92 @@ where
         (self.instance_check)(instance.instance.as_ref())
     }
 
+    /// Whether instances of the class of `instance` can be converted to
+    /// this class.
+    pub fn coerces(&self, instance: &Instance) -> bool {
+        self.coercions.contains_key(&instance.class.type_id)
+    }
+
+    /// Convert `instance` to this class, if it can be.
+    pub fn coerce(&self, instance: &Instance) -> Option<Arc<dyn Any + Send + Sync>> {
+        let coerce = self.coercions.get(&instance.class.type_id)?;
+        tracing::debug!(from = %instance.name, to = %self.name, "coercion");
+        coerce(instance.instance.as_ref())
+    }
+
     pub fn equals(&self, instance: &Instance, other: &Instance) -> crate::Result<bool> {
         (self.equality_check)(instance.instance.as_ref(), other.instance.as_ref())
     }
 }
 
 impl Class {
-    pub fn cast_to_instance(&self, instance: impl Any) -> Instance {
+    /// A class for the trait object type `U`, e.g. `dyn Resource`. Its
+    /// instances are the instances of the classes that declare they
+    /// implement `U` with [`Class::implements`].
+    pub fn interface<U: ?Sized + 'static>(name: &str) -> Self {
+        let mut class = Class::<()>::new().name(name);
+        class.type_id = TypeId::of::<U>();
+        class.instance_check = Arc::new(|_| false);
+        class.class_check = Arc::new(|type_id| TypeId::of::<U>() == type_id);
+        class
+    }
+
+    /// The version of the schema of this class, if it was set with
+    /// [`Class::set_version`].
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// The attribute set with [`Class::tenant_key`], if any.
+    pub(crate) fn tenant_column(&self) -> Option<&str> {
+        self.tenant_key.as_deref()
+    }
+
+    /// Whether the attribute `name` was removed, and its new name if it was
+    /// renamed.
+    pub(crate) fn attribute_change(&self, name: &Symbol) -> Option<Option<&str>> {
+        self.attribute_changes.get(name).map(Option::as_deref)
+    }
+
+    /// Return `true` if this class was registered as a subclass of `other`.
+    pub fn is_subclass_of(&self, other: &Class) -> bool {
+        self.type_id != other.type_id && (self.class_check)(other.type_id)
+    }
+
+    pub fn cast_to_instance(&self, instance: impl Any + Send + Sync) -> Instance {
+        self.wrap_instance(Arc::new(instance))
+    }
+
+    pub(crate) fn wrap_instance(&self, instance: Arc<dyn Any + Send + Sync>) -> Instance {
         Instance {
             name: self.name.clone(),
-            instance: Arc::new(instance),
-            attributes: Arc::new(self.attributes.clone()),
-            methods: Arc::new(self.instance_methods.clone()),
+            instance,
+            attributes: self.attributes.clone(),
+            methods: self.instance_methods.clone(),
             class: self.clone(),
         }
     }
 
-    pub fn init(&self, fields: Vec<Term>, host: &mut Host) -> crate::Result<Instance> {
+    pub fn init(
+        &self,
+        fields: Vec<Term>,
+        kwargs: Option<BTreeMap<Symbol, Term>>,
+        host: &mut Host,
+    ) -> crate::Result<Instance> {
         if let Some(constructor) = &self.constructor {
-            let instance = constructor.invoke(fields, host)?;
-            Ok(Instance {
-                name: self.name.clone(),
-                instance,
-                attributes: Arc::new(self.attributes.clone()),
-                methods: Arc::new(self.instance_methods.clone()),
-                class: self.clone(),
-            })
+            let mut instance = constructor.invoke(fields, host)?;
+            for (name, value) in kwargs.unwrap_or_default() {
+                let set = match self.kwargs.get(&name) {
+                    Some(set) => set,
+                    None => {
+                        return lazy_error!("{} has no keyword argument `{}`", self.name, name.0)
+                    }
+                };
+                // The instance was just made, so it isn't shared yet.
+                let instance = Arc::get_mut(&mut instance).unwrap();
+                set(instance, &value, host)?;
+            }
+            Ok(self.wrap_instance(instance))
         } else {
             Err(crate::OsoError::Custom {
                 message: format!("MissingConstructorError: {} has no constructor", self.name),
@@ -281,7 +849,7 @@ impl Class {
 #[derive(Clone)]
 pub struct Instance {
     pub name: String,
-    pub instance: Arc<dyn Any>,
+    pub instance: Arc<dyn Any + Send + Sync>,
     pub attributes: Arc<InstanceMethods>,
     pub methods: Arc<InstanceMethods>,
 
@@ -303,11 +871,216 @@ impl Instance {
         // pub.
         (self.class.equality_check)(&*self.instance, &*other.instance)
     }
+
+    /// Order the `instance` of self relative to the instance of `other`.
+    pub fn compare(&self, other: &Self) -> crate::Result<Option<Ordering>> {
+        tracing::trace!("compare");
+        (self.class.comparison_check)(&*self.instance, &*other.instance)
+    }
+
+    /// Return `true` if the `instance` of self contains the instance of `item`.
+    pub fn contains(&self, item: &Self) -> crate::Result<bool> {
+        tracing::trace!("contains");
+        (self.class.containment_check)(&*self.instance, &*item.instance)
+    }
+
+    /// The method `name` of the `instance` of self, or its attribute if
+    /// `method` is false, and the receiver to call it on. Falls back to
+    /// the classes it inherits from, depth first.
+    pub(crate) fn find_method(
+        &self,
+        name: &str,
+        method: bool,
+        host: &Host,
+    ) -> Option<(InstanceMethod, &dyn Any)> {
+        let methods = if method {
+            &self.methods
+        } else {
+            &self.attributes
+        };
+        match methods.get(name) {
+            Some(found) => Some((found.clone(), self.instance.as_ref())),
+            None => inherited_method(&self.class, self.instance.as_ref(), name, method, host),
+        }
+    }
+
+    /// Describe the `instance` of self, if its class has a repr.
+    pub fn repr(&self) -> Option<String> {
+        (self.class.repr)(&*self.instance)
+    }
+
+    /// The identity of the instance, if its class has one.
+    pub(crate) fn identity(&self) -> Option<String> {
+        self.class.identity.as_ref()?(&*self.instance)
+    }
+
+    /// The `instance` of self as a `T`, e.g. to use an instance returned
+    /// by a query.
+    ///
+    /// ```
+    /// # use oso::{Class, HostClass, Oso};
+    /// #[derive(Clone)]
+    /// struct User {
+    ///     name: String,
+    /// }
+    /// impl HostClass for User {}
+    ///
+    /// let mut oso = Oso::new();
+    /// oso.register_class(Class::<User>::new().name("User").build())?;
+    /// oso.register_constant("alice", &User { name: "alice".to_string() })?;
+    /// let mut query = oso.query("x = alice")?;
+    /// let instance: oso::Instance = query.next().unwrap()?.get_typed("x")?;
+    /// assert_eq!(instance.downcast::<User>()?.name, "alice");
+    /// assert!(instance.downcast::<String>().is_err());
+    /// # Ok::<(), oso::OsoError>(())
+    /// ```
+    pub fn downcast<T: 'static>(&self) -> Result<&T, TypeMismatchError> {
+        self.instance
+            .downcast_ref()
+            .ok_or_else(|| self.type_mismatch::<T>())
+    }
+
+    /// Convert self into the `T` it is an instance of, cloning the
+    /// instance only if it is shared.
+    pub fn try_into_owned<T>(self) -> Result<T, TypeMismatchError>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let mismatch = self.type_mismatch::<T>();
+        match self.instance.downcast::<T>() {
+            Ok(instance) => {
+                Ok(Arc::try_unwrap(instance).unwrap_or_else(|shared| (*shared).clone()))
+            }
+            Err(_) => Err(mismatch),
+        }
+    }
+
+    fn type_mismatch<T>(&self) -> TypeMismatchError {
+        TypeMismatchError {
+            expected: std::any::type_name::<T>().to_string(),
+            actual: self.name.clone(),
+        }
+    }
 }
 
-// @TODO: This is very unsafe.
-// Temporary workaround. We need to differentiate between instances which
-// _do_ need to be `Send` (e.g. registered as constants on the base `Oso` objects)
-// and instances which don't need to be Send (e.g. created/accessed on a single thread for
-// just one query).
-unsafe impl Send for Instance {}
+/// An instance owned by a single query, bound with
+/// [`Query::bind_local`](crate::Query::bind_local). Unlike [`Instance`], it
+/// is never cached in the host, so it need not be `Send`.
+#[derive(Clone)]
+pub(crate) struct LocalInstance {
+    instance: Rc<dyn Any>,
+    class: Class,
+}
+
+impl LocalInstance {
+    pub fn new<T: 'static>(class: Class, instance: T) -> Self {
+        Self {
+            instance: Rc::new(instance),
+            class,
+        }
+    }
+
+    /// Whether the instance matches `class`.
+    pub fn isa(&self, class: &Class) -> bool {
+        (class.instance_check)(&*self.instance) || self.class.is_subclass_of(class)
+    }
+}
+
+/// The receiver of an attribute lookup or method call.
+pub(crate) enum Receiver {
+    Shared(Instance),
+    Local(LocalInstance),
+}
+
+impl Receiver {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Shared(instance) => &instance.name,
+            Self::Local(instance) => &instance.class.name,
+        }
+    }
+
+    pub fn has_attribute_fallback(&self) -> bool {
+        self.class().attribute_fallback.is_some()
+    }
+
+    /// Look up the attribute `name` with the attribute fallback of the
+    /// class of the receiver, if it has one.
+    pub fn fallback_attribute(&self, name: &str) -> crate::Result<Option<Arc<dyn ToPolarResults>>> {
+        match &self.class().attribute_fallback {
+            Some(fallback) => fallback(self.value(), name),
+            None => Ok(None),
+        }
+    }
+
+    /// The method `name` of the receiver, or its attribute if `method` is
+    /// false, like `Instance::find_method`.
+    pub fn find_method(
+        &self,
+        name: &str,
+        method: bool,
+        host: &Host,
+    ) -> Option<(InstanceMethod, &dyn Any)> {
+        match self {
+            Self::Shared(instance) => instance.find_method(name, method, host),
+            Self::Local(instance) => {
+                let methods = if method {
+                    &instance.class.instance_methods
+                } else {
+                    &instance.class.attributes
+                };
+                match methods.get(name) {
+                    Some(found) => Some((found.clone(), instance.instance.as_ref())),
+                    None => inherited_method(
+                        &instance.class,
+                        instance.instance.as_ref(),
+                        name,
+                        method,
+                        host,
+                    ),
+                }
+            }
+        }
+    }
+
+    fn class(&self) -> &Class {
+        match self {
+            Self::Shared(instance) => &instance.class,
+            Self::Local(instance) => &instance.class,
+        }
+    }
+
+    fn value(&self) -> &dyn Any {
+        match self {
+            Self::Shared(instance) => instance.instance.as_ref(),
+            Self::Local(instance) => instance.instance.as_ref(),
+        }
+    }
+}
+
+fn inherited_method<'a>(
+    class: &Class,
+    receiver: &'a dyn Any,
+    name: &str,
+    method: bool,
+    host: &Host,
+) -> Option<(InstanceMethod, &'a dyn Any)> {
+    for (type_id, cast) in &class.parents {
+        let (parent, receiver) = match (host.get_class_by_type_id(*type_id), cast(receiver)) {
+            (Some(parent), Some(receiver)) => (parent, receiver),
+            _ => continue,
+        };
+        let methods = if method {
+            &parent.instance_methods
+        } else {
+            &parent.attributes
+        };
+        if let Some(found) = methods.get(name) {
+            return Some((found.clone(), receiver));
+        }
+        if let Some(found) = inherited_method(parent, receiver, name, method, host) {
+            return Some(found);
+        }
+    }
+    None
+}
@@ -1,17 +1,19 @@
 //! Wrapper structs for the generic `Function` and `Method` traits
-use polar_core::terms::{Symbol, Term};
+use polar_core::terms::{Dictionary, Symbol, Term, Value};
 
 use std::any::Any;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use super::to_polar::ToPolarResults;
+use crate::context::QueryContext;
 use crate::errors::InvariantError;
 use crate::host::to_polar::PolarIter;
-use crate::FromPolar;
+use crate::{Context, FromPolar, PolarValue};
 
 use super::class::Class;
 use super::downcast;
-use super::method::{Function, Method};
+use super::method::{ContextMethod, Function, Method};
 use super::Host;
 
 fn join<A, B>(left: crate::Result<A>, right: crate::Result<B>) -> crate::Result<(A, B)> {
@@ -20,33 +22,93 @@ fn join<A, B>(left: crate::Result<A>, right: crate::Result<B>) -> crate::Result<
 
 type TypeErasedFunction<R> =
     Arc<dyn Fn(Vec<Term>, &mut Host) -> crate::Result<Arc<R>> + Send + Sync>;
-type TypeErasedMethod<R> =
-    Arc<dyn Fn(&dyn Any, Vec<Term>, &mut Host) -> crate::Result<Arc<R>> + Send + Sync>;
+type TypeErasedMethod<R> = Arc<
+    dyn Fn(&dyn Any, Vec<Term>, &mut Host, &QueryContext) -> crate::Result<Arc<R>> + Send + Sync,
+>;
 
 #[derive(Clone)]
-pub struct Constructor(TypeErasedFunction<dyn Any>);
+pub struct Constructor(TypeErasedFunction<dyn Any + Send + Sync>);
 
 impl Constructor {
     pub fn new<Args, F>(f: F) -> Self
     where
         Args: FromPolar,
         F: Function<Args> + 'static,
-        F::Result: 'static,
+        F::Result: Send + Sync + 'static,
     {
         Constructor(Arc::new(move |args: Vec<Term>, host: &mut Host| {
-            Args::from_polar_list(&args, host).map(|args| Arc::new(f.invoke(args)) as Arc<dyn Any>)
+            Args::from_polar_list(&args, host)
+                .map(|args| Arc::new(f.invoke(args)) as Arc<dyn Any + Send + Sync>)
+        }))
+    }
+
+    /// A constructor that may fail. Its errors are returned to Polar as
+    /// application errors.
+    pub fn new_fallible<Args, F, T, E>(f: F) -> Self
+    where
+        Args: FromPolar,
+        F: Function<Args, Result = Result<T, E>> + 'static,
+        T: Send + Sync + 'static,
+        E: ToString,
+    {
+        Constructor(Arc::new(move |args: Vec<Term>, host: &mut Host| {
+            let args = Args::from_polar_list(&args, host)?;
+            match f.invoke(args) {
+                Ok(instance) => Ok(Arc::new(instance) as Arc<dyn Any + Send + Sync>),
+                Err(e) => Err(crate::OsoError::Custom {
+                    message: e.to_string(),
+                }),
+            }
         }))
     }
 
-    pub fn invoke(&self, args: Vec<Term>, host: &mut Host) -> crate::Result<Arc<dyn Any>> {
+    pub fn invoke(
+        &self,
+        args: Vec<Term>,
+        host: &mut Host,
+    ) -> crate::Result<Arc<dyn Any + Send + Sync>> {
         self.0(args, host)
     }
 }
 
 #[derive(Clone)]
-pub struct InstanceMethod(TypeErasedMethod<dyn ToPolarResults>);
+pub struct InstanceMethod {
+    method: TypeErasedMethod<dyn ToPolarResults>,
+    /// Whether keyword arguments are passed to the method, as a dictionary
+    /// after its other arguments.
+    kwargs: bool,
+    /// The number of arguments the method takes, if fixed.
+    arity: Option<usize>,
+}
 
 impl InstanceMethod {
+    fn from_erased(method: TypeErasedMethod<dyn ToPolarResults>) -> Self {
+        Self {
+            method,
+            kwargs: false,
+            arity: None,
+        }
+    }
+
+    fn with_arity(mut self, arity: Option<usize>) -> Self {
+        self.arity = arity;
+        self
+    }
+
+    /// Pass keyword arguments to the method as a dictionary after its
+    /// other arguments. The dictionary is empty if there are none.
+    pub fn with_kwargs(mut self) -> Self {
+        self.kwargs = true;
+        self.arity = self.arity.map(|arity| arity.saturating_sub(1));
+        self
+    }
+
+    /// The number of positional arguments the method takes, or `None` if
+    /// it takes any number of them.
+    pub fn arity(&self) -> Option<usize> {
+        self.arity
+    }
+
     pub fn new<T, F, Args>(f: F) -> Self
     where
         Args: FromPolar,
@@ -54,8 +116,8 @@ impl InstanceMethod {
         F::Result: ToPolarResults + 'static,
         T: 'static,
     {
-        Self(Arc::new(
-            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host| {
+        Self::from_erased(Arc::new(
+            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host, _: &QueryContext| {
                 let receiver = downcast(receiver).map_err(|e| e.invariant().into());
 
                 let args = Args::from_polar_list(&args, host);
@@ -65,6 +127,7 @@ impl InstanceMethod {
                 })
             },
         ))
+        .with_arity(Args::arity())
     }
 
     pub fn new_iterator<T, F, Args, I>(f: F) -> Self
@@ -72,55 +135,135 @@ impl InstanceMethod {
         Args: FromPolar,
         F: Method<T, Args> + 'static,
         F::Result: IntoIterator<Item = I>,
-        <<F as Method<T, Args>>::Result as IntoIterator>::IntoIter: Sized + Clone + 'static,
+        <<F as Method<T, Args>>::Result as IntoIterator>::IntoIter: Sized + 'static,
         I: ToPolarResults + 'static,
         T: 'static,
     {
-        Self(Arc::new(
-            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host| {
+        Self::from_erased(Arc::new(
+            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host, _: &QueryContext| {
                 let receiver = downcast(receiver).map_err(|e| e.invariant().into());
 
                 let args = Args::from_polar_list(&args, host);
 
                 join(receiver, args).map(|(receiver, args)| {
-                    let polar_values = PolarIter {
-                        iter: f.invoke(receiver, args).into_iter(),
-                    };
+                    let polar_values = PolarIter::new(f.invoke(receiver, args).into_iter());
                     Arc::new(polar_values) as Arc<dyn ToPolarResults>
                 })
             },
         ))
+        .with_arity(Args::arity())
     }
 
-    pub fn invoke(
+    pub fn new_with_context<T, F, Args>(f: F) -> Self
+    where
+        Args: FromPolar,
+        F: ContextMethod<T, Args> + 'static,
+        F::Result: ToPolarResults + 'static,
+        T: 'static,
+    {
+        Self::from_erased(Arc::new(
+            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host, query: &QueryContext| {
+                let receiver = downcast(receiver).map_err(|e| e.invariant().into());
+
+                let args = Args::from_polar_list(&args, host);
+
+                join(receiver, args).map(|(receiver, args)| {
+                    let context = Context::new(query, host);
+                    Arc::new(f.invoke(receiver, &context, args)) as Arc<dyn ToPolarResults>
+                })
+            },
+        ))
+        .with_arity(Args::arity())
+    }
+
+    pub fn new_context_iterator<T, F, Args, I>(f: F) -> Self
+    where
+        Args: FromPolar,
+        F: ContextMethod<T, Args> + 'static,
+        F::Result: IntoIterator<Item = I>,
+        <<F as ContextMethod<T, Args>>::Result as IntoIterator>::IntoIter: Sized + 'static,
+        I: ToPolarResults + 'static,
+        T: 'static,
+    {
+        Self::from_erased(Arc::new(
+            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host, query: &QueryContext| {
+                let receiver = downcast(receiver).map_err(|e| e.invariant().into());
+
+                let args = Args::from_polar_list(&args, host);
+
+                join(receiver, args).map(|(receiver, args)| {
+                    let context = Context::new(query, host);
+                    let polar_values =
+                        PolarIter::new(f.invoke(receiver, &context, args).into_iter());
+                    Arc::new(polar_values) as Arc<dyn ToPolarResults>
+                })
+            },
+        ))
+        .with_arity(Args::arity())
+    }
+
+    pub(crate) fn invoke(
         &self,
         receiver: &dyn Any,
-        args: Vec<Term>,
+        mut args: Vec<Term>,
+        kwargs: Option<BTreeMap<Symbol, Term>>,
         host: &mut Host,
+        context: &QueryContext,
     ) -> crate::Result<Arc<dyn ToPolarResults>> {
-        self.0(receiver, args, host)
+        if self.kwargs {
+            let fields = kwargs.unwrap_or_default();
+            args.push(Term::new_temporary(Value::Dictionary(Dictionary {
+                fields,
+            })));
+        } else if kwargs.is_some() {
+            return lazy_error!("method does not accept keyword arguments");
+        }
+        (self.method)(receiver, args, host, context)
     }
 
     pub fn from_class_method(name: Symbol) -> Self {
-        Self(Arc::new(
-            move |receiver: &dyn Any, args: Vec<Term>, host: &mut Host| {
+        Self::from_erased(Arc::new(
+            move |receiver: &dyn Any,
+                  args: Vec<Term>,
+                  host: &mut Host,
+                  _: &QueryContext|
+                  -> crate::Result<Arc<dyn ToPolarResults>> {
+                let class = downcast::<Class>(receiver).map_err(|e| e.invariant())?;
+                tracing::trace!(class = %class.name, method=%name, "class_method");
+                if let Some(class_method) = class.class_methods.get(&name) {
+                    class_method.invoke(args, host)
+                } else if let Some(constructor) = class.constructors.get(&name) {
+                    let instance = class.wrap_instance(constructor.invoke(args, host)?);
+                    Ok(Arc::new(PolarValue::Instance(instance)) as Arc<dyn ToPolarResults>)
+                } else {
+                    Err(InvariantError::MethodNotFound.into())
+                }
+            },
+        ))
+    }
+
+    pub fn from_class_constant(name: Symbol) -> Self {
+        Self::from_erased(Arc::new(
+            move |receiver: &dyn Any, _args: Vec<Term>, host: &mut Host, _: &QueryContext| {
                 downcast::<Class>(receiver)
                     .map_err(|e| e.invariant().into())
                     .and_then(|class| {
-                        tracing::trace!(class = %class.name, method=%name, "class_method");
+                        tracing::trace!(class = %class.name, constant=%name, "class_constant");
                         class
-                            .class_methods
+                            .constants
                             .get(&name)
-                            .ok_or_else(|| InvariantError::MethodNotFound.into())
+                            .ok_or_else(|| crate::OsoError::Custom {
+                                message: format!("{} has no constant {}", class.name, name.0),
+                            })
                     })
-                    .and_then(|class_method: &ClassMethod| class_method.invoke(args, host))
+                    .and_then(|constant: &ClassMethod| constant.invoke(vec![], host))
             },
         ))
     }
 }
 
 #[derive(Clone)]
-pub struct ClassMethod(TypeErasedFunction<dyn ToPolarResults>);
+pub struct ClassMethod(TypeErasedFunction<dyn ToPolarResults>, Option<usize>);
 
 impl ClassMethod {
     pub fn new<F, Args>(f: F) -> Self
@@ -129,10 +272,19 @@ impl ClassMethod {
         F: Function<Args> + 'static,
         F::Result: ToPolarResults + 'static,
     {
-        Self(Arc::new(move |args: Vec<Term>, host: &mut Host| {
-            Args::from_polar_list(&args, host)
-                .map(|args| Arc::new(f.invoke(args)) as Arc<dyn ToPolarResults>)
-        }))
+        Self(
+            Arc::new(move |args: Vec<Term>, host: &mut Host| {
+                Args::from_polar_list(&args, host)
+                    .map(|args| Arc::new(f.invoke(args)) as Arc<dyn ToPolarResults>)
+            }),
+            Args::arity(),
+        )
+    }
+
+    /// The number of arguments the method takes, or `None` if it takes any
+    /// number of them.
+    pub fn arity(&self) -> Option<usize> {
+        self.1
     }
 
     pub fn invoke(
@@ -0,0 +1,96 @@
+//! Conversions registered at runtime, for types of other crates.
+
+use polar_core::terms::*;
+
+use std::any::{type_name, Any, TypeId};
+use std::sync::Arc;
+
+use super::from_polar::FromPolar;
+use super::to_polar::ToPolar;
+use super::value::PolarValue;
+use super::Host;
+
+type ToFn = Arc<dyn Fn(&dyn Any) -> Option<PolarValue> + Send + Sync>;
+type FromFn = Arc<dyn Fn(PolarValue) -> crate::Result<Box<dyn Any>> + Send + Sync>;
+
+/// Functions converting a type to and from Polar, registered with
+/// [`Oso::register_conversion`](crate::Oso::register_conversion).
+#[derive(Clone)]
+pub(crate) struct Conversion {
+    to: ToFn,
+    from: FromFn,
+}
+
+impl Conversion {
+    pub fn new<T, To, From>(to: To, from: From) -> Self
+    where
+        T: 'static,
+        To: Fn(&T) -> PolarValue + Send + Sync + 'static,
+        From: Fn(PolarValue) -> crate::Result<T> + Send + Sync + 'static,
+    {
+        Self {
+            to: Arc::new(move |value| value.downcast_ref::<T>().map(&to)),
+            from: Arc::new(move |value| from(value).map(|value| Box::new(value) as Box<dyn Any>)),
+        }
+    }
+}
+
+fn not_registered<T>() -> crate::OsoError {
+    crate::OsoError::Custom {
+        message: format!("no conversion registered for `{}`", type_name::<T>()),
+    }
+}
+
+/// Converts a type that can't implement `ToPolar` and `FromPolar`, such as
+/// a type of another crate, with the functions registered for it with
+/// [`Oso::register_conversion`](crate::Oso::register_conversion):
+///
+/// ```
+/// use std::num::NonZeroU32;
+/// use oso::{Converted, Oso, PolarValue};
+///
+/// let mut oso = Oso::new();
+/// oso.register_conversion::<NonZeroU32, _, _>(
+///     |n| PolarValue::Integer(n.get().into()),
+///     |value| match value {
+///         PolarValue::Integer(i) => NonZeroU32::new(i as u32).ok_or(oso::OsoError::FromPolar),
+///         _ => Err(oso::OsoError::FromPolar),
+///     },
+/// );
+/// oso.load_str("allow(_, _, n) if n > 1;").unwrap();
+/// assert!(oso.is_allowed("alice", "read", Converted(NonZeroU32::new(2).unwrap())).unwrap());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Converted<T>(pub T);
+
+impl<T: 'static> ToPolar for Converted<T> {
+    /// Panics if no conversion is registered for `T`. Use
+    /// `try_to_polar_value` to handle this case.
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        self.try_to_polar_value(host)
+            .expect("Conversion not registered")
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        let value = host
+            .get_conversion(TypeId::of::<T>())
+            .and_then(|conversion| (conversion.to)(&self.0))
+            .ok_or_else(not_registered::<T>)?;
+        value.try_to_polar_value(host)
+    }
+}
+
+impl<T: 'static> FromPolar for Converted<T> {
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        let conversion = host
+            .get_conversion(TypeId::of::<T>())
+            .cloned()
+            .ok_or_else(not_registered::<T>)?;
+        let value = PolarValue::from_term(term, host)?;
+        let value = (conversion.from)(value)?;
+        value
+            .downcast()
+            .map(|value| Converted(*value))
+            .map_err(|_| crate::OsoError::FromPolar)
+    }
+}
@@ -3,10 +3,12 @@
 
 use polar_core::terms::*;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::hash::Hash;
 
 use super::class::Instance;
+use super::value::PolarValue;
 use super::{Host, HostClass};
 
 pub trait FromPolar: Sized {
@@ -16,15 +18,30 @@ pub trait FromPolar: Sized {
         assert_eq!(terms.len(), 1);
         Self::from_polar(&terms[0], host)
     }
+
+    /// The number of terms `from_polar_list` converts, or `None` if it
+    /// converts any number of them.
+    fn arity() -> Option<usize> {
+        Some(1)
+    }
 }
 
 impl<C: 'static + Clone + HostClass> FromPolar for C {
     fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
         match term.value() {
-            Value::ExternalInstance(ExternalInstance { instance_id, .. }) => host
-                .get_instance(*instance_id)
-                .and_then(|instance| instance.instance.downcast_ref::<C>().cloned())
-                .ok_or_else(|| crate::OsoError::FromPolar),
+            Value::ExternalInstance(ExternalInstance { instance_id, .. }) => {
+                let instance = host
+                    .get_instance(*instance_id)
+                    .ok_or_else(|| crate::OsoError::FromPolar)?;
+                if let Some(instance) = instance.instance.downcast_ref::<C>() {
+                    return Ok(instance.clone());
+                }
+                // Convert instances of classes that `C` coerces from.
+                host.get_class_from_type::<C>()
+                    .and_then(|class| class.coerce(instance))
+                    .and_then(|coerced| coerced.downcast_ref::<C>().cloned())
+                    .ok_or_else(|| crate::OsoError::FromPolar)
+            }
             _ => Err(crate::OsoError::FromPolar),
         }
     }
@@ -45,7 +62,10 @@ macro_rules! polar_to_int {
         impl FromPolar for $i {
             fn from_polar(term: &Term, _host: &mut Host) -> crate::Result<Self> {
                 if let Value::Number(Numeric::Integer(i)) = term.value() {
-                    <$i>::try_from(*i).map_err(|_| crate::OsoError::FromPolar)
+                    <$i>::try_from(*i).map_err(|_| crate::OsoError::IntegerOverflow {
+                        value: i.to_string(),
+                        target: stringify!($i).to_string(),
+                    })
                 } else {
                     Err(crate::OsoError::FromPolar)
                 }
@@ -61,6 +81,11 @@ polar_to_int!(i16);
 polar_to_int!(u32);
 polar_to_int!(i32);
 polar_to_int!(i64);
+polar_to_int!(u64);
+polar_to_int!(usize);
+polar_to_int!(isize);
+polar_to_int!(u128);
+polar_to_int!(i128);
 
 impl FromPolar for f64 {
     fn from_polar(term: &Term, _host: &mut Host) -> crate::Result<Self> {
@@ -72,6 +97,46 @@ impl FromPolar for f64 {
     }
 }
 
+/// Seconds, as an integer or a float, within the range of `Duration`.
+fn seconds(term: &Term) -> crate::Result<f64> {
+    let secs = match term.value() {
+        Value::Number(Numeric::Integer(i)) => *i as f64,
+        Value::Number(Numeric::Float(f)) => *f,
+        _ => return Err(crate::OsoError::FromPolar),
+    };
+    if secs.abs() < u64::MAX as f64 {
+        Ok(secs)
+    } else {
+        Err(crate::OsoError::FromPolar)
+    }
+}
+
+/// A non-negative number of seconds.
+impl FromPolar for std::time::Duration {
+    fn from_polar(term: &Term, _host: &mut Host) -> crate::Result<Self> {
+        match seconds(term)? {
+            secs if secs >= 0.0 => Ok(Self::from_secs_f64(secs)),
+            _ => Err(crate::OsoError::FromPolar),
+        }
+    }
+}
+
+/// A number of seconds since the Unix epoch.
+impl FromPolar for std::time::SystemTime {
+    fn from_polar(term: &Term, _host: &mut Host) -> crate::Result<Self> {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let secs = seconds(term)?;
+        let offset = Duration::from_secs_f64(secs.abs());
+        if secs >= 0.0 {
+            UNIX_EPOCH.checked_add(offset)
+        } else {
+            UNIX_EPOCH.checked_sub(offset)
+        }
+        .ok_or(crate::OsoError::FromPolar)
+    }
+}
+
 impl FromPolar for String {
     fn from_polar(term: &Term, _host: &mut Host) -> crate::Result<Self> {
         if let Value::String(s) = term.value() {
@@ -94,6 +159,10 @@ impl<T: FromPolar> FromPolar for Vec<T> {
     fn from_polar_list(terms: &[Term], host: &mut Host) -> crate::Result<Self> {
         terms.iter().map(|t| T::from_polar(t, host)).collect()
     }
+
+    fn arity() -> Option<usize> {
+        None
+    }
 }
 
 impl<T: FromPolar> FromPolar for HashMap<String, T> {
@@ -109,12 +178,69 @@ impl<T: FromPolar> FromPolar for HashMap<String, T> {
     }
 }
 
+impl<T: FromPolar> FromPolar for Option<T> {
+    /// `nil` converts to `None`, anything else is converted to `T`.
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        let is_nil = match term.value() {
+            Value::ExternalInstance(ExternalInstance { instance_id, .. }) => host
+                .get_instance(*instance_id)
+                .and_then(|instance| instance.instance.downcast_ref::<Option<PolarValue>>())
+                .map_or(false, Option::is_none),
+            _ => false,
+        };
+        if is_nil {
+            Ok(None)
+        } else {
+            T::from_polar(term, host).map(Some)
+        }
+    }
+}
+
+impl<T: FromPolar> FromPolar for BTreeMap<String, T> {
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        if let Value::Dictionary(dict) = term.value() {
+            dict.fields
+                .iter()
+                .map(|(k, v)| T::from_polar(v, host).map(|v| (k.0.clone(), v)))
+                .collect()
+        } else {
+            Err(crate::OsoError::FromPolar)
+        }
+    }
+}
+
+impl<T: FromPolar + Eq + Hash> FromPolar for HashSet<T> {
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        if let Value::List(l) = term.value() {
+            l.iter().map(|t| T::from_polar(t, host)).collect()
+        } else {
+            Err(crate::OsoError::FromPolar)
+        }
+    }
+}
+
+impl<T: FromPolar> FromPolar for VecDeque<T> {
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        if let Value::List(l) = term.value() {
+            l.iter().map(|t| T::from_polar(t, host)).collect()
+        } else {
+            Err(crate::OsoError::FromPolar)
+        }
+    }
+}
+
 impl FromPolar for Value {
     fn from_polar(term: &Term, _host: &mut Host) -> crate::Result<Self> {
         Ok(term.value().clone())
     }
 }
 
+impl FromPolar for PolarValue {
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        PolarValue::from_term(term, host)
+    }
+}
+
 impl FromPolar for Instance {
     fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
         let instance = match term.value().clone() {
@@ -144,7 +270,7 @@ impl FromPolar for Instance {
                 .cast_to_instance(d.fields),
             Value::ExternalInstance(ExternalInstance { instance_id, .. }) => host
                 .get_instance(instance_id)
-                .expect("instance not found")
+                .ok_or(crate::OsoError::MissingInstance { id: instance_id })?
                 .clone(),
             v => {
                 tracing::warn!(value = ?v, "invalid conversion attempted");
@@ -175,41 +301,54 @@ impl FromPolar for () {
             Err(crate::OsoError::FromPolar)
         }
     }
-}
 
-impl<A> FromPolar for (A,)
-where
-    A: FromPolar,
-{
-    fn from_polar(_term: &Term, _host: &mut Host) -> crate::Result<Self> {
-        Err(crate::OsoError::FromPolar)
-    }
-
-    fn from_polar_list(terms: &[Term], host: &mut Host) -> crate::Result<Self> {
-        if terms.len() == 1 {
-            A::from_polar(&terms[0], host).map(|a| (a,))
-        } else {
-            Err(crate::OsoError::FromPolar)
-        }
+    fn arity() -> Option<usize> {
+        Some(0)
     }
 }
 
-impl<A, B> FromPolar for (A, B)
-where
-    A: FromPolar,
-    B: FromPolar,
-{
-    fn from_polar(_term: &Term, _host: &mut Host) -> crate::Result<Self> {
-        Err(crate::OsoError::FromPolar)
-    }
+/// Implement `FromPolar` for tuples, which are converted from
+/// an argument list of the same length.
+macro_rules! tuple_from_polar {
+    ( $( $name:ident )+ ) => {
+        impl<$($name),+> FromPolar for ($($name,)+)
+        where
+            $($name: FromPolar),+
+        {
+            fn from_polar(_term: &Term, _host: &mut Host) -> crate::Result<Self> {
+                Err(crate::OsoError::FromPolar)
+            }
 
-    fn from_polar_list(terms: &[Term], host: &mut Host) -> crate::Result<Self> {
-        if terms.len() == 2 {
-            let a = A::from_polar(&terms[0], host)?;
-            let b = B::from_polar(&terms[1], host)?;
-            Ok((a, b))
-        } else {
-            Err(crate::OsoError::FromPolar)
+            fn from_polar_list(terms: &[Term], host: &mut Host) -> crate::Result<Self> {
+                let mut terms = terms.iter();
+                let result = ($(
+                    <$name as FromPolar>::from_polar(
+                        terms.next().ok_or(crate::OsoError::FromPolar)?,
+                        host,
+                    )?,
+                )+);
+                if terms.next().is_some() {
+                    return Err(crate::OsoError::FromPolar);
+                }
+                Ok(result)
+            }
+
+            fn arity() -> Option<usize> {
+                Some([$(stringify!($name)),+].len())
+            }
         }
-    }
+    };
 }
+
+tuple_from_polar! { A }
+tuple_from_polar! { A B }
+tuple_from_polar! { A B C }
+tuple_from_polar! { A B C D }
+tuple_from_polar! { A B C D E }
+tuple_from_polar! { A B C D E F }
+tuple_from_polar! { A B C D E F G }
+tuple_from_polar! { A B C D E F G H }
+tuple_from_polar! { A B C D E F G H I }
+tuple_from_polar! { A B C D E F G H I J }
+tuple_from_polar! { A B C D E F G H I J K }
+tuple_from_polar! { A B C D E F G H I J K L }
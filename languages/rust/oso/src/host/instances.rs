@@ -0,0 +1,222 @@
+//! The cache of instances passed to Polar, and its eviction policy.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use super::class::Instance;
+
+/// How long instances passed to Polar are kept by the host.
+///
+/// Instances registered as constants, including classes, are pinned and
+/// never evicted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceCachePolicy {
+    /// Keep every instance for the lifetime of the `Oso` object. This is
+    /// the default, and suits interactive sessions such as the REPL.
+    KeepAll,
+    /// Keep at most `max_entries` unpinned instances, evicting the least
+    /// recently used ones. Instances used by running queries are not
+    /// evicted, so the cache may exceed `max_entries` while they run.
+    Lru { max_entries: usize },
+    /// Evict the unpinned instances a query created once the query and
+    /// its results are dropped, and all unpinned instances once no query
+    /// is running.
+    PerQuery,
+}
+
+impl Default for InstanceCachePolicy {
+    fn default() -> Self {
+        Self::KeepAll
+    }
+}
+
+/// A snapshot of the instance cache, e.g. to export as metrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InstanceCacheStats {
+    /// The number of cached instances, including pinned ones.
+    pub size: usize,
+    /// The number of pinned instances, which are never evicted.
+    pub pinned: usize,
+    /// The largest `size` seen.
+    pub peak_size: usize,
+    /// The number of lookups of cached instances.
+    pub hits: u64,
+    /// The number of lookups of instances that were not cached, e.g.
+    /// because they were evicted.
+    pub misses: u64,
+    /// The number of instances evicted.
+    pub evictions: u64,
+}
+
+/// Called with the id of each evicted instance.
+pub type EvictionHook = Arc<dyn Fn(u64, &Instance) + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct InstanceCache {
+    policy: InstanceCachePolicy,
+    instances: HashMap<u64, Instance>,
+    pinned: HashSet<u64>,
+    /// Recency of unpinned instances, as a map from use to id and back.
+    /// Updated on lookup, hence the `RefCell`.
+    recency: RefCell<(BTreeMap<u64, u64>, HashMap<u64, u64>)>,
+    clock: Cell<u64>,
+    /// Number of queries and result sets that may still refer to instances.
+    live_queries: usize,
+    /// Identifies the next query to start.
+    next_query: u64,
+    /// The query the instances being cached belong to, if any.
+    owner: Option<u64>,
+    /// The unpinned instances cached for each live query, by query.
+    owned: HashMap<u64, Vec<u64>>,
+    on_evict: Option<EvictionHook>,
+    /// Counts of lookups and evictions, updated on lookup, hence the `Cell`.
+    stats: Cell<InstanceCacheStats>,
+}
+
+impl InstanceCache {
+    pub fn set_policy(&mut self, policy: InstanceCachePolicy) {
+        self.policy = policy;
+        match policy {
+            InstanceCachePolicy::KeepAll => {}
+            InstanceCachePolicy::Lru { max_entries } => self.evict(max_entries),
+            InstanceCachePolicy::PerQuery if self.live_queries == 0 => self.evict(0),
+            InstanceCachePolicy::PerQuery => {}
+        }
+    }
+
+    pub fn set_eviction_hook(&mut self, hook: EvictionHook) {
+        self.on_evict = Some(hook);
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Instance> {
+        let mut stats = self.stats.get();
+        let instance = self.instances.get(&id);
+        match instance {
+            Some(_) => stats.hits += 1,
+            None => stats.misses += 1,
+        }
+        self.stats.set(stats);
+        if instance.is_some() && !self.pinned.contains(&id) {
+            self.touch(id);
+        }
+        instance
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.instances.contains_key(&id)
+    }
+
+    pub fn insert(&mut self, id: u64, instance: Instance) {
+        self.instances.insert(id, instance);
+        let stats = self.stats.get_mut();
+        stats.peak_size = stats.peak_size.max(self.instances.len());
+        if !self.pinned.contains(&id) {
+            self.touch(id);
+            if let Some(owner) = self.owner {
+                self.owned.entry(owner).or_default().push(id);
+            }
+            if let InstanceCachePolicy::Lru { max_entries } = self.policy {
+                self.evict(max_entries);
+            }
+        }
+    }
+
+    /// Exempt instance `id` from eviction.
+    pub fn pin(&mut self, id: u64) {
+        self.pinned.insert(id);
+        let mut recency = self.recency.borrow_mut();
+        if let Some(used) = recency.1.remove(&id) {
+            recency.0.remove(&used);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn stats(&self) -> InstanceCacheStats {
+        InstanceCacheStats {
+            size: self.instances.len(),
+            pinned: self.pinned.len(),
+            ..self.stats.get()
+        }
+    }
+
+    /// Returns an id for the query, to attribute the instances cached for
+    /// it with `set_owner`.
+    pub fn start_query(&mut self) -> u64 {
+        self.live_queries += 1;
+        self.next_query += 1;
+        self.next_query
+    }
+
+    pub fn end_query(&mut self, query: u64) {
+        self.live_queries -= 1;
+        let owned = self.owned.remove(&query).unwrap_or_default();
+        if self.policy == InstanceCachePolicy::PerQuery {
+            if self.live_queries == 0 {
+                self.evict(0);
+            } else {
+                for id in owned {
+                    if !self.pinned.contains(&id) {
+                        self.remove(id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Attribute the instances cached from now on to `query`.
+    pub fn set_owner(&mut self, query: Option<u64>) {
+        self.owner = query;
+    }
+
+    fn touch(&self, id: u64) {
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        let mut recency = self.recency.borrow_mut();
+        if let Some(used) = recency.1.insert(id, now) {
+            recency.0.remove(&used);
+        }
+        recency.0.insert(now, id);
+    }
+
+    /// Evict the least recently used unpinned instances until at most
+    /// `max_entries` remain. Instances cached for live queries are kept,
+    /// since the queries may still refer to them.
+    fn evict(&mut self, max_entries: usize) {
+        let live: HashSet<u64> = self.owned.values().flatten().copied().collect();
+        let evicted: Vec<u64> = {
+            let recency = self.recency.borrow();
+            let excess = recency.1.len().saturating_sub(max_entries);
+            recency
+                .0
+                .values()
+                .filter(|id| !live.contains(id))
+                .take(excess)
+                .copied()
+                .collect()
+        };
+        for id in evicted {
+            self.remove(id);
+        }
+    }
+
+    /// Evict the instance `id`.
+    fn remove(&mut self, id: u64) {
+        {
+            let mut recency = self.recency.borrow_mut();
+            if let Some(used) = recency.1.remove(&id) {
+                recency.0.remove(&used);
+            }
+        }
+        if let Some(instance) = self.instances.remove(&id) {
+            tracing::trace!(id, instance = %instance.name, "evict");
+            self.stats.get_mut().evictions += 1;
+            if let Some(hook) = &self.on_evict {
+                hook(id, &instance);
+            }
+        }
+    }
+}
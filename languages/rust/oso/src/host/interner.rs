@@ -0,0 +1,21 @@
+//! Small ids for the names of classes, attributes and methods, so that
+//! caches keyed by them don't allocate on every external call.
+
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub(crate) struct Interner {
+    ids: HashMap<String, u32>,
+}
+
+impl Interner {
+    /// The id of `name`, allocated the first time it is interned.
+    pub fn intern(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.ids.len() as u32;
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+}
@@ -0,0 +1,112 @@
+//! Conversions between `serde_json::Value` and Polar values.
+//!
+//! Objects convert to dictionaries, arrays to lists and `null` to `nil`.
+//! Numbers convert to integers when they fit in an `i64`, and to floats
+//! otherwise.
+//!
+//! [`PolarSerde`] extends these conversions to any type implementing
+//! `Serialize` or `Deserialize`.
+
+use polar_core::terms::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Number, Value as Json};
+
+use super::from_polar::FromPolar;
+use super::to_polar::ToPolar;
+use super::value::PolarValue;
+use super::Host;
+
+impl ToPolar for Json {
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        match self {
+            Json::Null => PolarValue::nil().to_value(host),
+            Json::Bool(b) => Value::Boolean(*b),
+            Json::Number(n) => match n.as_i64() {
+                Some(i) => Value::Number(Numeric::Integer(i)),
+                None => Value::Number(Numeric::Float(n.as_f64().unwrap_or(f64::NAN))),
+            },
+            Json::String(s) => Value::String(s.clone()),
+            Json::Array(a) => Value::List(a.iter().map(|v| v.to_polar(host)).collect()),
+            Json::Object(o) => Value::Dictionary(Dictionary {
+                fields: o
+                    .iter()
+                    .map(|(k, v)| (Symbol(k.clone()), v.to_polar(host)))
+                    .collect(),
+            }),
+        }
+    }
+}
+
+impl FromPolar for Json {
+    /// Fails for values with no JSON equivalent, such as application
+    /// instances and floats that are not finite.
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        match term.value() {
+            Value::Boolean(b) => Ok(Json::Bool(*b)),
+            Value::Number(Numeric::Integer(i)) => Ok(Json::Number((*i).into())),
+            Value::Number(Numeric::Float(f)) => Number::from_f64(*f)
+                .map(Json::Number)
+                .ok_or(crate::OsoError::FromPolar),
+            Value::String(s) => Ok(Json::String(s.clone())),
+            Value::List(l) => l
+                .iter()
+                .map(|t| Json::from_polar(t, host))
+                .collect::<crate::Result<_>>()
+                .map(Json::Array),
+            Value::Dictionary(dict) => dict
+                .fields
+                .iter()
+                .map(|(k, v)| Json::from_polar(v, host).map(|v| (k.0.clone(), v)))
+                .collect::<crate::Result<Map<_, _>>>()
+                .map(Json::Object),
+            Value::ExternalInstance(_) => match Option::<PolarValue>::from_polar(term, host) {
+                Ok(None) => Ok(Json::Null),
+                _ => Err(crate::OsoError::FromPolar),
+            },
+            _ => Err(crate::OsoError::FromPolar),
+        }
+    }
+}
+
+/// Converts a plain data type to and from Polar through its `serde`
+/// implementations, without registering it as a class.
+///
+/// The value is passed to Polar as it would be serialized to JSON, so a
+/// struct becomes a dictionary of its fields:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct User { name: String, roles: Vec<String> }
+///
+/// oso.is_allowed(PolarSerde(user), "read", resource)?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolarSerde<T>(pub T);
+
+impl<T: Serialize> ToPolar for PolarSerde<T> {
+    /// Panics if `T` fails to serialize, e.g. a map with non-string keys.
+    /// Use `try_to_polar_value` to handle this case.
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        self.try_to_polar_value(host)
+            .expect("failed to serialize value for Polar")
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        let json = serde_json::to_value(&self.0).map_err(|e| crate::OsoError::Custom {
+            message: format!("failed to serialize value for Polar: {}", e),
+        })?;
+        Ok(json.to_polar_value(host))
+    }
+}
+
+impl<T: DeserializeOwned> FromPolar for PolarSerde<T> {
+    fn from_polar(term: &Term, host: &mut Host) -> crate::Result<Self> {
+        let json = Json::from_polar(term, host)?;
+        serde_json::from_value(json)
+            .map(PolarSerde)
+            .map_err(|e| crate::OsoError::Custom {
+                message: format!("failed to deserialize value from Polar: {}", e),
+            })
+    }
+}
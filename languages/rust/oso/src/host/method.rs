@@ -1,6 +1,8 @@
 //! Traits to help with passing around methods of arbitrary arities
 //! and to help downcast+convert the arguments.
 
+use crate::Context;
+
 /// An alternate version of the `Fn` trait
 /// which encodes the types of the arguments
 /// in a single type - a tuple.
@@ -21,28 +23,6 @@ where
     }
 }
 
-impl<A, F, R> Function<(A,)> for F
-where
-    F: Fn(A) -> R + Send + Sync,
-{
-    type Result = R;
-
-    fn invoke(&self, arg: (A,)) -> Self::Result {
-        (self)(arg.0)
-    }
-}
-
-impl<A, B, F, R> Function<(A, B)> for F
-where
-    F: Fn(A, B) -> R + Send + Sync,
-{
-    type Result = R;
-
-    fn invoke(&self, args: (A, B)) -> Self::Result {
-        (self)(args.0, args.1)
-    }
-}
-
 /// Similar to a `Function` but also takes an explicit `receiver`
 /// parameter than is the first argument of the call (i.e. the `self` param);
 pub trait Method<Receiver, Args = ()>: Send + Sync {
@@ -62,24 +42,110 @@ where
     }
 }
 
-impl<A, F, R, Receiver> Method<Receiver, (A,)> for F
+/// A `Method` that takes its `receiver` mutably.
+pub trait MutMethod<Receiver, Args = ()>: Send + Sync {
+    type Result;
+
+    fn invoke(&self, receiver: &mut Receiver, args: Args) -> Self::Result;
+}
+
+impl<F, R, Receiver> MutMethod<Receiver, ()> for F
 where
-    F: Fn(&Receiver, A) -> R + Send + Sync,
+    F: Fn(&mut Receiver) -> R + Send + Sync,
 {
     type Result = R;
 
-    fn invoke(&self, receiver: &Receiver, arg: (A,)) -> Self::Result {
-        (self)(receiver, arg.0)
+    fn invoke(&self, receiver: &mut Receiver, _: ()) -> Self::Result {
+        (self)(receiver)
     }
 }
 
-impl<A, B, F, R, Receiver> Method<Receiver, (A, B)> for F
+/// A `Method` that also takes the [`Context`] of the query calling it,
+/// after its `receiver`.
+pub trait ContextMethod<Receiver, Args = ()>: Send + Sync {
+    type Result;
+
+    fn invoke(&self, receiver: &Receiver, context: &Context, args: Args) -> Self::Result;
+}
+
+impl<F, R, Receiver> ContextMethod<Receiver, ()> for F
 where
-    F: Fn(&Receiver, A, B) -> R + Send + Sync,
+    F: Fn(&Receiver, &Context) -> R + Send + Sync,
 {
     type Result = R;
 
-    fn invoke(&self, receiver: &Receiver, args: (A, B)) -> Self::Result {
-        (self)(receiver, args.0, args.1)
+    fn invoke(&self, receiver: &Receiver, context: &Context, _: ()) -> Self::Result {
+        (self)(receiver, context)
     }
 }
+
+/// Implement `Function`, `Method`, `MutMethod` and `ContextMethod` for
+/// closures taking the given argument types.
+macro_rules! tuple_impls {
+    ( $( $name:ident )+ ) => {
+        impl<Fun, Res, $($name),+> Function<($($name,)+)> for Fun
+        where
+            Fun: Fn($($name),+) -> Res + Send + Sync,
+        {
+            type Result = Res;
+
+            #[allow(non_snake_case)]
+            fn invoke(&self, args: ($($name,)+)) -> Self::Result {
+                let ($($name,)+) = args;
+                (self)($($name),+)
+            }
+        }
+
+        impl<Fun, Res, Receiver, $($name),+> Method<Receiver, ($($name,)+)> for Fun
+        where
+            Fun: Fn(&Receiver, $($name),+) -> Res + Send + Sync,
+        {
+            type Result = Res;
+
+            #[allow(non_snake_case)]
+            fn invoke(&self, receiver: &Receiver, args: ($($name,)+)) -> Self::Result {
+                let ($($name,)+) = args;
+                (self)(receiver, $($name),+)
+            }
+        }
+
+        impl<Fun, Res, Receiver, $($name),+> MutMethod<Receiver, ($($name,)+)> for Fun
+        where
+            Fun: Fn(&mut Receiver, $($name),+) -> Res + Send + Sync,
+        {
+            type Result = Res;
+
+            #[allow(non_snake_case)]
+            fn invoke(&self, receiver: &mut Receiver, args: ($($name,)+)) -> Self::Result {
+                let ($($name,)+) = args;
+                (self)(receiver, $($name),+)
+            }
+        }
+
+        impl<Fun, Res, Receiver, $($name),+> ContextMethod<Receiver, ($($name,)+)> for Fun
+        where
+            Fun: Fn(&Receiver, &Context, $($name),+) -> Res + Send + Sync,
+        {
+            type Result = Res;
+
+            #[allow(non_snake_case)]
+            fn invoke(&self, receiver: &Receiver, context: &Context, args: ($($name,)+)) -> Self::Result {
+                let ($($name,)+) = args;
+                (self)(receiver, context, $($name),+)
+            }
+        }
+    };
+}
+
+tuple_impls! { A }
+tuple_impls! { A B }
+tuple_impls! { A B C }
+tuple_impls! { A B C D }
+tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
+tuple_impls! { A B C D E F G }
+tuple_impls! { A B C D E F G H }
+tuple_impls! { A B C D E F G H I }
+tuple_impls! { A B C D E F G H I J }
+tuple_impls! { A B C D E F G H I J K }
+tuple_impls! { A B C D E F G H I J K L }
@@ -1,6 +1,7 @@
 use std::any::Any;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use polar_core::terms::{ExternalInstance, Numeric, Operator, Symbol, Term, Value};
 
@@ -9,13 +10,29 @@ use crate::Polar;
 
 mod class;
 mod class_method;
+mod conversion;
 mod from_polar;
+mod instances;
+mod interner;
+#[cfg(feature = "json")]
+mod json;
 mod method;
+mod shared;
 mod to_polar;
+mod value;
 
 pub use class::{Class, Instance};
+pub(crate) use class::{LocalInstance, Receiver};
+pub(crate) use conversion::Conversion;
+pub use conversion::Converted;
 pub use from_polar::FromPolar;
+pub use instances::{EvictionHook, InstanceCachePolicy, InstanceCacheStats};
+#[cfg(feature = "json")]
+pub use json::PolarSerde;
+pub use shared::Shared;
+pub(crate) use to_polar::ToPolarResults;
 pub use to_polar::{PolarResultIter, ToPolar};
+pub use value::PolarValue;
 
 /// The meta class - the class of all classess (except itself)
 #[derive(Clone, Default)]
@@ -45,12 +62,31 @@ pub struct Host {
     classes: HashMap<Symbol, Class>,
 
     /// Map of cached instances
-    instances: HashMap<u64, class::Instance>,
+    instances: instances::InstanceCache,
 
     /// Map from type IDs, to class names
     /// This helps us go from a generic type `T` to the
     /// class name it is registered as
     class_names: HashMap<std::any::TypeId, Symbol>,
+
+    /// Map from deprecated class names to the names they were renamed to
+    aliases: HashMap<Symbol, Symbol>,
+
+    /// Aliases that a deprecation warning was logged for
+    used_aliases: RefCell<HashSet<Symbol>>,
+
+    /// Incremented whenever a class or alias is registered
+    generation: u64,
+
+    /// The generation each class name was registered at
+    class_generations: HashMap<Symbol, u64>,
+
+    /// Conversions of types that don't implement `ToPolar` and
+    /// `FromPolar`, by type
+    conversions: HashMap<std::any::TypeId, Conversion>,
+
+    /// Ids of the names of classes, attributes and methods called
+    symbols: interner::Interner,
 }
 
 impl Host {
@@ -58,7 +94,13 @@ impl Host {
         let mut host = Self {
             class_names: HashMap::new(),
             classes: HashMap::new(),
-            instances: HashMap::new(),
+            aliases: HashMap::new(),
+            used_aliases: RefCell::new(HashSet::new()),
+            generation: 0,
+            class_generations: HashMap::new(),
+            conversions: HashMap::new(),
+            symbols: interner::Interner::default(),
+            instances: instances::InstanceCache::default(),
             polar,
         };
         let type_class = type_class();
@@ -72,12 +114,86 @@ impl Host {
     }
 
     pub fn get_class(&self, name: &Symbol) -> Option<&Class> {
-        self.classes.get(name)
+        self.classes.get(name).or_else(|| {
+            let class = self.aliases.get(name)?;
+            if self.used_aliases.borrow_mut().insert(name.clone()) {
+                tracing::warn!(
+                    alias = %name.0,
+                    class = %class.0,
+                    "class `{}` is deprecated, use `{}` instead",
+                    name.0,
+                    class.0
+                );
+            }
+            self.classes.get(class)
+        })
+    }
+
+    /// The class registered as `name`, or aliased as `name`, without the
+    /// deprecation warning of [`Host::get_class`].
+    pub(crate) fn resolve_class(&self, name: &Symbol) -> Option<&Class> {
+        self.classes
+            .get(name)
+            .or_else(|| self.classes.get(self.aliases.get(name)?))
+    }
+
+    pub(crate) fn add_conversion(&mut self, type_id: std::any::TypeId, conversion: Conversion) {
+        self.conversions.insert(type_id, conversion);
+    }
+
+    pub(crate) fn get_conversion(&self, type_id: std::any::TypeId) -> Option<&Conversion> {
+        self.conversions.get(&type_id)
+    }
+
+    /// Make `alias` another name of the class registered as `class`.
+    ///
+    /// Returns the class.
+    pub fn add_alias(&mut self, alias: Symbol, class: Symbol) -> crate::Result<Class> {
+        if self.classes.contains_key(&alias) {
+            return lazy_error!(
+                "cannot alias `{}`: a class of that name is registered",
+                alias.0
+            );
+        }
+        let target = match self.classes.get(&class) {
+            Some(target) => target.clone(),
+            None => return lazy_error!("cannot alias unregistered class `{}`", class.0),
+        };
+        self.record_generation(&alias);
+        self.aliases.insert(alias, class);
+        Ok(target)
+    }
+
+    fn record_generation(&mut self, name: &Symbol) {
+        self.generation += 1;
+        self.class_generations.insert(name.clone(), self.generation);
+    }
+
+    /// The current registration generation. Queries only see the classes
+    /// registered up to the generation at which they were created.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The id of the class, attribute or method name `name`.
+    pub(crate) fn intern(&mut self, name: &str) -> u32 {
+        self.symbols.intern(name)
+    }
+
+    /// Whether the class `name` was registered by `generation`.
+    pub fn is_visible(&self, name: &Symbol, generation: u64) -> bool {
+        self.class_generations
+            .get(name)
+            .map_or(true, |registered| *registered <= generation)
     }
 
     pub fn get_class_from_type<C: 'static>(&self) -> Option<&Class> {
+        self.get_class_by_type_id(std::any::TypeId::of::<C>())
+    }
+
+    pub fn get_class_by_type_id(&self, type_id: std::any::TypeId) -> Option<&Class> {
         self.class_names
-            .get(&std::any::TypeId::of::<C>())
+            .get(&type_id)
             .and_then(|name| self.get_class(name))
     }
 
@@ -89,13 +205,117 @@ impl Host {
     ///
     /// Returns an instance of `Type` for this class.
     pub fn cache_class(&mut self, class: Class, name: Symbol) -> String {
+        self.record_generation(&name);
         self.class_names.insert(class.type_id, name.clone());
-        self.classes.insert(name.clone(), class);
+        let type_id = class.type_id;
+        if let Some(replaced) = self.classes.insert(name.clone(), class) {
+            if replaced.type_id != type_id && self.class_names.get(&replaced.type_id) == Some(&name)
+            {
+                self.class_names.remove(&replaced.type_id);
+            }
+        }
         name.0
     }
 
+    /// Remove the class registered as `name`, along with its aliases.
+    ///
+    /// Returns the names that no longer refer to a class.
+    pub fn remove_class(&mut self, name: &Symbol) -> Option<Vec<Symbol>> {
+        let class = self.classes.remove(name)?;
+        if self.class_names.get(&class.type_id) == Some(name) {
+            self.class_names.remove(&class.type_id);
+        }
+        let mut removed = vec![name.clone()];
+        self.aliases.retain(|alias, target| {
+            let keep = target != name;
+            if !keep {
+                removed.push(alias.clone());
+            }
+            keep
+        });
+        for name in removed.iter() {
+            self.class_generations.remove(name);
+        }
+        Some(removed)
+    }
+
+    pub fn is_registered(&self, name: &Symbol) -> bool {
+        self.classes.contains_key(name)
+    }
+
+    /// Describe the registered classes as a JSON Schema document, with a
+    /// definition for each class. Attributes are listed as properties, and
+    /// methods, class methods and constants in the `x-polar-methods`,
+    /// `x-polar-class-methods` and `x-polar-constants` extensions. Methods
+    /// are described by their arity, which is `null` if they take any
+    /// number of arguments. Aliases refer to the definition of their class.
+    #[cfg(feature = "json")]
+    pub fn export_schema(&self) -> serde_json::Value {
+        use serde_json::{json, Map, Value as Json};
+
+        fn arities<'a, I>(methods: I) -> Map<String, Json>
+        where
+            I: Iterator<Item = (&'a Symbol, Option<usize>)>,
+        {
+            methods
+                .map(|(name, arity)| (name.0.clone(), json!({ "arity": arity })))
+                .collect()
+        }
+
+        let mut definitions: Map<String, Json> = self
+            .classes
+            .iter()
+            .map(|(name, class)| {
+                let properties: Map<String, Json> = class
+                    .attributes
+                    .keys()
+                    .map(|name| (name.0.clone(), json!({})))
+                    .collect();
+                let methods = arities(
+                    class
+                        .instance_methods
+                        .iter()
+                        .map(|(name, method)| (name, method.arity())),
+                );
+                let class_methods = arities(
+                    class
+                        .class_methods
+                        .iter()
+                        .map(|(name, method)| (name, method.arity()))
+                        .chain(class.constructors.keys().map(|name| (name, None))),
+                );
+                let mut constants: Vec<&str> =
+                    class.constants.keys().map(|name| name.0.as_str()).collect();
+                constants.sort_unstable();
+                let schema = json!({
+                    "type": "object",
+                    "properties": properties,
+                    "x-polar-methods": methods,
+                    "x-polar-class-methods": class_methods,
+                    "x-polar-constants": constants,
+                });
+                (name.0.clone(), schema)
+            })
+            .collect();
+        for (alias, class) in self.aliases.iter() {
+            definitions.insert(
+                alias.0.clone(),
+                json!({ "$ref": format!("#/definitions/{}", class.0) }),
+            );
+        }
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "definitions": definitions,
+        })
+    }
+
     pub fn get_instance(&self, id: u64) -> Option<&class::Instance> {
-        self.instances.get(&id)
+        self.instances.get(id)
+    }
+
+    /// A new id for an instance that is not cached in the host.
+    pub(crate) fn new_instance_id(&self) -> u64 {
+        self.polar.get_external_id()
     }
 
     pub fn cache_instance(&mut self, instance: class::Instance, id: Option<u64>) -> u64 {
@@ -104,17 +324,47 @@ impl Host {
         id
     }
 
+    /// Exempt the instances in `term` from eviction, e.g. because
+    /// `term` is the value of a constant.
+    pub fn pin_instances(&mut self, term: &Term) {
+        match term.value() {
+            Value::ExternalInstance(ExternalInstance { instance_id, .. }) => {
+                self.instances.pin(*instance_id)
+            }
+            Value::List(terms) => terms.iter().for_each(|t| self.pin_instances(t)),
+            Value::Dictionary(dict) => dict.fields.values().for_each(|t| self.pin_instances(t)),
+            _ => {}
+        }
+    }
+
+    pub fn set_instance_cache_policy(&mut self, policy: InstanceCachePolicy) {
+        self.instances.set_policy(policy);
+    }
+
+    pub fn set_eviction_hook(&mut self, hook: EvictionHook) {
+        self.instances.set_eviction_hook(hook);
+    }
+
+    pub fn cached_instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn instance_cache_stats(&self) -> InstanceCacheStats {
+        self.instances.stats()
+    }
+
     pub fn make_instance(
         &mut self,
         name: &Symbol,
         fields: Vec<Term>,
+        kwargs: Option<BTreeMap<Symbol, Term>>,
         id: u64,
     ) -> crate::Result<()> {
         // @TODO: Handle the error if the class doesn't exist.
         let class = self.get_class(name).unwrap().clone();
-        debug_assert!(self.instances.get(&id).is_none());
+        debug_assert!(!self.instances.contains(id));
         let fields = fields; // TODO: use
-        let instance = class.init(fields, self)?;
+        let instance = class.init(fields, kwargs, self)?;
         self.cache_instance(instance, Some(id));
         Ok(())
     }
@@ -122,18 +372,30 @@ impl Host {
     pub fn unify(&self, left: u64, right: u64) -> crate::Result<bool> {
         tracing::trace!("unify {:?}, {:?}", left, right);
 
-        let left = self.get_instance(left).unwrap();
-        let right = self.get_instance(right).unwrap();
+        let left = self.cached_instance(left)?;
+        let right = self.cached_instance(right)?;
         left.equals(right)
     }
 
-    pub fn isa(&self, term: Term, class_tag: &Symbol) -> bool {
+    /// The cached instance `id`, or an error if it is not cached, e.g.
+    /// because it was evicted.
+    fn cached_instance(&self, id: u64) -> crate::Result<&class::Instance> {
+        self.get_instance(id)
+            .ok_or(OsoError::MissingInstance { id })
+    }
+
+    pub fn isa(&self, term: Term, class_tag: &Symbol) -> crate::Result<bool> {
         let name = &class_tag.0;
-        match term.value() {
+        let res = match term.value() {
             Value::ExternalInstance(ExternalInstance { instance_id, .. }) => {
-                let class = self.get_class(class_tag).unwrap();
-                let instance = self.get_instance(*instance_id).unwrap();
+                let class = match self.get_class(class_tag) {
+                    Some(class) => class,
+                    None => return Ok(false),
+                };
+                let instance = self.cached_instance(*instance_id)?;
                 class.is_instance(instance)
+                    || instance.class.is_subclass_of(class)
+                    || class.coerces(instance)
             }
             Value::Boolean(_) => name == "Boolean",
             Value::Dictionary(_) => name == "Dictionary",
@@ -147,22 +409,116 @@ impl Host {
             }
             Value::String(_) => name == "String",
             _ => false,
+        };
+        Ok(res)
+    }
+
+    /// Rules are only compared for instances that match both classes, so
+    /// the left class is more specific exactly if it is a subclass of the
+    /// right.
+    pub fn is_subspecializer(&self, _id: u64, left_tag: &Symbol, right_tag: &Symbol) -> bool {
+        match (self.get_class(left_tag), self.get_class(right_tag)) {
+            (Some(left), Some(right)) => left.is_subclass_of(right),
+            _ => false,
         }
     }
 
-    pub fn is_subspecializer(&self, _id: u64, _left_tag: &Symbol, _right_tag: &Symbol) -> bool {
-        // Rust has no notion of inheritance, so there are no subspecializers.
-        false
+    pub fn operator(&self, op: Operator, args: [class::Instance; 2]) -> crate::Result<bool> {
+        use std::cmp::Ordering::*;
+
+        let [left, right] = &args;
+        let res = match op {
+            Operator::Eq => left.equals(right)?,
+            Operator::Neq => !left.equals(right)?,
+            Operator::Lt => left.compare(right)? == Some(Less),
+            Operator::Leq => matches!(left.compare(right)?, Some(Less) | Some(Equal)),
+            Operator::Gt => left.compare(right)? == Some(Greater),
+            Operator::Geq => matches!(left.compare(right)?, Some(Greater) | Some(Equal)),
+            Operator::In => right.contains(left)?,
+            _ => {
+                return Err(OsoError::UnimplementedOperation {
+                    operation: format!("{:?} operators", op),
+                })
+            }
+        };
+        Ok(res)
     }
+}
 
-    pub fn operator(&self, _op: Operator, _args: [class::Instance; 2]) -> crate::Result<bool> {
-        // Operators are not supported
-        // TODO (dhatch): Implement.
-        Err(OsoError::UnimplementedOperation {
-            operation: String::from("comparison operators"),
+/// Keeps the instances used by a query from being evicted under
+/// `InstanceCachePolicy::PerQuery` while the query or its results are alive,
+/// and evicts the instances cached for it once they are dropped.
+pub(crate) struct LiveQuery {
+    host: Arc<Mutex<Host>>,
+    id: u64,
+}
+
+impl LiveQuery {
+    pub fn new(host: &Arc<Mutex<Host>>) -> Arc<Self> {
+        let id = host.lock().unwrap().instances.start_query();
+        Arc::new(Self {
+            host: host.clone(),
+            id,
         })
     }
+
+    /// Lock the host, attributing the instances cached until it is
+    /// unlocked to this query.
+    pub fn host(&self) -> QueryHost<'_> {
+        let mut host = self.host.lock().unwrap();
+        host.instances.set_owner(Some(self.id));
+        QueryHost(host)
+    }
+}
+
+impl Drop for LiveQuery {
+    fn drop(&mut self) {
+        if let Ok(mut host) = self.host.lock() {
+            host.instances.end_query(self.id);
+        }
+    }
+}
+
+/// The host, locked by a query with `LiveQuery::host`.
+pub(crate) struct QueryHost<'a>(MutexGuard<'a, Host>);
+
+impl std::ops::Deref for QueryHost<'_> {
+    type Target = Host;
+
+    fn deref(&self) -> &Host {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for QueryHost<'_> {
+    fn deref_mut(&mut self) -> &mut Host {
+        &mut self.0
+    }
+}
+
+impl Drop for QueryHost<'_> {
+    fn drop(&mut self) {
+        self.0.instances.set_owner(None);
+    }
 }
 
 /// Marker trait: implements "ToPolar" via a registered class
+///
+/// Instances passed to Polar are cached in the host, which may be used from
+/// any thread, so only host classes that are `Send + Sync` convert to Polar
+/// values, e.g. to be registered as constants. Classes of other types, such
+/// as types holding an `Rc`, can still be registered, but their values are
+/// rejected at compile time where they would be shared:
+///
+/// ```compile_fail
+/// use std::rc::Rc;
+///
+/// #[derive(Clone)]
+/// struct Counter(Rc<i64>);
+///
+/// impl oso::HostClass for Counter {}
+///
+/// let oso = oso::Oso::new();
+/// oso.register_constant("COUNTER", &Counter(Rc::new(0)));
+/// ```
 pub trait HostClass {}
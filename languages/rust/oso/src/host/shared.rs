@@ -0,0 +1,112 @@
+//! Instances that policies can mutate.
+
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::class::Class;
+use super::method::{Method, MutMethod};
+use super::to_polar::ToPolarResults;
+use super::HostClass;
+use crate::FromPolar;
+
+/// A value shared between the application and its policies, e.g. a
+/// context object that policies record decisions on.
+///
+/// Instances passed to Polar are clones, so plain values cannot be changed
+/// by policies. Clones of a `Shared` value refer to the same value, behind
+/// a lock, and the methods of `Class<Shared<T>>` registered with
+/// [`Class::add_mut_method`] take it as `&mut T`:
+///
+/// ```
+/// use oso::{Class, Oso, Shared};
+///
+/// #[derive(Default)]
+/// struct Audit {
+///     entries: Vec<String>,
+/// }
+///
+/// let oso = Oso::new();
+/// oso.register_class(
+///     Class::<Shared<Audit>>::new()
+///         .name("Audit")
+///         .add_mut_method("record", |audit: &mut Audit, entry: String| {
+///             audit.entries.push(entry)
+///         })
+///         .build(),
+/// )
+/// .unwrap();
+/// ```
+pub struct Shared<T>(Arc<RwLock<T>>);
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+
+    /// Lock the value for reading.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        self.0.read().unwrap()
+    }
+
+    /// Lock the value for writing.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.0.write().unwrap()
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Shared").field(&*self.read()).finish()
+    }
+}
+
+impl<T: Send + Sync + 'static> HostClass for Shared<T> {}
+
+/// Call a `MutMethod` with the write lock of a `Shared` receiver held.
+struct WithWriteLock<F>(F);
+
+impl<T, Args, F> Method<Shared<T>, Args> for WithWriteLock<F>
+where
+    F: MutMethod<T, Args>,
+{
+    type Result = F::Result;
+
+    fn invoke(&self, receiver: &Shared<T>, args: Args) -> Self::Result {
+        self.0.invoke(&mut receiver.write(), args)
+    }
+}
+
+impl<T: 'static> Class<Shared<T>> {
+    /// A method that takes `&mut T`, and may change the shared value. It
+    /// holds the write lock of the value while it runs, so it must not
+    /// call other methods of the same value.
+    pub fn add_mut_method<F, Args, R>(self, name: &str, f: F) -> Self
+    where
+        Args: FromPolar,
+        F: MutMethod<T, Args, Result = R> + 'static,
+        R: ToPolarResults + 'static,
+    {
+        self.add_method(name, WithWriteLock(f))
+    }
+
+    /// An attribute read from `&T`, with the read lock of the value held.
+    pub fn add_shared_attribute_getter<F, R>(self, name: &str, f: F) -> Self
+    where
+        F: Fn(&T) -> R + Send + Sync + 'static,
+        R: ToPolarResults + 'static,
+    {
+        self.add_attribute_getter(name, move |shared: &Shared<T>| f(&shared.read()))
+    }
+}
@@ -3,8 +3,12 @@
 
 use polar_core::terms::*;
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
+use super::value::PolarValue;
 use super::Host;
 
 pub trait ToPolar {
@@ -13,6 +17,16 @@ pub trait ToPolar {
     fn to_polar(&self, host: &mut Host) -> Term {
         Term::new_from_ffi(self.to_polar_value(host))
     }
+
+    /// Fallible version of `to_polar_value`, for types with values
+    /// that cannot be represented in Polar.
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        Ok(self.to_polar_value(host))
+    }
+
+    fn try_to_polar(&self, host: &mut Host) -> crate::Result<Term> {
+        self.try_to_polar_value(host).map(Term::new_from_ffi)
+    }
 }
 
 impl ToPolar for bool {
@@ -39,6 +53,36 @@ int_to_polar!(u32);
 int_to_polar!(i32);
 int_to_polar!(i64);
 
+/// Integers that do not necessarily fit in a Polar integer (`i64`).
+///
+/// `to_polar_value` panics for out of range values; oso itself always
+/// converts with `try_to_polar_value`, which returns an error instead.
+macro_rules! wide_int_to_polar {
+    ($i:ty) => {
+        impl ToPolar for $i {
+            fn to_polar_value(&self, host: &mut Host) -> Value {
+                self.try_to_polar_value(host)
+                    .expect("integer out of range for Polar")
+            }
+
+            fn try_to_polar_value(&self, _host: &mut Host) -> crate::Result<Value> {
+                i64::try_from(*self)
+                    .map(|i| Value::Number(Numeric::Integer(i)))
+                    .map_err(|_| crate::OsoError::IntegerOverflow {
+                        value: self.to_string(),
+                        target: "i64".to_string(),
+                    })
+            }
+        }
+    };
+}
+
+wide_int_to_polar!(u64);
+wide_int_to_polar!(usize);
+wide_int_to_polar!(isize);
+wide_int_to_polar!(u128);
+wide_int_to_polar!(i128);
+
 macro_rules! float_to_polar {
     ($i:ty) => {
         impl ToPolar for $i {
@@ -52,6 +96,26 @@ macro_rules! float_to_polar {
 float_to_polar!(f32);
 float_to_polar!(f64);
 
+/// Durations are passed to Polar as a number of seconds.
+impl ToPolar for std::time::Duration {
+    fn to_polar_value(&self, _host: &mut Host) -> Value {
+        Value::Number(Numeric::Float(self.as_secs_f64()))
+    }
+}
+
+/// Times are passed to Polar as a number of seconds since the Unix epoch,
+/// so that they can be compared with each other and with timestamps. There
+/// is no conversion for `Instant`, which has no such reference point.
+impl ToPolar for std::time::SystemTime {
+    fn to_polar_value(&self, _host: &mut Host) -> Value {
+        let secs = match self.duration_since(std::time::UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        Value::Number(Numeric::Float(secs))
+    }
+}
+
 impl ToPolar for String {
     fn to_polar_value(&self, _host: &mut Host) -> Value {
         Value::String(self.clone())
@@ -70,12 +134,120 @@ impl ToPolar for str {
     }
 }
 
-impl<T: ToPolar> ToPolar for Vec<T> {
+impl ToPolar for char {
+    fn to_polar_value(&self, _host: &mut Host) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl ToPolar for Cow<'_, str> {
+    fn to_polar_value(&self, _host: &mut Host) -> Value {
+        Value::String(self.as_ref().to_owned())
+    }
+}
+
+impl ToPolar for Box<str> {
+    fn to_polar_value(&self, _host: &mut Host) -> Value {
+        Value::String(self.as_ref().to_owned())
+    }
+}
+
+impl ToPolar for Arc<str> {
+    fn to_polar_value(&self, _host: &mut Host) -> Value {
+        Value::String(self.as_ref().to_owned())
+    }
+}
+
+impl<T: ToPolar> ToPolar for [T] {
     fn to_polar_value(&self, host: &mut Host) -> Value {
         Value::List(self.iter().map(|v| v.to_polar(host)).collect())
     }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        self.iter()
+            .map(|v| v.try_to_polar(host))
+            .collect::<crate::Result<_>>()
+            .map(Value::List)
+    }
+}
+
+impl<T: ToPolar> ToPolar for &[T] {
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        (**self).to_polar_value(host)
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        (**self).try_to_polar_value(host)
+    }
+}
+
+impl<T: ToPolar> ToPolar for Vec<T> {
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        self.as_slice().to_polar_value(host)
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        self.as_slice().try_to_polar_value(host)
+    }
+}
+
+/// Arrays convert to Polar lists.
+macro_rules! array_to_polar {
+    ( $( $n:literal )+ ) => {
+        $(
+            impl<T: ToPolar> ToPolar for [T; $n] {
+                fn to_polar_value(&self, host: &mut Host) -> Value {
+                    self[..].to_polar_value(host)
+                }
+
+                fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+                    self[..].try_to_polar_value(host)
+                }
+            }
+        )+
+    };
 }
 
+array_to_polar! {
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16
+    17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32
+}
+
+/// Tuples convert to Polar lists.
+macro_rules! tuple_to_polar {
+    ( $( $name:ident )+ ) => {
+        impl<$($name),+> ToPolar for ($($name,)+)
+        where
+            $($name: ToPolar),+
+        {
+            #[allow(non_snake_case)]
+            fn to_polar_value(&self, host: &mut Host) -> Value {
+                let ($($name,)+) = self;
+                Value::List(vec![$($name.to_polar(host)),+])
+            }
+
+            #[allow(non_snake_case)]
+            fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+                let ($($name,)+) = self;
+                Ok(Value::List(vec![$($name.try_to_polar(host)?),+]))
+            }
+        }
+    };
+}
+
+tuple_to_polar! { A }
+tuple_to_polar! { A B }
+tuple_to_polar! { A B C }
+tuple_to_polar! { A B C D }
+tuple_to_polar! { A B C D E }
+tuple_to_polar! { A B C D E F }
+tuple_to_polar! { A B C D E F G }
+tuple_to_polar! { A B C D E F G H }
+tuple_to_polar! { A B C D E F G H I }
+tuple_to_polar! { A B C D E F G H I J }
+tuple_to_polar! { A B C D E F G H I J K }
+tuple_to_polar! { A B C D E F G H I J K L }
+
 impl<T: ToPolar> ToPolar for HashMap<String, T> {
     fn to_polar_value(&self, host: &mut Host) -> Value {
         Value::Dictionary(Dictionary {
@@ -85,6 +257,64 @@ impl<T: ToPolar> ToPolar for HashMap<String, T> {
                 .collect(),
         })
     }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        let fields = self
+            .iter()
+            .map(|(k, v)| Ok((Symbol(k.to_string()), v.try_to_polar(host)?)))
+            .collect::<crate::Result<_>>()?;
+        Ok(Value::Dictionary(Dictionary { fields }))
+    }
+}
+
+/// Sets convert to Polar lists, in iteration order.
+impl<T: ToPolar> ToPolar for HashSet<T> {
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        Value::List(self.iter().map(|v| v.to_polar(host)).collect())
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        self.iter()
+            .map(|v| v.try_to_polar(host))
+            .collect::<crate::Result<_>>()
+            .map(Value::List)
+    }
+}
+
+impl<T: ToPolar> ToPolar for BTreeMap<String, T> {
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        Value::Dictionary(Dictionary {
+            fields: self
+                .iter()
+                .map(|(k, v)| (Symbol(k.to_string()), v.to_polar(host)))
+                .collect(),
+        })
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        let fields = self
+            .iter()
+            .map(|(k, v)| Ok((Symbol(k.to_string()), v.try_to_polar(host)?)))
+            .collect::<crate::Result<_>>()?;
+        Ok(Value::Dictionary(Dictionary { fields }))
+    }
+}
+
+impl<T: ToPolar> ToPolar for Option<T> {
+    /// `None` converts to `nil`.
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        match self {
+            Some(value) => value.to_polar_value(host),
+            None => PolarValue::nil().to_value(host),
+        }
+    }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        match self {
+            Some(value) => value.try_to_polar_value(host),
+            None => Ok(self.to_polar_value(host)),
+        }
+    }
 }
 
 impl ToPolar for Value {
@@ -93,23 +323,39 @@ impl ToPolar for Value {
     }
 }
 
+impl ToPolar for PolarValue {
+    fn to_polar_value(&self, host: &mut Host) -> Value {
+        self.to_value(host)
+    }
+}
+
 impl ToPolar for Box<dyn ToPolar> {
     fn to_polar_value(&self, host: &mut Host) -> Value {
         self.as_ref().to_polar_value(host)
     }
+
+    fn try_to_polar_value(&self, host: &mut Host) -> crate::Result<Value> {
+        self.as_ref().try_to_polar_value(host)
+    }
 }
 
 impl ToPolar for crate::Class {
     fn to_polar_value(&self, host: &mut Host) -> Value {
         let type_class = host.type_class();
-        for method_name in self.class_methods.keys() {
-            type_class
-                .instance_methods
+        for method_name in self.class_methods.keys().chain(self.constructors.keys()) {
+            Arc::make_mut(&mut type_class.instance_methods)
                 .entry(method_name.clone())
                 .or_insert_with(|| {
                     super::class_method::InstanceMethod::from_class_method(method_name.clone())
                 });
         }
+        for name in self.constants.keys() {
+            Arc::make_mut(&mut type_class.attributes)
+                .entry(name.clone())
+                .or_insert_with(|| {
+                    super::class_method::InstanceMethod::from_class_constant(name.clone())
+                });
+        }
         let repr = format!("type<{}>", self.name);
         let instance = type_class.cast_to_instance(self.clone());
         let instance = host.cache_instance(instance, None);
@@ -121,21 +367,23 @@ impl ToPolar for crate::Class {
     }
 }
 
-impl<C: 'static + Clone + super::HostClass> ToPolar for C {
+impl<C: 'static + Clone + Send + Sync + super::HostClass> ToPolar for C {
     fn to_polar_value(&self, host: &mut Host) -> Value {
         let class = host
             .get_class_from_type::<C>()
             .expect("Class not registered");
         let instance = class.cast_to_instance(self.clone());
+        let repr = instance.repr();
         let instance = host.cache_instance(instance, None);
         Value::ExternalInstance(ExternalInstance {
             constructor: None,
-            repr: None,
+            repr,
             instance_id: instance,
         })
     }
 }
 
+use std::cell::RefCell;
 use std::iter;
 
 pub type PolarResultIter = Box<dyn Iterator<Item = Result<Box<dyn ToPolar>, crate::OsoError>>>;
@@ -163,27 +411,32 @@ impl<C: ToPolarResults, E: ToString> ToPolarResults for Result<C, E> {
     }
 }
 
-impl<C: ToPolarResults> ToPolarResults for Option<C> {
-    fn to_polar_results(&self) -> PolarResultIter {
-        self.as_ref().map_or_else(
-            || Box::new(std::iter::empty()) as PolarResultIter,
-            |e| e.to_polar_results(),
-        )
-    }
-}
-
+/// The results of a call of an iterator method. Each call makes a new
+/// `PolarIter`, so the iterator is moved out on the first read, and doesn't
+/// need to be `Clone`.
 pub struct PolarIter<I, Iter>
 where
     I: ToPolarResults + 'static,
-    Iter: std::iter::Iterator<Item = I> + Sized + Clone + 'static,
+    Iter: std::iter::Iterator<Item = I> + Sized + 'static,
 {
-    pub iter: Iter,
+    iter: RefCell<Option<Iter>>,
 }
 
-impl<I: ToPolarResults, Iter: std::iter::Iterator<Item = I> + Clone + Sized + 'static>
-    ToPolarResults for PolarIter<I, Iter>
+impl<I: ToPolarResults, Iter: std::iter::Iterator<Item = I> + Sized + 'static> PolarIter<I, Iter> {
+    pub fn new(iter: Iter) -> Self {
+        Self {
+            iter: RefCell::new(Some(iter)),
+        }
+    }
+}
+
+impl<I: ToPolarResults, Iter: std::iter::Iterator<Item = I> + Sized + 'static> ToPolarResults
+    for PolarIter<I, Iter>
 {
     fn to_polar_results(&self) -> PolarResultIter {
-        Box::new(self.iter.clone().flat_map(|e| e.to_polar_results()))
+        match self.iter.borrow_mut().take() {
+            Some(iter) => Box::new(iter.flat_map(|e| e.to_polar_results())),
+            None => Box::new(iter::empty()),
+        }
     }
 }
@@ -0,0 +1,144 @@
+//! The public representation of Polar values in Rust.
+
+use polar_core::terms::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::class::Instance;
+use super::Host;
+
+/// A value that can be passed to or returned from Polar.
+///
+/// This is the crate-owned conversion currency: use it instead of the
+/// `polar_core` term types, which are an implementation detail and may
+/// change between releases.
+#[derive(Clone, Debug)]
+pub enum PolarValue {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    List(Vec<PolarValue>),
+    Map(HashMap<String, PolarValue>),
+    Instance(Instance),
+    /// An unbound variable, e.g. a query variable with no binding.
+    Variable(String),
+}
+
+impl PartialEq for PolarValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PolarValue::Integer(a), PolarValue::Integer(b)) => a == b,
+            (PolarValue::Float(a), PolarValue::Float(b)) => a == b,
+            (PolarValue::String(a), PolarValue::String(b)) => a == b,
+            (PolarValue::Bool(a), PolarValue::Bool(b)) => a == b,
+            (PolarValue::List(a), PolarValue::List(b)) => a == b,
+            (PolarValue::Map(a), PolarValue::Map(b)) => a == b,
+            (PolarValue::Variable(a), PolarValue::Variable(b)) => a == b,
+            (PolarValue::Instance(a), PolarValue::Instance(b)) => {
+                Arc::ptr_eq(&a.instance, &b.instance) || a.equals(b).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl PolarValue {
+    /// The value of the `nil` constant.
+    pub fn nil() -> Self {
+        PolarValue::Instance(crate::builtins::nil_instance())
+    }
+
+    /// Convert a Polar term into a `PolarValue`, looking up
+    /// external instances in the `host` cache.
+    pub(crate) fn from_term(term: &Term, host: &Host) -> crate::Result<Self> {
+        let value = match term.value() {
+            Value::Number(Numeric::Integer(i)) => PolarValue::Integer(*i),
+            Value::Number(Numeric::Float(f)) => PolarValue::Float(*f),
+            Value::String(s) => PolarValue::String(s.clone()),
+            Value::Boolean(b) => PolarValue::Bool(*b),
+            Value::List(l) => PolarValue::List(
+                l.iter()
+                    .map(|t| PolarValue::from_term(t, host))
+                    .collect::<crate::Result<Vec<_>>>()?,
+            ),
+            Value::Dictionary(dict) => PolarValue::Map(
+                dict.fields
+                    .iter()
+                    .map(|(k, v)| PolarValue::from_term(v, host).map(|v| (k.0.clone(), v)))
+                    .collect::<crate::Result<HashMap<_, _>>>()?,
+            ),
+            Value::ExternalInstance(ExternalInstance { instance_id, .. }) => host
+                .get_instance(*instance_id)
+                .cloned()
+                .map(PolarValue::Instance)
+                .ok_or(crate::OsoError::FromPolar)?,
+            Value::Variable(Symbol(name)) => PolarValue::Variable(name.clone()),
+            v => {
+                tracing::warn!(value = ?v, "invalid conversion attempted");
+                return Err(crate::OsoError::FromPolar);
+            }
+        };
+        Ok(value)
+    }
+
+    /// Convert `self` into a Polar value, caching any instances in the `host`.
+    pub(crate) fn to_value(&self, host: &mut Host) -> Value {
+        match self {
+            PolarValue::Integer(i) => Value::Number(Numeric::Integer(*i)),
+            PolarValue::Float(f) => Value::Number(Numeric::Float(*f)),
+            PolarValue::String(s) => Value::String(s.clone()),
+            PolarValue::Bool(b) => Value::Boolean(*b),
+            PolarValue::List(l) => Value::List(
+                l.iter()
+                    .map(|v| Term::new_from_ffi(v.to_value(host)))
+                    .collect(),
+            ),
+            PolarValue::Map(map) => Value::Dictionary(Dictionary {
+                fields: map
+                    .iter()
+                    .map(|(k, v)| (Symbol(k.clone()), Term::new_from_ffi(v.to_value(host))))
+                    .collect(),
+            }),
+            PolarValue::Instance(instance) => {
+                let instance_id = host.cache_instance(instance.clone(), None);
+                Value::ExternalInstance(ExternalInstance {
+                    constructor: None,
+                    repr: instance.repr(),
+                    instance_id,
+                })
+            }
+            PolarValue::Variable(name) => Value::Variable(Symbol(name.clone())),
+        }
+    }
+}
+
+macro_rules! polar_value_from {
+    ($($t:ty => $variant:ident),+ $(,)?) => {
+        $(
+            impl From<$t> for PolarValue {
+                fn from(value: $t) -> Self {
+                    PolarValue::$variant(value.into())
+                }
+            }
+        )+
+    };
+}
+
+polar_value_from!(
+    i32 => Integer,
+    i64 => Integer,
+    u32 => Integer,
+    f64 => Float,
+    bool => Bool,
+    String => String,
+    &str => String,
+    Instance => Instance,
+);
+
+impl<T: Into<PolarValue>> From<Vec<T>> for PolarValue {
+    fn from(values: Vec<T>) -> Self {
+        PolarValue::List(values.into_iter().map(Into::into).collect())
+    }
+}
@@ -5,17 +5,66 @@
 #[macro_use]
 pub mod macros;
 
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(feature = "arrow")]
+mod batch;
 pub(crate) mod builtins;
+mod compiled;
+mod conditions;
+mod conflicts;
+mod context;
+#[cfg(feature = "polars")]
+mod dataframe;
+#[cfg(feature = "chrono")]
+mod datetime;
+mod decisions;
+mod enumeration;
 mod errors;
+mod facts;
+mod filter;
+mod groups;
 mod host;
+mod loading;
+mod migrations;
+mod net;
 mod oso;
 mod query;
+mod recording;
+mod rollout;
+mod scope;
+mod simulation;
+mod sql;
+pub mod testing;
+mod timeline;
+mod tokens;
 
 pub use crate::oso::Oso;
-pub use errors::{OsoError, Result};
-pub use host::{Class, FromPolar, HostClass, ToPolar};
-pub use polar_core::{polar::Polar, terms::Value};
-pub use query::{Query, ResultSet};
+pub use conflicts::RuleConflict;
+pub use context::{Context, Page};
+pub use decisions::ExpiringMap;
+pub use enumeration::{Enumeration, EnumerationStats};
+pub use errors::{ForbiddenError, OsoError, Result, TypeMismatchError};
+pub use facts::{Consistency, ConsistencyToken, Fact, FactStore, MemoryFactStore};
+#[cfg(feature = "ldap")]
+pub use groups::LdapGroups;
+pub use groups::{GroupResolver, OidcClaims, StaticGroups};
+#[cfg(feature = "json")]
+pub use host::PolarSerde;
+pub use host::{
+    Class, Converted, FromPolar, HostClass, Instance, InstanceCachePolicy, InstanceCacheStats,
+    PolarValue, Shared, ToPolar,
+};
+pub use loading::LoadProgress;
+pub use migrations::AttributeMigration;
+pub use net::{Network, NetworkParseError};
+pub use polar_core::polar::{NumericComparison, Polar, QueryLimits};
+pub use query::{CancelHandle, Query, ResultSet};
+pub use recording::Recording;
+pub use scope::{remaining_budget, QueryScope};
+pub use simulation::{AccessStats, Distribution, Population, Simulation, SimulationReport};
+pub use sql::SqlFilter;
+pub use timeline::Timeline;
 
 pub trait PolarClass {
     fn get_polar_class() -> Class<()>;
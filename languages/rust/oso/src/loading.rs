@@ -0,0 +1,158 @@
+//! Loading large policy files in batches of statements, and several policy
+//! files in parallel.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use sha2::Digest;
+
+use crate::Oso;
+
+/// The size, in bytes, of the batches of statements that large policy
+/// files are parsed in.
+const BATCH_SIZE: usize = 1 << 20;
+
+/// How much of a policy file [`Oso::load_file_streaming`] has loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadProgress {
+    pub bytes_loaded: u64,
+    pub bytes_total: u64,
+}
+
+/// Finds the ends of statements in policy text: the `;`s outside of
+/// strings, comments and brackets.
+#[derive(Default)]
+struct Statements {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    in_comment: bool,
+}
+
+impl Statements {
+    /// Scan the next piece of text, returning the offset just after the
+    /// last statement that ends in it.
+    fn scan(&mut self, text: &str) -> Option<usize> {
+        let mut end = None;
+        for (i, c) in text.char_indices() {
+            if self.in_comment {
+                self.in_comment = c != '\n';
+            } else if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == '\\' {
+                    self.escaped = true;
+                } else if c == '"' {
+                    self.in_string = false;
+                }
+            } else {
+                match c {
+                    '#' => self.in_comment = true,
+                    '"' => self.in_string = true,
+                    '(' | '[' | '{' => self.depth += 1,
+                    ')' | ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                    ';' if self.depth == 0 => end = Some(i + 1),
+                    _ => {}
+                }
+            }
+        }
+        end
+    }
+}
+
+impl Oso {
+    /// Load the policy file `file` in batches of statements rather than all
+    /// at once, calling `progress` after each batch, e.g. for generated
+    /// policy files of many megabytes. The policy version is the same as
+    /// if the file were loaded with [`Oso::load_file`].
+    ///
+    /// Errors report positions within the batch the error is in. If loading
+    /// fails, the batches before the error stay loaded.
+    pub fn load_file_streaming<F>(&mut self, file: &str, mut progress: F) -> crate::Result<()>
+    where
+        F: FnMut(&LoadProgress),
+    {
+        if !file.ends_with(".polar") {
+            return Err(crate::OsoError::IncorrectFileType);
+        }
+        let f = File::open(&file)?;
+        let bytes_total = f.metadata()?.len();
+        let mut reader = BufReader::new(f);
+
+        let mut policy = self.policy_digest();
+        policy.input(bytes_total.to_be_bytes());
+        let conflicts = self.rule_conflicts();
+        let migrations = self.attribute_migrations();
+        let mut statements = Statements::default();
+        let mut buffer = String::new();
+        // The length of the complete statements at the start of `buffer`.
+        let mut complete = 0;
+        let mut bytes_loaded = 0;
+        let mut first = true;
+        loop {
+            let start = buffer.len();
+            let read = reader.read_line(&mut buffer)?;
+            if let Some(end) = statements.scan(&buffer[start..]) {
+                complete = start + end;
+            }
+            let end = if read == 0 { buffer.len() } else { complete };
+            if (read == 0 && (first || end > 0)) || end >= BATCH_SIZE {
+                let rest = buffer.split_off(end);
+                let batch = std::mem::replace(&mut buffer, rest);
+                self.inner.load_part(&batch, file, first)?;
+                policy.input(&batch);
+                first = false;
+                complete = 0;
+                bytes_loaded += batch.len() as u64;
+                progress(&LoadProgress {
+                    bytes_loaded,
+                    bytes_total,
+                });
+            }
+            if read == 0 {
+                break;
+            }
+        }
+        self.set_policy_digest(policy);
+        self.warn_conflicts(&conflicts);
+        self.warn_migrations(&migrations);
+        self.check_inline_queries()
+    }
+
+    /// Load several policy files, parsing them in parallel. The files are
+    /// loaded in order once they are all parsed, so the result is the same
+    /// as loading them one by one with [`Oso::load_file`].
+    pub fn load_files(&mut self, files: &[&str]) -> crate::Result<()> {
+        if !files.iter().all(|file| file.ends_with(".polar")) {
+            return Err(crate::OsoError::IncorrectFileType);
+        }
+        let parsers: Vec<_> = files
+            .iter()
+            .map(|file| {
+                let polar = self.inner.clone();
+                let file = file.to_string();
+                std::thread::spawn(move || -> crate::Result<_> {
+                    let src = std::fs::read_to_string(&file)?;
+                    Ok(polar.parse(&src, Some(file))?)
+                })
+            })
+            .collect();
+        let parsed = parsers
+            .into_iter()
+            .map(|parser| parser.join().expect("policy parser panicked"))
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let conflicts = self.rule_conflicts();
+        let migrations = self.attribute_migrations();
+        for parsed in parsed {
+            let mut policy = self.policy_digest();
+            policy.input((parsed.src().len() as u64).to_be_bytes());
+            policy.input(parsed.src());
+            self.inner.load_parsed(parsed)?;
+            self.set_policy_digest(policy);
+        }
+        self.warn_conflicts(&conflicts);
+        self.warn_migrations(&migrations);
+        self.check_inline_queries()
+    }
+}
@@ -10,3 +10,84 @@ macro_rules! lazy_error {
 macro_rules! check_messages {
     ($core_obj:expr) => {};
 }
+
+/// Construct a [`PolarValue`](crate::PolarValue) from a JSON-like literal.
+///
+/// Lists are written `[...]`, maps `{"key": value, ...}` with string
+/// literal keys, and `nil` is the `nil` constant. Any other Rust
+/// expression is converted with `PolarValue::from`.
+///
+/// ```
+/// use oso::{polar_value, PolarValue};
+///
+/// let level = 3;
+/// let user = polar_value!({
+///     "role": "admin",
+///     "tags": ["a", "b"],
+///     "level": level,
+///     "manager": nil,
+/// });
+/// if let PolarValue::Map(user) = user {
+///     assert_eq!(user["level"], PolarValue::Integer(3));
+/// }
+/// ```
+#[macro_export]
+macro_rules! polar_value {
+    // Munch list elements into `[$($elems,)*]`.
+    (@list [$($elems:expr,)*]) => {
+        vec![$($elems,)*]
+    };
+    (@list [$($elems:expr,)*] nil $(, $($rest:tt)*)?) => {
+        $crate::polar_value!(@list [$($elems,)* $crate::PolarValue::nil(),] $($($rest)*)?)
+    };
+    (@list [$($elems:expr,)*] [$($list:tt)*] $(, $($rest:tt)*)?) => {
+        $crate::polar_value!(@list [$($elems,)* $crate::polar_value!([$($list)*]),] $($($rest)*)?)
+    };
+    (@list [$($elems:expr,)*] {$($map:tt)*} $(, $($rest:tt)*)?) => {
+        $crate::polar_value!(@list [$($elems,)* $crate::polar_value!({$($map)*}),] $($($rest)*)?)
+    };
+    (@list [$($elems:expr,)*] $next:expr, $($rest:tt)*) => {
+        $crate::polar_value!(@list [$($elems,)* $crate::polar_value!($next),] $($rest)*)
+    };
+    (@list [$($elems:expr,)*] $last:expr) => {
+        $crate::polar_value!(@list [$($elems,)* $crate::polar_value!($last),])
+    };
+
+    // Munch map entries, inserting them into `$map`.
+    (@map $map:ident) => {};
+    (@map $map:ident $key:literal : nil $(, $($rest:tt)*)?) => {
+        $map.insert(::std::string::String::from($key), $crate::PolarValue::nil());
+        $crate::polar_value!(@map $map $($($rest)*)?);
+    };
+    (@map $map:ident $key:literal : [$($list:tt)*] $(, $($rest:tt)*)?) => {
+        $map.insert(::std::string::String::from($key), $crate::polar_value!([$($list)*]));
+        $crate::polar_value!(@map $map $($($rest)*)?);
+    };
+    (@map $map:ident $key:literal : {$($inner:tt)*} $(, $($rest:tt)*)?) => {
+        $map.insert(::std::string::String::from($key), $crate::polar_value!({$($inner)*}));
+        $crate::polar_value!(@map $map $($($rest)*)?);
+    };
+    (@map $map:ident $key:literal : $value:expr, $($rest:tt)*) => {
+        $map.insert(::std::string::String::from($key), $crate::polar_value!($value));
+        $crate::polar_value!(@map $map $($rest)*);
+    };
+    (@map $map:ident $key:literal : $value:expr) => {
+        $map.insert(::std::string::String::from($key), $crate::polar_value!($value));
+    };
+
+    (nil) => {
+        $crate::PolarValue::nil()
+    };
+    ([$($list:tt)*]) => {
+        $crate::PolarValue::List($crate::polar_value!(@list [] $($list)*))
+    };
+    ({$($map:tt)*}) => {{
+        #[allow(unused_mut)]
+        let mut map = ::std::collections::HashMap::new();
+        $crate::polar_value!(@map map $($map)*);
+        $crate::PolarValue::Map(map)
+    }};
+    ($other:expr) => {
+        $crate::PolarValue::from($other)
+    };
+}
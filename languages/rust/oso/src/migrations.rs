@@ -0,0 +1,138 @@
+//! Detect rules that use attributes their classes no longer have.
+//!
+//! When an application removes or renames a field faster than the policies
+//! using it are updated, lookups of the old attribute only fail when a
+//! query reaches them. Classes can instead record the attributes removed or
+//! renamed in their current version, and rules that look them up on an
+//! argument specialized on the class are reported when the policy is
+//! loaded.
+
+use std::fmt;
+
+use polar_core::formatting::ToPolarString;
+use polar_core::rules::Rule;
+use polar_core::terms::*;
+
+use crate::host::Host;
+use crate::Oso;
+
+/// A rule that uses an attribute removed from a class.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeMigration {
+    /// The rule using the attribute.
+    pub rule: String,
+    /// The name of the class as used in the rule.
+    pub class: String,
+    /// The removed attribute.
+    pub attribute: String,
+    /// The version of the class's schema, if it has one.
+    pub version: Option<u32>,
+    /// The new name of the attribute, if it was renamed.
+    pub renamed_to: Option<String>,
+}
+
+impl fmt::Display for AttributeMigration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` uses `{}.{}`, which was ",
+            self.rule, self.class, self.attribute
+        )?;
+        match &self.renamed_to {
+            Some(to) => write!(f, "renamed to `{}`", to)?,
+            None => write!(f, "removed")?,
+        }
+        if let Some(version) = self.version {
+            write!(f, " in version {}", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl Oso {
+    /// Find the rules that use attributes removed from or renamed in the
+    /// classes registered with
+    /// [`Class::add_removed_attribute`](crate::Class::add_removed_attribute)
+    /// or [`Class::add_renamed_attribute`](crate::Class::add_renamed_attribute).
+    /// Only lookups on parameters specialized on the class are found. They
+    /// are also logged as warnings when a policy is loaded.
+    pub fn attribute_migrations(&self) -> Vec<AttributeMigration> {
+        let host = self.host.lock().unwrap();
+        let kb = self.inner.kb.read().unwrap();
+        let mut names: Vec<_> = kb.rules.keys().collect();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut migrations = vec![];
+        for name in names {
+            for rule in kb.rules[name].rules() {
+                rule_migrations(&rule, &host, &mut migrations);
+            }
+        }
+        migrations
+    }
+
+    /// Log the migrations that are not in `known`.
+    pub(crate) fn warn_migrations(&self, known: &[AttributeMigration]) {
+        for migration in self.attribute_migrations() {
+            if !known.contains(&migration) {
+                tracing::warn!(
+                    class = %migration.class,
+                    attribute = %migration.attribute,
+                    "removed attribute: {}",
+                    migration
+                );
+            }
+        }
+    }
+}
+
+fn rule_migrations(rule: &Rule, host: &Host, migrations: &mut Vec<AttributeMigration>) {
+    for param in &rule.params {
+        let (var, literal) = match (param.parameter.value(), param.specializer.as_ref()) {
+            (Value::Variable(var), Some(specializer)) => match specializer.value() {
+                Value::Pattern(Pattern::Instance(literal)) => (var, literal),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let class = match host.resolve_class(&literal.tag) {
+            Some(class) => class,
+            None => continue,
+        };
+        let mut attributes = literal.fields.fields.keys().cloned().collect::<Vec<_>>();
+        lookups(&rule.body, var, &mut attributes);
+        for attribute in attributes {
+            if let Some(renamed_to) = class.attribute_change(&attribute) {
+                let migration = AttributeMigration {
+                    rule: rule.to_polar(),
+                    class: literal.tag.0.clone(),
+                    attribute: attribute.0,
+                    version: class.version(),
+                    renamed_to: renamed_to.map(str::to_string),
+                };
+                if !migrations.contains(&migration) {
+                    migrations.push(migration);
+                }
+            }
+        }
+    }
+}
+
+/// The attributes and methods looked up on `var` in `term`.
+fn lookups(term: &Term, var: &Symbol, names: &mut Vec<Symbol>) {
+    if let Value::Expression(Operation { operator, args }) = term.value() {
+        if *operator == Operator::Dot
+            && args.len() >= 2
+            && args[0].value() == &Value::Variable(var.clone())
+        {
+            match args[1].value() {
+                Value::String(field) => names.push(Symbol(field.clone())),
+                Value::Call(call) => names.push(call.name.clone()),
+                _ => (),
+            }
+        }
+        for arg in args {
+            lookups(arg, var, names);
+        }
+    }
+}
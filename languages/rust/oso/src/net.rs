@@ -0,0 +1,145 @@
+//! Builtin IP address and network classes, for network allowlists.
+//!
+//! - `IpAddr`: a `std::net::IpAddr`, created with `IpAddr.parse("10.1.2.3")`.
+//! - `Network`: a CIDR network, created with `Network.parse("10.0.0.0/8")`.
+//!   A bare address is a single host network.
+//!
+//! `ip in network` checks whether an address, given as an `IpAddr` or a
+//! string, belongs to a network:
+//!
+//! ```polar
+//! allow(_, _, request) if request.ip in Network.parse("10.0.0.0/8");
+//! ```
+
+use std::any::Any;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::{Class, HostClass};
+
+impl HostClass for IpAddr {}
+impl HostClass for Network {}
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn new(address: IpAddr, prefix: u8) -> Option<Self> {
+        if prefix > max_prefix(&address) {
+            return None;
+        }
+        Some(Self { address, prefix })
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` is in the network. Addresses of the other IP version
+    /// never are.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let (ip, address) = match (ip, &self.address) {
+            (IpAddr::V4(ip), IpAddr::V4(address)) => {
+                (u32::from(*ip) as u128, u32::from(*address) as u128)
+            }
+            (IpAddr::V6(ip), IpAddr::V6(address)) => (u128::from(*ip), u128::from(*address)),
+            _ => return false,
+        };
+        if self.prefix == 0 {
+            return true;
+        }
+        let shift = u32::from(max_prefix(&self.address) - self.prefix);
+        ip >> shift == address >> shift
+    }
+}
+
+fn max_prefix(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// An invalid network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkParseError(String);
+
+impl fmt::Display for NetworkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid CIDR network `{}`", self.0)
+    }
+}
+
+impl std::error::Error for NetworkParseError {}
+
+impl FromStr for Network {
+    type Err = NetworkParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NetworkParseError(s.to_string());
+        let (address, prefix) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => max_prefix(&address),
+        };
+        Self::new(address, prefix).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// Whether `network` contains `item`, an `IpAddr` or a string.
+fn network_contains(network: &Network, item: &dyn Any) -> bool {
+    if let Some(ip) = item.downcast_ref::<IpAddr>() {
+        network.contains(ip)
+    } else if let Some(ip) = item.downcast_ref::<String>() {
+        ip.parse().map_or(false, |ip| network.contains(&ip))
+    } else {
+        false
+    }
+}
+
+fn ip_addr() -> Class<IpAddr> {
+    Class::<IpAddr>::new()
+        .name("IpAddr")
+        .with_equality_check()
+        .with_comparison_check()
+        .add_class_method("parse", |s: String| s.parse::<IpAddr>())
+        .add_method("is_ipv4", IpAddr::is_ipv4)
+        .add_method("is_ipv6", IpAddr::is_ipv6)
+        .add_method("is_loopback", IpAddr::is_loopback)
+        .add_method("to_string", |ip: &IpAddr| ip.to_string())
+}
+
+fn network() -> Class<Network> {
+    Class::<Network>::new()
+        .name("Network")
+        .with_equality_check()
+        .set_containment_check(network_contains)
+        .add_class_method("parse", |s: String| s.parse::<Network>())
+        .add_attribute_getter("address", Network::address)
+        .add_attribute_getter("prefix", |n: &Network| i64::from(n.prefix))
+        .add_method("contains", |n: &Network, ip: IpAddr| n.contains(&ip))
+        .add_method("to_string", |n: &Network| n.to_string())
+}
+
+pub fn classes() -> Vec<Class> {
+    vec![ip_addr().erase_type(), network().erase_type()]
+}
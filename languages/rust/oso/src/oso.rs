@@ -4,18 +4,44 @@ use polar_core::terms::{Call, Symbol, Term, Value};
 
 use std::fs::File;
 use std::io::Read;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use crate::host::Host;
+use sha2::{Digest, Sha256};
+
+use crate::errors::ForbiddenError;
+use crate::host::{Host, LiveQuery};
 use crate::query::Query;
-use crate::ToPolar;
+use crate::{PolarValue, ToPolar};
 
+/// A loaded policy and the classes registered for it.
+///
+/// Clones are cheap and share the policy and classes, so one `Oso` can be
+/// loaded once and cloned into each worker thread. Loading into or
+/// registering classes on any clone changes them for all of them.
 #[derive(Clone)]
 pub struct Oso {
-    inner: Arc<polar_core::polar::Polar>,
-    host: Arc<Mutex<Host>>,
+    pub(crate) inner: Arc<polar_core::polar::Polar>,
+    pub(crate) host: Arc<Mutex<Host>>,
+    /// Digest of the policy sources loaded so far, in load order.
+    policy: Arc<Mutex<Sha256>>,
+    /// Hex of the digest of `policy`.
+    policy_version: Arc<RwLock<String>>,
+    message_resolver: Arc<RwLock<Option<MessageResolver>>>,
+    pub(crate) compiled: Arc<RwLock<crate::compiled::CompiledRules>>,
+    /// The timeout of queries, if not the default of Polar.
+    query_timeout: Arc<RwLock<Option<Duration>>>,
+    /// The goal limits of queries, if not the defaults of Polar.
+    query_limits: Arc<RwLock<Option<crate::QueryLimits>>>,
+    /// The cached decisions, if decisions are cached.
+    pub(crate) decisions: Arc<Mutex<Option<crate::decisions::DecisionCache>>>,
+    /// The store of asserted facts, and the version of it loaded.
+    pub(crate) facts: Arc<Mutex<crate::facts::FactSync>>,
 }
 
+/// Resolves a reason code and a locale to a message.
+type MessageResolver = Arc<dyn Fn(&str, &str) -> Option<String> + Send + Sync>;
+
 impl Default for Oso {
     fn default() -> Self {
         Self::new()
@@ -27,15 +53,26 @@ impl Oso {
         let inner = Arc::new(polar_core::polar::Polar::new());
         let host = Host::new(inner.clone());
 
-        let mut oso = Self {
+        let oso = Self {
             host: Arc::new(Mutex::new(host)),
             inner,
+            policy: Arc::new(Mutex::new(Sha256::new())),
+            policy_version: Arc::new(RwLock::new(hex_digest(Sha256::new()))),
+            message_resolver: Arc::new(RwLock::new(None)),
+            compiled: Arc::new(RwLock::new(Default::default())),
+            query_timeout: Arc::new(RwLock::new(None)),
+            query_limits: Arc::new(RwLock::new(None)),
+            decisions: Arc::new(Mutex::new(None)),
+            facts: Arc::new(Mutex::new(Default::default())),
         };
 
         for class in crate::builtins::classes() {
             oso.register_class(class)
                 .expect("failed to register builtin class");
         }
+        let nil = crate::PolarValue::nil();
+        oso.register_constant("nil", &nil)
+            .expect("failed to register nil constant");
         oso
     }
 
@@ -50,20 +87,125 @@ impl Oso {
         Action: ToPolar,
         Resource: ToPolar,
     {
-        let args: Vec<&dyn ToPolar> = vec![&actor, &action, &resource];
-        let mut query = self.query_rule("allow", args).unwrap();
-        match query.next() {
-            Some(Ok(_)) => Ok(true),
-            Some(Err(e)) => Err(e),
-            None => Ok(false),
+        let live = LiveQuery::new(&self.host);
+        let args = vec![
+            actor.try_to_polar(&mut live.host())?,
+            action.try_to_polar(&mut live.host())?,
+            resource.try_to_polar(&mut live.host())?,
+        ];
+        let key = self.decision_key("is_allowed", &args, &live);
+        if let Some(decision) = key.as_ref().and_then(|key| self.cached_decision(key)) {
+            return Ok(decision.is_ok());
+        }
+        let allowed = self.allowed(args, live)?;
+        if let Some(key) = key {
+            let decision = if allowed {
+                Ok(())
+            } else {
+                Err(ForbiddenError::default())
+            };
+            self.cache_decision(key, decision);
+        }
+        Ok(allowed)
+    }
+
+    /// Whether the `allow` rule holds for `args`.
+    fn allowed(&self, args: Vec<Term>, live: Arc<LiveQuery>) -> crate::Result<bool> {
+        if let Some(allowed) = self.compiled_is_allowed(&args[0], &args[1], &args[2]) {
+            tracing::debug!(allowed, policy_version = %self.policy_version(), "is_allowed");
+            return Ok(allowed);
+        }
+        let mut query = self.query_terms("allow", args, live);
+        let allowed = match query.next() {
+            Some(Ok(_)) => true,
+            Some(Err(e)) => return Err(e),
+            None => false,
+        };
+        query.recycle();
+        tracing::debug!(allowed, policy_version = %self.policy_version(), "is_allowed");
+        Ok(allowed)
+    }
+
+    /// Check that `actor` may perform `action` on `resource`, returning a
+    /// [`ForbiddenError`](crate::ForbiddenError) otherwise.
+    ///
+    /// `deny(actor, action, resource, reason)` rules take precedence over
+    /// `allow` rules, and the `reason` of the first matching one is
+    /// returned on the error, e.g.
+    ///
+    /// ```polar
+    /// deny(user, "upload", _, {code: "quota_exceeded", message: "Storage quota exceeded"})
+    ///     if user.usage >= user.quota;
+    /// ```
+    pub fn authorize<Actor, Action, Resource>(
+        &mut self,
+        actor: Actor,
+        action: Action,
+        resource: Resource,
+    ) -> crate::Result<()>
+    where
+        Actor: ToPolar,
+        Action: ToPolar,
+        Resource: ToPolar,
+    {
+        let live = LiveQuery::new(&self.host);
+        let args = vec![
+            actor.try_to_polar(&mut live.host())?,
+            action.try_to_polar(&mut live.host())?,
+            resource.try_to_polar(&mut live.host())?,
+        ];
+        let key = self.decision_key("authorize", &args, &live);
+        if let Some(decision) = key.as_ref().and_then(|key| self.cached_decision(key)) {
+            return decision.map_err(Into::into);
+        }
+
+        let reason = Term::new_temporary(Value::Variable(Symbol("reason".to_string())));
+        let mut deny_args = args.clone();
+        deny_args.push(reason);
+        let decision = match self.query_terms("deny", deny_args, live.clone()).next() {
+            Some(result) => Err(ForbiddenError::from_reason(result?.get("reason")?)),
+            None => {
+                if self.allowed(args, live)? {
+                    Ok(())
+                } else {
+                    Err(ForbiddenError::default())
+                }
+            }
+        };
+        if let Some(key) = key {
+            self.cache_decision(key, decision.clone());
+        }
+        decision.map_err(Into::into)
+    }
+
+    /// Resolve the reason codes of denied requests to localized messages
+    /// with `resolver`, called with the code and a locale, so that
+    /// user-facing text is kept in the application's message catalog
+    /// rather than in the policy.
+    pub fn set_message_resolver<F>(&mut self, resolver: F)
+    where
+        F: Fn(&str, &str) -> Option<String> + Send + Sync + 'static,
+    {
+        *self.message_resolver.write().unwrap() = Some(Arc::new(resolver));
+    }
+
+    /// The message for `error` in `locale`: the message resolved from its
+    /// reason code if there is one, and the message given by the policy
+    /// otherwise.
+    pub fn resolve_message(&self, error: &ForbiddenError, locale: &str) -> Option<String> {
+        let resolver = self.message_resolver.read().unwrap();
+        match (&error.code, resolver.as_ref()) {
+            (Some(code), Some(resolver)) => resolver(code, locale),
+            _ => None,
         }
+        .or_else(|| error.message.clone())
     }
 
     pub fn clear(&mut self) {
         *self = Self::new();
     }
 
-    fn check_inline_queries(&mut self) -> crate::Result<()> {
+    pub(crate) fn check_inline_queries(&mut self) -> crate::Result<()> {
         while let Some(q) = self.inner.next_inline_query(false) {
             let query = Query::new(q, self.host.clone());
             match query.collect::<crate::Result<Vec<_>>>() {
@@ -83,58 +225,468 @@ impl Oso {
         let mut f = File::open(&file)?;
         let mut policy = String::new();
         f.read_to_string(&mut policy)?;
+        let conflicts = self.rule_conflicts();
+        let migrations = self.attribute_migrations();
         self.inner.load(&policy, Some(file.to_string()))?;
+        self.warn_conflicts(&conflicts);
+        self.warn_migrations(&migrations);
+        self.record_policy(&policy);
         self.check_inline_queries()
     }
 
     pub fn load_str(&mut self, s: &str) -> crate::Result<()> {
+        let conflicts = self.rule_conflicts();
+        let migrations = self.attribute_migrations();
         self.inner.load(s, None)?;
+        self.warn_conflicts(&conflicts);
+        self.warn_migrations(&migrations);
+        self.record_policy(s);
         self.check_inline_queries()
     }
 
+    /// Run `f` with the rules in `src` loaded in place of any loaded rules
+    /// of the same names, e.g. to stub out a rule in a policy test without
+    /// building a new `Oso`. The rules in `src` are removed and the
+    /// original rules restored when `f` returns, even if it panics. The
+    /// `@budget`s of the original rules are replaced in the same way.
+    ///
+    /// ```
+    /// # use oso::Oso;
+    /// let mut oso = Oso::new();
+    /// oso.load_str(r#"allow(user, "read", _) if is_admin(user); is_admin("alice");"#)?;
+    /// let bob_allowed = oso.with_rules_overridden("is_admin(_);", |oso| {
+    ///     oso.is_allowed("bob", "read", "doc")
+    /// })??;
+    /// assert!(bob_allowed);
+    /// assert!(!oso.is_allowed("bob", "read", "doc")?);
+    /// # Ok::<(), oso::OsoError>(())
+    /// ```
+    pub fn with_rules_overridden<F, R>(&mut self, src: &str, f: F) -> crate::Result<R>
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let mut names = vec![];
+        for line in polar_core::parser::parse_lines(0, src)? {
+            if let polar_core::parser::Line::Rule(rule) = line {
+                if !names.contains(&rule.name) {
+                    names.push(rule.name);
+                }
+            }
+        }
+        let rules = {
+            let mut kb = self.inner.kb.write().unwrap();
+            names
+                .into_iter()
+                .map(|name| {
+                    let rule = kb.rules.remove(&name);
+                    let budget = kb.budgets.remove(&name);
+                    (name, rule, budget)
+                })
+                .collect()
+        };
+        let _restore = RestoreRules {
+            rules,
+            policy: self.policy_digest(),
+            oso: self.clone(),
+        };
+        self.load_str(src)?;
+        Ok(f(self))
+    }
+
+    pub(crate) fn record_policy(&self, src: &str) {
+        let mut policy = self.policy_digest();
+        policy.input((src.len() as u64).to_be_bytes());
+        policy.input(src);
+        self.set_policy_digest(policy);
+    }
+
+    /// The digest of the policy sources loaded so far.
+    pub(crate) fn policy_digest(&self) -> Sha256 {
+        self.policy.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_policy_digest(&self, policy: Sha256) {
+        *self.policy_version.write().unwrap() = hex_digest(policy.clone());
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    /// Identifies the loaded policy: the hex SHA-256 digest of the policy
+    /// sources loaded so far, in load order. Loading the same sources in
+    /// the same order gives the same version, in any process, so decisions
+    /// can be attributed to an exact policy revision, and decision tokens
+    /// and caches can reject decisions made with another one.
+    pub fn policy_version(&self) -> String {
+        self.policy_version.read().unwrap().clone()
+    }
+
     pub fn query(&mut self, s: &str) -> crate::Result<Query> {
-        let query = self.inner.new_query(s, false)?;
+        let mut query = self.inner.new_query(s, false)?;
+        self.set_limits(&mut query);
         check_messages!(self.inner);
         let query = Query::new(query, self.host.clone());
         Ok(query)
     }
 
+    /// Like [`query`](Oso::query), attaching `data` to the query with
+    /// [`Query::with_context`], e.g. the tenant the query is made for.
+    pub fn query_with_context<D>(&mut self, s: &str, data: D) -> crate::Result<Query>
+    where
+        D: std::any::Any + Send + Sync,
+    {
+        Ok(self.query(s)?.with_context(data))
+    }
+
+    /// Run the query recorded in `recording` again, answering its host
+    /// calls from the recording rather than from the host. The query fails
+    /// with an error if it makes a host call that was not recorded.
+    pub fn replay(&mut self, recording: &crate::Recording) -> crate::Result<Query> {
+        let replay = recording.replay().ok_or_else(|| crate::OsoError::Custom {
+            message: String::from("no query was recorded"),
+        })?;
+        let mut query = self.inner.new_query_from_term(replay.query.clone(), false);
+        self.set_limits(&mut query);
+        Ok(Query::new(query, self.host.clone()).with_replay(replay))
+    }
+
     pub fn query_rule<'a>(
         &mut self,
         name: &str,
         args: impl IntoIterator<Item = &'a dyn crate::host::ToPolar>,
     ) -> crate::Result<Query> {
+        let live = LiveQuery::new(&self.host);
         let args = args
             .into_iter()
-            .map(|arg| arg.to_polar(&mut self.host.lock().unwrap()))
-            .collect();
+            .map(|arg| arg.try_to_polar(&mut live.host()))
+            .collect::<crate::Result<_>>()?;
+        Ok(self.query_terms(name, args, live))
+    }
+
+    /// Query the rule `name` with `args`, converted while `live` was kept.
+    fn query_terms(&self, name: &str, args: Vec<Term>, live: Arc<LiveQuery>) -> Query {
         let query_value = Value::Call(Call {
             name: Symbol(name.to_string()),
             args,
             kwargs: None,
         });
         let query_term = Term::new_from_ffi(query_value);
-        let query = self.inner.new_query_from_term(query_term, false);
+        let mut query = crate::query::pooled_query(&self.inner, query_term);
+        self.set_limits(&mut query);
         check_messages!(self.inner);
-        let query = Query::new(query, self.host.clone());
-        Ok(query)
+        Query::new(query, self.host.clone()).with_live(live)
+    }
+
+    /// Fail queries with a timeout error once they have run for `timeout`,
+    /// including the time spent in host methods, instead of after the 30
+    /// seconds Polar allows by default, so that a pathological policy
+    /// can't hold a request thread for long. Single queries can be given
+    /// another timeout with [`Query::with_timeout`].
+    pub fn set_query_timeout(&mut self, timeout: Duration) {
+        *self.query_timeout.write().unwrap() = Some(timeout);
+    }
+
+    /// Fail queries with a stack overflow error once they have more than
+    /// `limits.max_stack_size` goals on their stack, e.g. for deeply
+    /// recursive rules, and with a query limit error once they have run
+    /// more than `limits.max_goals` goals, instead of at the limits Polar
+    /// sets by default. Raising the stack size lets deeper recursion
+    /// through, at the cost of memory.
+    ///
+    /// Queries taking up more than `limits.max_memory` bytes for their
+    /// bindings, goals and choices fail with
+    /// [`OsoError::ResourceExhausted`](crate::OsoError::ResourceExhausted),
+    /// e.g. to protect a service evaluating untrusted policies.
+    ///
+    /// ```
+    /// # use oso::{Oso, QueryLimits};
+    /// let mut oso = Oso::new();
+    /// oso.set_query_limits(QueryLimits {
+    ///     max_stack_size: 100_000,
+    ///     max_goals: Some(10_000_000),
+    ///     max_memory: Some(64 << 20),
+    /// });
+    /// ```
+    pub fn set_query_limits(&mut self, limits: crate::QueryLimits) {
+        *self.query_limits.write().unwrap() = Some(limits);
     }
 
-    pub fn register_class(&mut self, class: crate::host::Class) -> crate::Result<()> {
-        let name = class.name.clone();
-        let name = Symbol(name);
+    fn set_limits(&self, query: &mut polar_core::polar::Query) {
+        if let Some(timeout) = *self.query_timeout.read().unwrap() {
+            query.set_timeout(timeout);
+        }
+        if let Some(limits) = *self.query_limits.read().unwrap() {
+            query.set_limits(limits);
+        }
+    }
+
+    /// Query the rule `name` with `args` followed by the variables `vars`,
+    /// and convert the bindings of `vars` in each result to `T`, usually a
+    /// tuple with one element per variable, e.g.
+    ///
+    /// ```ignore
+    /// let roles = oso.query_rule_with_vars::<(String, String)>(
+    ///     "has_role",
+    ///     vec![&user as &dyn ToPolar],
+    ///     &["role", "scope"],
+    /// )?;
+    /// ```
+    pub fn query_rule_with_vars<'a, T: crate::FromPolar>(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = &'a dyn crate::host::ToPolar>,
+        vars: &[&str],
+    ) -> crate::Result<impl Iterator<Item = crate::Result<T>>> {
+        let variables: Vec<PolarValue> = vars
+            .iter()
+            .map(|var| PolarValue::Variable(var.to_string()))
+            .collect();
+        let mut args: Vec<&dyn ToPolar> = args.into_iter().collect();
+        args.extend(variables.iter().map(|var| var as &dyn ToPolar));
+        let query = self.query_rule(name, args)?;
+
+        let vars: Vec<Symbol> = vars.iter().map(|var| Symbol(var.to_string())).collect();
+        Ok(query.map(move |result| result.and_then(|result| result.get_vars(&vars))))
+    }
+
+    /// Register `class`, making it available to policies by its name.
+    ///
+    /// Classes and constants can be registered at any time, including
+    /// through a clone of this `Oso` while queries run on other threads.
+    /// Queries see the classes and constants registered before they were
+    /// created; queries that are already running do not see later
+    /// registrations.
+    ///
+    /// Fails if a class of the same name is already registered; use
+    /// [`Oso::replace_class`] to replace it.
+    pub fn register_class(&self, class: crate::host::Class) -> crate::Result<()> {
+        let name = Symbol(class.name.clone());
+        if self.host.lock().unwrap().is_registered(&name) {
+            return lazy_error!(
+                "class `{}` is already registered, use `replace_class` to replace it",
+                name.0
+            );
+        }
+        self.replace_class(class)
+    }
+
+    /// Register `class`, replacing any class registered under the same
+    /// name, e.g. to reload host bindings in a long-running service.
+    pub fn replace_class(&self, class: crate::host::Class) -> crate::Result<()> {
+        let name = Symbol(class.name.clone());
         let class_name = self.host.lock().unwrap().cache_class(class.clone(), name);
         self.register_constant(&class_name, &class)
     }
 
-    pub fn register_constant<V: crate::host::ToPolar>(
-        &mut self,
+    /// Unregister the class for `T`, along with its aliases, so that
+    /// policies can no longer refer to it. Instances of `T` that are
+    /// already bound in running queries are unaffected.
+    pub fn unregister_class<T: 'static>(&self) -> crate::Result<()> {
+        let mut host = self.host.lock().unwrap();
+        let name = match host.get_class_from_type::<T>() {
+            Some(class) => Symbol(class.name.clone()),
+            None => {
+                return lazy_error!(
+                    "cannot unregister `{}`: it is not registered",
+                    std::any::type_name::<T>()
+                )
+            }
+        };
+        for name in host.remove_class(&name).unwrap_or_default() {
+            self.inner.unregister_constant(&name);
+        }
+        Ok(())
+    }
+
+    /// Register the trait `U` as the class `name`, e.g.
+    /// `register_trait::<dyn Resource>("Resource")`, so that instances of
+    /// every class declared to implement it with
+    /// [`Class::implements`](crate::Class::implements) match `Resource`
+    /// specializers, and rules can be shared between them.
+    pub fn register_trait<U: ?Sized + 'static>(&self, name: &str) -> crate::Result<()> {
+        self.register_class(crate::host::Class::interface::<U>(name))
+    }
+
+    /// Make the registered class `class` available under the deprecated
+    /// name `alias` as well, e.g. `register_alias("LegacyRepo", "Repo")`, so
+    /// that policies keep working while a class is renamed. A warning is
+    /// logged the first time the alias is used as a specializer or with
+    /// `new`.
+    pub fn register_alias(&self, alias: &str, class: &str) -> crate::Result<()> {
+        let class = self
+            .host
+            .lock()
+            .unwrap()
+            .add_alias(Symbol(alias.to_string()), Symbol(class.to_string()))?;
+        self.register_constant(alias, &class)
+    }
+
+    /// Register several aliases, as pairs of an alias and a class name.
+    pub fn register_aliases<'a, I>(&self, aliases: I) -> crate::Result<()>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        for (alias, class) in aliases {
+            self.register_alias(alias, class)?;
+        }
+        Ok(())
+    }
+
+    /// Convert values of `T` to Polar with `to` and back with `from`, for
+    /// types that can't implement `ToPolar` and `FromPolar` because they are
+    /// defined in another crate. Values of `T` are passed wrapped in
+    /// [`Converted`](crate::Converted). Replaces any conversion registered
+    /// for `T` before.
+    pub fn register_conversion<T, To, From>(&self, to: To, from: From)
+    where
+        T: 'static,
+        To: Fn(&T) -> PolarValue + Send + Sync + 'static,
+        From: Fn(PolarValue) -> crate::Result<T> + Send + Sync + 'static,
+    {
+        self.host.lock().unwrap().add_conversion(
+            std::any::TypeId::of::<T>(),
+            crate::host::Conversion::new(to, from),
+        );
+    }
+
+    /// Make `value` available to policies as `name`. Any value that can be
+    /// passed to Polar can be registered, e.g. configuration such as
+    /// `register_constant("ENV", "production")`, as well as classes and
+    /// instances.
+    pub fn register_constant<V: crate::host::ToPolar + ?Sized>(
+        &self,
         name: &str,
         value: &V,
     ) -> crate::Result<()> {
         let mut host = self.host.lock().unwrap();
+        let value = value.try_to_polar(&mut host)?;
+        host.pin_instances(&value);
         self.inner
-            .register_constant(Symbol(name.to_string()), value.to_polar(&mut host));
+            .register_constant(Symbol(name.to_string()), value);
         Ok(())
     }
+
+    /// Compile `pattern` and register it as the `Regex` constant `name`,
+    /// e.g. `register_pattern("SLUG", "^[a-z0-9-]+$")` for policies like
+    /// `allow(_, "read", path) if SLUG.matches(path);`.
+    #[cfg(feature = "regex")]
+    pub fn register_pattern(&self, name: &str, pattern: &str) -> crate::Result<()> {
+        let regex = regex::Regex::new(pattern).map_err(|e| crate::OsoError::Custom {
+            message: format!("invalid pattern `{}`: {}", pattern, e),
+        })?;
+        self.register_constant(name, &regex)
+    }
+
+    /// Make comparisons of numbers that are easily wrong errors, e.g. of
+    /// an integer ID with a float that it was converted to, in queries made
+    /// from now on. The `==`, `!=`, `<`, `<=`, `>` and `>=` operators are
+    /// affected; unification with `=` is not.
+    pub fn set_numeric_comparison(&self, numeric_comparison: crate::NumericComparison) {
+        self.inner.set_numeric_comparison(numeric_comparison);
+    }
+
+    /// Let instances of classes with a
+    /// [`truthiness`](crate::Class::set_truthiness) be used as conditions
+    /// in rule bodies, e.g. `allow(user, _, _) if user.nickname;`, in
+    /// queries made from now on. Instances of other classes, and all
+    /// instances while this is disabled, fail the query with an error as
+    /// before.
+    pub fn set_instance_truthiness(&self, enabled: bool) {
+        self.inner.set_instance_truthiness(enabled);
+    }
+
+    /// Answer repeated calls of a rule with the same arguments from a
+    /// table within each query made from now on, instead of proving them
+    /// again, e.g. for role hierarchies that reach the same role in many
+    /// ways. Only calls whose arguments are all bound are tabled. A
+    /// tabled call succeeds at most once, so queries may return fewer
+    /// duplicate results, and a call of itself while it is being proved
+    /// fails instead of recursing forever.
+    pub fn set_tabling(&self, enabled: bool) {
+        self.inner.set_tabling(enabled);
+    }
+
+    /// Describe the registered classes, their attributes and the arities
+    /// of their methods as a JSON Schema document, e.g. for editors and
+    /// linters to offer completion and validation for policies.
+    #[cfg(feature = "json")]
+    pub fn export_schema(&self) -> serde_json::Value {
+        self.host.lock().unwrap().export_schema()
+    }
+
+    /// Make loading a policy fail if it refers to a class or constant that
+    /// is not registered, e.g. in a specializer, a `new` or a `Constant.attr`
+    /// lookup, so that typos are found when the policy is loaded rather than
+    /// when a query first reaches them.
+    ///
+    /// This is off by default. Leave it off if classes are registered after
+    /// the policies that use them are loaded.
+    pub fn set_validate_references(&self, validate: bool) {
+        self.inner.set_validate_references(validate);
+    }
+
+    /// Set how long instances passed to Polar are kept. See
+    /// [`InstanceCachePolicy`](crate::InstanceCachePolicy).
+    pub fn set_instance_cache_policy(&mut self, policy: crate::InstanceCachePolicy) {
+        self.host.lock().unwrap().set_instance_cache_policy(policy);
+    }
+
+    /// Call `hook` with the id of every instance evicted from the cache,
+    /// e.g. to record metrics. The hook must not use this `Oso` object.
+    pub fn on_instance_evicted<F>(&mut self, hook: F)
+    where
+        F: Fn(u64, &crate::Instance) + Send + Sync + 'static,
+    {
+        self.host.lock().unwrap().set_eviction_hook(Arc::new(hook));
+    }
+
+    /// The number of instances currently cached.
+    pub fn cached_instances(&self) -> usize {
+        self.host.lock().unwrap().cached_instances()
+    }
+
+    /// The size of the instance cache and counts of its lookups and
+    /// evictions, e.g. to export as metrics.
+    pub fn instance_cache_stats(&self) -> crate::InstanceCacheStats {
+        self.host.lock().unwrap().instance_cache_stats()
+    }
+}
+
+fn hex_digest(digest: Sha256) -> String {
+    digest
+        .result()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Puts back the rules replaced by [`Oso::with_rules_overridden`] when
+/// dropped.
+struct RestoreRules {
+    /// The overridden rules, with their `@budget`s.
+    rules: Vec<(Symbol, Option<polar_core::rules::GenericRule>, Option<u64>)>,
+    policy: Sha256,
+    oso: Oso,
+}
+
+impl Drop for RestoreRules {
+    fn drop(&mut self) {
+        // Restore even if a panic in the closure poisoned the lock.
+        let mut kb = self.oso.inner.kb.write().unwrap_or_else(|e| e.into_inner());
+        for (name, rule, budget) in self.rules.drain(..) {
+            match budget {
+                Some(budget) => kb.budgets.insert(name.clone(), budget),
+                None => kb.budgets.remove(&name),
+            };
+            match rule {
+                Some(rule) => kb.rules.insert(name, rule),
+                None => kb.rules.remove(&name),
+            };
+        }
+        let policy = self.policy.clone();
+        *self
+            .oso
+            .policy_version
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = hex_digest(policy.clone());
+        *self.oso.policy.lock().unwrap_or_else(|e| e.into_inner()) = policy;
+    }
 }
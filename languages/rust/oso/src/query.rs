@@ -1,11 +1,20 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use crate::host::{Instance, PolarResultIter};
+use crate::context::QueryContext;
+use crate::host::{Instance, LiveQuery, LocalInstance, PolarResultIter, Receiver};
+use crate::recording::{self, Recording, Replay};
+use crate::scope::{with_deadline, ScopeState};
+use crate::timeline::{self, Timeline};
 use crate::{FromPolar, ToPolar};
 
+use polar_core::error::{ErrorKind, RuntimeError};
 use polar_core::events::*;
 use polar_core::terms::*;
+use polar_core::timeline::TimelineEvent;
 
 impl Iterator for Query {
     type Item = crate::Result<ResultSet>;
@@ -14,30 +23,330 @@ impl Iterator for Query {
     }
 }
 
+/// The most finished queries kept for reuse on each thread.
+const QUERY_POOL_SIZE: usize = 8;
+
+thread_local! {
+    /// Finished queries, whose virtual machines are reused for new queries
+    /// instead of allocating new ones, e.g. for an `is_allowed` check per
+    /// request. Queries are not `Send`, so each thread has its own pool.
+    static QUERY_POOL: RefCell<Vec<polar_core::polar::Query>> = RefCell::new(vec![]);
+}
+
+/// A query of `polar` for `term`, reusing a finished query if one was
+/// returned to the pool with [`Query::recycle`].
+pub(crate) fn pooled_query(
+    polar: &polar_core::polar::Polar,
+    term: Term,
+) -> polar_core::polar::Query {
+    match QUERY_POOL.with(|pool| pool.borrow_mut().pop()) {
+        Some(mut query) => {
+            polar.reset_query(&mut query, term, false);
+            query
+        }
+        None => polar.new_query_from_term(term, false),
+    }
+}
+
+/// Cancels a query from another thread, e.g. when the request it was made
+/// for is dropped. See [`Query::cancel_handle`].
+#[derive(Clone, Debug)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Stop the query. Its next call to `next` returns
+    /// [`OsoError::Cancelled`](crate::OsoError::Cancelled), also if it is
+    /// running, and later ones return no results.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 pub struct Query {
     inner: polar_core::polar::Query,
     calls: HashMap<u64, PolarResultIter>,
     host: Arc<Mutex<crate::host::Host>>,
+    /// The scope this query runs in, if any.
+    scope: Option<Arc<ScopeState>>,
+    /// Whether the query was stopped by its scope.
+    stopped: bool,
+    live: Arc<LiveQuery>,
+    /// The registration generation of the host when the query was created.
+    generation: u64,
+    /// Where the host calls of the query are recorded, if anywhere.
+    recording: Option<Recording>,
+    /// The recorded calls in progress.
+    recorded_calls: HashMap<u64, (String, usize)>,
+    /// The recording host calls are answered from instead of the host.
+    replay: Option<Replay>,
+    /// Passed to methods that receive the context of the query.
+    context: QueryContext,
+    /// The methods (`true`) and attributes (`false`) found missing on
+    /// classes, by the interned ids of the class and method names, so that
+    /// they aren't looked up again. Not kept for the attributes of classes
+    /// with an attribute fallback, which depend on the instance.
+    missing: HashSet<(u32, u32, bool)>,
+    /// The number of results found, counting the ones skipped by the
+    /// offset of the query.
+    results: usize,
+    /// Set by the handles returned by `cancel_handle`.
+    cancelled: Arc<AtomicBool>,
+    /// The timeline the query is recorded in, if any.
+    timeline: Option<Timeline>,
+    /// The instances bound with `bind_local`, by id.
+    locals: HashMap<u64, LocalInstance>,
 }
 
 impl Query {
-    pub fn new(inner: polar_core::polar::Query, host: Arc<Mutex<crate::host::Host>>) -> Self {
+    pub fn new(mut inner: polar_core::polar::Query, host: Arc<Mutex<crate::host::Host>>) -> Self {
+        let live = LiveQuery::new(&host);
+        let generation = host.lock().unwrap().generation();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        inner.set_cancel_flag(cancelled.clone());
         Self {
             calls: HashMap::new(),
             inner,
             host,
+            scope: None,
+            stopped: false,
+            live,
+            generation,
+            recording: None,
+            recorded_calls: HashMap::new(),
+            replay: None,
+            context: QueryContext::new(),
+            missing: HashSet::new(),
+            results: 0,
+            cancelled,
+            timeline: None,
+            locals: HashMap::new(),
+        }
+    }
+
+    /// Return the virtual machine of the query to the pool of its thread,
+    /// for the next query made there to reuse. See [`pooled_query`].
+    pub(crate) fn recycle(self) {
+        QUERY_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < QUERY_POOL_SIZE {
+                pool.push(self.inner);
+            }
+        })
+    }
+
+    /// Record the host calls of the query in `recording`, replacing what
+    /// it recorded before.
+    pub fn record(mut self, recording: &Recording) -> Self {
+        recording.start(self.inner.term().clone());
+        self.recording = Some(recording.clone());
+        self
+    }
+
+    /// Record the goals, rules and host calls of the query in `timeline`
+    /// as they run, replacing what it recorded before.
+    pub fn record_timeline(mut self, timeline: &Timeline) -> Self {
+        timeline.start();
+        self.inner.set_timeline(true);
+        self.timeline = Some(timeline.clone());
+        self
+    }
+
+    pub(crate) fn with_replay(mut self, replay: Replay) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Keep the instances of a query alive from the point `live` was
+    /// created, e.g. before its arguments were converted.
+    pub(crate) fn with_live(mut self, live: Arc<LiveQuery>) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Attach `value` to the query, e.g. a database connection or the
+    /// span of a request, for methods that receive the
+    /// [`Context`](crate::Context) of the query to retrieve by its type.
+    /// A value of the same type attached before is replaced.
+    pub fn with_context<T>(mut self, value: T) -> Self
+    where
+        T: std::any::Any + Send + Sync,
+    {
+        self.context
+            .data
+            .insert(std::any::TypeId::of::<T>(), Arc::new(value));
+        self
+    }
+
+    /// Fail the query with a timeout error once it has run for `timeout`,
+    /// instead of after the timeout set with
+    /// [`Oso::set_query_timeout`](crate::Oso::set_query_timeout).
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.inner.set_timeout(timeout);
+        self
+    }
+
+    /// A handle to cancel the query with from another thread.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle(self.cancelled.clone())
+    }
+
+    /// Skip the first `offset` results of the query. Methods registered
+    /// with [`Class::add_context_iterator_method`](crate::Class::add_context_iterator_method)
+    /// receive it in the [`Context::page`](crate::Context::page) of the
+    /// query.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.context.page.offset = offset;
+        self
+    }
+
+    /// Return at most `limit` results, after the ones skipped by
+    /// [`offset`](Query::offset). Methods receive it like the offset.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.context.page.limit = Some(limit);
+        self
+    }
+
+    /// Bind the variable `name` of the query to `value`, an instance of a
+    /// registered class. The value is owned by the query instead of being
+    /// cached in the host, so it need not be `Send`, e.g. a value holding
+    /// an `Rc` or a `RefCell`.
+    ///
+    /// Policies can look up the attributes of the value, call its methods
+    /// and match it against its class, but it can't be passed to methods,
+    /// compared, or returned in results. Fails if the query has started.
+    ///
+    /// ```
+    /// # use oso::{Class, Oso};
+    /// use std::rc::Rc;
+    ///
+    /// #[derive(Clone)]
+    /// struct User {
+    ///     name: Rc<str>,
+    /// }
+    /// impl oso::HostClass for User {}
+    ///
+    /// let mut oso = Oso::new();
+    /// oso.register_class(
+    ///     Class::<User>::new()
+    ///         .name("User")
+    ///         .add_attribute_getter("name", |user: &User| user.name.to_string())
+    ///         .build(),
+    /// )?;
+    /// oso.load_str(r#"allow(user: User, "read", _) if user.name = "alice";"#)?;
+    /// let user = User { name: Rc::from("alice") };
+    /// let mut query = oso
+    ///     .query(r#"allow(user, "read", "doc")"#)?
+    ///     .bind_local("user", user)?;
+    /// assert!(query.next().is_some());
+    /// # Ok::<(), oso::OsoError>(())
+    /// ```
+    pub fn bind_local<T: 'static>(mut self, name: &str, value: T) -> crate::Result<Self> {
+        let (class, instance_id) = {
+            let host = self.host.lock().unwrap();
+            match host.get_class_from_type::<T>() {
+                Some(class) => (class.clone(), host.new_instance_id()),
+                None => {
+                    return lazy_error!(
+                        "cannot bind `{}`: class `{}` is not registered",
+                        name,
+                        std::any::type_name::<T>()
+                    )
+                }
+            }
+        };
+        let term = Term::new_from_ffi(Value::ExternalInstance(ExternalInstance {
+            constructor: None,
+            repr: None,
+            instance_id,
+        }));
+        if !self.inner.bind(Symbol(name.to_string()), term) {
+            return lazy_error!("cannot bind `{}`: the query has started", name);
+        }
+        self.locals
+            .insert(instance_id, LocalInstance::new(class, value));
+        Ok(self)
+    }
+
+    /// The instance bound with `bind_local` that `term` refers to, if any.
+    fn local_instance(&self, term: &Term) -> Option<LocalInstance> {
+        match term.value() {
+            Value::ExternalInstance(ExternalInstance { instance_id, .. }) => {
+                self.locals.get(instance_id).cloned()
+            }
+            _ => None,
         }
     }
 
+    pub(crate) fn with_scope(mut self, scope: Arc<ScopeState>) -> Self {
+        self.scope = Some(scope);
+        self
+    }
+
     pub fn next_result(&mut self) -> Option<crate::Result<ResultSet>> {
+        let page = self.context.page;
+        if self.stopped || page.end().map_or(false, |end| self.results >= end) {
+            return None;
+        }
         loop {
-            let event = self.inner.next()?;
+            let result = self.run();
+            if let (Some(scope), Some(Err(_))) = (&self.scope, &result) {
+                scope.cancel();
+            }
+            if let Some(Ok(_)) = result {
+                self.results += 1;
+                if self.results <= page.offset {
+                    continue;
+                }
+            }
+            return result;
+        }
+    }
+
+    fn run(&mut self) -> Option<crate::Result<ResultSet>> {
+        loop {
+            if let Some(Err(e)) = self.scope.as_ref().map(|scope| scope.check()) {
+                self.stopped = true;
+                return Some(Err(e));
+            }
+            if self.cancelled.load(Ordering::Relaxed) {
+                self.stopped = true;
+                return Some(Err(crate::OsoError::Cancelled));
+            }
+            let event = self.inner.next();
+            if let Some(timeline) = &self.timeline {
+                timeline.extend(self.inner.take_timeline_events());
+            }
+            let event = event?;
             check_messages!(self.inner);
             if let Err(e) = event {
+                match e.kind {
+                    ErrorKind::Runtime(RuntimeError::Cancelled) => {
+                        self.stopped = true;
+                        return Some(Err(crate::OsoError::Cancelled));
+                    }
+                    ErrorKind::Runtime(RuntimeError::ResourceExhausted { used, limit, .. }) => {
+                        self.stopped = true;
+                        return Some(Err(crate::OsoError::ResourceExhausted { used, limit }));
+                    }
+                    _ => {}
+                }
                 return Some(Err(e.into()));
             }
             let event = event.unwrap();
             tracing::debug!(event=?event);
+            let event = match self.replay_event(event) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            let host_call = match &self.timeline {
+                Some(_) => timeline::host_call_name(&event).map(|name| (name, Instant::now())),
+                None => None,
+            };
             let result = match event {
                 QueryEvent::None => Ok(()),
                 QueryEvent::Done => return None,
@@ -45,6 +354,7 @@ impl Query {
                     return Some(Ok(ResultSet {
                         bindings,
                         host: self.host.clone(),
+                        _live: self.live.clone(),
                     }));
                 }
                 QueryEvent::MakeExternal {
@@ -56,7 +366,8 @@ impl Query {
                     instance,
                     attribute,
                     args,
-                } => self.handle_external_call(call_id, instance, attribute, args),
+                    kwargs,
+                } => self.handle_external_call(call_id, instance, attribute, args, kwargs),
                 QueryEvent::ExternalOp {
                     call_id,
                     operator,
@@ -85,6 +396,14 @@ impl Query {
                 ),
                 QueryEvent::Debug { message } => self.handle_debug(message),
             };
+            if let (Some(timeline), Some((name, start))) = (&self.timeline, host_call) {
+                timeline.push(TimelineEvent {
+                    name,
+                    category: "host",
+                    start,
+                    duration: start.elapsed(),
+                });
+            }
             if let Err(e) = result {
                 // TODO (dhatch): These seem to be getting swallowed
                 tracing::error!("application error {}", e);
@@ -93,58 +412,203 @@ impl Query {
         }
     }
 
+    /// Answer host calls from the recording being replayed, if any.
+    /// Returns the events that are not host calls.
+    fn replay_event(&mut self, event: QueryEvent) -> crate::Result<Option<QueryEvent>> {
+        let replay = match &mut self.replay {
+            Some(replay) => replay,
+            None => return Ok(Some(event)),
+        };
+        match event {
+            QueryEvent::MakeExternal {
+                instance_id,
+                constructor,
+            } => {
+                let key = recording::new_key(&constructor, &replay.ids);
+                replay.make_instance(key, instance_id)?;
+            }
+            QueryEvent::ExternalCall {
+                call_id,
+                instance,
+                attribute,
+                args,
+                kwargs,
+            } => {
+                let key = recording::call_key(&instance, &attribute, &args, &kwargs, &replay.ids);
+                match replay.next_result(call_id, key)? {
+                    Some(Ok(value)) => self.inner.call_result(call_id, Some(value))?,
+                    Some(Err(message)) => {
+                        self.inner.application_error(message);
+                        self.inner.call_result(call_id, None)?
+                    }
+                    None => self.inner.call_result(call_id, None)?,
+                }
+            }
+            QueryEvent::ExternalOp {
+                call_id,
+                operator,
+                args,
+            } => {
+                let key = recording::op_key(operator, &args, &replay.ids);
+                let answer = replay.answer(&key)?;
+                self.inner.question_result(call_id, answer);
+            }
+            QueryEvent::ExternalIsa {
+                call_id,
+                instance,
+                class_tag,
+            } => {
+                let key = recording::isa_key(&instance, &class_tag, &replay.ids);
+                let answer = replay.answer(&key)?;
+                self.inner.question_result(call_id, answer);
+            }
+            QueryEvent::ExternalUnify {
+                call_id,
+                left_instance_id,
+                right_instance_id,
+            } => {
+                let key = recording::unify_key(left_instance_id, right_instance_id, &replay.ids);
+                let answer = replay.answer(&key)?;
+                self.inner.question_result(call_id, answer);
+            }
+            QueryEvent::ExternalIsSubSpecializer {
+                call_id,
+                instance_id,
+                left_class_tag,
+                right_class_tag,
+            } => {
+                let key = recording::subspecializer_key(
+                    instance_id,
+                    &left_class_tag,
+                    &right_class_tag,
+                    &replay.ids,
+                );
+                let answer = replay.answer(&key)?;
+                self.inner.question_result(call_id, answer);
+            }
+            event => return Ok(Some(event)),
+        }
+        Ok(None)
+    }
+
+    /// Record the answer to a question, if the query is recorded.
+    fn record_question(&self, key: impl FnOnce(&HashMap<u64, u64>) -> String, answer: bool) {
+        if let Some(recording) = &self.recording {
+            recording.record_question(key(&HashMap::new()), answer);
+        }
+    }
+
+    /// Record a result of the call `call_id`, if the query is recorded.
+    fn record_result(&self, call_id: u64, result: Result<Term, String>) {
+        if let (Some(recording), Some(call)) = (&self.recording, self.recorded_calls.get(&call_id))
+        {
+            recording.record_result(call, result);
+        }
+    }
+
     fn question_result(&mut self, call_id: u64, result: bool) {
         self.inner.question_result(call_id, result);
     }
 
     fn call_result(&mut self, call_id: u64, result: Box<dyn ToPolar>) -> crate::Result<()> {
-        let mut host = self.host.lock().unwrap();
-        let value = result.to_polar(&mut host);
+        let mut host = self.live.host();
+        let value = result.try_to_polar(&mut host)?;
+        self.record_result(call_id, Ok(value.clone()));
         Ok(self.inner.call_result(call_id, Some(value))?)
     }
 
+    /// Report the error of the call `call_id` to Polar.
+    fn call_error(&mut self, call_id: u64, error: crate::OsoError) -> crate::Result<()> {
+        self.record_result(call_id, Err(error.to_string()));
+        self.application_error(error);
+        self.call_result_none(call_id)
+    }
+
     fn call_result_none(&mut self, call_id: u64) -> crate::Result<()> {
         Ok(self.inner.call_result(call_id, None)?)
     }
 
+    /// The deadline of the scope of the query, passed to host methods.
+    fn deadline(&self) -> Option<std::time::Instant> {
+        self.scope.as_ref().and_then(|scope| scope.deadline())
+    }
+
     fn application_error(&mut self, error: crate::OsoError) {
         self.inner.application_error(error.to_string())
     }
 
     fn handle_make_external(&mut self, instance_id: u64, constructor: Term) -> crate::Result<()> {
-        let mut host = self.host.lock().unwrap();
+        let deadline = self.deadline();
+        let mut host = self.live.host();
         match constructor.value() {
             Value::InstanceLiteral(InstanceLiteral { .. }) => todo!("instantiate from literal"),
-            Value::Call(Call { name, args, .. }) => {
-                let _instance = host.make_instance(name, args.clone(), instance_id);
+            Value::Call(Call { name, args, kwargs }) => {
+                with_deadline(deadline, || {
+                    host.make_instance(name, args.clone(), kwargs.clone(), instance_id)
+                })?;
             }
             _ => panic!("not valid"),
         }
+        if let Some(recording) = &self.recording {
+            recording.record_instance(
+                recording::new_key(&constructor, &HashMap::new()),
+                instance_id,
+            );
+        }
         Ok(())
     }
 
     fn register_call(
         &mut self,
         call_id: u64,
-        instance: Instance,
+        instance: Receiver,
         name: Symbol,
         args: Option<Vec<Term>>,
+        kwargs: Option<BTreeMap<Symbol, Term>>,
     ) -> crate::Result<()> {
         if self.calls.get(&call_id).is_none() {
-            let (f, args) = if let Some(args) = args {
-                if let Some(m) = instance.methods.get(&name) {
-                    (m, args)
+            let (missing, found) = {
+                let mut host = self.host.lock().unwrap();
+                let missing = (
+                    host.intern(instance.name()),
+                    host.intern(&name.0),
+                    args.is_some(),
+                );
+                if self.missing.contains(&missing) {
+                    tracing::trace!(call_id, name = %name, "known missing");
+                    (missing, None)
                 } else {
+                    let found = instance.find_method(&name.0, args.is_some(), &host);
+                    (missing, found)
+                }
+            };
+            let (f, receiver, args) = match (found, args) {
+                (Some((f, receiver)), args) => (f, receiver, args.unwrap_or_default()),
+                (None, Some(_)) => {
+                    self.missing.insert(missing);
                     return lazy_error!("instance method not found");
                 }
-            } else if let Some(attr) = instance.attributes.get(&name) {
-                (attr, vec![])
-            } else {
-                return lazy_error!("attribute lookup not found");
+                (None, None) => {
+                    if !instance.has_attribute_fallback() {
+                        self.missing.insert(missing);
+                        return lazy_error!("attribute lookup not found");
+                    }
+                    let deadline = self.deadline();
+                    return match with_deadline(deadline, || instance.fallback_attribute(&name.0))? {
+                        Some(result) => {
+                            self.calls.insert(call_id, result.to_polar_results());
+                            Ok(())
+                        }
+                        None => lazy_error!("attribute lookup not found"),
+                    };
+                }
             };
             tracing::trace!(call_id, name = %name, args = ?args, "register_call");
-            let host = &mut self.host.lock().unwrap();
-            let result = f.invoke(instance.instance.as_ref(), args, host)?;
+            let deadline = self.deadline();
+            let host = &mut self.live.host();
+            let context = &self.context;
+            let result =
+                with_deadline(deadline, || f.invoke(receiver, args, kwargs, host, context))?;
             self.calls.insert(call_id, result.to_polar_results());
         }
         Ok(())
@@ -154,7 +618,9 @@ impl Query {
         &mut self,
         call_id: u64,
     ) -> Option<Result<Box<dyn ToPolar>, crate::OsoError>> {
-        self.calls.get_mut(&call_id).and_then(|c| c.next())
+        let deadline = self.deadline();
+        let results = self.calls.get_mut(&call_id)?;
+        with_deadline(deadline, || results.next())
     }
 
     fn handle_external_call(
@@ -163,20 +629,32 @@ impl Query {
         instance: Term,
         name: Symbol,
         args: Option<Vec<Term>>,
+        kwargs: Option<BTreeMap<Symbol, Term>>,
     ) -> crate::Result<()> {
-        let instance = Instance::from_polar(&instance, &mut self.host.lock().unwrap()).unwrap();
-        if let Err(e) = self.register_call(call_id, instance, name, args) {
-            self.application_error(e);
-            return self.call_result_none(call_id);
+        if let Some(recording) = &self.recording {
+            if !self.recorded_calls.contains_key(&call_id) {
+                let key = recording::call_key(&instance, &name, &args, &kwargs, &HashMap::new());
+                let call = recording.record_call(key);
+                self.recorded_calls.insert(call_id, call);
+            }
+        }
+        let instance = match self.local_instance(&instance) {
+            Some(local) => Ok(Receiver::Local(local)),
+            None => Instance::from_polar(&instance, &mut self.host.lock().unwrap())
+                .map(Receiver::Shared),
+        };
+        let instance = match instance {
+            Ok(instance) => instance,
+            Err(e) => return self.call_error(call_id, e),
+        };
+        if let Err(e) = self.register_call(call_id, instance, name, args, kwargs) {
+            return self.call_error(call_id, e);
         }
 
         if let Some(result) = self.next_call_result(call_id) {
             match result {
                 Ok(r) => self.call_result(call_id, r),
-                Err(e) => {
-                    self.application_error(e);
-                    self.call_result_none(call_id)
-                }
+                Err(e) => self.call_error(call_id, e),
             }
         } else {
             self.call_result_none(call_id)
@@ -193,11 +671,12 @@ impl Query {
         let res = {
             let mut host = self.host.lock().unwrap();
             let args = [
-                Instance::from_polar(&args[0], &mut host).unwrap(),
-                Instance::from_polar(&args[1], &mut host).unwrap(),
+                Instance::from_polar(&args[0], &mut host)?,
+                Instance::from_polar(&args[1], &mut host)?,
             ];
             host.operator(operator, args)?
         };
+        self.record_question(|ids| recording::op_key(operator, &args, ids), res);
         self.question_result(call_id, res);
         Ok(())
     }
@@ -209,7 +688,17 @@ impl Query {
         class_tag: Symbol,
     ) -> crate::Result<()> {
         tracing::debug!(instance = ?instance, class = %class_tag, "isa");
-        let res = self.host.lock().unwrap().isa(instance, &class_tag);
+        let local = self.local_instance(&instance);
+        let res = {
+            let host = self.host.lock().unwrap();
+            host.is_visible(&class_tag, self.generation)
+                && match (local, host.get_class(&class_tag)) {
+                    (Some(local), Some(class)) => local.isa(class),
+                    (Some(_), None) => false,
+                    (None, _) => host.isa(instance.clone(), &class_tag)?,
+                }
+        };
+        self.record_question(|ids| recording::isa_key(&instance, &class_tag, ids), res);
         self.question_result(call_id, res);
         Ok(())
     }
@@ -225,6 +714,10 @@ impl Query {
             .lock()
             .unwrap()
             .unify(left_instance_id, right_instance_id)?;
+        self.record_question(
+            |ids| recording::unify_key(left_instance_id, right_instance_id, ids),
+            res,
+        );
         self.question_result(call_id, res);
         Ok(())
     }
@@ -241,6 +734,12 @@ impl Query {
             &left_class_tag,
             &right_class_tag,
         );
+        self.record_question(
+            |ids| {
+                recording::subspecializer_key(instance_id, &left_class_tag, &right_class_tag, ids)
+            },
+            res,
+        );
         self.question_result(call_id, res);
         Ok(())
     }
@@ -256,26 +755,50 @@ impl Query {
 pub struct ResultSet {
     pub bindings: polar_core::kb::Bindings,
     pub host: Arc<Mutex<crate::host::Host>>,
+    _live: Arc<LiveQuery>,
 }
 
 impl ResultSet {
-    pub fn get(&self, name: &str) -> Option<crate::Value> {
+    /// The variables bound in the result, in order of their names, so that
+    /// results print the same from run to run.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<_> = self.bindings.keys().map(|var| var.0.as_str()).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// The value bound to `name`, or `None` if `name` is not bound. Fails
+    /// if the value cannot be converted to a `PolarValue`.
+    pub fn get(&self, name: &str) -> crate::Result<Option<crate::PolarValue>> {
         self.bindings
-            .get(&Symbol(name.to_string()))
-            .map(|t| t.value().clone())
+            .get(name)
+            .map(|t| crate::PolarValue::from_term(t, &self.host.lock().unwrap()))
+            .transpose()
     }
 
     pub fn get_typed<T: crate::host::FromPolar>(&self, name: &str) -> crate::Result<T> {
         self.bindings
-            .get(&Symbol(name.to_string()))
+            .get(name)
             .ok_or_else(|| crate::OsoError::FromPolar)
             .and_then(|term| T::from_polar(term, &mut self.host.lock().unwrap()))
     }
+
+    /// Convert the bindings of `vars` to `T` as an argument list, e.g. to a
+    /// tuple.
+    pub(crate) fn get_vars<T: crate::host::FromPolar>(&self, vars: &[Symbol]) -> crate::Result<T> {
+        let terms = vars
+            .iter()
+            .map(|var| self.bindings.get(var).cloned())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(crate::OsoError::FromPolar)?;
+        T::from_polar_list(&terms, &mut self.host.lock().unwrap())
+    }
 }
 
 impl std::fmt::Debug for ResultSet {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(fmt, "{:#?}", self.bindings)
+        let bindings: BTreeMap<_, _> = self.bindings.iter().collect();
+        write!(fmt, "{:#?}", bindings)
     }
 }
 
@@ -0,0 +1,228 @@
+//! Recording the host calls of a query, and replaying them without the
+//! host.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use polar_core::terms::{ExternalInstance, Operator, Symbol, Term, ToPolarString, Value};
+
+/// The host calls made by a query and their results.
+///
+/// Record a query with [`Query::record`](crate::Query::record), then run it
+/// again with [`Oso::replay`](crate::Oso::replay), e.g. on an `Oso` that
+/// only loaded the policy. Host calls are answered from the recording, so
+/// the classes and instances of the host are not needed, and a policy can
+/// be tested against host objects that are slow or hard to set up.
+///
+/// Calls are matched by their receiver, name and arguments rather than by
+/// their order, so a changed policy can be replayed as long as it makes no
+/// calls that were not recorded.
+#[derive(Clone, Default)]
+pub struct Recording(Arc<Mutex<Entries>>);
+
+#[derive(Clone, Default)]
+pub(crate) struct Entries {
+    query: Option<Term>,
+    /// The ids of the instances made by each `new`.
+    instances: HashMap<String, VecDeque<u64>>,
+    /// The results of each call.
+    calls: HashMap<String, VecDeque<Vec<Result<Term, String>>>>,
+    /// The answers to `matches`, operators and unification.
+    questions: HashMap<String, bool>,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of host calls recorded.
+    pub fn len(&self) -> usize {
+        let entries = self.0.lock().unwrap();
+        entries.instances.values().map(VecDeque::len).sum::<usize>()
+            + entries.calls.values().map(VecDeque::len).sum::<usize>()
+            + entries.questions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Start recording the query `term`, forgetting the previous one.
+    pub(crate) fn start(&self, term: Term) {
+        *self.0.lock().unwrap() = Entries {
+            query: Some(term),
+            ..Entries::default()
+        };
+    }
+
+    pub(crate) fn record_instance(&self, key: String, instance_id: u64) {
+        let mut entries = self.0.lock().unwrap();
+        entries
+            .instances
+            .entry(key)
+            .or_default()
+            .push_back(instance_id);
+    }
+
+    /// Start recording a call, returning the index of its results.
+    pub(crate) fn record_call(&self, key: String) -> (String, usize) {
+        let mut entries = self.0.lock().unwrap();
+        let calls = entries.calls.entry(key.clone()).or_default();
+        calls.push_back(vec![]);
+        (key, calls.len() - 1)
+    }
+
+    pub(crate) fn record_result(&self, call: &(String, usize), result: Result<Term, String>) {
+        let mut entries = self.0.lock().unwrap();
+        if let Some(results) = entries
+            .calls
+            .get_mut(&call.0)
+            .and_then(|calls| calls.get_mut(call.1))
+        {
+            results.push(result);
+        }
+    }
+
+    pub(crate) fn record_question(&self, key: String, answer: bool) {
+        self.0.lock().unwrap().questions.insert(key, answer);
+    }
+
+    pub(crate) fn replay(&self) -> Option<Replay> {
+        let entries = self.0.lock().unwrap().clone();
+        Some(Replay {
+            query: entries.query.clone()?,
+            entries,
+            ids: HashMap::new(),
+            calls: HashMap::new(),
+        })
+    }
+}
+
+/// The state of a query replaying a recording.
+pub(crate) struct Replay {
+    pub query: Term,
+    entries: Entries,
+    /// Maps the ids of instances made in the replay to the recorded ids.
+    pub ids: HashMap<u64, u64>,
+    /// The remaining results of the calls in progress.
+    calls: HashMap<u64, VecDeque<Result<Term, String>>>,
+}
+
+impl Replay {
+    fn missing(key: &str) -> crate::OsoError {
+        crate::OsoError::Custom {
+            message: format!("no recorded host call for `{}`", key),
+        }
+    }
+
+    pub fn make_instance(&mut self, key: String, instance_id: u64) -> crate::Result<()> {
+        let recorded = self
+            .entries
+            .instances
+            .get_mut(&key)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| Self::missing(&key))?;
+        self.ids.insert(instance_id, recorded);
+        Ok(())
+    }
+
+    /// The next result of the call `call_id`, or `None` if there are no
+    /// more.
+    pub fn next_result(
+        &mut self,
+        call_id: u64,
+        key: String,
+    ) -> crate::Result<Option<Result<Term, String>>> {
+        if !self.calls.contains_key(&call_id) {
+            let results = self
+                .entries
+                .calls
+                .get_mut(&key)
+                .and_then(VecDeque::pop_front)
+                .ok_or_else(|| Self::missing(&key))?;
+            self.calls.insert(call_id, results.into());
+        }
+        Ok(self.calls.get_mut(&call_id).and_then(VecDeque::pop_front))
+    }
+
+    pub fn answer(&self, key: &str) -> crate::Result<bool> {
+        self.entries
+            .questions
+            .get(key)
+            .copied()
+            .ok_or_else(|| Self::missing(key))
+    }
+}
+
+/// Describe `term`, with the ids of instances mapped by `ids`.
+fn describe(term: &Term, ids: &HashMap<u64, u64>) -> String {
+    let mut term = term.clone();
+    term.map_replace(&mut |term| match term.value() {
+        Value::ExternalInstance(instance) => {
+            let instance_id = ids
+                .get(&instance.instance_id)
+                .copied()
+                .unwrap_or(instance.instance_id);
+            term.clone_with_value(Value::ExternalInstance(ExternalInstance {
+                instance_id,
+                constructor: None,
+                repr: None,
+            }))
+        }
+        _ => term.clone(),
+    });
+    term.to_polar()
+}
+
+pub(crate) fn new_key(constructor: &Term, ids: &HashMap<u64, u64>) -> String {
+    format!("new {}", describe(constructor, ids))
+}
+
+pub(crate) fn call_key(
+    instance: &Term,
+    attribute: &Symbol,
+    args: &Option<Vec<Term>>,
+    kwargs: &Option<BTreeMap<Symbol, Term>>,
+    ids: &HashMap<u64, u64>,
+) -> String {
+    let mut key = format!("{}.{}", describe(instance, ids), attribute.0);
+    if let Some(args) = args {
+        let mut args = args
+            .iter()
+            .map(|arg| describe(arg, ids))
+            .collect::<Vec<_>>();
+        for (name, value) in kwargs.iter().flatten() {
+            args.push(format!("{}: {}", name.0, describe(value, ids)));
+        }
+        key.push_str(&format!("({})", args.join(", ")));
+    }
+    key
+}
+
+pub(crate) fn op_key(operator: Operator, args: &[Term], ids: &HashMap<u64, u64>) -> String {
+    let args = args
+        .iter()
+        .map(|arg| describe(arg, ids))
+        .collect::<Vec<_>>();
+    format!("{:?}({})", operator, args.join(", "))
+}
+
+pub(crate) fn isa_key(instance: &Term, class_tag: &Symbol, ids: &HashMap<u64, u64>) -> String {
+    format!("{} matches {}", describe(instance, ids), class_tag.0)
+}
+
+pub(crate) fn unify_key(left: u64, right: u64, ids: &HashMap<u64, u64>) -> String {
+    let id = |instance_id| ids.get(&instance_id).copied().unwrap_or(instance_id);
+    format!("^{{id: {}}} = ^{{id: {}}}", id(left), id(right))
+}
+
+pub(crate) fn subspecializer_key(
+    instance_id: u64,
+    left: &Symbol,
+    right: &Symbol,
+    ids: &HashMap<u64, u64>,
+) -> String {
+    let id = ids.get(&instance_id).copied().unwrap_or(instance_id);
+    format!("^{{id: {}}}: {} < {}", id, left.0, right.0)
+}
@@ -134,8 +134,8 @@ pub fn main() -> anyhow::Result<()> {
                 if res.bindings.is_empty() {
                     println!("true");
                 } else {
-                    for (var, value) in res.bindings {
-                        println!("{} = {}", var, value.to_polar());
+                    for var in res.keys() {
+                        println!("{} = {}", var, res.bindings[var].to_polar());
                     }
                 }
             } else {
@@ -0,0 +1,55 @@
+//! Deterministic bucketing for gradual rollouts and experiments.
+//!
+//! `Rollout.bucket(key, experiment, buckets)` assigns `key` (e.g. a user id)
+//! to one of `buckets` buckets, numbered from 0, so that a permission change
+//! can be rolled out to a share of actors:
+//!
+//! ```polar
+//! allow(actor, "use", _: BetaFeature) if Rollout.bucket(actor.id, "beta", 100) < 20;
+//! ```
+//!
+//! The bucket is the first 8 bytes of the SHA-256 digest of
+//! `"{experiment}:{key}"`, as a big-endian unsigned integer, modulo
+//! `buckets`. Integer keys are written in decimal. The assignment only
+//! depends on these inputs, so it is stable across processes and
+//! releases, and other hosts can reproduce it; different experiments
+//! bucket the same keys independently.
+
+use sha2::{Digest, Sha256};
+
+use crate::errors::TypeError;
+use crate::{Class, PolarValue};
+
+#[derive(Clone, Default)]
+pub struct Rollout;
+
+pub fn class() -> Class<Rollout> {
+    Class::<Rollout>::new()
+        .name("Rollout")
+        .add_class_method("bucket", bucket)
+}
+
+fn bucket(key: PolarValue, experiment: String, buckets: i64) -> crate::Result<i64> {
+    let key = match key {
+        PolarValue::String(s) => s,
+        PolarValue::Integer(i) => i.to_string(),
+        _ => {
+            return Err(TypeError {
+                expected: String::from("string or integer key"),
+            }
+            .user())
+        }
+    };
+    if buckets <= 0 {
+        return lazy_error!("the number of buckets must be positive, got {}", buckets);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.input(experiment.as_bytes());
+    hasher.input(b":");
+    hasher.input(key.as_bytes());
+    let digest = hasher.result();
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    Ok((u64::from_be_bytes(prefix) % buckets as u64) as i64)
+}
@@ -0,0 +1,164 @@
+//! Groups of queries that are cancelled together.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Oso, Query, ToPolar};
+
+/// State shared by a scope and its queries.
+#[derive(Debug)]
+pub(crate) struct ScopeState {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+}
+
+impl ScopeState {
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Fails if the scope was cancelled or its deadline has passed.
+    pub(crate) fn check(&self) -> crate::Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(crate::OsoError::Cancelled);
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.cancel();
+                Err(crate::OsoError::DeadlineExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+thread_local! {
+    /// The deadline of the query whose host method runs on this thread.
+    static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// The time left before the deadline of the query that called the running
+/// host method, or `None` outside of host methods and for queries without
+/// a deadline. Host methods can use it to bound their own calls to other
+/// services, instead of overshooting the deadline of the query:
+///
+/// ```ignore
+/// fn is_member(&self, org: String) -> bool {
+///     let timeout = oso::remaining_budget().unwrap_or(DEFAULT_TIMEOUT);
+///     self.directory.is_member(&self.id, &org, timeout)
+/// }
+/// ```
+pub fn remaining_budget() -> Option<Duration> {
+    DEADLINE
+        .with(Cell::get)
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Run `f`, a host method call, with `deadline` as the deadline for
+/// [`remaining_budget`].
+pub(crate) fn with_deadline<R>(deadline: Option<Instant>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Instant>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            DEADLINE.with(|d| d.set(self.0));
+        }
+    }
+
+    let _restore = Restore(DEADLINE.with(|d| d.replace(deadline)));
+    f()
+}
+
+/// A group of queries made on behalf of one request, e.g. authorizing it
+/// and filtering the data it returns.
+///
+/// The queries of a scope share a single deadline and are cancelled
+/// together: when one of them fails, when [`QueryScope::cancel`] is called,
+/// or when the scope is dropped at the end of the request. A cancelled
+/// query returns [`OsoError::Cancelled`](crate::OsoError::Cancelled) from
+/// its next call to `next`, and no further results.
+pub struct QueryScope {
+    oso: Oso,
+    state: Arc<ScopeState>,
+}
+
+impl QueryScope {
+    fn new(oso: Oso, deadline: Option<Instant>) -> Self {
+        Self {
+            oso,
+            state: Arc::new(ScopeState {
+                cancelled: AtomicBool::new(false),
+                deadline,
+            }),
+        }
+    }
+
+    pub fn query(&self, s: &str) -> crate::Result<Query> {
+        Ok(self.oso.clone().query(s)?.with_scope(self.state.clone()))
+    }
+
+    pub fn query_rule<'a>(
+        &self,
+        name: &str,
+        args: impl IntoIterator<Item = &'a dyn ToPolar>,
+    ) -> crate::Result<Query> {
+        Ok(self
+            .oso
+            .clone()
+            .query_rule(name, args)?
+            .with_scope(self.state.clone()))
+    }
+
+    pub fn is_allowed<Actor, Action, Resource>(
+        &self,
+        actor: Actor,
+        action: Action,
+        resource: Resource,
+    ) -> crate::Result<bool>
+    where
+        Actor: ToPolar,
+        Action: ToPolar,
+        Resource: ToPolar,
+    {
+        let args: Vec<&dyn ToPolar> = vec![&actor, &action, &resource];
+        let mut query = self.query_rule("allow", args)?;
+        match query.next() {
+            Some(Ok(_)) => Ok(true),
+            Some(Err(e)) => Err(e),
+            None => Ok(false),
+        }
+    }
+
+    /// Cancel all queries in the scope.
+    pub fn cancel(&self) {
+        self.state.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.check().is_err()
+    }
+}
+
+impl Drop for QueryScope {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl Oso {
+    /// Start a scope for the queries of one request.
+    pub fn scope(&self) -> QueryScope {
+        QueryScope::new(self.clone(), None)
+    }
+
+    /// Start a scope whose queries fail once `timeout` has elapsed.
+    pub fn scope_with_timeout(&self, timeout: Duration) -> QueryScope {
+        QueryScope::new(self.clone(), Some(Instant::now() + timeout))
+    }
+}
@@ -0,0 +1,301 @@
+//! Estimate what a policy allows by evaluating it over generated
+//! populations of actors and resources.
+//!
+//! Each member of a [`Population`] has fields drawn from a
+//! [`Distribution`], and is passed to Polar as a dictionary, or as whatever
+//! the population's constructor builds from the fields. [`Oso::simulate`]
+//! evaluates `allow(actor, action, resource)` for randomly sampled pairs
+//! and reports the share of allowed requests per action, and per value of
+//! an actor field such as a role.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::{Oso, PolarValue};
+
+/// A distribution of field values.
+#[derive(Clone, Debug)]
+pub enum Distribution {
+    Constant(PolarValue),
+    /// Values with relative weights. There must be at least one value, and
+    /// the weights must be finite and non-negative.
+    Weighted(Vec<(PolarValue, f64)>),
+    /// Integers uniformly distributed in `[low, high]`, where `low <= high`.
+    Range(i64, i64),
+    /// `true` with the given probability.
+    Bool(f64),
+}
+
+impl Distribution {
+    /// Values with equal weights.
+    pub fn uniform(values: Vec<PolarValue>) -> Self {
+        Self::Weighted(values.into_iter().map(|v| (v, 1.0)).collect())
+    }
+
+    fn check(&self) -> crate::Result<()> {
+        match self {
+            Self::Weighted(values) if values.is_empty() => {
+                lazy_error!("weighted distribution has no values")
+            }
+            Self::Weighted(values) => match values.iter().find(|(_, w)| !w.is_finite() || *w < 0.0)
+            {
+                Some((_, weight)) => {
+                    lazy_error!("invalid weight {} in weighted distribution", weight)
+                }
+                None => Ok(()),
+            },
+            Self::Range(low, high) if low > high => {
+                lazy_error!("range distribution has low {} above high {}", low, high)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> PolarValue {
+        match self {
+            Self::Constant(value) => value.clone(),
+            Self::Weighted(values) => {
+                let total: f64 = values.iter().map(|(_, w)| w).sum();
+                let mut target = rng.next_f64() * total;
+                for (value, weight) in values {
+                    if target < *weight {
+                        return value.clone();
+                    }
+                    target -= weight;
+                }
+                values
+                    .last()
+                    .map(|(v, _)| v.clone())
+                    .expect("weighted distribution has no values")
+            }
+            Self::Range(low, high) => {
+                // The span of `[i64::MIN, i64::MAX]` wraps to 0.
+                let span = (*high as u64).wrapping_sub(*low as u64).wrapping_add(1);
+                let offset = match span {
+                    0 => rng.next_u64(),
+                    span => rng.next_u64() % span,
+                };
+                PolarValue::Integer(low.wrapping_add(offset as i64))
+            }
+            Self::Bool(p) => PolarValue::Bool(rng.next_f64() < *p),
+        }
+    }
+}
+
+type Constructor = Arc<dyn Fn(HashMap<String, PolarValue>) -> PolarValue + Send + Sync>;
+
+/// A generated set of actors or resources.
+#[derive(Clone)]
+pub struct Population {
+    size: usize,
+    fields: Vec<(String, Distribution)>,
+    constructor: Option<Constructor>,
+}
+
+impl Population {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            fields: vec![],
+            constructor: None,
+        }
+    }
+
+    pub fn field(mut self, name: &str, distribution: Distribution) -> Self {
+        self.fields.push((name.to_string(), distribution));
+        self
+    }
+
+    /// Build the value passed to Polar from the generated fields, e.g. an
+    /// instance of an application class. Defaults to a dictionary.
+    pub fn with_constructor<F>(mut self, f: F) -> Self
+    where
+        F: Fn(HashMap<String, PolarValue>) -> PolarValue + Send + Sync + 'static,
+    {
+        self.constructor = Some(Arc::new(f));
+        self
+    }
+
+    /// Generate the members, along with their fields.
+    fn generate(&self, rng: &mut Rng) -> Vec<(HashMap<String, PolarValue>, PolarValue)> {
+        (0..self.size)
+            .map(|_| {
+                let fields: HashMap<_, _> = self
+                    .fields
+                    .iter()
+                    .map(|(name, d)| (name.clone(), d.sample(rng)))
+                    .collect();
+                let value = match &self.constructor {
+                    Some(constructor) => constructor(fields.clone()),
+                    None => PolarValue::Map(fields.clone()),
+                };
+                (fields, value)
+            })
+            .collect()
+    }
+}
+
+/// The parameters of a simulation.
+#[derive(Clone)]
+pub struct Simulation {
+    actors: Population,
+    resources: Population,
+    actions: Vec<String>,
+    samples: usize,
+    group_by: Option<String>,
+    seed: u64,
+}
+
+impl Simulation {
+    /// Simulate `actions` by `actors` on `resources`, sampling 1,000
+    /// actor and resource pairs per action.
+    pub fn new(actors: Population, resources: Population, actions: &[&str]) -> Self {
+        Self {
+            actors,
+            resources,
+            actions: actions.iter().map(|a| a.to_string()).collect(),
+            samples: 1000,
+            group_by: None,
+            seed: 0,
+        }
+    }
+
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Also report statistics per value of the actor field `field`.
+    pub fn group_by(mut self, field: &str) -> Self {
+        self.group_by = Some(field.to_string());
+        self
+    }
+
+    /// Seed the random generator. Simulations with the same seed
+    /// and parameters produce the same report.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// How many of the sampled requests were allowed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AccessStats {
+    pub allowed: usize,
+    pub total: usize,
+}
+
+impl AccessStats {
+    /// The share of allowed requests, between 0 and 1.
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.allowed as f64 / self.total as f64
+        }
+    }
+
+    fn record(&mut self, allowed: bool) {
+        self.total += 1;
+        if allowed {
+            self.allowed += 1;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    /// Statistics per action.
+    pub actions: BTreeMap<String, AccessStats>,
+    /// Statistics per action and value of the grouping field.
+    pub groups: BTreeMap<(String, String), AccessStats>,
+}
+
+impl SimulationReport {
+    pub fn action(&self, action: &str) -> AccessStats {
+        self.actions.get(action).copied().unwrap_or_default()
+    }
+
+    pub fn group(&self, action: &str, group: &str) -> AccessStats {
+        self.groups
+            .get(&(action.to_string(), group.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Oso {
+    /// Evaluate the policy over the populations of `simulation`.
+    ///
+    /// Fails if one of the distributions is invalid, e.g. an empty
+    /// [`Distribution::Weighted`] or a [`Distribution::Range`] whose low
+    /// bound is above its high bound.
+    pub fn simulate(&mut self, simulation: &Simulation) -> crate::Result<SimulationReport> {
+        for (_, distribution) in simulation
+            .actors
+            .fields
+            .iter()
+            .chain(&simulation.resources.fields)
+        {
+            distribution.check()?;
+        }
+        let mut rng = Rng(simulation.seed);
+        let actors = simulation.actors.generate(&mut rng);
+        let resources = simulation.resources.generate(&mut rng);
+        let mut report = SimulationReport::default();
+        if actors.is_empty() || resources.is_empty() {
+            return Ok(report);
+        }
+
+        for action in &simulation.actions {
+            for _ in 0..simulation.samples {
+                let (fields, actor) = &actors[rng.next_u64() as usize % actors.len()];
+                let (_, resource) = &resources[rng.next_u64() as usize % resources.len()];
+                let allowed = self.is_allowed(actor.clone(), action.clone(), resource.clone())?;
+                report
+                    .actions
+                    .entry(action.clone())
+                    .or_default()
+                    .record(allowed);
+                if let Some(field) = &simulation.group_by {
+                    let group = fields.get(field).map_or_else(String::new, group_name);
+                    report
+                        .groups
+                        .entry((action.clone(), group))
+                        .or_default()
+                        .record(allowed);
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn group_name(value: &PolarValue) -> String {
+    match value {
+        PolarValue::String(s) => s.clone(),
+        PolarValue::Integer(i) => i.to_string(),
+        PolarValue::Float(f) => f.to_string(),
+        PolarValue::Bool(b) => b.to_string(),
+        value => format!("{:?}", value),
+    }
+}
+
+/// A small deterministic random generator (splitmix64), so that
+/// simulations are reproducible without extra dependencies.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,129 @@
+//! Translate `allow` rules into SQL, so that authorization can be enforced
+//! by Postgres itself with a view or a row-level security policy.
+//!
+//! See the `filter` module for the rules that can be translated. The actor
+//! is the database user, `current_user`, and its tenant is the `oso.tenant`
+//! setting of the session, e.g. `SET oso.tenant = 'acme'`. Queries of
+//! classes with a tenant key fail if it isn't set.
+
+use polar_core::terms::*;
+
+use crate::filter::{Comparison, Filter, Operand};
+use crate::Oso;
+
+/// The SQL condition implementing the authorization filter for one
+/// resource class and action over a table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SqlFilter {
+    table: String,
+    condition: String,
+}
+
+impl SqlFilter {
+    /// The boolean SQL expression selecting authorized rows.
+    pub fn condition(&self) -> &str {
+        &self.condition
+    }
+
+    /// `CREATE VIEW` statement for a view of the authorized rows.
+    pub fn create_view(&self, view: &str) -> String {
+        format!(
+            "CREATE VIEW {} AS SELECT * FROM {} WHERE {};",
+            identifier(view),
+            identifier(&self.table),
+            self.condition
+        )
+    }
+
+    /// `CREATE POLICY` statement for a row-level security policy applying to
+    /// `command` (e.g. `SELECT`, `UPDATE` or `ALL`).
+    pub fn create_policy(&self, policy: &str, command: &str) -> String {
+        format!(
+            "CREATE POLICY {} ON {} FOR {} USING ({});",
+            identifier(policy),
+            identifier(&self.table),
+            command,
+            self.condition
+        )
+    }
+}
+
+impl Oso {
+    /// Translate the `allow` rules for `action` on instances of `class`
+    /// into a filter over `table`.
+    pub fn sql_filter(&self, class: &str, action: &str, table: &str) -> crate::Result<SqlFilter> {
+        let filter = self.data_filter(class, action, "SQL")?;
+        Ok(SqlFilter {
+            table: table.to_string(),
+            condition: condition(&filter),
+        })
+    }
+}
+
+fn condition(filter: &Filter) -> String {
+    let join = |filters: &[Filter], empty: &str, separator: &str| match filters {
+        [] => empty.to_string(),
+        [filter] => condition(filter),
+        _ => filters
+            .iter()
+            .map(|f| format!("({})", condition(f)))
+            .collect::<Vec<_>>()
+            .join(separator),
+    };
+    match filter {
+        Filter::Bool(b) => boolean(*b),
+        Filter::And(filters) => join(filters, "TRUE", " AND "),
+        Filter::Or(filters) => join(filters, "FALSE", " OR "),
+        Filter::Not(filter) => format!("NOT ({})", condition(filter)),
+        // The setting is text, whatever the type of the column.
+        Filter::Compare(Operand::Column(column), Comparison::Eq, Operand::Tenant) => format!(
+            "{}::text = {}",
+            identifier(column),
+            operand(&Operand::Tenant)
+        ),
+        Filter::Compare(left, comparison, right) => {
+            let operator = match comparison {
+                Comparison::Eq => "=",
+                Comparison::Neq => "<>",
+                Comparison::Lt => "<",
+                Comparison::Leq => "<=",
+                Comparison::Gt => ">",
+                Comparison::Geq => ">=",
+            };
+            format!("{} {} {}", operand(left), operator, operand(right))
+        }
+        Filter::In(_, values) if values.is_empty() => boolean(false),
+        Filter::In(value, values) => format!(
+            "{} IN ({})",
+            operand(value),
+            values.iter().map(operand).collect::<Vec<_>>().join(", ")
+        ),
+        Filter::ActorIs(actor, filter) => format!(
+            "current_user = {} AND ({})",
+            operand(actor),
+            condition(filter)
+        ),
+    }
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Actor => "current_user".to_string(),
+        Operand::Tenant => "current_setting('oso.tenant')".to_string(),
+        Operand::Column(column) => identifier(column),
+        Operand::Literal(Value::Number(Numeric::Integer(i))) => i.to_string(),
+        Operand::Literal(Value::Number(Numeric::Float(f))) => f.to_string(),
+        Operand::Literal(Value::Boolean(b)) => boolean(*b),
+        Operand::Literal(Value::String(s)) => format!("'{}'", s.replace('\'', "''")),
+        Operand::Literal(value) => unreachable!("`{:?}` is not a literal", value),
+    }
+}
+
+fn boolean(b: bool) -> String {
+    if b { "TRUE" } else { "FALSE" }.to_string()
+}
+
+/// Quote a SQL identifier.
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
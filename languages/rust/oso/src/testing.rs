@@ -0,0 +1,328 @@
+//! Property tests for conversions between Rust and Polar values.
+//!
+//! [`roundtrip`] generates values of a type, converts each to Polar and
+//! back, and panics with the first value that does not convert back to
+//! itself. Applications can use it to check their own `ToPolar` and
+//! `FromPolar` implementations:
+//!
+//! ```
+//! oso::testing::roundtrip::<Vec<Option<String>>>();
+//! ```
+//!
+//! Values of host classes are converted through the classes registered on
+//! an `Oso`, with [`roundtrip_with`]. Generation is deterministic, so
+//! failures are reproducible.
+//!
+//! [`diff_results`] compares the results of two queries, e.g. of the same
+//! query before and after a policy change, or of a replayed recording.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+
+use polar_core::formatting::ToPolarString;
+
+use crate::simulation::Rng;
+use crate::{FromPolar, Oso, ResultSet, ToPolar};
+
+/// The number of values checked by [`roundtrip`].
+pub const DEFAULT_CASES: usize = 256;
+
+/// A deterministic source of generated values.
+pub struct Gen {
+    rng: Rng,
+    /// The largest collection generated.
+    size: usize,
+}
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng(seed),
+            size: 4,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// A collection length.
+    pub fn length(&mut self) -> usize {
+        self.below(self.size + 1)
+    }
+
+    /// An integer, with extreme values and small values more likely than
+    /// in a uniform distribution.
+    pub fn i64(&mut self) -> i64 {
+        match self.below(8) {
+            0 => [0, 1, -1, i64::MIN, i64::MAX][self.below(5)],
+            1 | 2 => self.below(201) as i64 - 100,
+            _ => self.next_u64() as i64,
+        }
+    }
+}
+
+/// Types whose values can be generated.
+pub trait Arbitrary: Sized {
+    fn arbitrary(g: &mut Gen) -> Self;
+}
+
+impl Arbitrary for bool {
+    fn arbitrary(g: &mut Gen) -> Self {
+        g.below(2) == 1
+    }
+}
+
+/// Integers are truncated from an `i64`, so that wide integers stay in the
+/// range that Polar represents.
+macro_rules! int_arbitrary {
+    ($($i:ty)+) => {
+        $(
+            impl Arbitrary for $i {
+                fn arbitrary(g: &mut Gen) -> Self {
+                    g.i64() as $i
+                }
+            }
+        )+
+    };
+}
+
+int_arbitrary!(u8 i8 u16 i16 u32 i32 i64 isize i128);
+
+macro_rules! unsigned_arbitrary {
+    ($($i:ty)+) => {
+        $(
+            impl Arbitrary for $i {
+                fn arbitrary(g: &mut Gen) -> Self {
+                    (g.i64() as u64 >> 1) as $i
+                }
+            }
+        )+
+    };
+}
+
+unsigned_arbitrary!(u64 usize u128);
+
+/// Finite floats only, since `NaN` is not equal to itself.
+impl Arbitrary for f64 {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match g.below(4) {
+            0 => [0.0, -0.0, 1.5, f64::MIN, f64::MAX, f64::EPSILON][g.below(6)],
+            1 => g.i64() as f64,
+            _ => {
+                let f = f64::from_bits(g.next_u64());
+                if f.is_finite() {
+                    f
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+const CHARS: &[char] = &[
+    'a', 'z', 'A', 'Z', '0', '9', ' ', '_', '-', '.', '"', '\'', '\\', '\n', '\t', '\0', 'é', 'ß',
+    '€', '中', '😀',
+];
+
+impl Arbitrary for String {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let len = g.length() * 2;
+        (0..len).map(|_| CHARS[g.below(CHARS.len())]).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        if g.below(4) == 0 {
+            None
+        } else {
+            Some(T::arbitrary(g))
+        }
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Vec<T> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let len = g.length();
+        (0..len).map(|_| T::arbitrary(g)).collect()
+    }
+}
+
+impl<T: Arbitrary + Eq + Hash> Arbitrary for HashSet<T> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Vec::arbitrary(g).into_iter().collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for HashMap<String, T> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Vec::arbitrary(g).into_iter().collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for BTreeMap<String, T> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Vec::arbitrary(g).into_iter().collect()
+    }
+}
+
+impl<A: Arbitrary, B: Arbitrary> Arbitrary for (A, B) {
+    fn arbitrary(g: &mut Gen) -> Self {
+        (A::arbitrary(g), B::arbitrary(g))
+    }
+}
+
+/// Check that [`DEFAULT_CASES`] generated values of `T` convert to Polar
+/// and back unchanged, panicking with the first that doesn't.
+pub fn roundtrip<T>()
+where
+    T: Arbitrary + ToPolar + FromPolar + PartialEq + Debug,
+{
+    roundtrip_with::<T>(&Oso::new(), DEFAULT_CASES)
+}
+
+/// Like [`roundtrip`], converting values with the classes registered on
+/// `oso`, and checking `cases` values.
+pub fn roundtrip_with<T>(oso: &Oso, cases: usize)
+where
+    T: Arbitrary + ToPolar + FromPolar + PartialEq + Debug,
+{
+    let mut g = Gen::new(0);
+    for case in 0..cases {
+        let value = T::arbitrary(&mut g);
+        let mut host = oso.host.lock().unwrap();
+        let result = value
+            .try_to_polar(&mut host)
+            .and_then(|term| T::from_polar(&term, &mut host));
+        match result {
+            Ok(converted) if converted == value => (),
+            Ok(converted) => panic!(
+                "{} round trip failed for case {}: {:?} converted back to {:?}",
+                std::any::type_name::<T>(),
+                case,
+                value,
+                converted
+            ),
+            Err(e) => panic!(
+                "{} round trip failed for case {}: {:?}: {}",
+                std::any::type_name::<T>(),
+                case,
+                value,
+                e
+            ),
+        }
+    }
+}
+
+/// The results of one query missing from, or added in, another, each
+/// rendered as its bindings, e.g. `{x: 1, y: "a"}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResultDiff {
+    /// Results of the first query that the second doesn't have.
+    pub removed: Vec<String>,
+    /// Results of the second query that the first doesn't have.
+    pub added: Vec<String>,
+}
+
+impl ResultDiff {
+    /// Whether the queries had the same results.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+/// One line per result, `-` for removed and `+` for added ones.
+impl fmt::Display for ResultDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.removed {
+            writeln!(f, "- {}", result)?;
+        }
+        for result in &self.added {
+            writeln!(f, "+ {}", result)?;
+        }
+        Ok(())
+    }
+}
+
+/// Render the bindings of a result, ordered by variable name.
+fn render(result: &ResultSet) -> String {
+    let bindings = result
+        .bindings
+        .iter()
+        .map(|(var, value)| (&var.0, value.to_polar()))
+        .collect::<BTreeMap<_, _>>();
+    let bindings = bindings
+        .iter()
+        .map(|(var, value)| format!("{}: {}", var, value))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", bindings.join(", "))
+}
+
+/// Compare the results `a` of one query with the results `b` of another,
+/// ignoring their order. A result found more often in one than in the other
+/// is in the diff as many times as the difference.
+pub fn diff_results(a: &[ResultSet], b: &[ResultSet]) -> ResultDiff {
+    let mut counts = BTreeMap::<String, isize>::new();
+    for result in a {
+        *counts.entry(render(result)).or_default() += 1;
+    }
+    for result in b {
+        *counts.entry(render(result)).or_default() -= 1;
+    }
+    let mut diff = ResultDiff::default();
+    for (result, count) in counts {
+        let side = if count > 0 {
+            &mut diff.removed
+        } else {
+            &mut diff.added
+        };
+        side.extend(std::iter::repeat(result).take(count.abs() as usize));
+    }
+    diff
+}
+
+/// Like [`diff_results`], but also comparing the order of the results: the
+/// diff is the fewest results to remove from `a` and add to `b` for the
+/// rest to be in the same order, in the order of `a` and `b`.
+pub fn diff_results_ordered(a: &[ResultSet], b: &[ResultSet]) -> ResultDiff {
+    let a = a.iter().map(render).collect::<Vec<_>>();
+    let b = b.iter().map(render).collect::<Vec<_>>();
+
+    // Longest common subsequence lengths of the suffixes of `a` and `b`.
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = ResultDiff::default();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.removed.push(a[i].clone());
+            i += 1;
+        } else {
+            diff.added.push(b[j].clone());
+            j += 1;
+        }
+    }
+    diff.removed.extend_from_slice(&a[i..]);
+    diff.added.extend_from_slice(&b[j..]);
+    diff
+}
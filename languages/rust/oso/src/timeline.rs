@@ -0,0 +1,84 @@
+//! Timelines of the evaluation of queries, for performance investigations.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use polar_core::events::QueryEvent;
+use polar_core::timeline::{chrome_trace, TimelineEvent};
+
+/// The goals a query ran, the rules it was in and the host calls it made,
+/// over time.
+///
+/// Record a query with [`Query::record_timeline`](crate::Query::record_timeline),
+/// then write the timeline with [`write_chrome_trace`](Timeline::write_chrome_trace)
+/// and open it in `about://tracing` or <https://ui.perfetto.dev>.
+#[derive(Clone, Default)]
+pub struct Timeline(Arc<Mutex<Entries>>);
+
+#[derive(Default)]
+struct Entries {
+    /// When the query started, which the times of events are relative to.
+    origin: Option<Instant>,
+    events: Vec<TimelineEvent>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of events recorded.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Start recording a query, forgetting the previous one.
+    pub(crate) fn start(&self) {
+        *self.0.lock().unwrap() = Entries {
+            origin: Some(Instant::now()),
+            events: vec![],
+        };
+    }
+
+    pub(crate) fn push(&self, event: TimelineEvent) {
+        self.0.lock().unwrap().events.push(event);
+    }
+
+    pub(crate) fn extend(&self, events: Vec<TimelineEvent>) {
+        self.0.lock().unwrap().events.extend(events);
+    }
+
+    /// The timeline as a Chrome trace event document.
+    pub fn to_chrome_trace(&self) -> String {
+        let entries = self.0.lock().unwrap();
+        let origin = entries
+            .origin
+            .or_else(|| entries.events.iter().map(|event| event.start).min())
+            .unwrap_or_else(Instant::now);
+        chrome_trace(&entries.events, origin)
+    }
+
+    /// Write the timeline to `path` as a Chrome trace event document.
+    pub fn write_chrome_trace<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_chrome_trace())
+    }
+}
+
+/// The name of the host call asked for by `event` in timelines, if it
+/// asks for one.
+pub(crate) fn host_call_name(event: &QueryEvent) -> Option<String> {
+    match event {
+        QueryEvent::MakeExternal { constructor, .. } => Some(format!("new {}", constructor)),
+        QueryEvent::ExternalCall { attribute, .. } => Some(format!(".{}", attribute)),
+        QueryEvent::ExternalOp { operator, .. } => Some(format!("{:?}", operator)),
+        QueryEvent::ExternalIsa { class_tag, .. } => Some(format!("matches {}", class_tag)),
+        QueryEvent::ExternalUnify { .. } => Some("unify".to_string()),
+        QueryEvent::ExternalIsSubSpecializer { .. } => Some("is_subspecializer".to_string()),
+        _ => None,
+    }
+}
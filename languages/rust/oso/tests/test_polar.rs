@@ -1,5 +1,5 @@
 use maplit::hashmap;
-use oso::{Class, HostClass, Oso, PolarClass, ToPolar};
+use oso::{Class, Enumeration, HostClass, Oso, PolarClass, PolarValue, ToPolar};
 use oso_derive::*;
 
 struct OsoTest {
@@ -120,18 +120,81 @@ fn test_data_conversions() {
     test.qvar_one("a(x)", "x", 1);
     test.qvar_one("b(x)", "x", "two".to_string());
     test.qvar_one("c(x)", "x", true);
-    use polar_core::terms::Value;
     // TODO: do we want to handle hlists better?
     // e.g. https://docs.rs/hlist/0.1.2/hlist/
     test.qvar_one(
         "d(x)",
         "x",
         vec![
-            Value::Number(polar_core::terms::Numeric::Integer(1)),
-            Value::String("two".to_string()),
-            Value::Boolean(true),
+            PolarValue::Integer(1),
+            PolarValue::String("two".to_string()),
+            PolarValue::Bool(true),
         ],
     );
+    test.qvar_one("a(x)", "x", PolarValue::Integer(1));
+
+    let results = test.query("d(x)");
+    assert_eq!(
+        results[0].get("x").unwrap(),
+        Some(PolarValue::List(vec![
+            PolarValue::Integer(1),
+            PolarValue::String("two".to_string()),
+            PolarValue::Bool(true),
+        ]))
+    );
+    assert_eq!(
+        results[0].get("y").unwrap(),
+        None,
+        "unbound names have no value"
+    );
+}
+
+#[test]
+fn test_string_conversions() {
+    use std::borrow::Cow;
+    use std::sync::Arc;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Names {
+        #[polar(attribute)]
+        initial: char,
+        #[polar(attribute)]
+        borrowed: Cow<'static, str>,
+        #[polar(attribute)]
+        owned: Cow<'static, str>,
+        #[polar(attribute)]
+        shared: Arc<str>,
+        #[polar(attribute)]
+        boxed: Box<str>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Names::get_polar_class()).unwrap();
+    test.load_str(
+        r#"names(names: Names, x) if
+               x = [names.initial, names.borrowed, names.owned, names.shared, names.boxed];"#,
+    );
+    let names = Names {
+        initial: 'a',
+        borrowed: Cow::Borrowed("alice"),
+        owned: Cow::Owned("bob".to_string()),
+        shared: Arc::from("carol"),
+        boxed: Box::from("dave"),
+    };
+    let mut query = test
+        .oso
+        .query_rule(
+            "names",
+            vec![&names as &dyn ToPolar, &PolarValue::Variable("x".into())],
+        )
+        .unwrap();
+    let result = query.next().unwrap().unwrap();
+    assert_eq!(
+        result.get_typed::<Vec<String>>("x").unwrap(),
+        vec!["a", "alice", "bob", "carol", "dave"]
+    );
 }
 
 // This logic is changing. Updated when fixed
@@ -445,8 +508,9 @@ fn test_results_and_options() {
     test.qvar_one(r#"new Foo().ok() = x"#, "x", 1);
     test.query_err("new Foo().err()");
     test.qvar_one(r#"new Foo().some() = x"#, "x", 1);
-    let results = test.query("new Foo().none()");
-    assert!(results.is_empty());
+    // `None` is `nil`, rather than no results.
+    test.qvar_one(r#"new Foo().none() = x"#, "x", Option::<i32>::None);
+    test.qeval("new Foo().none() = nil");
 }
 
 // TODO: dhatch see if there is a relevant test to port.
@@ -580,3 +644,4015 @@ fn test_values() {
     assert!(result == vec![vec![1, 2, 3]]);
     println!("{:?}", result);
 }
+
+#[test]
+fn test_wide_integers() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str("f(x) if x = 9223372036854775807; g(-1);");
+    test.qvar_one("f(x)", "x", i64::MAX as u64);
+    test.qvar_one("f(x)", "x", i64::MAX as usize);
+    test.qvar_one("f(x)", "x", i64::MAX as u128);
+    test.qvar_one("g(x)", "x", -1i128);
+
+    let mut results = test.query("g(x)");
+    let err = results.pop().unwrap().get_typed::<u64>("x").unwrap_err();
+    assert!(matches!(err, oso::OsoError::IntegerOverflow { .. }));
+
+    test.load_str("h(x, x);");
+    let mut results = test
+        .oso
+        .query_rule(
+            "h",
+            vec![&5u64 as &dyn ToPolar, &PolarValue::Variable("y".into())],
+        )
+        .unwrap();
+    assert_eq!(
+        results
+            .next()
+            .unwrap()
+            .unwrap()
+            .get_typed::<u64>("y")
+            .unwrap(),
+        5
+    );
+
+    let err = test
+        .oso
+        .query_rule("h", vec![&u64::MAX as &dyn ToPolar, &0])
+        .err()
+        .expect("u64::MAX does not fit in a Polar integer");
+    assert!(matches!(err, oso::OsoError::IntegerOverflow { .. }));
+    assert!(test
+        .oso
+        .register_constant("big", &vec![1u128 << 64])
+        .is_err());
+}
+
+#[test]
+fn test_std_time() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Token {
+        #[polar(attribute)]
+        expires_at: SystemTime,
+        #[polar(attribute)]
+        idle: Duration,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Token::get_polar_class()).unwrap();
+    test.load_str(
+        r#"allow(now, "use", token: Token) if
+               now < token.expires_at and token.idle < 300;"#,
+    );
+
+    let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let token = |expires_in: u64, idle: u64| Token {
+        expires_at: now + Duration::from_secs(expires_in),
+        idle: Duration::from_secs(idle),
+    };
+    assert!(test.oso.is_allowed(now, "use", token(60, 10)).unwrap());
+    assert!(!test.oso.is_allowed(now, "use", token(0, 10)).unwrap());
+    assert!(!test.oso.is_allowed(now, "use", token(60, 600)).unwrap());
+
+    test.qvar_one("x = 1.5", "x", Duration::from_millis(1500));
+    test.qvar_one("x = 90", "x", Duration::from_secs(90));
+    test.qvar_one("x = 1600000000", "x", now);
+    test.qvar_one("x = -1", "x", UNIX_EPOCH - Duration::from_secs(1));
+    assert!(test
+        .oso
+        .query("x = -1")
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .get_typed::<Duration>("x")
+        .is_err());
+}
+
+#[test]
+fn test_option() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(PolarClass)]
+    struct Foo {
+        #[polar(attribute)]
+        maybe: Option<i64>,
+    }
+
+    impl Foo {
+        fn new(maybe: Option<i64>) -> Self {
+            Self { maybe }
+        }
+
+        fn or_zero(&self, x: Option<i64>) -> i64 {
+            x.unwrap_or(0)
+        }
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Foo::get_polar_class_builder()
+                .set_constructor(Foo::new)
+                .add_method("or_zero", Foo::or_zero)
+                .build(),
+        )
+        .unwrap();
+
+    test.qeval("new Foo(nil).maybe = nil");
+    test.qvar_one("new Foo(3).maybe = x", "x", 3);
+    test.qvar_one("new Foo(nil).maybe = x", "x", Option::<i64>::None);
+
+    test.qvar_one("new Foo(nil).or_zero(nil) = x", "x", 0);
+    test.qvar_one("new Foo(nil).or_zero(2) = x", "x", 2);
+
+    test.load_str("f(nil); f(1);");
+    assert_eq!(test.qvar::<Option<i64>>("f(x)", "x"), vec![None, Some(1)]);
+    test.qeval("nil = nil");
+}
+
+#[test]
+fn test_composite_results() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(PolarClass, Default)]
+    struct Foo;
+
+    const PRIMES: &[i64] = &[2, 3, 5];
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Foo::get_polar_class_builder()
+                .set_constructor(Foo::default)
+                .add_method("pair", |_: &Foo| (1, "one".to_string()))
+                .add_method("array", |_: &Foo| [true, false])
+                .add_method("primes", |_: &Foo| PRIMES)
+                .build(),
+        )
+        .unwrap();
+
+    test.qeval(r#"new Foo().pair() = [1, "one"]"#);
+    test.qeval("new Foo().array() = [true, false]");
+    test.qvar_one("new Foo().primes() = x", "x", vec![2, 3, 5]);
+
+    test.load_str("f(x, x);");
+    let mut results = test
+        .oso
+        .query_rule(
+            "f",
+            vec![
+                &(1, 2.5, "three") as &dyn ToPolar,
+                &PolarValue::Variable("y".to_string()),
+            ],
+        )
+        .unwrap();
+    let y = results.next().unwrap().unwrap().get("y").unwrap().unwrap();
+    assert_eq!(
+        y,
+        PolarValue::List(vec![
+            PolarValue::Integer(1),
+            PolarValue::Float(2.5),
+            PolarValue::String("three".to_string())
+        ])
+    );
+}
+
+#[test]
+fn test_collections() {
+    use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"
+        a({x: 1, y: 2});
+        b([1, 2, 2, 3]);
+        c({x: [1, 2], y: []});
+        d([{x: 1}, {y: 2}]);"#,
+    );
+
+    let mut btree = BTreeMap::new();
+    btree.insert("x".to_string(), 1);
+    btree.insert("y".to_string(), 2);
+    test.qvar_one("a(x)", "x", btree);
+
+    let set: HashSet<i64> = vec![1, 2, 3].into_iter().collect();
+    test.qvar_one("b(x)", "x", set);
+    test.qvar_one("b(x)", "x", VecDeque::from(vec![1, 2, 2, 3]));
+
+    let mut nested = BTreeMap::new();
+    nested.insert("x".to_string(), VecDeque::from(vec![1, 2]));
+    nested.insert("y".to_string(), VecDeque::new());
+    test.qvar_one("c(x)", "x", nested);
+
+    let dicts = test.qvar::<VecDeque<HashMap<String, i64>>>("d(x)", "x");
+    assert_eq!(dicts[0][0]["x"], 1);
+    assert_eq!(dicts[0][1]["y"], 2);
+
+    test.load_str("e(x, x);");
+    let mut btree = BTreeMap::new();
+    btree.insert("b".to_string(), vec![1]);
+    btree.insert("a".to_string(), vec![]);
+    let y = PolarValue::Variable("y".to_string());
+    let mut results = test
+        .oso
+        .query_rule("e", vec![&btree as &dyn ToPolar, &y])
+        .unwrap();
+    let result = results.next().unwrap().unwrap();
+    assert_eq!(
+        result.get_typed::<BTreeMap<String, Vec<i64>>>("y").unwrap(),
+        btree
+    );
+
+    let set: HashSet<String> = vec!["a".to_string(), "b".to_string()].into_iter().collect();
+    let mut results = test
+        .oso
+        .query_rule("e", vec![&set as &dyn ToPolar, &y])
+        .unwrap();
+    let result = results.next().unwrap().unwrap();
+    assert_eq!(result.get_typed::<HashSet<String>>("y").unwrap(), set);
+}
+
+#[test]
+fn test_arities() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(PolarClass, Default)]
+    struct Foo;
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Foo::get_polar_class_builder()
+                .set_constructor(Foo::default)
+                .add_method(
+                    "sum",
+                    |_: &Foo,
+                     a: i64,
+                     b: i64,
+                     c: i64,
+                     d: i64,
+                     e: i64,
+                     f: i64,
+                     g: i64,
+                     h: i64,
+                     i: i64,
+                     j: i64,
+                     k: i64,
+                     l: i64| a + b + c + d + e + f + g + h + i + j + k + l,
+                )
+                .add_class_method("join", |a: String, b: String, c: String| {
+                    format!("{}{}{}", a, b, c)
+                })
+                .build(),
+        )
+        .unwrap();
+
+    test.qvar_one(
+        "new Foo().sum(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12) = x",
+        "x",
+        78,
+    );
+    test.query_err("new Foo().sum(1, 2, 3) = x");
+    test.qvar_one(r#"Foo.join("a", "b", "c") = x"#, "x", "abc".to_string());
+}
+
+#[test]
+fn test_conditions() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.qeval(r#"Condition.StringLike("home/alice/notes.txt", "home/*/*.txt")"#);
+    test.qeval(r#"Condition.StringLike("a*b", "a?b")"#);
+    test.qnull(r#"Condition.StringLike("home/alice", "home/?")"#);
+    test.qeval(r#"Condition.StringNotLike("tmp/x", "home/*")"#);
+    test.qeval(r#"Condition.StringEqualsIgnoreCase("Alice", "ALICE")"#);
+
+    test.qeval("Condition.NumericLessThanEquals(3, 3)");
+    test.qeval(r#"Condition.NumericLessThanEquals(2.5, "3")"#);
+    test.qnull("Condition.NumericGreaterThan(1, 2)");
+    test.query_err(r#"Condition.NumericLessThan("one", 2)"#);
+
+    test.qeval(r#"Condition.DateGreaterThan("2020-06-01T12:00:00Z", "2020-06-01")"#);
+    test.qeval(r#"Condition.DateEquals("2020-06-01T14:00:00+02:00", "2020-06-01T12:00:00Z")"#);
+    test.qeval(r#"Condition.DateLessThan("1969-12-31T23:59:59Z", 0)"#);
+    test.qnull(r#"Condition.DateGreaterThan("2019-12-31", "2020-01-01")"#);
+    test.query_err(r#"Condition.DateLessThan("yesterday", 0)"#);
+
+    test.qeval(r#"Condition.IpAddress("10.1.2.3", "10.0.0.0/8")"#);
+    test.qeval(r#"Condition.IpAddress("192.168.0.1", "192.168.0.1")"#);
+    test.qeval(r#"Condition.IpAddress("2001:db8::1", "2001:db8::/32")"#);
+    test.qnull(r#"Condition.IpAddress("11.0.0.1", "10.0.0.0/8")"#);
+    test.qnull(r#"Condition.IpAddress("10.0.0.1", "2001:db8::/32")"#);
+    test.qeval(r#"Condition.NotIpAddress("11.0.0.1", "10.0.0.0/8")"#);
+    test.query_err(r#"Condition.IpAddress("10.0.0.1", "10.0.0.0/33")"#);
+
+    test.qeval("Condition.Bool(true, true)");
+
+    test.load_str(
+        r#"allow(actor, "read", resource) if
+            Condition.StringLike(resource, "home/*") and
+            Condition.IpAddress(actor, "10.0.0.0/8");"#,
+    );
+    assert!(test
+        .oso
+        .is_allowed("10.0.0.1", "read", "home/notes")
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed("10.0.0.1", "read", "etc/passwd")
+        .unwrap());
+}
+
+#[test]
+fn test_network() {
+    use std::net::IpAddr;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Request {
+        #[polar(attribute)]
+        ip: IpAddr,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Request::get_polar_class()).unwrap();
+
+    test.qeval(r#"IpAddr.parse("10.1.2.3") in Network.parse("10.0.0.0/8")"#);
+    test.qeval(r#""10.1.2.3" in Network.parse("10.0.0.0/8")"#);
+    test.qeval(r#""2001:db8::1" in Network.parse("2001:db8::/32")"#);
+    test.qeval(r#""192.168.0.1" in Network.parse("192.168.0.1")"#);
+    test.qnull(r#""11.0.0.1" in Network.parse("10.0.0.0/8")"#);
+    test.qnull(r#""10.0.0.1" in Network.parse("2001:db8::/32")"#);
+    test.qnull(r#""not an address" in Network.parse("10.0.0.0/8")"#);
+    test.query_err(r#"Network.parse("10.0.0.0/33")"#);
+    test.query_err(r#"1 in IpAddr.parse("10.0.0.1")"#);
+
+    test.qeval(r#"IpAddr.parse("10.0.0.1") == IpAddr.parse("10.0.0.1")"#);
+    test.qeval(r#"IpAddr.parse("::1").is_loopback()"#);
+    test.qvar_one(r#"x = Network.parse("10.1.0.0/16").prefix"#, "x", 16);
+    test.qvar_one(
+        r#"x = Network.parse("10.1.0.0/16").to_string()"#,
+        "x",
+        "10.1.0.0/16".to_string(),
+    );
+
+    test.load_str(
+        r#"allow(_, "read", request: Request) if request.ip in Network.parse("10.0.0.0/8");"#,
+    );
+    let request = |ip: &str| Request {
+        ip: ip.parse().unwrap(),
+    };
+    assert!(test
+        .oso
+        .is_allowed("alice", "read", request("10.0.0.1"))
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed("alice", "read", request("192.168.0.1"))
+        .unwrap());
+}
+
+#[test]
+fn test_rule_conflicts() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"f(x, _) if x > 0;
+           f(_, y) if y > 0;
+           g(_, "a") if cut;
+           g(_, "b");
+           h(x: Integer, _) if cut and x > 0;
+           h(x, _) if x < 0;"#,
+    );
+    assert!(test.oso.rule_conflicts().is_empty());
+
+    test.load_str(
+        r#"allow(_, action, _) if cut and action = "read";
+           allow(_, "write", _);"#,
+    );
+    let conflicts = test.oso.rule_conflicts();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].rule, "allow");
+    assert!(conflicts[0].shadowing.contains("cut"));
+    assert!(conflicts[0].shadowed.contains("\"write\""));
+    assert!(conflicts[0].suggestion.contains("narrower rule first"));
+    // The outcome depends on the order: the second rule is never tried.
+    assert!(!test.oso.is_allowed("alice", "write", "doc").unwrap());
+}
+
+#[test]
+fn test_deny_with_reason() {
+    use oso::{ForbiddenError, OsoError};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"allow(_, _, _);
+           deny(_, "upload", size, {code: "quota_exceeded", message: "Storage quota exceeded"})
+               if size > 100;
+           deny("mallory", _, _, "Account suspended");"#,
+    );
+
+    test.oso.authorize("alice", "upload", 10).unwrap();
+    match test.oso.authorize("alice", "upload", 1000) {
+        Err(OsoError::Forbidden(e)) => assert_eq!(
+            e,
+            ForbiddenError {
+                code: Some("quota_exceeded".to_string()),
+                message: Some("Storage quota exceeded".to_string()),
+            }
+        ),
+        r => panic!("expected a forbidden error, got {:?}", r),
+    }
+    let err = test.oso.authorize("mallory", "read", 1).unwrap_err();
+    assert_eq!(err.to_string(), "forbidden: Account suspended");
+
+    let mut test = OsoTest::new();
+    test.load_str(r#"allow("alice", _, _);"#);
+    test.oso.authorize("alice", "read", 1).unwrap();
+    match test.oso.authorize("bob", "read", 1) {
+        Err(OsoError::Forbidden(e)) => assert_eq!(e, ForbiddenError::default()),
+        r => panic!("expected a forbidden error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_message_resolver() {
+    use oso::OsoError;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"deny(_, "upload", _, {code: "quota_exceeded", message: "Quota exceeded"});
+           deny(_, "delete", _, {code: "unknown_code"});
+           deny(_, "share", _, "Sharing is disabled");"#,
+    );
+    let mut forbidden = |action: &'static str| match test.oso.authorize("alice", action, 1) {
+        Err(OsoError::Forbidden(e)) => e,
+        r => panic!("expected a forbidden error, got {:?}", r),
+    };
+    let (upload, delete, share) = (forbidden("upload"), forbidden("delete"), forbidden("share"));
+
+    assert_eq!(
+        test.oso.resolve_message(&upload, "de"),
+        Some("Quota exceeded".to_string())
+    );
+
+    test.oso
+        .set_message_resolver(|code, locale| match (code, locale) {
+            ("quota_exceeded", "de") => Some("Speicherplatz erschöpft".to_string()),
+            ("quota_exceeded", _) => Some("Storage quota exceeded".to_string()),
+            _ => None,
+        });
+    assert_eq!(
+        test.oso.resolve_message(&upload, "de"),
+        Some("Speicherplatz erschöpft".to_string())
+    );
+    assert_eq!(
+        test.oso.resolve_message(&upload, "en"),
+        Some("Storage quota exceeded".to_string())
+    );
+    assert_eq!(test.oso.resolve_message(&delete, "en"), None);
+    assert_eq!(
+        test.oso.resolve_message(&share, "en"),
+        Some("Sharing is disabled".to_string())
+    );
+}
+
+#[test]
+fn test_rollout_bucket() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    // Buckets are fixed by the hashing scheme, so hosts agree on them.
+    test.qvar_one(r#"x = Rollout.bucket("alice", "beta", 100)"#, "x", 33);
+    test.qvar_one(r#"x = Rollout.bucket(42, "beta", 100)"#, "x", 83);
+    test.qvar_one(r#"x = Rollout.bucket("alice", "other", 100)"#, "x", 21);
+    test.query_err(r#"Rollout.bucket("alice", "beta", 0)"#);
+    test.query_err(r#"Rollout.bucket(1.5, "beta", 100)"#);
+
+    test.load_str(r#"allow(actor, "use", "beta") if Rollout.bucket(actor, "beta", 100) < 20;"#);
+    let allowed = (0..1000)
+        .filter(|i| {
+            test.oso
+                .is_allowed(format!("user-{}", i), "use", "beta")
+                .unwrap()
+        })
+        .count();
+    assert_eq!(allowed, 187);
+}
+
+#[test]
+fn test_class_aliases() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo {
+        #[polar(attribute)]
+        public: bool,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_constructor(|| Repo { public: true })
+                .build(),
+        )
+        .unwrap();
+    test.oso.register_alias("LegacyRepo", "Repo").unwrap();
+    test.oso
+        .register_aliases(hashmap! {"OldRepo" => "Repo", "AncientRepo" => "Repo"})
+        .unwrap();
+    assert!(test.oso.register_alias("Repo", "String").is_err());
+    assert!(test.oso.register_alias("LegacyUser", "User").is_err());
+
+    test.load_str(
+        r#"allow(_, "read", repo: LegacyRepo) if repo.public;
+           allow(_, "write", _: AncientRepo);"#,
+    );
+    assert!(test
+        .oso
+        .is_allowed("alice", "read", Repo { public: true })
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed("alice", "read", Repo { public: false })
+        .unwrap());
+    assert!(test
+        .oso
+        .is_allowed("alice", "write", Repo::default())
+        .unwrap());
+    test.qeval("new OldRepo().public = true");
+    test.qeval("new OldRepo() matches Repo");
+}
+
+#[test]
+fn test_register_constants() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.oso.register_constant("ENV", "production").unwrap();
+    test.oso.register_constant("MAX_SIZE", &100).unwrap();
+    test.oso.register_constant("RATIO", &0.5).unwrap();
+    test.oso
+        .register_constant("ADMINS", &vec!["alice", "bob"])
+        .unwrap();
+    test.oso
+        .register_constant("LIMITS", &hashmap! {"upload".to_string() => 10})
+        .unwrap();
+
+    test.qvar_one("x = ENV", "x", "production".to_string());
+    test.qeval("MAX_SIZE = 100 and RATIO < 1");
+    test.qeval(r#""bob" in ADMINS"#);
+    test.qvar_one("x = LIMITS.upload", "x", 10);
+
+    test.load_str(
+        r#"allow(actor, "upload", size) if
+               ENV = "production" and actor in ADMINS and size <= MAX_SIZE;"#,
+    );
+    assert!(test.oso.is_allowed("alice", "upload", 50).unwrap());
+    assert!(!test.oso.is_allowed("alice", "upload", 500).unwrap());
+    assert!(!test.oso.is_allowed("carol", "upload", 50).unwrap());
+}
+
+#[test]
+fn test_class_constants() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Upload {
+        #[polar(attribute)]
+        size: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Upload::get_polar_class_builder()
+                .add_constant("MAX_SIZE", 100)
+                .add_constant("KINDS", vec!["image", "video"])
+                .build(),
+        )
+        .unwrap();
+
+    test.qvar_one("x = Upload.MAX_SIZE", "x", 100);
+    test.qeval(r#""video" in Upload.KINDS"#);
+    test.query_err("x = Upload.MIN_SIZE");
+
+    test.load_str(r#"allow(_, "create", upload: Upload) if upload.size <= Upload.MAX_SIZE;"#);
+    assert!(test
+        .oso
+        .is_allowed("alice", "create", Upload { size: 50 })
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed("alice", "create", Upload { size: 500 })
+        .unwrap());
+}
+
+#[test]
+fn test_late_registration() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Widget;
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Widget::get_polar_class()).unwrap();
+    test.load_str(
+        r#"kind(_: Widget, "widget");
+           kind(_: Gadget, "gadget");"#,
+    );
+
+    let kinds = |query: oso::Query| -> Vec<String> {
+        query
+            .map(|result| result.unwrap().get_typed("x").unwrap())
+            .collect()
+    };
+    let x = PolarValue::Variable("x".into());
+    let args = || vec![&Widget as &dyn ToPolar, &x];
+    let before = test.oso.query_rule("kind", args()).unwrap();
+    let constant_before = test.oso.query("x = LATE").unwrap();
+
+    // Register from another thread, through a clone.
+    let oso = test.oso.clone();
+    std::thread::spawn(move || {
+        oso.register_alias("Gadget", "Widget").unwrap();
+        oso.register_constant("LATE", "registered").unwrap();
+    })
+    .join()
+    .unwrap();
+
+    // Queries created before the registration don't see it.
+    assert_eq!(kinds(before), vec!["widget"]);
+    let unbound = constant_before.collect::<Result<Vec<_>, _>>().unwrap();
+    assert!(unbound[0].get_typed::<String>("x").is_err());
+
+    // New queries do.
+    let after = test.oso.query_rule("kind", args()).unwrap();
+    assert_eq!(kinds(after), vec!["widget", "gadget"]);
+    test.qvar_one("x = LATE", "x", "registered".to_string());
+}
+
+#[test]
+fn test_query_scope() {
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Service;
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Service::get_polar_class_builder()
+                .set_constructor(|| Service)
+                .add_method("fail", |_: &Service| Err::<i32, _>("unavailable"))
+                .build(),
+        )
+        .unwrap();
+    test.load_str(
+        r#"
+        f(1);
+        f(2);
+        f(3);
+        allow("alice", "read", "doc");"#,
+    );
+
+    // An error in one query cancels the others.
+    let scope = test.oso.scope();
+    assert!(scope.is_allowed("alice", "read", "doc").unwrap());
+    let mut f = scope.query("f(x)").unwrap();
+    assert!(f.next().unwrap().is_ok());
+    assert!(!scope.is_cancelled());
+    let mut failing = scope.query("new Service().fail() = x").unwrap();
+    assert!(failing.next().unwrap().is_err());
+    assert!(scope.is_cancelled());
+    assert!(matches!(f.next(), Some(Err(oso::OsoError::Cancelled))));
+    assert!(f.next().is_none());
+    assert!(matches!(
+        scope.is_allowed("alice", "read", "doc"),
+        Err(oso::OsoError::Cancelled)
+    ));
+
+    // Queries are cancelled when their scope ends.
+    let scope = test.oso.scope();
+    let mut f = scope.query("f(x)").unwrap();
+    assert!(f.next().unwrap().is_ok());
+    drop(scope);
+    assert!(matches!(f.next(), Some(Err(oso::OsoError::Cancelled))));
+
+    let scope = test.oso.scope_with_timeout(Duration::from_secs(0));
+    assert!(matches!(
+        scope.is_allowed("alice", "read", "doc"),
+        Err(oso::OsoError::DeadlineExceeded)
+    ));
+
+    // Queries outside of a scope are unaffected.
+    test.qvar_one("f(x) and x > 2", "x", 3);
+}
+
+#[test]
+fn test_remaining_budget() {
+    use std::time::Duration;
+
+    #[derive(Clone, PolarClass)]
+    struct Directory;
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Directory::get_polar_class_builder()
+                .add_class_method("budget", || {
+                    oso::remaining_budget().map_or(-1.0, |budget| budget.as_secs_f64())
+                })
+                .build(),
+        )
+        .unwrap();
+
+    let scope = test.oso.scope_with_timeout(Duration::from_secs(60));
+    let mut query = scope.query("x = Directory.budget()").unwrap();
+    let budget: f64 = query.next().unwrap().unwrap().get_typed("x").unwrap();
+    assert!(budget > 0.0 && budget <= 60.0);
+
+    test.qvar_one("x = Directory.budget()", "x", -1.0);
+    assert_eq!(oso::remaining_budget(), None);
+}
+
+#[test]
+fn test_comparison_operators() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, Debug, PartialEq, PartialOrd, PolarClass)]
+    struct Version(u32, u32);
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Version::get_polar_class_builder()
+                .set_constructor(Version)
+                .with_equality_check()
+                .with_comparison_check()
+                .build(),
+        )
+        .unwrap();
+    test.qeval("new Version(1, 2) < new Version(1, 10)");
+    test.qeval("new Version(1, 2) <= new Version(1, 2)");
+    test.qeval("new Version(2, 0) > new Version(1, 10)");
+    test.qeval("new Version(2, 0) >= new Version(1, 10)");
+    test.qeval("new Version(2, 0) == new Version(2, 0)");
+    test.qeval("new Version(2, 0) != new Version(2, 1)");
+    test.qnull("new Version(2, 0) < new Version(1, 0)");
+
+    #[derive(Clone, PolarClass)]
+    struct Unordered;
+    test.oso
+        .register_class(
+            Unordered::get_polar_class_builder()
+                .set_constructor(|| Unordered)
+                .build(),
+        )
+        .unwrap();
+    test.query_err("new Unordered() < new Unordered()");
+
+    #[derive(Clone, PartialEq, PartialOrd, PolarClass)]
+    struct Score(f64);
+    test.oso
+        .register_class(
+            Score::get_polar_class_builder()
+                .set_constructor(Score)
+                .with_ordering()
+                .build(),
+        )
+        .unwrap();
+    test.qeval("new Score(0.5) < new Score(1.5)");
+    test.qeval("new Score(1.5) == new Score(1.5)");
+    test.oso
+        .register_constant("UNSCORED", &Score(f64::NAN))
+        .unwrap();
+    test.qnull("UNSCORED < new Score(1.0)");
+    test.qnull("UNSCORED >= new Score(1.0)");
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_chrono() {
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Document {
+        #[polar(attribute)]
+        expires_at: chrono::DateTime<Utc>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(Document::get_polar_class())
+        .unwrap();
+    test.load_str(
+        r#"
+        allow(_, "read", doc: Document) if doc.expires_at > Datetime.now();
+        recent(date) if date >= Date.parse("2020-01-01");"#,
+    );
+    let fresh = Document {
+        expires_at: Utc::now() + Duration::hours(1),
+    };
+    let stale = Document {
+        expires_at: Utc.ymd(2020, 1, 1).and_hms(0, 0, 0),
+    };
+    assert!(test.oso.is_allowed("alice", "read", fresh).unwrap());
+    assert!(!test.oso.is_allowed("alice", "read", stale).unwrap());
+
+    test.qeval(r#"recent(Date.from_ymd(2020, 8, 1))"#);
+    test.qnull(r#"recent(Date.from_ymd(2019, 12, 31))"#);
+    test.qvar_one(r#"x = Date.from_ymd(2020, 2, 30)"#, "x", None::<NaiveDate>);
+    test.qvar_one(r#"x = Date.parse("2020-08-01").month"#, "x", 8);
+    test.qvar_one(
+        r#"x = Datetime.from_timestamp(0).add(Duration.days(1))"#,
+        "x",
+        Utc.ymd(1970, 1, 2).and_hms(0, 0, 0),
+    );
+    test.qvar_one(
+        r#"x = Datetime.parse("2020-08-01T12:00:00Z").since(Datetime.from_timestamp(0)).num_seconds()"#,
+        "x",
+        1596283200,
+    );
+    test.qeval("Duration.minutes(1) < Duration.hours(1)");
+    test.qeval("Duration.minutes(60) == Duration.hours(1)");
+    test.query_err(r#"Datetime.parse("yesterday")"#);
+}
+
+#[test]
+fn test_instance_cache_policy() {
+    use oso::InstanceCachePolicy;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Foo {
+        #[polar(attribute)]
+        x: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Foo::get_polar_class_builder()
+                .set_constructor(|x: i64| Foo { x })
+                .build(),
+        )
+        .unwrap();
+    test.oso.register_constant("foo", &Foo { x: 1 }).unwrap();
+    test.load_str("allow(foo: Foo, _, _) if foo.x = 1;");
+    let evicted = Arc::new(AtomicUsize::new(0));
+    let counter = evicted.clone();
+    test.oso.on_instance_evicted(move |_, _| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    // By default, every instance is kept.
+    let pinned = test.oso.cached_instances();
+    for _ in 0..5 {
+        assert!(test.oso.is_allowed(Foo { x: 1 }, "read", "doc").unwrap());
+    }
+    assert_eq!(test.oso.cached_instances(), pinned + 5);
+
+    test.oso
+        .set_instance_cache_policy(InstanceCachePolicy::Lru { max_entries: 2 });
+    assert_eq!(test.oso.cached_instances(), pinned + 2);
+    assert_eq!(evicted.load(Ordering::SeqCst), 3);
+    for _ in 0..5 {
+        assert!(test.oso.is_allowed(Foo { x: 1 }, "read", "doc").unwrap());
+    }
+    assert_eq!(test.oso.cached_instances(), pinned + 2);
+
+    // Instances are kept while a query or its results are alive.
+    test.oso
+        .set_instance_cache_policy(InstanceCachePolicy::PerQuery);
+    assert_eq!(test.oso.cached_instances(), pinned);
+    let results = test.query("x = new Foo(2)");
+    assert_eq!(test.oso.cached_instances(), pinned + 1);
+    assert_eq!(results[0].get_typed::<Foo>("x").unwrap().x, 2);
+    drop(results);
+    assert_eq!(test.oso.cached_instances(), pinned);
+
+    // Constants are never evicted.
+    test.qvar_one("x = foo.x", "x", 1);
+    test.qeval("new Foo(3) matches Foo");
+}
+
+#[test]
+fn test_lru_eviction_while_queries_run() {
+    use oso::InstanceCachePolicy;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Foo {
+        #[polar(attribute)]
+        x: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Foo::get_polar_class_builder()
+                .set_constructor(|x: i64| Foo { x })
+                .build(),
+        )
+        .unwrap();
+    test.load_str("allow(actor: Foo, _, resource: Foo) if actor.x = resource.x;");
+    test.oso
+        .set_instance_cache_policy(InstanceCachePolicy::Lru { max_entries: 1 });
+    let pinned = test.oso.cached_instances();
+
+    // Queries keep the instances they bind, even beyond the capacity.
+    assert!(test
+        .oso
+        .is_allowed(Foo { x: 1 }, "read", Foo { x: 1 })
+        .unwrap());
+    test.qvar_one(
+        "x = new Foo(1) and y = new Foo(2) and z = new Foo(3) and \
+         x matches Foo and y matches Foo and z matches Foo and r = x.x + z.x",
+        "r",
+        4,
+    );
+
+    // They are evicted once the queries are done.
+    test.qvar_one("x = new Foo(4).x", "x", 4);
+    assert_eq!(test.oso.cached_instances(), pinned + 1);
+}
+
+#[test]
+fn test_per_query_eviction_while_other_queries_run() {
+    use oso::InstanceCachePolicy;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Foo {
+        #[polar(attribute)]
+        x: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Foo::get_polar_class_builder()
+                .set_constructor(|x: i64| Foo { x })
+                .build(),
+        )
+        .unwrap();
+    test.load_str("allow(foo: Foo, _, _) if foo.x = 1;");
+    test.oso
+        .set_instance_cache_policy(InstanceCachePolicy::PerQuery);
+    let pinned = test.oso.cached_instances();
+
+    // A long-running query keeps its own instances...
+    let held = test.query("x = new Foo(2)");
+    assert_eq!(test.oso.cached_instances(), pinned + 1);
+
+    // ...but not those of the queries that finish meanwhile.
+    for _ in 0..5 {
+        assert!(test.oso.is_allowed(Foo { x: 1 }, "read", "doc").unwrap());
+        test.qvar_one("x = new Foo(3).x", "x", 3);
+    }
+    assert_eq!(test.oso.cached_instances(), pinned + 1);
+    assert_eq!(held[0].get_typed::<Foo>("x").unwrap().x, 2);
+
+    drop(held);
+    assert_eq!(test.oso.cached_instances(), pinned);
+}
+
+#[test]
+fn test_instance_cache_stats() {
+    use oso::InstanceCachePolicy;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Foo {
+        #[polar(attribute)]
+        x: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Foo::get_polar_class()).unwrap();
+    test.load_str("allow(foo: Foo, _, _) if foo.x = 1;");
+    let before = test.oso.instance_cache_stats();
+    assert_eq!(before.size, test.oso.cached_instances());
+    assert_eq!(before.pinned, before.size);
+
+    test.oso
+        .set_instance_cache_policy(InstanceCachePolicy::Lru { max_entries: 2 });
+    for _ in 0..5 {
+        assert!(test.oso.is_allowed(Foo { x: 1 }, "read", "doc").unwrap());
+    }
+    let stats = test.oso.instance_cache_stats();
+    assert_eq!(stats.size, before.pinned + 2);
+    assert_eq!(stats.pinned, before.pinned);
+    assert_eq!(stats.evictions, 3);
+    assert_eq!(stats.peak_size, before.pinned + 3);
+    assert!(stats.hits > before.hits);
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_uuid() {
+    use uuid::Uuid;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Document {
+        #[polar(attribute)]
+        owner: Uuid,
+    }
+
+    let alice = Uuid::new_v4();
+    let bob = Uuid::new_v4();
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(Document::get_polar_class())
+        .unwrap();
+    test.load_str(r#"allow(user, "read", doc: Document) if doc.owner = user;"#);
+    let doc = Document { owner: alice };
+    assert!(test.oso.is_allowed(alice, "read", doc.clone()).unwrap());
+    assert!(!test.oso.is_allowed(bob, "read", doc).unwrap());
+
+    test.qeval(&format!(
+        r#"Uuid.parse("{}") == Uuid.parse("{}")"#,
+        alice, alice
+    ));
+    test.qeval(r#"Uuid.nil() < Uuid.parse("936da01f-9abd-4d9d-80c7-02af85c822a8")"#);
+    test.qvar_one(
+        r#"x = Uuid.parse("936DA01F-9ABD-4D9D-80C7-02AF85C822A8")"#,
+        "x",
+        Uuid::parse_str("936da01f-9abd-4d9d-80c7-02af85c822a8").unwrap(),
+    );
+    test.qvar_one(
+        "x = Uuid.nil().to_string()",
+        "x",
+        "00000000-0000-0000-0000-000000000000".to_string(),
+    );
+    test.query_err(r#"Uuid.parse("not-a-uuid")"#);
+}
+
+#[cfg(feature = "rust_decimal")]
+#[test]
+fn test_decimal() {
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Payment {
+        #[polar(attribute)]
+        amount: Decimal,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Payment::get_polar_class()).unwrap();
+    test.load_str(
+        r#"allow(_, "approve", payment: Payment) if payment.amount <= Decimal.parse("1000.00");"#,
+    );
+    let payment = |amount: &str| Payment {
+        amount: Decimal::from_str(amount).unwrap(),
+    };
+    assert!(test
+        .oso
+        .is_allowed("alice", "approve", payment("1000.00"))
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed("alice", "approve", payment("1000.01"))
+        .unwrap());
+
+    // 0.1 + 0.2 is exactly 0.3, unlike with floats.
+    test.qeval(r#"Decimal.parse("0.1").add(Decimal.parse("0.2")) == Decimal.parse("0.3")"#);
+    test.qeval(r#"Decimal.from_int(3) > Decimal.parse("2.99")"#);
+    test.qvar_one(
+        r#"x = Decimal.parse("2.675").round(2).to_string()"#,
+        "x",
+        "2.68".to_string(),
+    );
+    test.qvar_one(
+        r#"x = Decimal.parse("1.5").mul(Decimal.from_int(2))"#,
+        "x",
+        Some(Decimal::from(3)),
+    );
+    test.query_err(r#"Decimal.parse("ten")"#);
+}
+
+#[test]
+fn test_simulation() {
+    use oso::{Distribution, Population, Simulation};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    #[derive(Clone, PolarClass)]
+    struct Document {
+        #[polar(attribute)]
+        public: bool,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(Document::get_polar_class())
+        .unwrap();
+    test.load_str(
+        r#"allow(actor, _, _: Document) if actor.role = "admin";
+           allow(_, "read", doc: Document) if doc.public;"#,
+    );
+
+    let actors = Population::new(100).field(
+        "role",
+        Distribution::Weighted(vec![
+            (PolarValue::String("admin".to_string()), 1.0),
+            (PolarValue::String("member".to_string()), 4.0),
+        ]),
+    );
+    let class = Document::get_polar_class();
+    let documents = Population::new(100)
+        .field("public", Distribution::Bool(0.25))
+        .with_constructor(move |fields| {
+            let public = fields["public"] == PolarValue::Bool(true);
+            PolarValue::Instance(class.cast_to_instance(Document { public }))
+        });
+    let simulation = Simulation::new(actors, documents, &["read", "delete"])
+        .samples(400)
+        .group_by("role")
+        .seed(7);
+    let report = test.oso.simulate(&simulation).unwrap();
+
+    assert_eq!(report.action("read").total, 400);
+    assert_eq!(report.group("read", "admin").ratio(), 1.0);
+    assert_eq!(report.group("delete", "admin").ratio(), 1.0);
+    assert_eq!(report.group("delete", "member").allowed, 0);
+    let member_reads = report.group("read", "member").ratio();
+    assert!(member_reads > 0.1 && member_reads < 0.4, "{}", member_reads);
+    let admin_share = report.group("read", "admin").total as f64 / 400.0;
+    assert!(admin_share > 0.1 && admin_share < 0.3, "{}", admin_share);
+
+    // Simulations are reproducible.
+    assert_eq!(test.oso.simulate(&simulation).unwrap(), report);
+}
+
+#[test]
+fn test_simulation_distributions() {
+    use oso::{Distribution, Population, Simulation};
+
+    let mut test = OsoTest::new();
+    test.load_str("allow(actor, _, _) if actor.level > 0;");
+    let simulate = |oso: &mut Oso, distribution: Distribution| {
+        let actors = Population::new(10).field("level", distribution);
+        let simulation = Simulation::new(actors, Population::new(1), &["read"]).samples(10);
+        oso.simulate(&simulation)
+    };
+
+    assert!(simulate(&mut test.oso, Distribution::Range(1, 0)).is_err());
+    assert!(simulate(&mut test.oso, Distribution::Weighted(vec![])).is_err());
+    assert!(simulate(
+        &mut test.oso,
+        Distribution::Weighted(vec![(PolarValue::Integer(1), -1.0)])
+    )
+    .is_err());
+
+    let report = simulate(&mut test.oso, Distribution::Range(i64::MIN, i64::MAX)).unwrap();
+    assert_eq!(report.action("read").total, 10);
+    let report = simulate(&mut test.oso, Distribution::Range(1, 1)).unwrap();
+    assert_eq!(report.action("read").ratio(), 1.0);
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.qeval(r#"Regex.compile("^/home/[a-z]+/").matches("/home/alice/notes")"#);
+    test.qnull(r#"Regex.compile("^/home/[a-z]+/").matches("/etc/passwd")"#);
+    test.qvar_one(
+        r#"x = Regex.compile("[0-9]+").find("build 1234")"#,
+        "x",
+        "1234".to_string(),
+    );
+    test.qeval(r#"Regex.compile(Regex.escape("a.b")).matches("a.b")"#);
+    test.qnull(r#"Regex.compile(Regex.escape("a.b")).matches("axb")"#);
+    test.qeval(r#"Regex.compile("a+") == Regex.compile("a+")"#);
+    test.query_err(r#"Regex.compile("(")"#);
+
+    test.oso.register_pattern("SLUG", "^[a-z0-9-]+$").unwrap();
+    assert!(test.oso.register_pattern("BAD", "(").is_err());
+    test.load_str(r#"allow(_, "read", name) if SLUG.matches(name);"#);
+    assert!(test.oso.is_allowed("alice", "read", "my-post").unwrap());
+    assert!(!test.oso.is_allowed("alice", "read", "../etc").unwrap());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json() {
+    use serde_json::json;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    let claims = json!({
+        "sub": "alice",
+        "groups": ["admin", "dev"],
+        "exp": 1600000000,
+        "score": 0.5,
+        "email": null,
+    });
+    test.oso.register_constant("claims", &claims).unwrap();
+    test.load_str(
+        r#"
+        allowed(sub) if sub = claims.sub and "admin" in claims.groups;
+        expires(exp) if exp = claims.exp;"#,
+    );
+    test.qvar_one("allowed(x)", "x", json!("alice"));
+    test.qvar_one("expires(x)", "x", json!(1600000000));
+    test.qvar_one("x = claims.score", "x", json!(0.5));
+    test.qvar_one("x = claims.email", "x", json!(null));
+    test.qvar_one("x = claims", "x", claims);
+    test.qvar_one(
+        "x = [1, {a: \"b\"}, nil]",
+        "x",
+        json!([1, {"a": "b"}, null]),
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_serde_bridge() {
+    use oso::PolarSerde;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        roles: Vec<String>,
+        manager: Option<String>,
+    }
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"
+        allow(user, "read", _) if "reader" in user.roles;
+        manager(user, manager) if manager = user.manager;
+        promote(user, promoted) if promoted = {name: user.name, roles: ["admin"]};"#,
+    );
+    let alice = User {
+        name: "alice".to_string(),
+        roles: vec!["reader".to_string()],
+        manager: Some("bob".to_string()),
+    };
+    assert!(test
+        .oso
+        .is_allowed(PolarSerde(alice.clone()), "read", "doc")
+        .unwrap());
+
+    let mut query = test
+        .oso
+        .query_rule(
+            "promote",
+            vec![
+                &PolarSerde(alice.clone()) as &dyn ToPolar,
+                &PolarValue::Variable("u".into()),
+            ],
+        )
+        .unwrap();
+    let promoted: PolarSerde<User> = query.next().unwrap().unwrap().get_typed("u").unwrap();
+    assert_eq!(
+        promoted.0,
+        User {
+            name: "alice".to_string(),
+            roles: vec!["admin".to_string()],
+            manager: None,
+        }
+    );
+
+    let mut query = test
+        .oso
+        .query_rule(
+            "manager",
+            vec![
+                &PolarSerde(alice) as &dyn ToPolar,
+                &PolarValue::Variable("m".into()),
+            ],
+        )
+        .unwrap();
+    let manager: PolarSerde<String> = query.next().unwrap().unwrap().get_typed("m").unwrap();
+    assert_eq!(manager.0, "bob");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_export_schema() {
+    use serde_json::json;
+
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        name: String,
+    }
+
+    let test = OsoTest::new();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .add_method("greet", |user: &User, greeting: String| {
+                    format!("{}, {}", greeting, user.name)
+                })
+                .add_method("is_admin", |_: &User| false)
+                .add_class_method("find", |_id: i64, _name: String| true)
+                .add_constant("ROOT", String::from("root"))
+                .build(),
+        )
+        .unwrap();
+    test.oso.register_alias("Person", "User").unwrap();
+
+    let schema = test.oso.export_schema();
+    assert_eq!(
+        schema["definitions"]["User"],
+        json!({
+            "type": "object",
+            "properties": { "name": {} },
+            "x-polar-methods": {
+                "greet": { "arity": 1 },
+                "is_admin": { "arity": 0 },
+            },
+            "x-polar-class-methods": { "find": { "arity": 2 } },
+            "x-polar-constants": ["ROOT"],
+        })
+    );
+    assert_eq!(
+        schema["definitions"]["Person"],
+        json!({ "$ref": "#/definitions/User" })
+    );
+    assert!(schema["definitions"]["String"].is_object());
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn test_filter_batch() {
+    use std::sync::Arc;
+
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let schema = Schema::new(vec![
+        Field::new("owner", DataType::Utf8, false),
+        Field::new("amount", DataType::Int64, true),
+    ]);
+    let batch = RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(StringArray::from(vec!["alice", "bob", "alice"])),
+            Arc::new(Int64Array::from(vec![Some(10), Some(20), None])),
+        ],
+    )
+    .unwrap();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"allow(actor, "read", row) if row.owner = actor and row.amount < 100;
+           allow("admin", "read", _row);"#,
+    );
+
+    let mask = test.oso.authorize_batch("alice", "read", &batch).unwrap();
+    let mask: Vec<bool> = (0..mask.len()).map(|i| mask.value(i)).collect();
+    assert_eq!(mask, vec![true, false, false]);
+
+    let filtered = test.oso.filter_batch("admin", "read", &batch).unwrap();
+    assert_eq!(filtered.num_rows(), 3);
+    let filtered = test.oso.filter_batch("bob", "read", &batch).unwrap();
+    assert_eq!(filtered.num_rows(), 1);
+}
+
+#[test]
+fn test_sql_filter() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"allow(actor, "read", doc: Document) if doc.owner = actor;
+           allow(_actor, action, doc: Document) if
+               action in ["read", "list"] and
+               doc.public = true and
+               not doc.archived = true;
+           allow("admin", _action, _doc: Document);
+           allow(_actor, "read", _: Comment);
+           allow(actor, "write", doc: Document) if doc.editors.contains(actor);"#,
+    );
+
+    let filter = test
+        .oso
+        .sql_filter("Document", "read", "documents")
+        .unwrap();
+    assert_eq!(
+        filter.condition(),
+        "(\"owner\" = current_user) OR \
+         (('read' IN ('read', 'list')) AND (\"public\" = TRUE) AND (NOT (\"archived\" = TRUE))) OR \
+         (current_user = 'admin' AND (TRUE))"
+    );
+    assert!(filter
+        .create_view("readable_documents")
+        .starts_with("CREATE VIEW \"readable_documents\" AS SELECT * FROM \"documents\" WHERE "));
+    assert!(filter
+        .create_policy("read_documents", "SELECT")
+        .starts_with("CREATE POLICY \"read_documents\" ON \"documents\" FOR SELECT USING ("));
+
+    let filter = test
+        .oso
+        .sql_filter("Comment", "delete", "comments")
+        .unwrap();
+    assert_eq!(filter.condition(), "FALSE");
+
+    let err = test
+        .oso
+        .sql_filter("Document", "write", "documents")
+        .unwrap_err();
+    assert!(err.to_string().contains("cannot be expressed in SQL"));
+}
+
+#[test]
+fn test_sql_tenant_key() {
+    #[derive(Clone, Default, PolarClass)]
+    struct Document;
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Document::get_polar_class_builder()
+                .tenant_key("org_id")
+                .build(),
+        )
+        .unwrap();
+    test.load_str(
+        r#"allow(actor, "read", doc: Document) if doc.owner = actor;
+           allow(_actor, "list", _doc: Document);"#,
+    );
+
+    let filter = test
+        .oso
+        .sql_filter("Document", "read", "documents")
+        .unwrap();
+    assert_eq!(
+        filter.condition(),
+        "(\"org_id\"::text = current_setting('oso.tenant')) AND (\"owner\" = current_user)"
+    );
+
+    // Rules that don't check the tenant are still constrained to it.
+    let filter = test
+        .oso
+        .sql_filter("Document", "list", "documents")
+        .unwrap();
+    assert_eq!(
+        filter.condition(),
+        "(\"org_id\"::text = current_setting('oso.tenant')) AND (TRUE)"
+    );
+    let filter = test
+        .oso
+        .sql_filter("Document", "delete", "documents")
+        .unwrap();
+    assert_eq!(
+        filter.condition(),
+        "(\"org_id\"::text = current_setting('oso.tenant')) AND (FALSE)"
+    );
+}
+
+#[test]
+fn test_compiled_rules() {
+    #[derive(Clone, PolarClass)]
+    struct Document {
+        #[polar(attribute)]
+        owner: String,
+        #[polar(attribute)]
+        public: bool,
+        #[polar(attribute)]
+        archived: bool,
+        #[polar(attribute)]
+        editors: Vec<String>,
+        #[polar(attribute)]
+        size: f64,
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        name: String,
+    }
+
+    let policy = r#"allow(actor, "read", doc: Document) if doc.owner = actor;
+        allow(_actor, action, doc: Document) if
+            action in ["read", "list"] and
+            doc.public = true and
+            not doc.archived = true;
+        allow("admin", _action, _doc: Document);
+        allow(_actor, "read", _: Comment);
+        allow(_actor, "print", doc: Document) if doc.size < 10.0;
+        allow(actor, "write", doc: Document) if doc.editors.contains(actor);
+        allow(user: User, "edit", doc: Document) if doc.owner = user.name;"#;
+
+    let docs = vec![
+        Document {
+            owner: "alice".to_string(),
+            public: false,
+            archived: false,
+            editors: vec!["bob".to_string()],
+            size: 1.0,
+        },
+        Document {
+            owner: "bob".to_string(),
+            public: true,
+            archived: false,
+            editors: vec![],
+            size: 20.0,
+        },
+        Document {
+            owner: "bob".to_string(),
+            public: true,
+            archived: true,
+            editors: vec!["alice".to_string()],
+            size: f64::NAN,
+        },
+    ];
+    let actions = ["read", "list", "write", "print", "edit", "delete"];
+
+    let decide = |compiled: bool| {
+        let mut oso = Oso::new();
+        oso.register_class(Document::get_polar_class()).unwrap();
+        oso.register_class(User::get_polar_class()).unwrap();
+        oso.load_str(policy).unwrap();
+        oso.set_compiled_rules(compiled);
+
+        let mut decisions = vec![];
+        for doc in &docs {
+            for action in &actions {
+                for actor in &["alice", "bob", "admin"] {
+                    decisions.push(oso.is_allowed(*actor, *action, doc.clone()).unwrap());
+                }
+                let user = User {
+                    name: "alice".to_string(),
+                };
+                decisions.push(oso.is_allowed(user, *action, doc.clone()).unwrap());
+                decisions.push(oso.is_allowed(1, *action, doc.clone()).unwrap());
+            }
+        }
+        decisions
+    };
+
+    let decisions = decide(true);
+    assert_eq!(decisions, decide(false));
+    // alice reads her document, the public one and not the archived one.
+    assert_eq!(
+        decisions
+            .iter()
+            .step_by(5 * actions.len())
+            .collect::<Vec<_>>(),
+        vec![&true, &true, &false]
+    );
+}
+
+#[cfg(feature = "polars")]
+#[test]
+fn test_polars_filter() {
+    use polars::prelude::*;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"allow(actor, "read", doc: Document) if doc.owner = actor;
+           allow(_actor, "read", doc: Document) if doc.public = true and doc.score > 10;
+           allow("admin", _action, _doc: Document);
+           allow(actor, "write", doc: Document) if doc.editors.contains(actor);"#,
+    );
+    let df = DataFrame::new(vec![
+        Series::new("owner", &["alice", "bob", "carol"]),
+        Series::new("public", &[false, true, true]),
+        Series::new("score", &[0i64, 20, 5]),
+    ])
+    .unwrap();
+
+    let authorized = |actor: &str| {
+        let filter = test
+            .oso
+            .polars_filter(&PolarValue::String(actor.to_string()), "read", "Document")
+            .unwrap();
+        df.clone().lazy().filter(filter).collect().unwrap().height()
+    };
+    assert_eq!(authorized("alice"), 2);
+    assert_eq!(authorized("carol"), 2);
+    assert_eq!(authorized("dave"), 1);
+    assert_eq!(authorized("admin"), 3);
+
+    let err = test
+        .oso
+        .polars_filter(
+            &PolarValue::String("alice".to_string()),
+            "write",
+            "Document",
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("cannot be expressed in polars"));
+}
+
+#[cfg(feature = "polars")]
+#[test]
+fn test_polars_tenant_filter() {
+    use polars::prelude::*;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Document;
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Document::get_polar_class_builder()
+                .tenant_key("org_id")
+                .build(),
+        )
+        .unwrap();
+    test.load_str(r#"allow(actor, "read", doc: Document) if doc.owner = actor;"#);
+    let df = DataFrame::new(vec![
+        Series::new("owner", &["alice", "alice", "bob"]),
+        Series::new("org_id", &[1i64, 2, 1]),
+    ])
+    .unwrap();
+
+    let alice = PolarValue::String("alice".to_string());
+    let filter = test
+        .oso
+        .polars_tenant_filter(&alice, &PolarValue::Integer(2), "read", "Document")
+        .unwrap();
+    let authorized = df.lazy().filter(filter).collect().unwrap();
+    assert_eq!(authorized.height(), 1);
+
+    let err = test
+        .oso
+        .polars_filter(&alice, "read", "Document")
+        .unwrap_err();
+    assert!(err.to_string().contains("tenant key"));
+}
+
+#[test]
+fn test_group_resolvers() {
+    use oso::{OidcClaims, StaticGroups};
+    use std::sync::Arc;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_group_resolver(
+            StaticGroups::new()
+                .add_member("alice", "readers")
+                .add_member("alice", "writers")
+                .add_member("bob", "readers"),
+        )
+        .unwrap();
+    test.load_str(
+        r#"allow(user, "read", _) if Groups.member(user, "readers");
+           allow(user, "write", _) if "writers" in Groups.of(user);"#,
+    );
+    assert!(test.oso.is_allowed("alice", "write", "doc").unwrap());
+    assert!(test.oso.is_allowed("bob", "read", "doc").unwrap());
+    assert!(!test.oso.is_allowed("bob", "write", "doc").unwrap());
+    assert!(!test.oso.is_allowed("carol", "read", "doc").unwrap());
+
+    // Claims are recorded through a shared handle after registration.
+    let claims = Arc::new(OidcClaims::new("groups"));
+    test.oso.register_group_resolver(claims.clone()).unwrap();
+    assert!(!test.oso.is_allowed("carol", "read", "doc").unwrap());
+
+    let token = hashmap! {
+        "sub".to_string() => PolarValue::String("carol".to_string()),
+        "groups".to_string() => PolarValue::List(vec![PolarValue::String("readers".to_string())]),
+    };
+    claims.insert("carol", &token);
+    assert!(test.oso.is_allowed("carol", "read", "doc").unwrap());
+    assert!(!test.oso.is_allowed("alice", "read", "doc").unwrap());
+
+    let token = hashmap! {
+        "groups".to_string() => PolarValue::String("readers writers".to_string()),
+    };
+    claims.insert("carol", &token);
+    assert!(test.oso.is_allowed("carol", "write", "doc").unwrap());
+    claims.remove("carol");
+    assert!(!test.oso.is_allowed("carol", "read", "doc").unwrap());
+}
+
+#[test]
+fn test_decision_tokens() {
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let key = b"secret";
+    let policy = r#"allow("alice", "read", "doc:1");"#;
+
+    let mut gateway = Oso::new();
+    gateway.load_str(policy).unwrap();
+    assert!(gateway.is_allowed("alice", "read", "doc:1").unwrap());
+    let token = gateway.mint_decision_token(key, "alice", "read", "doc:1", Duration::from_secs(60));
+
+    let mut service = Oso::new();
+    service.load_str(policy).unwrap();
+    service
+        .verify_decision_token(key, &token, "alice", "read", "doc:1")
+        .unwrap();
+
+    let invalid = |result: oso::Result<()>| match result {
+        Err(oso::OsoError::InvalidDecisionToken { reason }) => reason,
+        _ => panic!("expected an invalid token"),
+    };
+    assert_eq!(
+        invalid(service.verify_decision_token(b"other", &token, "alice", "read", "doc:1")),
+        "bad signature"
+    );
+    assert_eq!(
+        invalid(service.verify_decision_token(key, &token, "alice", "write", "doc:1")),
+        "issued for a different decision"
+    );
+    let tampered = token.replacen("v1.", "v1.Ym9i", 1);
+    assert_eq!(
+        invalid(service.verify_decision_token(key, &tampered, "bob", "read", "doc:1")),
+        "bad signature"
+    );
+
+    let expired =
+        gateway.mint_decision_token(key, "alice", "read", "doc:1", Duration::from_secs(0));
+    assert_eq!(
+        invalid(service.verify_decision_token(key, &expired, "alice", "read", "doc:1")),
+        "expired"
+    );
+
+    service
+        .load_str(r#"allow("bob", "read", "doc:1");"#)
+        .unwrap();
+    assert_eq!(
+        invalid(service.verify_decision_token(key, &token, "alice", "read", "doc:1")),
+        "issued for a different policy"
+    );
+}
+
+#[test]
+fn test_fact_consistency() {
+    use oso::{Consistency, Fact, FactStore, MemoryFactStore};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    fn replica(store: Arc<dyn FactStore>) -> Oso {
+        let mut oso = Oso::new();
+        oso.load_str("allow(user, action, _) if grant(user, action);")
+            .unwrap();
+        oso.set_fact_store(store);
+        oso
+    }
+
+    let store = Arc::new(MemoryFactStore::default());
+    let mut writer = replica(store.clone());
+    let mut reader = replica(store.clone());
+    let grant = |user: &str, action: &str| vec![PolarValue::from(user), PolarValue::from(action)];
+
+    let token = writer.assert_fact("grant", grant("alice", "read")).unwrap();
+    assert!(writer.is_allowed("alice", "read", "doc").unwrap());
+    // The other replica loads the fact when it is passed the token.
+    assert!(!reader.is_allowed("alice", "read", "doc").unwrap());
+    assert_eq!(
+        reader
+            .is_allowed_after(token.to_string().parse().unwrap(), "alice", "read", "doc")
+            .unwrap(),
+        (true, Consistency::Consistent)
+    );
+    // Facts are loaded once.
+    reader.sync_facts().unwrap();
+    assert_eq!(reader.query("grant(user, action)").unwrap().count(), 1);
+
+    // A store that lags behind its writes.
+    struct Lagging {
+        facts: MemoryFactStore,
+        visible: AtomicU64,
+    }
+    impl FactStore for Lagging {
+        fn append(&self, fact: Fact) -> oso::Result<u64> {
+            self.facts.append(fact)
+        }
+        fn since(&self, version: u64) -> oso::Result<(Vec<Fact>, u64)> {
+            let visible = self.visible.load(Ordering::SeqCst);
+            let (mut facts, _) = self.facts.since(version)?;
+            facts.truncate(visible.saturating_sub(version) as usize);
+            Ok((facts, visible.max(version)))
+        }
+    }
+    let lagging = Arc::new(Lagging {
+        facts: MemoryFactStore::default(),
+        visible: AtomicU64::new(0),
+    });
+    let token = lagging.append(Fact {
+        name: "grant".to_string(),
+        args: grant("bob", "read"),
+    });
+    let token = token.unwrap().to_string().parse().unwrap();
+    let mut reader = replica(lagging.clone());
+    assert_eq!(
+        reader
+            .is_allowed_after(token, "bob", "read", "doc")
+            .unwrap(),
+        (false, Consistency::PotentiallyStale)
+    );
+    lagging.visible.store(1, Ordering::SeqCst);
+    assert_eq!(
+        reader
+            .is_allowed_after(token, "bob", "read", "doc")
+            .unwrap(),
+        (true, Consistency::Consistent)
+    );
+
+    // Facts must be plain data.
+    assert!(writer.assert_fact("grant(x)", grant("a", "b")).is_err());
+    assert!(writer
+        .assert_fact("grant", vec![PolarValue::Float(1.5)])
+        .is_err());
+    writer
+        .assert_fact("note", vec![PolarValue::from(r#"say "hi" \"#)])
+        .unwrap();
+    let mut notes = writer.query("note(x)").unwrap();
+    let note: String = notes.next().unwrap().unwrap().get_typed("x").unwrap();
+    assert_eq!(note, r#"say "hi" \"#);
+}
+
+#[test]
+fn test_instances_are_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<oso::Instance>();
+    assert_send_sync::<PolarValue>();
+    assert_send_sync::<Oso>();
+
+    #[derive(PolarClass, Clone, PartialEq)]
+    struct Token {
+        #[polar(attribute)]
+        id: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Token::get_polar_class_builder()
+                .set_equality_check(|a, b| a == b)
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_constant("TOKEN", &Token { id: 7 })
+        .unwrap();
+
+    let mut query = test.oso.query("x = TOKEN").unwrap();
+    let token = match query.next().unwrap().unwrap().get("x").unwrap() {
+        Some(PolarValue::Instance(instance)) => instance,
+        _ => panic!("expected an instance"),
+    };
+    let other = token.clone();
+    let equal = std::thread::spawn(move || token.equals(&other).unwrap())
+        .join()
+        .unwrap();
+    assert!(equal);
+}
+
+#[test]
+fn test_register_non_send_class() {
+    use std::rc::Rc;
+
+    // Types that are not `Send` can be registered as classes, though their
+    // values can't be cached in the host.
+    #[derive(Clone, PolarClass)]
+    struct Counter {
+        count: Rc<i64>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Counter::get_polar_class_builder()
+                .add_method("count", |counter: &Counter| *counter.count)
+                .build(),
+        )
+        .unwrap();
+    test.qeval("Counter = Counter");
+}
+
+#[test]
+fn test_bind_local() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone, PolarClass)]
+    struct Counter {
+        count: Rc<Cell<i64>>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Counter::get_polar_class_builder()
+                .add_method("bump", |counter: &Counter| {
+                    counter.count.set(counter.count.get() + 1);
+                    counter.count.get()
+                })
+                .build(),
+        )
+        .unwrap();
+    test.load_str(
+        r#"allow(counter: Counter, "bump", _) if counter.bump() > 1;
+           is_counter(_: Counter);"#,
+    );
+
+    let count = Rc::new(Cell::new(0));
+    let counter = Counter {
+        count: count.clone(),
+    };
+    let query = |test: &mut OsoTest, q: &str| {
+        test.oso
+            .query(q)
+            .unwrap()
+            .bind_local("counter", counter.clone())
+            .unwrap()
+    };
+
+    // The query calls the methods of the value it owns.
+    assert_eq!(query(&mut test, r#"allow(counter, "bump", 1)"#).count(), 0);
+    assert_eq!(query(&mut test, r#"allow(counter, "bump", 1)"#).count(), 1);
+    assert_eq!(count.get(), 2);
+    assert_eq!(query(&mut test, "is_counter(counter)").count(), 1);
+    assert_eq!(query(&mut test, "is_counter(1)").count(), 0);
+
+    // Local values are not returned in results.
+    let mut results = query(&mut test, "x = counter");
+    assert!(results.next().unwrap().unwrap().get("x").is_err());
+
+    // Their class must be registered, and they must be bound before the
+    // query runs.
+    struct Unregistered;
+    assert!(test
+        .oso
+        .query("x = y")
+        .unwrap()
+        .bind_local("y", Unregistered)
+        .is_err());
+    let mut started = test.oso.query("x = 1 or x = 2").unwrap();
+    started.next();
+    assert!(started.bind_local("counter", counter).is_err());
+}
+
+#[test]
+fn test_instance_repr() {
+    #[derive(Clone, PolarClass)]
+    struct Repo {
+        name: String,
+    }
+
+    #[derive(Clone, Debug, PolarClass)]
+    struct Issue(i64);
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_repr(|repo| format!("Repo({})", repo.name))
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(Issue::get_polar_class_builder().with_debug_repr().build())
+        .unwrap();
+    test.oso
+        .register_constant(
+            "REPO",
+            &Repo {
+                name: "oso".to_string(),
+            },
+        )
+        .unwrap();
+    test.oso.register_constant("ISSUE", &Issue(42)).unwrap();
+
+    assert!(test.query_err("REPO < 1").contains("got: Repo(oso), 1"));
+    assert!(test.query_err("ISSUE < 1").contains("got: Issue(42), 1"));
+}
+
+#[test]
+fn test_conversion_roundtrips() {
+    use oso::testing::{roundtrip, roundtrip_with, Arbitrary, Gen};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    roundtrip::<bool>();
+    roundtrip::<u8>();
+    roundtrip::<i16>();
+    roundtrip::<u32>();
+    roundtrip::<i64>();
+    roundtrip::<u64>();
+    roundtrip::<usize>();
+    roundtrip::<i128>();
+    roundtrip::<f64>();
+    roundtrip::<String>();
+    roundtrip::<Option<i64>>();
+    roundtrip::<Vec<String>>();
+    roundtrip::<Vec<Vec<bool>>>();
+    roundtrip::<HashSet<i64>>();
+    roundtrip::<HashMap<String, f64>>();
+    roundtrip::<BTreeMap<String, Vec<Option<String>>>>();
+
+    #[derive(Clone, Debug, PartialEq, PolarClass)]
+    struct Account {
+        id: i64,
+        owner: String,
+    }
+
+    impl Arbitrary for Account {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Account {
+                id: i64::arbitrary(g),
+                owner: String::arbitrary(g),
+            }
+        }
+    }
+
+    let oso = Oso::new();
+    oso.register_class(Account::get_polar_class()).unwrap();
+    roundtrip_with::<Vec<Account>>(&oso, 64);
+}
+
+#[test]
+fn test_subclasses() {
+    #[derive(Clone, PolarClass)]
+    struct Resource;
+
+    #[derive(Clone, PolarClass)]
+    struct Document;
+
+    #[derive(Clone, PolarClass)]
+    struct User;
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Resource::get_polar_class_builder()
+                .set_constructor(|| Resource)
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            Document::get_polar_class_builder()
+                .set_constructor(|| Document)
+                .with_subclass_of::<Resource>()
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .set_constructor(|| User)
+                .build(),
+        )
+        .unwrap();
+
+    test.qeval("new Document() matches Resource");
+    test.qeval("new Document() matches Document");
+    test.qnull("new Resource() matches Document");
+    test.qnull("new User() matches Resource");
+
+    test.load_str(
+        r#"kind(_: Resource, "resource");
+           kind(_: Document, "document");"#,
+    );
+    assert_eq!(
+        test.qvar::<String>("kind(new Document(), x)", "x"),
+        vec!["document".to_string(), "resource".to_string()]
+    );
+    assert_eq!(
+        test.qvar::<String>("kind(new Resource(), x)", "x"),
+        vec!["resource".to_string()]
+    );
+}
+
+#[test]
+fn test_polar_value_macro() {
+    use oso::polar_value;
+
+    assert_eq!(polar_value!(1), PolarValue::Integer(1));
+    assert_eq!(polar_value!(-2.5), PolarValue::Float(-2.5));
+    assert_eq!(polar_value!([]), PolarValue::List(vec![]));
+    assert_eq!(
+        polar_value!([1, "a", [true], nil,]),
+        PolarValue::List(vec![
+            PolarValue::Integer(1),
+            PolarValue::String("a".to_string()),
+            PolarValue::List(vec![PolarValue::Bool(true)]),
+            PolarValue::nil(),
+        ])
+    );
+
+    let tags = vec!["a", "b"];
+    let user = polar_value!({
+        "role": "admin",
+        "tags": tags,
+        "limits": {"daily": 10 * 2, "burst": {}},
+    });
+    let mut test = OsoTest::new();
+    test.oso.register_constant("USER", &user).unwrap();
+    test.qeval(r#"USER.role = "admin""#);
+    test.qeval(r#"USER.tags = ["a", "b"]"#);
+    test.qeval("USER.limits.daily = 20");
+    test.qeval("USER.limits.burst = {}");
+}
+
+#[test]
+fn test_query_rule_with_vars() {
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"has_role("alice", "admin", "org:1");
+           has_role("alice", "viewer", "repo:2");
+           has_role("bob", "viewer", "repo:2");"#,
+    );
+
+    let roles = test
+        .oso
+        .query_rule_with_vars::<(String, String)>(
+            "has_role",
+            vec![&"alice" as &dyn ToPolar],
+            &["role", "scope"],
+        )
+        .unwrap()
+        .collect::<oso::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        roles,
+        vec![
+            ("admin".to_string(), "org:1".to_string()),
+            ("viewer".to_string(), "repo:2".to_string()),
+        ]
+    );
+
+    let users = test
+        .oso
+        .query_rule_with_vars::<(String, String, String)>(
+            "has_role",
+            Vec::<&dyn ToPolar>::new(),
+            &["user", "role", "scope"],
+        )
+        .unwrap()
+        .map(|result| result.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(users, vec!["alice", "alice", "bob"]);
+}
+
+#[test]
+fn test_register_trait() {
+    trait Resource {
+        fn owner(&self) -> &str;
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct Repo {
+        #[polar(attribute)]
+        owner: String,
+    }
+
+    impl Resource for Repo {
+        fn owner(&self) -> &str {
+            &self.owner
+        }
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct Issue;
+
+    impl Resource for Issue {
+        fn owner(&self) -> &str {
+            "nobody"
+        }
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct User;
+
+    let mut test = OsoTest::new();
+    test.oso.register_trait::<dyn Resource>("Resource").unwrap();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .implements::<dyn Resource, _>(|repo| repo)
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            Issue::get_polar_class_builder()
+                .set_constructor(|| Issue)
+                .implements::<dyn Resource, _>(|issue| issue)
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .set_constructor(|| User)
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_constant(
+            "REPO",
+            &Repo {
+                owner: "alice".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(Issue.owner(), "nobody");
+
+    test.qeval("REPO matches Resource");
+    test.qeval("new Issue() matches Resource");
+    test.qnull("new User() matches Resource");
+
+    test.load_str(
+        r#"visible(_: Resource, "shared");
+           visible(repo: Repo, owner) if owner = repo.owner;"#,
+    );
+    assert_eq!(
+        test.qvar::<String>("visible(REPO, x)", "x"),
+        vec!["alice".to_string(), "shared".to_string()]
+    );
+    assert_eq!(
+        test.qvar::<String>("visible(new Issue(), x)", "x"),
+        vec!["shared".to_string()]
+    );
+}
+
+#[test]
+fn test_attribute_fallback() {
+    #[derive(Clone, PolarClass)]
+    struct Row {
+        #[polar(attribute)]
+        table: String,
+        columns: std::collections::HashMap<String, String>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Row::get_polar_class_builder()
+                .set_attribute_fallback(|row: &Row, name| row.columns.get(name).cloned())
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_constant(
+            "ROW",
+            &Row {
+                table: "documents".to_string(),
+                columns: hashmap! {
+                    "owner".to_string() => "alice".to_string(),
+                    "table".to_string() => "shadowed".to_string(),
+                },
+            },
+        )
+        .unwrap();
+
+    test.qvar_one("x = ROW.owner", "x", "alice".to_string());
+    test.qvar_one("x = ROW.table", "x", "documents".to_string());
+    test.query_err("x = ROW.title");
+}
+
+#[test]
+fn test_mut_methods() {
+    use oso::Shared;
+
+    struct Workflow {
+        status: String,
+        decisions: Vec<String>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Class::<Shared<Workflow>>::new()
+                .name("Workflow")
+                .add_shared_attribute_getter("status", |workflow: &Workflow| {
+                    workflow.status.clone()
+                })
+                .add_mut_method("set_status", |workflow: &mut Workflow, status: String| {
+                    workflow.status = status;
+                    true
+                })
+                .add_mut_method("record", |workflow: &mut Workflow, decision: String| {
+                    workflow.decisions.push(decision);
+                    true
+                })
+                .build(),
+        )
+        .unwrap();
+    test.load_str(
+        r#"allow(workflow: Workflow, "approve", _) if
+               workflow.status = "pending" and
+               workflow.record("approved") and
+               workflow.set_status("approved");"#,
+    );
+
+    let workflow = Shared::new(Workflow {
+        status: "pending".to_string(),
+        decisions: vec![],
+    });
+    assert!(test
+        .oso
+        .is_allowed(workflow.clone(), "approve", "doc")
+        .unwrap());
+    assert_eq!(workflow.read().status, "approved");
+    assert_eq!(workflow.read().decisions, vec!["approved"]);
+
+    // The status changed, so the rule no longer applies.
+    assert!(!test
+        .oso
+        .is_allowed(workflow.clone(), "approve", "doc")
+        .unwrap());
+    assert_eq!(workflow.read().decisions.len(), 1);
+}
+
+#[test]
+fn test_numeric_comparison() {
+    use oso::NumericComparison;
+
+    let mut test = OsoTest::new();
+    test.oso.register_constant("NAN", &f64::NAN).unwrap();
+    test.qeval("9007199254740993 != 9007199254740992.0");
+    test.qeval("1 == 1.0");
+    test.qnull("NAN < 1.0");
+
+    test.oso.set_numeric_comparison(NumericComparison {
+        reject_nan: true,
+        reject_mixed: true,
+    });
+    test.qeval("1 == 1");
+    test.qeval("1.5 < 2.0");
+    assert!(test
+        .query_err("9007199254740993 != 9007199254740992.0")
+        .contains("an integer cannot be compared with a float"));
+    assert!(test
+        .query_err("NAN < 1.0")
+        .contains("NaN cannot be compared"));
+    // Unification is unaffected.
+    test.qeval("1 = 1.0");
+}
+
+#[test]
+fn test_fallible_constructors() {
+    #[derive(Clone, PolarClass)]
+    struct Port {
+        #[polar(attribute)]
+        number: i64,
+    }
+
+    impl Port {
+        fn new(number: i64) -> Result<Self, String> {
+            if (1..=65535).contains(&number) {
+                Ok(Port { number })
+            } else {
+                Err(format!("invalid port {}", number))
+            }
+        }
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Port::get_polar_class_builder()
+                .set_fallible_constructor(Port::new)
+                .build(),
+        )
+        .unwrap();
+
+    test.qvar_one("p = new Port(8080) and x = p.number", "x", 8080);
+    assert!(test
+        .query_err("x = new Port(70000)")
+        .contains("invalid port 70000"));
+}
+
+#[test]
+fn test_reference_validation() {
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo;
+
+    let mut test = OsoTest::new();
+    test.oso.set_validate_references(true);
+
+    let err = test.oso.load_str("allow(_, _, _: Missing);").unwrap_err();
+    assert!(err.to_string().contains("unknown class Missing"), "{}", err);
+    let err = test.oso.load_str("f(x) if x = new Mising();").unwrap_err();
+    assert!(err.to_string().contains("unknown class Mising"), "{}", err);
+    let err = test.oso.load_str("g(x) if x = Roles.ADMIN;").unwrap_err();
+    assert!(
+        err.to_string().contains("unknown constant Roles"),
+        "{}",
+        err
+    );
+
+    // Variables may be capitalized, and built-in classes are always known.
+    test.load_str("h(Role, x: Integer) if Role = {name: x} and Role.name = 1;");
+
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_constructor(Repo::default)
+                .build(),
+        )
+        .unwrap();
+    test.oso.register_constant("ADMIN", &"admin").unwrap();
+    test.load_str("allow(_, _, _: Repo); f(x) if x = new Repo(); g(ADMIN);");
+    test.qeval("g(\"admin\")");
+
+    // Validation is off by default, so classes may be registered after the
+    // policies that use them are loaded.
+    #[derive(Clone, Default, PolarClass)]
+    struct Issue;
+
+    let mut test = OsoTest::new();
+    test.load_str("allow(_, _, _: Issue);");
+    test.oso
+        .register_class(
+            Issue::get_polar_class_builder()
+                .set_constructor(Issue::default)
+                .build(),
+        )
+        .unwrap();
+    test.qeval("allow(1, 2, new Issue())");
+}
+
+#[test]
+fn test_kwargs() {
+    use std::collections::HashMap;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Page {
+        #[polar(attribute)]
+        title: String,
+        #[polar(attribute)]
+        size: i64,
+    }
+
+    impl Page {
+        fn resize(&self, by: i64, options: HashMap<String, PolarValue>) -> i64 {
+            match options.get("max") {
+                Some(PolarValue::Integer(max)) => (self.size + by).min(*max),
+                _ => self.size + by,
+            }
+        }
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Page::get_polar_class_builder()
+                .set_constructor(Page::default)
+                .add_kwarg("title", |page: &mut Page, title| page.title = title)
+                .add_kwarg("size", |page: &mut Page, size| page.size = size)
+                .add_kwargs_method("resize", Page::resize)
+                .add_method("double", |page: &Page| page.size * 2)
+                .build(),
+        )
+        .unwrap();
+
+    test.qvar_one(
+        "p = new Page(title: \"home\", size: 3) and x = p.title",
+        "x",
+        "home".to_string(),
+    );
+    test.qvar_one("p = new Page(size: 3) and x = p.size", "x", 3);
+    test.qvar_one("p = new Page() and x = p.size", "x", 0);
+    assert!(test
+        .query_err("p = new Page(color: \"red\")")
+        .contains("no keyword argument `color`"));
+
+    test.qvar_one("p = new Page(size: 3) and x = p.resize(4)", "x", 7);
+    test.qvar_one("p = new Page(size: 3) and x = p.resize(4, max: 5)", "x", 5);
+    assert!(test
+        .query_err("p = new Page(size: 3) and x = p.double(by: 2)")
+        .contains("does not accept keyword arguments"));
+}
+
+#[test]
+fn test_policy_version() {
+    let empty = Oso::new().policy_version();
+    assert_eq!(
+        empty,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+
+    let mut first = Oso::new();
+    let mut second = Oso::new();
+    first.load_str("f(1);").unwrap();
+    assert_ne!(first.policy_version(), empty);
+    second.load_str("f(1);").unwrap();
+    assert_eq!(first.policy_version(), second.policy_version());
+
+    // Clones share the loaded policy.
+    let clone = first.clone();
+    first.load_str("g(1);").unwrap();
+    assert_eq!(first.policy_version(), clone.policy_version());
+    assert_ne!(first.policy_version(), second.policy_version());
+
+    first.clear();
+    assert_eq!(first.policy_version(), empty);
+}
+
+#[test]
+fn test_named_constructors() {
+    #[derive(Clone, PolarClass)]
+    struct Repo {
+        #[polar(attribute)]
+        id: i64,
+        #[polar(attribute)]
+        name: String,
+    }
+
+    impl Repo {
+        fn new(id: i64, name: String) -> Self {
+            Self { id, name }
+        }
+
+        fn from_id(id: i64) -> Self {
+            Self::new(id, format!("repo-{}", id))
+        }
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_constructor(Repo::new)
+                .add_constructor("from_id", Repo::from_id)
+                .add_constructor("from_name", |name: String| Repo::new(0, name))
+                .build(),
+        )
+        .unwrap();
+
+    test.qvar_one(
+        "r = new Repo(1, \"oso\") and x = r.name",
+        "x",
+        "oso".to_string(),
+    );
+    test.qvar_one(
+        "r = Repo.from_id(2) and x = r.name",
+        "x",
+        "repo-2".to_string(),
+    );
+    test.qvar_one("r = Repo.from_name(\"polar\") and x = r.id", "x", 0);
+    test.qeval("Repo.from_id(3) matches Repo{id: 3}");
+    assert!(!test.query_err("r = Repo.from_email(\"x\")").is_empty());
+}
+
+#[test]
+fn test_decision_cache() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, PolarClass)]
+    struct Document {
+        #[polar(attribute)]
+        id: u32,
+    }
+
+    impl Document {
+        fn owner(&self) -> String {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            "alice".to_string()
+        }
+    }
+
+    let mut oso = Oso::new();
+    oso.register_class(
+        Document::get_polar_class_builder()
+            .add_method("owner", Document::owner)
+            .set_identity(|doc: &Document| doc.id.to_string())
+            .build(),
+    )
+    .unwrap();
+    oso.load_str(
+        r#"allow(user, "read", doc: Document) if doc.owner() = user;
+           deny(_, "read", doc: Document, "archived") if doc.id = 0;"#,
+    )
+    .unwrap();
+    oso.set_decision_cache(Duration::from_secs(60), 100);
+
+    let doc = Document { id: 1 };
+    assert!(oso.is_allowed("alice", "read", doc.clone()).unwrap());
+    assert!(oso.is_allowed("alice", "read", doc.clone()).unwrap());
+    assert!(!oso.is_allowed("bob", "read", doc.clone()).unwrap());
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    assert_eq!(oso.cached_decisions(), 2);
+
+    assert!(oso.authorize("alice", "read", doc.clone()).is_ok());
+    assert!(oso.authorize("alice", "read", doc.clone()).is_ok());
+    let archived = Document { id: 0 };
+    for _ in 0..2 {
+        match oso.authorize("alice", "read", archived.clone()) {
+            Err(oso::OsoError::Forbidden(e)) => assert_eq!(e.message.as_deref(), Some("archived")),
+            _ => panic!("expected the deny rule to apply"),
+        }
+    }
+    assert_eq!(oso.cached_decisions(), 4);
+
+    // Invalidated decisions are made again.
+    oso.invalidate_decisions_about(doc.clone()).unwrap();
+    assert_eq!(oso.cached_decisions(), 1);
+    let calls = CALLS.load(Ordering::SeqCst);
+    assert!(oso.is_allowed("alice", "read", doc.clone()).unwrap());
+    assert_eq!(CALLS.load(Ordering::SeqCst), calls + 1);
+
+    // So are decisions of a changed policy.
+    assert!(!oso.is_allowed("bob", "read", doc.clone()).unwrap());
+    oso.load_str(r#"allow("bob", "read", _doc: Document);"#)
+        .unwrap();
+    assert!(oso.is_allowed("bob", "read", doc).unwrap());
+
+    oso.invalidate_decisions();
+    assert_eq!(oso.cached_decisions(), 0);
+}
+
+#[test]
+fn test_record_replay() {
+    use oso::Recording;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, PolarClass)]
+    struct User {
+        name: String,
+    }
+
+    impl User {
+        fn name(&self) -> String {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            self.name.clone()
+        }
+
+        fn documents(&self) -> Vec<String> {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            vec!["readme".to_string(), "notes".to_string()]
+        }
+    }
+
+    let policy = r#"allow(user: User, "read", doc) if
+        user.name() = "alice" and doc in user.documents();"#;
+
+    let mut oso = Oso::new();
+    oso.register_class(
+        User::get_polar_class_builder()
+            .add_method("name", User::name)
+            .add_method("documents", User::documents)
+            .build(),
+    )
+    .unwrap();
+    oso.load_str(policy).unwrap();
+
+    let alice = User {
+        name: "alice".to_string(),
+    };
+    let recording = Recording::new();
+    let query = oso
+        .query_rule("allow", vec![&alice as &dyn ToPolar, &"read", &"notes"])
+        .unwrap()
+        .record(&recording);
+    assert_eq!(query.count(), 1);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    assert!(!recording.is_empty());
+
+    // The replay needs neither the class nor the instance.
+    let mut replay = Oso::new();
+    replay.load_str(policy).unwrap();
+    let results = replay.replay(&recording).unwrap().collect::<Vec<_>>();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_ok());
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+
+    // Calls that were not recorded are errors.
+    let mut changed = Oso::new();
+    changed
+        .load_str(r#"allow(user, "read", _) if user.email() = "alice@example.com";"#)
+        .unwrap();
+    let err = changed
+        .replay(&recording)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert!(err.to_string().contains("no recorded host call"), "{}", err);
+
+    assert!(Oso::new().replay(&Recording::new()).is_err());
+}
+
+#[test]
+fn test_non_clone_iterator_methods() {
+    /// An iterator that can't be cloned, like a database cursor.
+    struct Cursor {
+        rows: std::vec::IntoIter<Box<i64>>,
+    }
+
+    impl Iterator for Cursor {
+        type Item = i64;
+
+        fn next(&mut self) -> Option<i64> {
+            self.rows.next().map(|row| *row)
+        }
+    }
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Table;
+
+    impl Table {
+        fn rows(&self) -> Cursor {
+            Cursor {
+                rows: vec![Box::new(1), Box::new(2)].into_iter(),
+            }
+        }
+
+        fn evens(&self, limit: i64) -> impl Iterator<Item = i64> {
+            let mut counter = std::sync::Mutex::new(0);
+            std::iter::from_fn(move || {
+                let counter = counter.get_mut().unwrap();
+                *counter += 2;
+                Some(*counter).filter(|n| *n <= limit)
+            })
+        }
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Table::get_polar_class_builder()
+                .set_constructor(Table::default)
+                .add_iterator_method("rows", Table::rows)
+                .add_iterator_method("evens", Table::evens)
+                .build(),
+        )
+        .unwrap();
+
+    assert_eq!(test.qvar::<i64>("new Table().rows() = x", "x"), vec![1, 2]);
+    assert_eq!(
+        test.qvar::<i64>("new Table().evens(6) = x", "x"),
+        vec![2, 4, 6]
+    );
+    // Each call gets its own iterator.
+    assert_eq!(
+        test.qvar::<i64>("t = new Table() and t.rows() = x and t.rows() = 2", "x"),
+        vec![1, 2]
+    );
+}
+
+#[test]
+fn test_sort_and_unique() {
+    #[derive(Clone, Debug, PartialEq, PartialOrd, PolarClass)]
+    struct Version {
+        #[polar(attribute)]
+        major: u32,
+        #[polar(attribute)]
+        minor: u32,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Version::get_polar_class_builder()
+                .set_constructor(|major, minor| Version { major, minor })
+                .with_equality_check()
+                .with_comparison_check()
+                .build(),
+        )
+        .unwrap();
+    test.load_str(
+        r#"versions(vs) if
+             vs = [new Version(1, 10), new Version(0, 5), new Version(1, 2), new Version(0, 5)];
+           minors(vs, ms) if
+             [a, b, c, d] = vs and ms = [a.minor, b.minor, c.minor, d.minor];"#,
+    );
+
+    // Instances are compared by the application.
+    test.qvar_one(
+        "versions(vs) and minors(sort(vs), ms)",
+        "ms",
+        vec![5, 5, 2, 10],
+    );
+    // Sorting by a field is stable.
+    test.qvar_one(
+        r#"versions(vs) and minors(sort(vs, "major"), ms)"#,
+        "ms",
+        vec![5, 5, 10, 2],
+    );
+    test.qvar_one(
+        "versions(vs) and [a, b, c] = unique(vs) and ms = [a.minor, b.minor, c.minor]",
+        "ms",
+        vec![10, 5, 2],
+    );
+    test.qvar_one("x = sort(unique([3, 1, 3, 2]))", "x", vec![1, 2, 3]);
+
+    test.query_err("x = sort([1, \"a\"])");
+    test.query_err("x = sort(1)");
+}
+
+#[test]
+fn test_context_methods() {
+    use oso::Context;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo;
+
+    struct Tenant(String);
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_constructor(Repo::default)
+                .add_context_method("query_id", |_: &Repo, context: &Context| context.query_id())
+                .add_context_method("tenant", |_: &Repo, context: &Context, suffix: String| {
+                    context
+                        .data::<Tenant>()
+                        .map(|tenant| format!("{}{}", tenant.0, suffix))
+                        .unwrap_or_default()
+                })
+                .add_context_method("has_class", |_: &Repo, context: &Context, name: String| {
+                    context.class(&name).is_some()
+                })
+                .build(),
+        )
+        .unwrap();
+
+    // The id is the same within a query, and differs between queries.
+    test.qeval("repo = new Repo() and repo.query_id() = repo.query_id()");
+    let first = test.qvar::<u64>("x = new Repo().query_id()", "x");
+    let second = test.qvar::<u64>("x = new Repo().query_id()", "x");
+    assert_ne!(first, second);
+
+    test.qeval("new Repo().has_class(\"Repo\")");
+    test.qnull("new Repo().has_class(\"Tenant\")");
+
+    // Queries without data.
+    test.qvar_one("x = new Repo().tenant(\"!\")", "x", String::new());
+
+    let mut query = test
+        .oso
+        .query_with_context("x = new Repo().tenant(\"!\")", Tenant(String::from("acme")))
+        .unwrap();
+    let result = query.next().unwrap().unwrap();
+    assert_eq!(result.get_typed::<String>("x").unwrap(), "acme!");
+}
+
+#[test]
+fn test_query_with_context() {
+    use oso::Context;
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo;
+
+    struct Connection(Vec<String>);
+    struct RequestId(u64);
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_constructor(Repo::default)
+                .add_context_method("rows", |_: &Repo, context: &Context| {
+                    context
+                        .data::<Connection>()
+                        .map(|connection| connection.0.clone())
+                        .unwrap_or_default()
+                })
+                .add_context_method("request_id", |_: &Repo, context: &Context| {
+                    context.data::<RequestId>().map(|id| id.0)
+                })
+                .build(),
+        )
+        .unwrap();
+    test.load_str("row(repo, row) if row in repo.rows();");
+
+    let repo = Repo;
+    let results = test
+        .oso
+        .query_rule(
+            "row",
+            vec![&repo as &dyn ToPolar, &PolarValue::Variable("x".into())],
+        )
+        .unwrap()
+        .with_context(Connection(vec!["a".into(), "b".into()]))
+        .with_context(RequestId(1))
+        .with_context(RequestId(7))
+        .map(|result| result.unwrap().get_typed::<String>("x").unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, vec!["a", "b"]);
+
+    let mut query = test
+        .oso
+        .query("x = new Repo().request_id()")
+        .unwrap()
+        .with_context(RequestId(7));
+    let result = query.next().unwrap().unwrap();
+    assert_eq!(result.get_typed::<u64>("x").unwrap(), 7);
+
+    // Values are attached to a single query.
+    assert!(test.qvar::<String>("row(new Repo(), x)", "x").is_empty());
+}
+
+#[test]
+fn test_query_timeout() {
+    use std::time::{Duration, Instant};
+
+    let mut test = OsoTest::new();
+    test.load_str("f(0); f(n) if n > 0 and f(n - 1) and f(n - 1);");
+    test.qeval("f(3)");
+
+    let is_timeout = |result: Option<oso::Result<oso::ResultSet>>| match result {
+        Some(Err(e)) => e.to_string().contains("Query timeout"),
+        _ => false,
+    };
+
+    let start = Instant::now();
+    let mut query = test
+        .oso
+        .query("f(40)")
+        .unwrap()
+        .with_timeout(Duration::from_millis(50));
+    assert!(is_timeout(query.next()));
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    test.oso.set_query_timeout(Duration::from_millis(50));
+    let mut query = test.oso.query("f(40)").unwrap();
+    assert!(is_timeout(query.next()));
+    let mut query = test.oso.query_rule("f", vec![&40 as &dyn ToPolar]).unwrap();
+    assert!(is_timeout(query.next()));
+
+    // A single query can be given more time.
+    let mut query = test
+        .oso
+        .query("f(3)")
+        .unwrap()
+        .with_timeout(Duration::from_secs(30));
+    assert!(query.next().unwrap().is_ok());
+}
+
+#[test]
+fn test_query_limits() {
+    use oso::QueryLimits;
+    use polar_core::error::{ErrorKind, RuntimeError};
+
+    let mut test = OsoTest::new();
+    test.load_str("depth(0); depth(n) if n > 0 and depth(n - 1) and true;");
+    test.qeval("depth(100)");
+
+    let is_overflow = |result: Option<oso::Result<oso::ResultSet>>| match result {
+        Some(Err(e)) => e.to_string().contains("Goal stack overflow"),
+        _ => false,
+    };
+    assert!(is_overflow(test.oso.query("depth(20000)").unwrap().next()));
+
+    test.oso.set_query_limits(QueryLimits {
+        max_stack_size: 1_000_000,
+        ..QueryLimits::default()
+    });
+    test.qeval("depth(20000)");
+
+    test.oso.set_query_limits(QueryLimits {
+        max_goals: Some(100),
+        ..QueryLimits::default()
+    });
+    let mut query = test.oso.query("depth(100)").unwrap();
+    match query.next() {
+        Some(Err(oso::OsoError::Polar(e))) => {
+            assert!(matches!(
+                e.kind,
+                ErrorKind::Runtime(RuntimeError::QueryLimit { .. })
+            ));
+            assert!(e.to_string().contains("ran more than 100 goals"));
+        }
+        _ => panic!("expected the goal limit to be exceeded"),
+    }
+    test.qeval("depth(1)");
+}
+
+#[test]
+fn test_query_memory_budget() {
+    use oso::{OsoError, QueryLimits};
+
+    let mut test = OsoTest::new();
+    test.load_str("depth(0); depth(n) if n > 0 and depth(n - 1) and true;");
+    test.oso.set_query_limits(QueryLimits {
+        max_stack_size: 1_000_000,
+        max_memory: Some(1 << 20),
+        ..QueryLimits::default()
+    });
+    test.qeval("depth(100)");
+
+    let mut query = test.oso.query("depth(100000)").unwrap();
+    match query.next() {
+        Some(Err(OsoError::ResourceExhausted { used, limit })) => {
+            assert_eq!(limit, 1 << 20);
+            assert!(used > limit);
+        }
+        _ => panic!("expected the memory budget to be exceeded"),
+    }
+}
+
+#[test]
+fn test_query_timeline() {
+    let mut test = OsoTest::new();
+    test.load_str("f(x) if g(x); g(x) if x.ends_with(\"c\");");
+
+    let timeline = oso::Timeline::new();
+    assert!(timeline.is_empty());
+    let results: Vec<_> = test
+        .oso
+        .query("f(\"abc\")")
+        .unwrap()
+        .record_timeline(&timeline)
+        .collect();
+    assert_eq!(results.len(), 1);
+    assert!(!timeline.is_empty());
+
+    let trace = timeline.to_chrome_trace();
+    assert!(trace.starts_with("{\"traceEvents\":["));
+    for event in &[
+        "\"cat\":\"rule\"",
+        "\"name\":\"f\"",
+        "\"name\":\"g\"",
+        "\"cat\":\"host\"",
+        "\"name\":\".ends_with\"",
+        "\"cat\":\"goal\"",
+        "\"name\":\"Query\"",
+    ] {
+        assert!(trace.contains(event), "{} not in {}", event, trace);
+    }
+}
+
+#[test]
+fn test_instance_truthiness() {
+    #[derive(Clone)]
+    struct Nickname(Option<String>);
+
+    #[derive(Clone)]
+    struct Plain;
+
+    let mut test = OsoTest::new();
+    let nickname_class = Class::with_constructor(|name: String| {
+        Nickname(Some(name).filter(|name| !name.is_empty()))
+    })
+    .name("Nickname")
+    .set_truthiness(|nickname: &Nickname| nickname.0.is_some())
+    .build();
+    test.oso.register_class(nickname_class).unwrap();
+    let plain_class = Class::with_constructor(|| Plain).name("Plain").build();
+    test.oso.register_class(plain_class).unwrap();
+    test.load_str("has_nickname(name) if nickname = new Nickname(name) and nickname;");
+
+    // Instances used as conditions are errors unless enabled.
+    test.query_err("has_nickname(\"Bob\")");
+
+    test.oso.set_instance_truthiness(true);
+    test.qeval("has_nickname(\"Bob\")");
+    test.qnull("has_nickname(\"\")");
+    test.qeval("not has_nickname(\"\")");
+    // Classes without a truthiness are still errors.
+    test.query_err("x = new Plain() and x");
+}
+
+#[test]
+fn test_query_cancel_handle() {
+    use std::time::Duration;
+
+    let mut test = OsoTest::new();
+    test.load_str("f(0); f(n) if n > 0 and f(n - 1) and f(n - 1);");
+
+    // Cancelled while running, from another thread.
+    let mut query = test.oso.query("f(40)").unwrap();
+    let handle = query.cancel_handle();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        handle.cancel();
+    });
+    assert!(matches!(query.next(), Some(Err(oso::OsoError::Cancelled))));
+    assert!(query.next().is_none());
+    canceller.join().unwrap();
+
+    // Cancelled before running.
+    let mut query = test.oso.query("f(1)").unwrap();
+    let handle = query.cancel_handle();
+    assert!(!handle.is_cancelled());
+    handle.cancel();
+    assert!(handle.is_cancelled());
+    assert!(matches!(query.next(), Some(Err(oso::OsoError::Cancelled))));
+
+    test.qeval("f(1)");
+}
+
+#[test]
+fn test_query_pages() {
+    use oso::{Context, Page};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo {
+        pages: Arc<Mutex<Vec<Page>>>,
+    }
+
+    let repo = Repo::default();
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .add_context_iterator_method("issues", |repo: &Repo, context: &Context| {
+                    let page = context.page();
+                    repo.pages.lock().unwrap().push(page);
+                    (0..page.end().unwrap_or(10) as i64).collect::<Vec<_>>()
+                })
+                .build(),
+        )
+        .unwrap();
+    test.load_str("issue(repo, issue) if issue = repo.issues();");
+
+    let issues = |query: oso::Query| {
+        query
+            .map(|result| result.unwrap().get_typed::<i64>("x").unwrap())
+            .collect::<Vec<_>>()
+    };
+    let x = PolarValue::Variable("x".into());
+    let args = || vec![&repo as &dyn ToPolar, &x];
+
+    let query = test.oso.query_rule("issue", args()).unwrap();
+    assert_eq!(issues(query), (0..10).collect::<Vec<_>>());
+
+    let query = test.oso.query_rule("issue", args()).unwrap().limit(3);
+    assert_eq!(issues(query), vec![0, 1, 2]);
+
+    let query = test
+        .oso
+        .query_rule("issue", args())
+        .unwrap()
+        .offset(2)
+        .limit(3);
+    assert_eq!(issues(query), vec![2, 3, 4]);
+
+    let query = test.oso.query_rule("issue", args()).unwrap().offset(8);
+    assert_eq!(issues(query), vec![8, 9]);
+
+    assert_eq!(
+        *repo.pages.lock().unwrap(),
+        vec![
+            Page::default(),
+            Page {
+                offset: 0,
+                limit: Some(3)
+            },
+            Page {
+                offset: 2,
+                limit: Some(3)
+            },
+            Page {
+                offset: 8,
+                limit: None
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parameter_destructuring() {
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        id: i64,
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct Repo {
+        #[polar(attribute)]
+        owner_id: i64,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(User::get_polar_class()).unwrap();
+    test.oso.register_class(Repo::get_polar_class()).unwrap();
+    test.load_str(
+        r#"allow(User{id: uid}, "edit", Repo{owner_id: uid});
+           owner(Repo{owner_id: id}, id);"#,
+    );
+
+    let alice = User { id: 1 };
+    let repo = Repo { owner_id: 1 };
+    let other = Repo { owner_id: 2 };
+    assert!(test
+        .oso
+        .is_allowed(alice.clone(), "edit", repo.clone())
+        .unwrap());
+    assert!(!test.oso.is_allowed(alice, "edit", other).unwrap());
+
+    let results = test
+        .oso
+        .query_rule(
+            "owner",
+            vec![&repo as &dyn ToPolar, &PolarValue::Variable("id".into())],
+        )
+        .unwrap()
+        .map(|result| result.unwrap().get_typed::<i64>("id").unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, vec![1]);
+
+    // Matches only instances of the class.
+    test.qnull("owner({owner_id: 1}, _)");
+}
+
+#[test]
+fn test_inherited_methods() {
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        name: String,
+    }
+
+    impl User {
+        fn greet(&self, greeting: String) -> String {
+            format!("{}, {}", greeting, self.name)
+        }
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct Admin {
+        user: User,
+        #[polar(attribute)]
+        level: i64,
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct Root {
+        admin: Admin,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .add_method("greet", User::greet)
+                .add_attribute_getter("title", |_: &User| "user")
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            Admin::get_polar_class_builder()
+                .with_parent(|admin: &Admin| &admin.user)
+                .add_attribute_getter("title", |_: &Admin| "admin")
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            Root::get_polar_class_builder()
+                .with_parent(|root: &Root| &root.admin)
+                .build(),
+        )
+        .unwrap();
+
+    let admin = Admin {
+        user: User {
+            name: String::from("alice"),
+        },
+        level: 2,
+    };
+    let root = Root {
+        admin: admin.clone(),
+    };
+    test.oso.register_constant("admin", &admin).unwrap();
+    test.oso.register_constant("root", &root).unwrap();
+
+    test.qvar_one("x = admin.name", "x", String::from("alice"));
+    test.qvar_one("x = admin.greet(\"hi\")", "x", String::from("hi, alice"));
+    test.qvar_one("x = admin.level", "x", 2);
+    // Attributes of the class take precedence.
+    test.qvar_one("x = admin.title", "x", String::from("admin"));
+    // Parents are searched transitively.
+    test.qvar_one("x = root.greet(\"hi\")", "x", String::from("hi, alice"));
+    test.qvar_one("x = root.level", "x", 2);
+    test.qeval("root matches Admin");
+    test.query_err("root.missing");
+}
+
+#[test]
+fn test_missing_attributes_per_query() {
+    #[derive(Clone, PolarClass)]
+    struct Doc {
+        #[polar(attribute)]
+        owner: String,
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct Tag;
+
+    #[derive(Clone, PolarClass)]
+    struct Row {
+        columns: std::collections::HashMap<String, String>,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(Doc::get_polar_class()).unwrap();
+    test.oso.register_class(Tag::get_polar_class()).unwrap();
+    test.oso
+        .register_class(
+            Row::get_polar_class_builder()
+                .set_attribute_fallback(|row: &Row, name| row.columns.get(name).cloned())
+                .build(),
+        )
+        .unwrap();
+    let doc = |owner: &str| Doc {
+        owner: owner.to_string(),
+    };
+    let row = |columns: Vec<(&str, &str)>| Row {
+        columns: columns
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    };
+    test.oso.register_constant("TAG", &Tag).unwrap();
+    test.oso.register_constant("ALICE", &doc("alice")).unwrap();
+    test.oso.register_constant("CAROL", &doc("carol")).unwrap();
+    test.oso.register_constant("EMPTY", &row(vec![])).unwrap();
+    test.oso
+        .register_constant("BOB", &row(vec![("owner", "bob")]))
+        .unwrap();
+    test.load_str("resource(r) if r in [TAG, ALICE, TAG, EMPTY, BOB, CAROL];");
+
+    // Missing attributes are skipped in patterns, every time they are
+    // probed, while attributes found by a fallback depend on the instance.
+    let mut owners = test.qvar::<String>("resource(r) and r matches {owner: x}", "x");
+    owners.sort();
+    assert_eq!(owners, vec!["alice", "bob", "carol"]);
+
+    test.query_err("resource(r) and r.missing()");
+}
+
+#[test]
+fn test_coercions() {
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        name: String,
+    }
+
+    #[derive(Clone, PolarClass)]
+    struct UserRef {
+        #[polar(attribute)]
+        id: i64,
+    }
+
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo;
+
+    let users = vec![String::from("alice"), String::from("bob")];
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .coerces_from(move |user: &UserRef| User {
+                    name: users[user.id as usize].clone(),
+                })
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            UserRef::get_polar_class_builder()
+                .set_constructor(|id| UserRef { id })
+                .build(),
+        )
+        .unwrap();
+    test.oso
+        .register_class(
+            Repo::get_polar_class_builder()
+                .set_constructor(Repo::default)
+                .add_method("owner_name", |_: &Repo, user: User| user.name)
+                .build(),
+        )
+        .unwrap();
+    test.load_str(r#"allow(user: User, "read", _: Repo) if user.id = 1;"#);
+
+    // Converted when passed to a method that takes a `User`.
+    test.qvar_one(
+        "x = new Repo().owner_name(new UserRef(1))",
+        "x",
+        String::from("bob"),
+    );
+    // Matches `User` in rules and `matches`.
+    test.qeval("new UserRef(0) matches User");
+    assert!(test
+        .oso
+        .is_allowed(UserRef { id: 1 }, "read", Repo)
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed(UserRef { id: 0 }, "read", Repo)
+        .unwrap());
+    test.qnull("new Repo() matches User");
+}
+
+#[test]
+fn test_unregister_and_replace_classes() {
+    #[derive(Clone, Default, PolarClass)]
+    struct Repo;
+
+    let mut test = OsoTest::new();
+    let repo = || Repo::get_polar_class_builder().set_constructor(Repo::default);
+    test.oso
+        .register_class(repo().add_method("kind", |_: &Repo| 1).build())
+        .unwrap();
+    test.oso.register_alias("Repository", "Repo").unwrap();
+    test.qvar_one("x = new Repo().kind()", "x", 1);
+
+    // Registering the same name again is an error unless replacing is asked for.
+    assert!(test
+        .oso
+        .register_class(repo().add_method("kind", |_: &Repo| 2).build())
+        .is_err());
+    test.oso
+        .replace_class(repo().add_method("kind", |_: &Repo| 2).build())
+        .unwrap();
+    test.qvar_one("x = new Repo().kind()", "x", 2);
+
+    test.oso.unregister_class::<Repo>().unwrap();
+    test.query_err("new Repo()");
+    test.query_err("new Repository()");
+    assert!(test.oso.unregister_class::<Repo>().is_err());
+
+    test.oso
+        .register_class(repo().add_method("kind", |_: &Repo| 3).build())
+        .unwrap();
+    test.qvar_one("x = new Repo().kind()", "x", 3);
+}
+
+#[test]
+fn test_with_rules_overridden() {
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"allow(user, "read", _) if is_admin(user);
+           is_admin("alice");"#,
+    );
+    let version = test.oso.policy_version();
+
+    let stubbed = test
+        .oso
+        .with_rules_overridden(r#"is_admin(_); stub(1);"#, |oso| {
+            assert!(oso.is_allowed("bob", "read", "doc").unwrap());
+            oso.query("stub(1)").unwrap().count()
+        })
+        .unwrap();
+    assert_eq!(stubbed, 1);
+    assert!(!test.oso.is_allowed("bob", "read", "doc").unwrap());
+    assert!(test.oso.is_allowed("alice", "read", "doc").unwrap());
+    test.qnull("stub(1)");
+    assert_eq!(test.oso.policy_version(), version);
+
+    // The original rules are restored when the closure panics.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        test.oso
+            .with_rules_overridden("is_admin(_);", |_| panic!("failed"))
+            .unwrap()
+    }));
+    assert!(result.is_err());
+    assert!(!test.oso.is_allowed("bob", "read", "doc").unwrap());
+}
+
+#[test]
+fn test_with_rules_overridden_budgets() {
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"@budget(goals=50)
+           count(0);
+           count(n) if n > 0 and count(n - 1);
+           walk(0);
+           walk(n) if n > 0 and walk(n - 1);"#,
+    );
+    test.query_err("count(100)");
+
+    // Overrides don't inherit the budget of the original rules, which is
+    // restored afterwards.
+    test.oso
+        .with_rules_overridden("count(_);", |oso| {
+            assert_eq!(oso.query("count(100)").unwrap().count(), 1);
+        })
+        .unwrap();
+    test.qeval("count(2)");
+    test.query_err("count(100)");
+
+    // The original rules don't keep the budget of an override.
+    test.oso
+        .with_rules_overridden(
+            "@budget(goals=5) walk(0); walk(n) if n > 0 and walk(n - 1);",
+            |oso| assert!(oso.query("walk(100)").unwrap().next().unwrap().is_err()),
+        )
+        .unwrap();
+    test.qeval("walk(100)");
+}
+
+#[test]
+fn test_load_file_streaming() {
+    let dir = std::env::temp_dir().join(format!("oso-streaming-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    // Large enough to be loaded in several batches.
+    let mut grants = String::from("# generated\n");
+    for i in 0..50_000 {
+        grants.push_str(&format!("grant(\"user_{}\", \"read;write\", {});\n", i, i));
+    }
+    grants.push_str("allow(user, action, id) if grant(user, _, id) and action = \"read\";\n");
+    let grants_file = path("grants.polar");
+    std::fs::write(&grants_file, &grants).unwrap();
+
+    let mut test = OsoTest::new();
+    let mut progress = vec![];
+    test.oso
+        .load_file_streaming(&grants_file, |p| progress.push(p.clone()))
+        .unwrap();
+    assert!(progress.len() > 1);
+    let last = progress.last().unwrap();
+    assert_eq!(last.bytes_loaded, last.bytes_total);
+    assert_eq!(last.bytes_total, grants.len() as u64);
+    test.qvar_one(
+        r#"grant("user_49999", x, 49999)"#,
+        "x",
+        "read;write".to_string(),
+    );
+    assert!(test.oso.is_allowed("user_7", "read", 7).unwrap());
+    assert!(test.oso.load_file_streaming(&grants_file, |_| {}).is_err());
+
+    let mut oso = Oso::new();
+    oso.load_file(&grants_file).unwrap();
+    assert_eq!(test.oso.policy_version(), oso.policy_version());
+
+    // Parse several files in parallel.
+    let roles_file = path("roles.polar");
+    std::fs::write(&roles_file, r#"role("alice", "admin");"#).unwrap();
+    let mut parallel = Oso::new();
+    parallel.load_files(&[&grants_file, &roles_file]).unwrap();
+    let mut sequential = Oso::new();
+    sequential.load_file(&grants_file).unwrap();
+    sequential.load_file(&roles_file).unwrap();
+    assert_eq!(parallel.policy_version(), sequential.policy_version());
+    assert_eq!(parallel.query(r#"role("alice", x)"#).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_instance_downcast() {
+    #[derive(Clone, Debug, PartialEq, PolarClass)]
+    struct User {
+        name: String,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .set_constructor(|name| User { name })
+                .build(),
+        )
+        .unwrap();
+
+    let mut query = test.oso.query(r#"x = new User("alice")"#).unwrap();
+    let instance: oso::Instance = query.next().unwrap().unwrap().get_typed("x").unwrap();
+    assert_eq!(instance.downcast::<User>().unwrap().name, "alice");
+
+    let err = instance.downcast::<String>().unwrap_err();
+    assert_eq!(err.actual, "User");
+    assert_eq!(
+        err.to_string(),
+        "expected an instance of `alloc::string::String`, got an instance of `User`"
+    );
+    assert!(instance.clone().try_into_owned::<i64>().is_err());
+    assert_eq!(
+        instance.try_into_owned::<User>().unwrap(),
+        User {
+            name: "alice".to_string()
+        }
+    );
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn test_load_archive() {
+    use std::io::Write;
+
+    let files = [
+        (
+            "policy/allow.polar",
+            r#"allow(user, "read", _) if admin(user);"#,
+        ),
+        ("policy/admins.polar", r#"admin("alice");"#),
+        ("README.md", "not a policy"),
+    ];
+
+    let mut tar = tar::Builder::new(vec![]);
+    for (name, src) in files.iter() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(src.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, src.as_bytes()).unwrap();
+    }
+    let tar = tar.into_inner().unwrap();
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (name, src) in files.iter() {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(src.as_bytes()).unwrap();
+    }
+    let zip = zip.finish().unwrap().into_inner();
+
+    for archive in [tar, zip].iter() {
+        let mut test = OsoTest::new();
+        test.oso.load_archive(archive).unwrap();
+        assert!(test.oso.is_allowed("alice", "read", "doc").unwrap());
+        assert!(!test.oso.is_allowed("bob", "read", "doc").unwrap());
+        // The same files can't be loaded twice.
+        assert!(test.oso.load_archive(archive).is_err());
+    }
+
+    let mut test = OsoTest::new();
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    zip.start_file("broken.polar", zip::write::FileOptions::default())
+        .unwrap();
+    zip.write_all(b"allow(").unwrap();
+    let zip = zip.finish().unwrap().into_inner();
+    let err = test.oso.load_archive(&zip).unwrap_err().to_string();
+    assert!(err.contains("broken.polar"), "{}", err);
+}
+
+#[test]
+fn test_diff_results() {
+    use oso::testing::{diff_results, diff_results_ordered};
+
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"before(1, "a");
+           before(2, "b");
+           before(3, "c");
+           after(3, "c");
+           after(2, "b");
+           after(4, "d");"#,
+    );
+    let before = test.query("before(x, y)");
+    let after = test.query("after(x, y)");
+
+    assert!(diff_results(&before, &before).is_empty());
+    assert!(diff_results_ordered(&before, &before).is_empty());
+
+    let diff = diff_results(&before, &after);
+    assert_eq!(diff.removed, vec![r#"{x: 1, y: "a"}"#]);
+    assert_eq!(diff.added, vec![r#"{x: 4, y: "d"}"#]);
+    assert_eq!(diff.to_string(), "- {x: 1, y: \"a\"}\n+ {x: 4, y: \"d\"}\n");
+
+    let diff = diff_results_ordered(&before, &after);
+    assert_eq!(diff.removed, vec![r#"{x: 1, y: "a"}"#, r#"{x: 2, y: "b"}"#]);
+    assert_eq!(diff.added, vec![r#"{x: 2, y: "b"}"#, r#"{x: 4, y: "d"}"#]);
+
+    let twice = test.query("before(x, y) or before(x, y)");
+    let diff = diff_results(&twice, &before);
+    assert_eq!(diff.removed.len(), 3);
+    assert!(diff.added.is_empty());
+}
+
+#[test]
+fn test_instances_share_class_methods() {
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        name: String,
+    }
+
+    let class = User::get_polar_class_builder()
+        .add_method("greeting", |user: &User| format!("hi, {}", user.name))
+        .build();
+    let alice = class.cast_to_instance(User {
+        name: "alice".to_string(),
+    });
+    let bob = class.cast_to_instance(User {
+        name: "bob".to_string(),
+    });
+    assert!(std::sync::Arc::ptr_eq(&alice.attributes, &bob.attributes));
+    assert!(std::sync::Arc::ptr_eq(
+        &alice.methods,
+        &class.instance_methods
+    ));
+
+    let mut test = OsoTest::new();
+    test.oso.register_class(class).unwrap();
+    test.oso
+        .register_constant("ALICE", &PolarValue::Instance(alice))
+        .unwrap();
+    test.qvar_one("x = ALICE.greeting()", "x", "hi, alice".to_string());
+    test.qvar_one("x = ALICE.name", "x", "alice".to_string());
+}
+
+#[test]
+fn test_clones_share_policy() {
+    let mut test = OsoTest::new();
+    test.load_str("allow(actor, _action, _resource) if actor = \"alice\";");
+
+    let workers = (0..4)
+        .map(|_| {
+            let mut oso = test.oso.clone();
+            std::thread::spawn(move || {
+                oso.is_allowed("alice", "read", "doc").unwrap()
+                    && !oso.is_allowed("bob", "read", "doc").unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        assert!(worker.join().unwrap());
+    }
+
+    let mut clone = test.oso.clone();
+    clone
+        .load_str("allow(\"bob\", _action, _resource);")
+        .unwrap();
+    assert!(test.oso.is_allowed("bob", "read", "doc").unwrap());
+    assert_eq!(test.oso.policy_version(), clone.policy_version());
+}
+
+#[test]
+fn test_attribute_migrations() {
+    #[derive(Clone, PolarClass)]
+    struct User {
+        #[polar(attribute)]
+        username: String,
+    }
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            User::get_polar_class_builder()
+                .set_version(2)
+                .add_renamed_attribute("login", "username")
+                .add_removed_attribute("legacy_id")
+                .build(),
+        )
+        .unwrap();
+    test.load_str(
+        r#"allow(user: User, "read", _resource) if user.login = "alice";
+           allow(user: User{legacy_id: 1}, "write", _resource);
+           allow(user: User, "admin", _resource) if user.username = "root";
+           allow(user, "delete", _resource) if user.login = "bob";"#,
+    );
+
+    let migrations = test.oso.attribute_migrations();
+    assert_eq!(migrations.len(), 2);
+    assert_eq!(migrations[0].class, "User");
+    assert_eq!(migrations[0].attribute, "login");
+    assert_eq!(migrations[0].version, Some(2));
+    assert_eq!(migrations[0].renamed_to.as_deref(), Some("username"));
+    assert!(migrations[0]
+        .to_string()
+        .ends_with("uses `User.login`, which was renamed to `username` in version 2"));
+    assert_eq!(migrations[1].attribute, "legacy_id");
+    assert_eq!(migrations[1].renamed_to, None);
+    assert!(migrations[1]
+        .to_string()
+        .ends_with("uses `User.legacy_id`, which was removed in version 2"));
+}
+
+#[test]
+fn test_registered_conversions() {
+    use oso::Converted;
+    use std::path::PathBuf;
+
+    let mut test = OsoTest::new();
+    test.oso.register_conversion::<PathBuf, _, _>(
+        |path| PolarValue::String(path.to_string_lossy().into_owned()),
+        |value| match value {
+            PolarValue::String(path) => Ok(PathBuf::from(path)),
+            _ => Err(oso::OsoError::FromPolar),
+        },
+    );
+    test.load_str(r#"allow(_actor, "read", path) if path.ends_with(".pub");"#);
+
+    let path = |p: &str| Converted(PathBuf::from(p));
+    assert!(test
+        .oso
+        .is_allowed("alice", "read", path("/docs/a.pub"))
+        .unwrap());
+    assert!(!test
+        .oso
+        .is_allowed("alice", "read", path("/docs/a"))
+        .unwrap());
+
+    let mut query = test.oso.query(r#"x = "/etc/hosts""#).unwrap();
+    let result = query.next().unwrap().unwrap();
+    let Converted(x) = result.get_typed::<Converted<PathBuf>>("x").unwrap();
+    assert_eq!(x, PathBuf::from("/etc/hosts"));
+
+    let err = result
+        .get_typed::<Converted<std::time::Instant>>("x")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "no conversion registered for `std::time::Instant`"
+    );
+}
+
+#[test]
+fn test_is_allowed_reuses_queries() {
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"allow(actor, "read", resource) if actor = resource;
+           allow("admin", _action, _resource);"#,
+    );
+
+    // Checks reuse the queries of earlier checks on the same thread, which
+    // must not keep any of their state.
+    for i in 0..20 {
+        assert!(test.oso.is_allowed(i, "read", i).unwrap());
+        assert!(!test.oso.is_allowed(i, "read", i + 1).unwrap());
+        assert!(test.oso.is_allowed("admin", "write", i).unwrap());
+        assert_eq!(test.qvar::<i64>("x = 1 or x = 2", "x"), vec![1, 2]);
+    }
+
+    // Including checks of other instances.
+    let mut other = Oso::new();
+    other
+        .load_str("allow(_actor, \"write\", _resource);")
+        .unwrap();
+    assert!(other.is_allowed(1, "write", 2).unwrap());
+    assert!(!test.oso.is_allowed(1, "write", 2).unwrap());
+}
+
+#[test]
+fn test_enumerate() {
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"pair(x, y) if x in [1, 2, 3] and y in [1, 2, 3];
+           pair(1, 1);"#,
+    );
+    let x = PolarValue::Variable("x".to_string());
+    let y = PolarValue::Variable("y".to_string());
+    let args = || vec![&x as &dyn ToPolar, &y];
+
+    let mut pairs = vec![];
+    let stats = test
+        .oso
+        .enumerate("pair", args(), &Enumeration::new(), |result| {
+            pairs.push((result.get_typed::<i64>("x")?, result.get_typed::<i64>("y")?));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(pairs.len(), 10);
+    assert_eq!(stats.results, 10);
+    assert_eq!(stats.duplicates, 0);
+
+    // Spill all but the two most recent fingerprints.
+    let dir = std::env::temp_dir().join(format!("oso-enumerate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut pairs = vec![];
+    let enumeration = Enumeration::new().dedup().max_in_memory(2).spill_dir(&dir);
+    let stats = test
+        .oso
+        .enumerate("pair", args(), &enumeration, |result| {
+            pairs.push((result.get_typed::<i64>("x")?, result.get_typed::<i64>("y")?));
+            Ok(())
+        })
+        .unwrap();
+    pairs.sort_unstable();
+    pairs.dedup();
+    assert_eq!(pairs.len(), 9);
+    assert_eq!(stats.results, 9);
+    assert_eq!(stats.duplicates, 1);
+    assert!(stats.spills > 0);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Errors of the sink stop the enumeration.
+    let mut calls = 0;
+    let err = test
+        .oso
+        .enumerate("pair", args(), &Enumeration::new(), |_| {
+            calls += 1;
+            Err(oso::OsoError::FromPolar)
+        })
+        .unwrap_err();
+    assert!(matches!(err, oso::OsoError::FromPolar));
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn test_enumerate_dedup_instances() {
+    #[derive(Clone, PolarClass)]
+    struct Widget(i64);
+
+    let mut test = OsoTest::new();
+    test.oso
+        .register_class(
+            Widget::get_polar_class_builder()
+                .set_constructor(Widget)
+                .set_repr(|_| "Widget".to_string())
+                .build(),
+        )
+        .unwrap();
+    test.oso.register_constant("W", &Widget(0)).unwrap();
+    test.load_str(
+        r#"widget(x) if x = new Widget(1);
+           widget(x) if x = new Widget(2);
+           widget(W);
+           widget(W);"#,
+    );
+    let x = PolarValue::Variable("x".to_string());
+
+    // Distinct instances are kept even though their reprs are the same.
+    let mut widgets = vec![];
+    let stats = test
+        .oso
+        .enumerate(
+            "widget",
+            vec![&x as &dyn ToPolar],
+            &Enumeration::new().dedup(),
+            |result| {
+                widgets.push(result.get_typed::<Widget>("x")?.0);
+                Ok(())
+            },
+        )
+        .unwrap();
+    widgets.sort_unstable();
+    assert_eq!(widgets, vec![0, 1, 2]);
+    assert_eq!(stats.duplicates, 1);
+}
+
+#[test]
+fn test_tabling() {
+    let mut test = OsoTest::new();
+    test.load_str(
+        r#"inherits("owner", "editor");
+           inherits("owner", "viewer");
+           inherits("editor", "viewer");
+           inherits("viewer", "editor");
+           has_role("alice", "owner");
+           has_role(user, role) if inherits(other, role) and has_role(user, other);"#,
+    );
+    test.oso.set_query_limits(oso::QueryLimits {
+        max_goals: Some(100_000),
+        ..Default::default()
+    });
+
+    // The cycle between editors and viewers recurses forever.
+    test.query_err(r#"has_role("bob", "viewer")"#);
+
+    test.oso.set_tabling(true);
+    test.qnull(r#"has_role("bob", "viewer")"#);
+    // Proved in several ways, but answered once.
+    assert_eq!(test.query(r#"has_role("alice", "viewer")"#).len(), 1);
+    test.qeval(r#"has_role("alice", "editor") and has_role("alice", "viewer")"#);
+    test.qeval(r#"has_role("bob", "editor") or has_role("alice", "editor")"#);
+    let mut roles = test.qvar::<String>(r#"has_role("alice", role)"#, "role");
+    roles.sort();
+    roles.dedup();
+    assert_eq!(roles, vec!["editor", "owner", "viewer"]);
+}
+
+#[test]
+fn test_result_ordering() {
+    let mut test = OsoTest::new();
+    let results = test.query("z = 1 and a = 2 and m = {y: 3, b: 4}");
+    assert_eq!(results[0].keys(), vec!["a", "m", "z"]);
+    let debug = format!("{:?}", results[0]);
+    let position = |var: &str| debug.find(&format!("\"{}\"", var)).unwrap();
+    assert!(position("a") < position("m"));
+    assert!(position("m") < position("z"));
+}
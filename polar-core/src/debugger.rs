@@ -259,8 +259,9 @@ impl Debugger {
                         .bindings(true)
                         .keys()
                         .map(|k| k.to_polar())
-                        .collect::<Vec<_>>()
-                        .join(", ");
+                        .collect::<Vec<_>>();
+                    vars.sort();
+                    let mut vars = vars.join(", ");
                     if vars.is_empty() {
                         vars = "No variables in scope.".to_string();
                     }
@@ -214,9 +214,16 @@ pub enum RuntimeError {
     },
     StackOverflow {
         msg: String,
+        trace: Option<PartialTrace>,
     },
     QueryTimeout {
         msg: String,
+        trace: Option<PartialTrace>,
+    },
+    /// The query ran more goals than its limit.
+    QueryLimit {
+        msg: String,
+        trace: Option<PartialTrace>,
     },
     Application {
         msg: String,
@@ -225,6 +232,50 @@ pub enum RuntimeError {
     FileLoading {
         msg: String,
     },
+    NumericComparison {
+        msg: String,
+    },
+    UnknownReference {
+        msg: String,
+    },
+    BudgetExceeded {
+        rule: Symbol,
+        goals: u64,
+        trace: Option<PartialTrace>,
+    },
+    /// The query was cancelled by its host.
+    Cancelled,
+    /// The query used more memory than its budget.
+    ResourceExhausted {
+        used: usize,
+        limit: usize,
+        trace: Option<PartialTrace>,
+    },
+}
+
+/// Where a query that was stopped for exceeding a limit was spending its
+/// time.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartialTrace {
+    /// The innermost rules being called when the query stopped, outermost
+    /// first.
+    pub rules: Vec<Symbol>,
+    /// The rules called most often by the query, and how often, most
+    /// called first.
+    pub calls: Vec<(Symbol, u64)>,
+}
+
+impl fmt::Display for PartialTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rules: Vec<&str> = self.rules.iter().map(|rule| rule.0.as_str()).collect();
+        writeln!(f, "rules being called: {}", rules.join(" > "))?;
+        let calls: Vec<String> = self
+            .calls
+            .iter()
+            .map(|(rule, calls)| format!("{} ({} calls)", rule.0, calls))
+            .collect();
+        write!(f, "most called rules: {}", calls.join(", "))
+    }
 }
 
 impl RuntimeError {
@@ -251,8 +302,27 @@ impl fmt::Display for RuntimeError {
                 write!(f, "Type error: {}", msg)
             }
             Self::UnboundVariable { sym } => write!(f, "{} is an unbound variable", sym.0),
-            Self::StackOverflow { msg } => write!(f, "Hit a stack limit: {}", msg),
-            Self::QueryTimeout { msg } => write!(f, "Query timeout: {}", msg),
+            Self::StackOverflow { msg, trace } => {
+                write!(f, "Hit a stack limit: {}", msg)?;
+                if let Some(trace) = trace {
+                    write!(f, "\n{}", trace)?;
+                }
+                Ok(())
+            }
+            Self::QueryTimeout { msg, trace } => {
+                write!(f, "Query timeout: {}", msg)?;
+                if let Some(trace) = trace {
+                    write!(f, "\n{}", trace)?;
+                }
+                Ok(())
+            }
+            Self::QueryLimit { msg, trace } => {
+                write!(f, "Query limit exceeded: {}", msg)?;
+                if let Some(trace) = trace {
+                    write!(f, "\n{}", trace)?;
+                }
+                Ok(())
+            }
             Self::Application { msg, stack_trace } => {
                 if let Some(stack_trace) = stack_trace {
                     writeln!(f, "{}", stack_trace)?;
@@ -260,6 +330,31 @@ impl fmt::Display for RuntimeError {
                 write!(f, "Application error: {}", msg)
             }
             Self::FileLoading { msg } => write!(f, "Problem loading file: {}", msg),
+            Self::NumericComparison { msg } => write!(f, "Numeric comparison error: {}", msg),
+            Self::UnknownReference { msg } => write!(f, "Unknown reference: {}", msg),
+            Self::BudgetExceeded { rule, goals, trace } => {
+                write!(
+                    f,
+                    "Budget exceeded: rule {} ran more than its budget of {} goals",
+                    rule.0, goals
+                )?;
+                if let Some(trace) = trace {
+                    write!(f, "\n{}", trace)?;
+                }
+                Ok(())
+            }
+            Self::Cancelled => write!(f, "Query cancelled"),
+            Self::ResourceExhausted { used, limit, trace } => {
+                write!(
+                    f,
+                    "Resource exhausted: query used about {} bytes, more than its budget of {} bytes",
+                    used, limit
+                )?;
+                if let Some(trace) = trace {
+                    write!(f, "\n{}", trace)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -1,7 +1,9 @@
 use super::kb::*;
 use super::terms::*;
 use super::traces::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+
+use std::collections::BTreeMap;
 
 #[allow(clippy::large_enum_variant)]
 #[must_use]
@@ -30,6 +32,8 @@ pub enum QueryEvent {
         attribute: Symbol,
         /// List of arguments to use if this is a method call.
         args: Option<Vec<Term>>,
+        /// Keyword arguments of the method call, if any.
+        kwargs: Option<BTreeMap<Symbol, Term>>,
     },
 
     /// Checks if the instance is an instance of (a subclass of) the class_tag.
@@ -55,6 +59,7 @@ pub enum QueryEvent {
     },
 
     Result {
+        #[serde(serialize_with = "serialize_sorted")]
         bindings: Bindings,
         trace: Option<TraceResult>,
     },
@@ -65,3 +70,38 @@ pub enum QueryEvent {
         args: TermList,
     },
 }
+
+/// Serialize bindings ordered by variable, so that the results of a query
+/// are the same from run to run, e.g. in snapshot tests and audit logs.
+fn serialize_sorted<S>(bindings: &Bindings, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.collect_map(bindings.iter().collect::<BTreeMap<_, _>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_bindings_are_sorted() {
+        let bindings: Bindings = (0..20)
+            .map(|i| {
+                (
+                    Symbol(format!("x{:02}", i)),
+                    Term::new_temporary(Value::Boolean(true)),
+                )
+            })
+            .collect();
+        let event = QueryEvent::Result {
+            bindings,
+            trace: None,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        let positions: Vec<_> = (0..20)
+            .map(|i| json.find(&format!("\"x{:02}\"", i)).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", json);
+    }
+}
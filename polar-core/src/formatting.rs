@@ -239,6 +239,7 @@ pub mod display {
                     field.to_polar(),
                 ),
                 Goal::PopQuery { term } => write!(fmt, "PopQuery({})", term.to_polar()),
+                Goal::PopBudget => write!(fmt, "PopBudget"),
                 Goal::Query { term } => write!(fmt, "Query({})", term.to_polar()),
                 Goal::FilterRules {
                     applicable_rules,
@@ -378,6 +379,8 @@ pub mod to_polar {
                 ForAll => "forall",
                 Debug => "debug",
                 Print => "print",
+                Sort => "sort",
+                Unique => "unique",
                 Isa => "matches",
             }
             .to_string()
@@ -392,6 +395,8 @@ pub mod to_polar {
             match self.operator {
                 Debug => "debug()".to_owned(),
                 Print => format!("print({})", format_args(self.operator, &self.args, ", ")),
+                Sort => format!("sort({})", format_args(self.operator, &self.args, ", ")),
+                Unique => format!("unique({})", format_args(self.operator, &self.args, ", ")),
                 Cut => "cut".to_owned(),
                 ForAll => format!(
                     "forall({}, {})",
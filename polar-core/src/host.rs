@@ -0,0 +1,271 @@
+//! The protocol between a query and its host: the application that owns
+//! the instances the policy refers to.
+//!
+//! A query asks its host what it can't answer itself with `QueryEvent`s,
+//! and is answered with `Query::call_result` and `Query::question_result`.
+//! The `Host` trait puts those questions in one place, and
+//! `Query::next_result` runs a query to its next result against an
+//! implementation of it, so that a host can be written without handling
+//! events, e.g. one backed by JSON documents rather than application
+//! objects.
+
+use std::collections::BTreeMap;
+
+use super::error::PolarResult;
+use super::events::QueryEvent;
+use super::kb::Bindings;
+use super::polar::Query;
+use super::terms::{Operator, Symbol, Term};
+
+/// Answers the questions a query asks about external instances.
+///
+/// Errors are reported to the query as application errors, which fail it
+/// where the answer was needed.
+pub trait Host {
+    /// Make an instance with `constructor`, e.g. `Foo(1)` for `new Foo(1)`,
+    /// referred to by `instance_id` from now on.
+    fn make_instance(&mut self, constructor: &Term, instance_id: u64) -> Result<(), String>;
+
+    /// The next result of looking up `attribute` on `instance`, called with
+    /// `args` and `kwargs` if it is a method call, or `None` once there
+    /// are no more. Called with the same `call_id` until it returns `None`.
+    fn call(
+        &mut self,
+        call_id: u64,
+        instance: &Term,
+        attribute: &Symbol,
+        args: Option<&[Term]>,
+        kwargs: Option<&BTreeMap<Symbol, Term>>,
+    ) -> Result<Option<Term>, String>;
+
+    /// Whether `instance` is an instance of the class `class_tag`.
+    fn isa(&mut self, instance: &Term, class_tag: &Symbol) -> Result<bool, String>;
+
+    /// Whether the class `left_class_tag` is more specific than
+    /// `right_class_tag` for the instance `instance_id`, to order rules.
+    fn is_subspecializer(
+        &mut self,
+        instance_id: u64,
+        left_class_tag: &Symbol,
+        right_class_tag: &Symbol,
+    ) -> Result<bool, String>;
+
+    /// Whether the instances `left_instance_id` and `right_instance_id`
+    /// are equal.
+    fn unify(&mut self, left_instance_id: u64, right_instance_id: u64) -> Result<bool, String>;
+
+    /// Whether `args` satisfy the comparison `operator`, e.g. `Lt`.
+    fn operator(&mut self, operator: Operator, args: &[Term]) -> Result<bool, String>;
+
+    /// Show a message of the debugger. Ignored by default.
+    fn debug(&mut self, message: &str) {
+        let _ = message;
+    }
+}
+
+impl Query {
+    /// Run the query to its next result, answering its questions with
+    /// `host`. Returns `None` once there are no more results.
+    pub fn next_result(&mut self, host: &mut dyn Host) -> Option<PolarResult<Bindings>> {
+        loop {
+            let event = match self.next()? {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+            match event {
+                QueryEvent::None => (),
+                QueryEvent::Done => return None,
+                QueryEvent::Result { bindings, .. } => return Some(Ok(bindings)),
+                QueryEvent::Debug { message } => host.debug(&message),
+                QueryEvent::MakeExternal {
+                    instance_id,
+                    constructor,
+                } => {
+                    if let Err(message) = host.make_instance(&constructor, instance_id) {
+                        self.application_error(message);
+                    }
+                }
+                QueryEvent::ExternalCall {
+                    call_id,
+                    instance,
+                    attribute,
+                    args,
+                    kwargs,
+                } => {
+                    let result = host.call(
+                        call_id,
+                        &instance,
+                        &attribute,
+                        args.as_deref(),
+                        kwargs.as_ref(),
+                    );
+                    let value = result.unwrap_or_else(|message| {
+                        self.application_error(message);
+                        None
+                    });
+                    if let Err(e) = self.call_result(call_id, value) {
+                        return Some(Err(e));
+                    }
+                }
+                QueryEvent::ExternalIsa {
+                    call_id,
+                    instance,
+                    class_tag,
+                } => {
+                    let answer = host.isa(&instance, &class_tag);
+                    self.answer(call_id, answer);
+                }
+                QueryEvent::ExternalIsSubSpecializer {
+                    call_id,
+                    instance_id,
+                    left_class_tag,
+                    right_class_tag,
+                } => {
+                    let answer =
+                        host.is_subspecializer(instance_id, &left_class_tag, &right_class_tag);
+                    self.answer(call_id, answer);
+                }
+                QueryEvent::ExternalUnify {
+                    call_id,
+                    left_instance_id,
+                    right_instance_id,
+                } => {
+                    let answer = host.unify(left_instance_id, right_instance_id);
+                    self.answer(call_id, answer);
+                }
+                QueryEvent::ExternalOp {
+                    call_id,
+                    operator,
+                    args,
+                } => {
+                    let answer = host.operator(operator, &args);
+                    self.answer(call_id, answer);
+                }
+            }
+        }
+    }
+
+    fn answer(&mut self, call_id: u64, answer: Result<bool, String>) {
+        match answer {
+            Ok(answer) => self.question_result(call_id, answer),
+            Err(message) => {
+                self.application_error(message);
+                self.question_result(call_id, false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polar::Polar;
+    use crate::terms::{Call, ExternalInstance, Value};
+    use std::collections::{HashMap, HashSet};
+
+    /// A host whose instances are JSON documents, with their class in
+    /// their `type` field.
+    #[derive(Default)]
+    struct JsonHost {
+        documents: HashMap<u64, serde_json::Value>,
+        /// The calls that returned their result.
+        answered: HashSet<u64>,
+    }
+
+    impl JsonHost {
+        fn document(&self, instance: &Term) -> Result<&serde_json::Value, String> {
+            match instance.value() {
+                Value::ExternalInstance(ExternalInstance { instance_id, .. }) => self
+                    .documents
+                    .get(instance_id)
+                    .ok_or_else(|| format!("unknown document {}", instance_id)),
+                _ => Err("not a document".to_string()),
+            }
+        }
+    }
+
+    impl Host for JsonHost {
+        fn make_instance(&mut self, _: &Term, _: u64) -> Result<(), String> {
+            Err("documents can't be made".to_string())
+        }
+
+        fn call(
+            &mut self,
+            call_id: u64,
+            instance: &Term,
+            attribute: &Symbol,
+            _: Option<&[Term]>,
+            _: Option<&BTreeMap<Symbol, Term>>,
+        ) -> Result<Option<Term>, String> {
+            if !self.answered.insert(call_id) {
+                return Ok(None);
+            }
+            match &self.document(instance)?[attribute.0.as_str()] {
+                serde_json::Value::String(s) => {
+                    Ok(Some(Term::new_temporary(Value::String(s.clone()))))
+                }
+                _ => Err(format!("no string attribute {}", attribute.0)),
+            }
+        }
+
+        fn isa(&mut self, instance: &Term, class_tag: &Symbol) -> Result<bool, String> {
+            Ok(self.document(instance)?["type"] == class_tag.0.as_str())
+        }
+
+        fn is_subspecializer(&mut self, _: u64, _: &Symbol, _: &Symbol) -> Result<bool, String> {
+            Ok(false)
+        }
+
+        fn unify(&mut self, left: u64, right: u64) -> Result<bool, String> {
+            Ok(left == right)
+        }
+
+        fn operator(&mut self, _: Operator, _: &[Term]) -> Result<bool, String> {
+            Err("documents can't be compared".to_string())
+        }
+    }
+
+    #[test]
+    fn test_json_host() {
+        let polar = Polar::new();
+        polar
+            .load_str(
+                r#"allow(user: User, "read", doc: Document) if doc.owner = user.name;
+                   allow(user: User, "edit", doc: Document) if doc.editor = user.name;"#,
+            )
+            .unwrap();
+        let mut host = JsonHost::default();
+        host.documents
+            .insert(1, serde_json::json!({"type": "User", "name": "alice"}));
+        host.documents
+            .insert(2, serde_json::json!({"type": "Document", "owner": "alice"}));
+
+        let instance = |instance_id| {
+            Term::new_temporary(Value::ExternalInstance(ExternalInstance {
+                instance_id,
+                constructor: None,
+                repr: None,
+            }))
+        };
+        let mut allowed = |action: &str, resource: u64| {
+            let query = Term::new_temporary(Value::Call(Call {
+                name: Symbol("allow".to_string()),
+                args: vec![
+                    instance(1),
+                    Term::new_temporary(Value::String(action.to_string())),
+                    instance(resource),
+                ],
+                kwargs: None,
+            }));
+            let mut query = polar.new_query_from_term(query, false);
+            query
+                .next_result(&mut host)
+                .map(|result| result.map(|_| ()))
+        };
+        assert!(matches!(allowed("read", 2), Some(Ok(()))));
+        // Users aren't documents.
+        assert!(allowed("read", 1).is_none());
+        // The document has no editor.
+        assert!(matches!(allowed("edit", 2), Some(Err(_))));
+    }
+}
@@ -19,6 +19,11 @@ pub struct KnowledgeBase {
     pub constants: Bindings,
     pub types: HashMap<Symbol, Type>,
     pub rules: HashMap<Symbol, GenericRule>,
+    /// Rule name -> most goals a call of the rule may run, from `@budget`.
+    /// The budget applies to every clause of the rule, not only to the
+    /// annotated one; if several clauses are annotated, the last one
+    /// loaded wins.
+    pub budgets: HashMap<Symbol, u64>,
     pub sources: Sources,
     /// For symbols returned from gensym.
     gensym_counter: AtomicU64,
@@ -35,6 +40,7 @@ impl KnowledgeBase {
             constants: HashMap::new(),
             types: HashMap::new(),
             rules: HashMap::new(),
+            budgets: HashMap::new(),
             sources: Sources::default(),
             id_counter: AtomicU64::new(1),
             gensym_counter: AtomicU64::new(1),
@@ -81,6 +87,11 @@ impl KnowledgeBase {
         self.constants.insert(name, value);
     }
 
+    /// Remove a constant variable, returning its value.
+    pub fn remove_constant(&mut self, name: &Symbol) -> Option<Term> {
+        self.constants.remove(name)
+    }
+
     /// Return true if a constant with the given name has been defined.
     pub fn is_constant(&self, name: &Symbol) -> bool {
         self.constants.contains_key(name)
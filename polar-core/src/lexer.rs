@@ -85,6 +85,7 @@ pub enum Token {
     Or,        // or
     Not,       // not
     Matches,   // matches
+    At,        // @
 }
 
 impl ToString for Token {
@@ -132,6 +133,7 @@ impl ToString for Token {
             Token::Or => "or".to_owned(),           // or
             Token::Not => "not".to_owned(),         // not
             Token::Matches => "matches".to_owned(), // matches
+            Token::At => "@".to_owned(),            // @
         }
     }
 }
@@ -463,6 +465,7 @@ impl<'input> Iterator for Lexer<'input> {
                 '(' => self.scan_1c_op(i, Token::LP),
                 ')' => self.scan_1c_op(i, Token::RP),
                 '.' => self.scan_1c_op(i, Token::Dot),
+                '@' => self.scan_1c_op(i, Token::At),
                 '+' => self.scan_1c_op(i, Token::Add),
                 '-' => self.scan_1c_op(i, Token::Sub),
                 '*' => self.scan_1c_op(i, Token::Mul),
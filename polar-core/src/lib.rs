@@ -5,6 +5,7 @@ extern crate maplit;
 mod debugger;
 pub mod error;
 pub mod formatting;
+pub mod host;
 mod lexer;
 #[macro_use]
 pub mod macros;
@@ -18,6 +19,7 @@ mod rewrites;
 pub mod rules;
 mod sources;
 pub mod terms;
+pub mod timeline;
 pub mod traces;
 mod vm;
 mod warnings;
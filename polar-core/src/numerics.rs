@@ -124,6 +124,34 @@ impl Div for Numeric {
     }
 }
 
+/// Which comparisons of numbers are errors rather than being evaluated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NumericComparison {
+    /// Comparing `NaN` is an error, instead of being false.
+    pub reject_nan: bool,
+    /// Comparing an integer with a float is an error, instead of comparing
+    /// their exact values.
+    pub reject_mixed: bool,
+}
+
+impl NumericComparison {
+    /// Why comparing `left` with `right` is an error, if it is one.
+    pub fn check(&self, left: Numeric, right: Numeric) -> Option<&'static str> {
+        let is_nan = |n| matches!(n, Numeric::Float(f) if f.is_nan());
+        match (left, right) {
+            _ if self.reject_nan && (is_nan(left) || is_nan(right)) => {
+                Some("NaN cannot be compared")
+            }
+            (Numeric::Integer(_), Numeric::Float(_)) | (Numeric::Float(_), Numeric::Integer(_))
+                if self.reject_mixed =>
+            {
+                Some("an integer cannot be compared with a float")
+            }
+            _ => None,
+        }
+    }
+}
+
 impl PartialEq for Numeric {
     fn eq(&self, other: &Self) -> bool {
         matches!(self.partial_cmp(other), Some(Ordering::Equal))
@@ -322,6 +350,26 @@ mod tests {
         assert!(Numeric::Integer(2) < Numeric::Float(3.0));
     }
 
+    #[test]
+    fn numeric_comparison_check() {
+        let lenient = NumericComparison::default();
+        let strict = NumericComparison {
+            reject_nan: true,
+            reject_mixed: true,
+        };
+        let nan = Numeric::Float(f64::NAN);
+        let (one, float_one) = (Numeric::Integer(1), Numeric::Float(1.0));
+
+        assert_eq!(lenient.check(nan, float_one), None);
+        assert_eq!(lenient.check(one, float_one), None);
+        assert_eq!(strict.check(one, one), None);
+        assert_eq!(strict.check(float_one, float_one), None);
+        assert!(strict.check(nan, float_one).is_some());
+        assert!(strict.check(float_one, nan).is_some());
+        assert!(strict.check(one, float_one).is_some());
+        assert!(strict.check(float_one, one).is_some());
+    }
+
     #[test]
     fn numeric_hash() {
         let nan1 = f64::NAN;
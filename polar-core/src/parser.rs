@@ -15,6 +15,12 @@ use super::terms::*;
 pub enum Line {
     Rule(Rule),
     Query(Term),
+    /// A `@budget(goals=N)` annotation of the rule named `rule`, which
+    /// applies to all of its clauses.
+    Budget {
+        rule: Symbol,
+        goals: u64,
+    },
 }
 
 lazy_static::lazy_static! {
@@ -204,6 +210,13 @@ mod tests {
         // not parenthesized => parse as a type
         let rule = parse_rule(r#"f(x: y);"#);
         assert_eq!(rule, rule!("f", ["x"; value!(instance!("y"))]));
+
+        // bare instance pattern => anonymous parameter
+        let rule = parse_rule(r#"f(User{id: uid}, Repo{owner_id: uid});"#);
+        assert_eq!(
+            rule,
+            parse_rule(r#"f(_: User{id: uid}, _: Repo{owner_id: uid});"#)
+        );
     }
 
     #[test]
@@ -267,11 +280,12 @@ mod tests {
         let f = r#"a(x) if x = new Foo(bar: 3, baz: 4, 1, 2);"#;
         parse_rules(0, f).expect_err("parse error");
 
-        // Don't allow kwargs in calls or dot ops.
+        // Allow kwargs in dot ops, but not in calls.
         let f = r#"a(x) if f(x: 1)"#;
         parse_rules(0, f).expect_err("parse error");
-        let f = r#"a(x) if x.f(x: 1)"#;
-        parse_rules(0, f).expect_err("parse error");
+        let f = r#"a(x) if x.f(1, y: 2);"#;
+        let results = parse_rules(0, f).unwrap();
+        assert_eq!(results[0].to_polar(), r#"a(x) if x.f(1, y: 2);"#);
     }
 
     #[test]
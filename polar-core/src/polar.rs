@@ -1,16 +1,19 @@
-use super::error::PolarResult;
+use super::error::{PolarError, PolarResult};
 use super::events::*;
 use super::kb::*;
 use super::messages::*;
+pub use super::numerics::NumericComparison;
 use super::parser;
 use super::rewrites::*;
 use super::rules::*;
 use super::sources::*;
 use super::terms::*;
 use super::vm::*;
-use super::warnings::check_singletons;
+pub use super::vm::{QueryLimits, TRUTHINESS_METHOD};
+use super::warnings::{check_singletons, find_unknown_reference};
 
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 
 pub struct Query {
@@ -47,6 +50,44 @@ impl Query {
     pub fn source_info(&self) -> String {
         self.vm.term_source(&self.term, true)
     }
+
+    /// Fail the query with a `QueryTimeout` error once it has run for
+    /// `timeout`. The default is 30 seconds.
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) {
+        self.vm.set_query_timeout(timeout);
+    }
+
+    /// Record the goals and rules run by the query in a timeline.
+    pub fn set_timeline(&mut self, enabled: bool) {
+        self.vm.set_timeline(enabled);
+    }
+
+    /// Take the goals and rules run since the last call.
+    pub fn take_timeline_events(&mut self) -> Vec<crate::timeline::TimelineEvent> {
+        self.vm.take_timeline_events()
+    }
+
+    /// Limit the goals the query may use.
+    pub fn set_limits(&mut self, limits: QueryLimits) {
+        self.vm.set_query_limits(limits);
+    }
+
+    /// Fail the query with a `Cancelled` error once `cancelled` is set.
+    pub fn set_cancel_flag(&mut self, cancelled: Arc<AtomicBool>) {
+        self.vm.set_cancel_flag(cancelled);
+    }
+
+    /// The query term, after rewriting.
+    pub fn term(&self) -> &Term {
+        &self.term
+    }
+
+    /// Bind `var` to `value` for the whole query, like a constant, e.g. to
+    /// pass a value that only the host can look into. Returns `false`,
+    /// binding nothing, if the query has started running.
+    pub fn bind(&mut self, var: Symbol, value: Term) -> bool {
+        self.vm.bind_constant(var, value)
+    }
 }
 
 // Query as an iterator returns `None` after the first time `Done` is seen
@@ -65,6 +106,21 @@ impl Iterator for Query {
     }
 }
 
+/// A policy source parsed by `Polar::parse`, ready to be added to the
+/// knowledge base.
+pub struct Parsed {
+    source: Source,
+    src_id: u64,
+    lines: Vec<parser::Line>,
+}
+
+impl Parsed {
+    /// The source text that was parsed.
+    pub fn src(&self) -> &str {
+        &self.source.src
+    }
+}
+
 pub struct Polar {
     pub kb: Arc<RwLock<KnowledgeBase>>,
     messages: MessageQueue,
@@ -72,6 +128,16 @@ pub struct Polar {
     loaded_files: Arc<RwLock<HashSet<String>>>,
     /// Map from source code loaded to the filename it was loaded as
     loaded_content: Arc<RwLock<HashMap<String, String>>>,
+    /// Which comparisons of numbers are errors in new queries.
+    numeric_comparison: RwLock<NumericComparison>,
+    /// Whether external instances used as conditions in new queries ask
+    /// the host for their truthiness.
+    instance_truthiness: AtomicBool,
+    /// Whether new queries answer repeated calls of rules from a table.
+    tabling: AtomicBool,
+    /// Whether loading fails for policies that refer to unknown classes
+    /// and constants.
+    validate_references: AtomicBool,
 }
 
 impl Default for Polar {
@@ -87,6 +153,10 @@ impl Polar {
             messages: MessageQueue::new(),
             loaded_content: Arc::new(RwLock::new(HashMap::new())), // file content -> file name
             loaded_files: Arc::new(RwLock::new(HashSet::new())),   // set of file names
+            numeric_comparison: RwLock::new(NumericComparison::default()),
+            instance_truthiness: AtomicBool::new(false),
+            tabling: AtomicBool::new(false),
+            validate_references: AtomicBool::new(false),
         }
     }
 
@@ -137,14 +207,82 @@ impl Polar {
         if let Some(ref filename) = filename {
             self.check_file(src, filename)?;
         }
+        let parsed = self.parse(src, filename)?;
+        self.add_parsed(parsed)
+    }
+
+    /// Parse `src` without adding it to the knowledge base, e.g. to parse
+    /// several sources in parallel before adding them in order with
+    /// `load_parsed`.
+    pub fn parse(&self, src: &str, filename: Option<String>) -> PolarResult<Parsed> {
         let source = Source {
             filename,
             src: src.to_owned(),
         };
-        let mut kb = self.kb.write().unwrap();
-        let src_id = kb.new_id();
-        let mut lines =
+        let src_id = self.kb.read().unwrap().new_id();
+        let lines =
             parser::parse_lines(src_id, src).map_err(|e| e.set_context(Some(&source), None))?;
+        Ok(Parsed {
+            source,
+            src_id,
+            lines,
+        })
+    }
+
+    /// Add a source parsed with `parse` to the knowledge base. Like
+    /// `load`, fails if a file of the same name or contents was loaded.
+    pub fn load_parsed(&self, parsed: Parsed) -> PolarResult<()> {
+        if let Some(ref filename) = parsed.source.filename {
+            self.check_file(&parsed.source.src, filename)?;
+        }
+        self.add_parsed(parsed)
+    }
+
+    /// Add a part of the file `filename`, e.g. a batch of the statements
+    /// of a file too large to parse at once. The file name is checked and
+    /// recorded when `first` is set, but its contents are not.
+    pub fn load_part(&self, src: &str, filename: &str, first: bool) -> PolarResult<()> {
+        if first {
+            if self.loaded_files.read().unwrap().contains(filename) {
+                return Err(error::RuntimeError::FileLoading {
+                    msg: format!("File {} has already been loaded.", filename),
+                }
+                .into());
+            }
+            self.loaded_files
+                .write()
+                .unwrap()
+                .insert(filename.to_string());
+        }
+        let parsed = self.parse(src, Some(filename.to_string()))?;
+        self.add_parsed(parsed)
+    }
+
+    fn add_parsed(&self, parsed: Parsed) -> PolarResult<()> {
+        let Parsed {
+            source,
+            src_id,
+            mut lines,
+        } = parsed;
+        let mut kb = self.kb.write().unwrap();
+        if self.validate_references.load(Ordering::SeqCst) {
+            for line in &lines {
+                let terms = match line {
+                    parser::Line::Rule(rule) => rule
+                        .params
+                        .iter()
+                        .flat_map(|param| param.specializer.iter().chain(Some(&param.parameter)))
+                        .chain(Some(&rule.body))
+                        .collect(),
+                    parser::Line::Query(term) => vec![term],
+                    parser::Line::Budget { .. } => vec![],
+                };
+                if let Some((msg, term)) = find_unknown_reference(&terms, &kb) {
+                    let error = error::RuntimeError::UnknownReference { msg };
+                    return Err(PolarError::from(error).set_context(Some(&source), Some(&term)));
+                }
+            }
+        }
         lines.reverse();
         kb.sources.add_source(source, src_id);
         let mut warnings = vec![];
@@ -165,6 +303,9 @@ impl Polar {
                 parser::Line::Query(term) => {
                     kb.inline_queries.push(term);
                 }
+                parser::Line::Budget { rule, goals } => {
+                    kb.budgets.insert(rule, goals);
+                }
             }
         }
         self.messages.extend(warnings.iter().map(|m| Message {
@@ -200,8 +341,11 @@ impl Polar {
             term
         };
         let query = Goal::Query { term: term.clone() };
-        let vm =
+        let mut vm =
             PolarVirtualMachine::new(self.kb.clone(), trace, vec![query], self.messages.clone());
+        vm.set_numeric_comparison(*self.numeric_comparison.read().unwrap());
+        vm.set_instance_truthiness(self.instance_truthiness.load(Ordering::SeqCst));
+        vm.set_tabling(self.tabling.load(Ordering::SeqCst));
         Ok(Query {
             done: false,
             term,
@@ -215,8 +359,11 @@ impl Polar {
             rewrite_term(&mut term, &mut kb);
         }
         let query = Goal::Query { term: term.clone() };
-        let vm =
+        let mut vm =
             PolarVirtualMachine::new(self.kb.clone(), trace, vec![query], self.messages.clone());
+        vm.set_numeric_comparison(*self.numeric_comparison.read().unwrap());
+        vm.set_instance_truthiness(self.instance_truthiness.load(Ordering::SeqCst));
+        vm.set_tabling(self.tabling.load(Ordering::SeqCst));
         Query {
             done: false,
             term,
@@ -224,6 +371,54 @@ impl Polar {
         }
     }
 
+    /// Reuse the finished `query`, which may have been made by another
+    /// `Polar`, to query for `term`, keeping the memory allocated for its
+    /// virtual machine.
+    pub fn reset_query(&self, query: &mut Query, mut term: Term, trace: bool) {
+        {
+            let mut kb = self.kb.write().unwrap();
+            rewrite_term(&mut term, &mut kb);
+        }
+        let vm = &mut query.vm;
+        vm.kb = self.kb.clone();
+        vm.messages = self.messages.clone();
+        vm.reset(trace, vec![Goal::Query { term: term.clone() }]);
+        vm.set_numeric_comparison(*self.numeric_comparison.read().unwrap());
+        vm.set_instance_truthiness(self.instance_truthiness.load(Ordering::SeqCst));
+        vm.set_tabling(self.tabling.load(Ordering::SeqCst));
+        query.term = term;
+        query.done = false;
+    }
+
+    /// Make loading fail for policies that refer to classes and constants
+    /// that are not registered, so that they are found when the policy is
+    /// loaded rather than when it is queried. Classes and constants must
+    /// then be registered before the policies that use them are loaded.
+    pub fn set_validate_references(&self, validate: bool) {
+        self.validate_references.store(validate, Ordering::SeqCst);
+    }
+
+    /// Set which comparisons of numbers are errors in queries made from now
+    /// on.
+    pub fn set_numeric_comparison(&self, numeric_comparison: NumericComparison) {
+        *self.numeric_comparison.write().unwrap() = numeric_comparison;
+    }
+
+    /// Make external instances used as conditions in queries made from now
+    /// on succeed if the host answers `true` for their `TRUTHINESS_METHOD`
+    /// method, rather than fail with a type error.
+    pub fn set_instance_truthiness(&self, enabled: bool) {
+        self.instance_truthiness.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Prove each call of a rule whose arguments are all bound at most
+    /// once per query in queries made from now on, answering repeated
+    /// calls from a table. Tabled calls succeed at most once, and fail
+    /// when called again while being proved.
+    pub fn set_tabling(&self, enabled: bool) {
+        self.tabling.store(enabled, Ordering::SeqCst);
+    }
+
     // @TODO: Direct load_rules endpoint.
 
     pub fn get_external_id(&self) -> u64 {
@@ -234,6 +429,10 @@ impl Polar {
         self.kb.write().unwrap().constant(name, value)
     }
 
+    pub fn unregister_constant(&self, name: &Symbol) -> Option<Term> {
+        self.kb.write().unwrap().remove_constant(name)
+    }
+
     pub fn next_message(&self) -> Option<Message> {
         self.messages.next()
     }
@@ -88,6 +88,37 @@ fn rewrite(term: &mut Term, kb: &KnowledgeBase) -> Option<Term> {
             term.replace_value(temp);
             Some(term.clone_with_value(new_op))
         }
+        Value::Expression(Operation {
+            operator: Operator::Sort,
+            args,
+        }) if !args.is_empty() && args.len() <= 2 => {
+            // Rewrite sort(a) and sort(a, k) to sort(a, k, x) with x a temporary
+            // and k defaulting to "", which sorts by the elements themselves.
+            let temp = Value::Variable(kb.gensym("sorted"));
+            let key = args
+                .get(1)
+                .cloned()
+                .unwrap_or_else(|| term.clone_with_value(Value::String(String::new())));
+            let new_op = Value::Expression(Operation {
+                operator: Operator::Sort,
+                args: vec![args[0].clone(), key, term.clone_with_value(temp.clone())],
+            });
+            term.replace_value(temp);
+            Some(term.clone_with_value(new_op))
+        }
+        Value::Expression(Operation {
+            operator: Operator::Unique,
+            args,
+        }) if args.len() == 1 => {
+            // Rewrite unique(a) to unique(a, x) with x a temporary.
+            let temp = Value::Variable(kb.gensym("unique"));
+            let new_op = Value::Expression(Operation {
+                operator: Operator::Unique,
+                args: vec![args[0].clone(), term.clone_with_value(temp.clone())],
+            });
+            term.replace_value(temp);
+            Some(term.clone_with_value(new_op))
+        }
         Value::Variable(Symbol(name)) if name == "_" => {
             // Change _ in-place to a temporary, but don't rewrite it.
             term.replace_value(Value::Variable(kb.gensym("_")));
@@ -97,6 +128,83 @@ fn rewrite(term: &mut Term, kb: &KnowledgeBase) -> Option<Term> {
     }
 }
 
+/// The built-in operator called by `call`, if any.
+fn builtin_operator(call: &Call) -> Option<Operator> {
+    match (call.name.0.as_str(), call.args.len(), &call.kwargs) {
+        ("sort", 1..=2, None) => Some(Operator::Sort),
+        ("unique", 1, None) => Some(Operator::Unique),
+        _ => None,
+    }
+}
+
+/// Resolve calls of the `sort` and `unique` built-ins in place. They are
+/// not keywords, so rules, variables and methods may have the same names:
+/// only calls in value position, as in `x = sort(l)`, are resolved, while
+/// calls in goal position, as in `sort(l) and ...`, remain rule calls.
+fn resolve_builtins(term: &mut Term, goal: bool) {
+    let value = match term.value() {
+        Value::Call(call) => {
+            let mut call = call.clone();
+            call.args
+                .iter_mut()
+                .for_each(|arg| resolve_builtins(arg, false));
+            if let Some(kwargs) = call.kwargs.as_mut() {
+                kwargs
+                    .values_mut()
+                    .for_each(|arg| resolve_builtins(arg, false));
+            }
+            match builtin_operator(&call) {
+                Some(operator) if !goal => Value::Expression(Operation {
+                    operator,
+                    args: call.args,
+                }),
+                _ => Value::Call(call),
+            }
+        }
+        Value::Expression(Operation { operator, args }) => {
+            let operator = *operator;
+            let goals = matches!(
+                operator,
+                Operator::And | Operator::Or | Operator::Not | Operator::ForAll
+            );
+            let mut args = args.clone();
+            for (i, arg) in args.iter_mut().enumerate() {
+                match operator {
+                    // Keep method calls and constructors like goals,
+                    // resolving only their arguments.
+                    Operator::Dot if i == 1 => resolve_builtins(arg, true),
+                    Operator::New => resolve_builtins(arg, true),
+                    _ => resolve_builtins(arg, goals),
+                }
+            }
+            Value::Expression(Operation { operator, args })
+        }
+        Value::List(terms) => {
+            let mut terms = terms.clone();
+            terms.iter_mut().for_each(|t| resolve_builtins(t, false));
+            Value::List(terms)
+        }
+        Value::Dictionary(dict) => {
+            let mut dict = dict.clone();
+            dict.fields
+                .values_mut()
+                .for_each(|t| resolve_builtins(t, false));
+            Value::Dictionary(dict)
+        }
+        Value::InstanceLiteral(literal) => {
+            let mut literal = literal.clone();
+            literal
+                .fields
+                .fields
+                .values_mut()
+                .for_each(|t| resolve_builtins(t, false));
+            Value::InstanceLiteral(literal)
+        }
+        _ => return,
+    };
+    term.replace_value(value);
+}
+
 /// Walks the term and does an in-place rewrite.
 /// Uses `rewrites` as a buffer of new lookup terms.
 fn do_rewrite(term: &mut Term, kb: &mut KnowledgeBase, rewrites: &mut Vec<Term>) {
@@ -140,6 +248,7 @@ fn do_rewrite(term: &mut Term, kb: &mut KnowledgeBase, rewrites: &mut Vec<Term>)
 /// Rewrite the parameter term and return all new lookups as a vec.
 pub fn rewrite_parameter(parameter: &mut Term, kb: &mut KnowledgeBase) -> Vec<Term> {
     let mut rewrites = vec![];
+    resolve_builtins(parameter, false);
     do_rewrite(parameter, kb, &mut rewrites);
     rewrites
 }
@@ -148,6 +257,7 @@ pub fn rewrite_parameter(parameter: &mut Term, kb: &mut KnowledgeBase) -> Vec<Te
 pub fn rewrite_term(term: &mut Term, kb: &mut KnowledgeBase) {
     let mut rewrites = vec![];
 
+    resolve_builtins(term, true);
     do_rewrite(term, kb, &mut rewrites);
 
     // any other leftover rewrites which didn't get handled earlier
@@ -93,6 +93,15 @@ impl RuleIndex {
                     ruleset.extend(filter_next_args(index));
                 }
                 ruleset
+            } else if let Value::ExternalInstance(_) = arg {
+                // Instances only unify with instances, so rules with a
+                // ground parameter here don't apply, e.g. `allow` facts
+                // for resources named by strings when the resource is an
+                // instance.
+                self.index
+                    .get(&None)
+                    .map(|index| filter_next_args(index))
+                    .unwrap_or_else(RuleSet::default)
             } else {
                 // Accumulate all indexed arguments.
                 self.index.values().fold(
@@ -154,6 +163,13 @@ impl GenericRule {
             .collect()
     }
 
+    /// The rules, in the order they were added.
+    pub fn rules(&self) -> Rules {
+        let mut ids: Vec<_> = self.rules.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| self.rules[id].clone()).collect()
+    }
+
     fn next_rule_id(&mut self) -> u64 {
         let v = self.next_rule_id;
         self.next_rule_id += 1;
@@ -220,4 +236,42 @@ mod tests {
         let index13 = index1.index.get(&Some(value!(3))).unwrap();
         assert_eq!(args, keys(index13));
     }
+
+    #[test]
+    fn test_rule_index_external_instances() {
+        let polar = Polar::new();
+        for i in 0..1000 {
+            polar
+                .load_str(&format!(r#"allow("alice", "read", "doc:{}");"#, i))
+                .unwrap();
+        }
+        polar
+            .load_str(r#"allow("alice", "read", doc) if doc.public;"#)
+            .unwrap();
+
+        let kb = polar.kb.read().unwrap();
+        let generic_rule = kb.rules.get(&sym!("allow")).unwrap();
+        let instance = term!(Value::ExternalInstance(ExternalInstance {
+            instance_id: 1,
+            constructor: None,
+            repr: None,
+        }));
+
+        // Only the rule with a variable parameter applies to an instance.
+        let rules =
+            generic_rule.get_applicable_rules(&vec![term!("alice"), term!("read"), instance]);
+        assert_eq!(rules.len(), 1);
+        assert!(!rules[0].is_ground());
+
+        let rules =
+            generic_rule.get_applicable_rules(&vec![term!("alice"), term!("read"), term!("doc:7")]);
+        assert_eq!(rules.len(), 2);
+
+        let rules = generic_rule.get_applicable_rules(&vec![
+            term!("alice"),
+            term!("read"),
+            term!(sym!("x")),
+        ]);
+        assert_eq!(rules.len(), 1001);
+    }
 }
@@ -1,6 +1,7 @@
 use super::sources::SourceInfo;
 pub use super::{error, formatting::ToPolarString};
 use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
 use std::collections::{BTreeMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
@@ -102,6 +103,13 @@ impl Symbol {
     }
 }
 
+/// Look up maps keyed by symbols by name, without allocating a symbol.
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct Call {
     pub name: Symbol,
@@ -134,6 +142,8 @@ pub enum Operator {
     And,
     ForAll,
     Assign,
+    Sort,
+    Unique,
 }
 
 impl Operator {
@@ -144,6 +154,8 @@ impl Operator {
             Operator::New => 10,
             Operator::Cut => 10,
             Operator::ForAll => 10,
+            Operator::Sort => 10,
+            Operator::Unique => 10,
             Operator::Dot => 9,
             Operator::In => 8,
             Operator::Isa => 8,
@@ -484,4 +496,15 @@ mod tests {
             "b:2"
         );
     }
+
+    #[test]
+    fn test_symbol_lookup_by_name() {
+        let mut table = HashMap::new();
+        table.insert(sym!("a"), 1);
+        let mut tree = BTreeMap::new();
+        tree.insert(sym!("b"), 2);
+        assert_eq!(table.get("a"), Some(&1));
+        assert_eq!(table.get("b"), None);
+        assert_eq!(tree.get("b"), Some(&2));
+    }
 }
@@ -0,0 +1,96 @@
+//! Timelines of the evaluation of queries, in the Chrome trace event format
+//! read by `about://tracing` and Perfetto.
+
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// A span of time spent on a goal, in a rule or in the host.
+#[derive(Clone, Debug)]
+pub struct TimelineEvent {
+    pub name: String,
+    /// `"goal"`, `"rule"` or `"host"`.
+    pub category: &'static str,
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+/// The events of a query, and the rules it is in.
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    pub events: Vec<TimelineEvent>,
+    /// The rules entered and not yet left, by the depth of their body.
+    frames: Vec<(usize, String, Instant)>,
+}
+
+impl Timeline {
+    /// Enter the rule `name`, whose body is at `depth`, leaving the rules
+    /// at the same depth or deeper, which failed.
+    pub fn enter_rule(&mut self, name: String, depth: usize) {
+        self.leave_rules(depth);
+        self.frames.push((depth, name, Instant::now()));
+    }
+
+    /// Leave the rules at `depth` or deeper.
+    pub fn leave_rules(&mut self, depth: usize) {
+        let now = Instant::now();
+        while let Some((_, name, start)) = self.frames.last().filter(|frame| frame.0 >= depth) {
+            self.events.push(TimelineEvent {
+                name: name.clone(),
+                category: "rule",
+                start: *start,
+                duration: now - *start,
+            });
+            self.frames.pop();
+        }
+    }
+
+    /// Take the events so far, leaving the rules that are still running.
+    pub fn take_events(&mut self) -> Vec<TimelineEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// Format `events` as a Chrome trace event document, with their times
+/// relative to `origin`.
+pub fn chrome_trace(events: &[TimelineEvent], origin: Instant) -> String {
+    let micros = |duration: Duration| duration.as_secs_f64() * 1_000_000.0;
+    let events: Vec<_> = events
+        .iter()
+        .map(|event| {
+            json!({
+                "name": event.name,
+                "cat": event.category,
+                "ph": "X",
+                "ts": micros(event.start.saturating_duration_since(origin)),
+                "dur": micros(event.duration),
+                "pid": 1,
+                "tid": 1,
+            })
+        })
+        .collect();
+    json!({ "traceEvents": events }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_rules_are_left() {
+        let mut timeline = Timeline::default();
+        timeline.enter_rule("f".to_string(), 0);
+        timeline.enter_rule("g".to_string(), 1);
+        // `g` failed, and `f` tries another rule.
+        timeline.enter_rule("h".to_string(), 1);
+        timeline.leave_rules(1);
+        timeline.leave_rules(0);
+        let names: Vec<_> = timeline.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["g", "h", "f"]);
+        assert!(timeline.frames.is_empty());
+
+        let trace = chrome_trace(&timeline.events, timeline.events[2].start);
+        let trace: serde_json::Value = serde_json::from_str(&trace).unwrap();
+        assert_eq!(trace["traceEvents"][2]["name"], "f");
+        assert_eq!(trace["traceEvents"][2]["ts"], 0.0);
+    }
+}
@@ -1,7 +1,9 @@
-use std::collections::{HashMap, HashSet};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Write;
 use std::rc::Rc;
 use std::string::ToString;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 
 use ::log::trace;
@@ -17,14 +19,46 @@ use super::numerics::*;
 use super::rules::*;
 use super::sources::*;
 use super::terms::*;
+use super::timeline::*;
 use super::traces::*;
 
 pub const MAX_STACK_SIZE: usize = 10_000;
+/// How many goals are run between checks of the memory budget of a query.
+const MEMORY_CHECK_INTERVAL: u64 = 64;
+/// The most rules listed in each part of a `PartialTrace`.
+pub const MAX_PARTIAL_TRACE_RULES: usize = 10;
 #[cfg(not(target_arch = "wasm32"))]
 pub const QUERY_TIMEOUT_S: std::time::Duration = std::time::Duration::from_secs(30);
 #[cfg(target_arch = "wasm32")]
 pub const QUERY_TIMEOUT_S: f64 = 30_000.0;
 
+/// The method the host is asked for the truthiness of an instance used as
+/// a condition, when instance truthiness is enabled.
+pub const TRUTHINESS_METHOD: &str = "__bool__";
+
+/// Limits on the goals a query may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// The most goals on the goal stack at once, which deep recursion
+    /// grows. Defaults to `MAX_STACK_SIZE`.
+    pub max_stack_size: usize,
+    /// The most goals the query may run in total, if limited.
+    pub max_goals: Option<u64>,
+    /// The most bytes the bindings, goals and choices of the query may
+    /// take up, estimated from their number, if limited.
+    pub max_memory: Option<usize>,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_stack_size: MAX_STACK_SIZE,
+            max_goals: None,
+            max_memory: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 #[must_use = "ignored goals are never accomplished"]
 #[allow(clippy::large_enum_variant)]
@@ -83,6 +117,7 @@ pub enum Goal {
     PopQuery {
         term: Term,
     },
+    PopBudget,
     FilterRules {
         args: TermList,
         applicable_rules: Rules,
@@ -94,6 +129,25 @@ pub enum Goal {
         outer: usize,
         inner: usize,
     },
+    SortList {
+        list: TermList,
+        key: Term,
+        result: Term,
+        outer: usize,
+        inner: usize,
+    },
+    UniqueList {
+        list: TermList,
+        kept: TermList,
+        result: Term,
+    },
+    TableFailed {
+        key: TableKey,
+    },
+    TableSucceeded {
+        key: TableKey,
+        choice_index: usize, // cuts the other ways to prove the call
+    },
     TraceRule {
         trace: Rc<Trace>,
     },
@@ -108,12 +162,48 @@ pub enum Goal {
 #[derive(Clone, Debug)]
 pub struct Binding(pub Symbol, pub Term);
 
+impl Goal {
+    /// The name of the kind of goal, e.g. in timelines.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Goal::Backtrack => "Backtrack",
+            Goal::Cut { .. } => "Cut",
+            Goal::Debug { .. } => "Debug",
+            Goal::Halt => "Halt",
+            Goal::Isa { .. } => "Isa",
+            Goal::IsMoreSpecific { .. } => "IsMoreSpecific",
+            Goal::IsSubspecializer { .. } => "IsSubspecializer",
+            Goal::Lookup { .. } => "Lookup",
+            Goal::LookupExternal { .. } => "LookupExternal",
+            Goal::MakeExternal { .. } => "MakeExternal",
+            Goal::IsaExternal { .. } => "IsaExternal",
+            Goal::UnifyExternal { .. } => "UnifyExternal",
+            Goal::CheckError => "CheckError",
+            Goal::Noop => "Noop",
+            Goal::Query { .. } => "Query",
+            Goal::PopQuery { .. } => "PopQuery",
+            Goal::PopBudget => "PopBudget",
+            Goal::FilterRules { .. } => "FilterRules",
+            Goal::SortRules { .. } => "SortRules",
+            Goal::SortList { .. } => "SortList",
+            Goal::UniqueList { .. } => "UniqueList",
+            Goal::TableFailed { .. } => "TableFailed",
+            Goal::TableSucceeded { .. } => "TableSucceeded",
+            Goal::TraceRule { .. } => "TraceRule",
+            Goal::TracePush => "TracePush",
+            Goal::TracePop => "TracePop",
+            Goal::Unify { .. } => "Unify",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Choice {
     pub alternatives: Vec<GoalStack>,
     bsp: usize,            // binding stack pointer
     pub goals: GoalStack,  // goal stack snapshot
     queries: Queries,      // query stack snapshot
+    budgets: Budgets,      // budget stack snapshot
     trace: Vec<Rc<Trace>>, // trace snapshot
     trace_stack: TraceStack,
 }
@@ -155,12 +245,42 @@ impl std::ops::DerefMut for GoalStack {
 
 pub type Queries = TermList;
 
+/// The goals left to a call of a rule annotated with `@budget`.
+/// Shared between the choices made under the call, so that goals run
+/// before backtracking still count.
+#[derive(Debug)]
+pub struct Budget {
+    rule: Symbol,
+    goals: u64,
+    used: Cell<u64>,
+}
+
+pub type Budgets = Vec<Rc<Budget>>;
+
+/// A call of a rule whose arguments are all bound, by the name of the rule
+/// and the arguments.
+pub type TableKey = (Symbol, TermList);
+
+/// What is known about a tabled call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TableEntry {
+    /// Being proved.
+    InProgress {
+        /// Set once the call, or a call it depends on, was called again
+        /// while being proved, and failed there.
+        cycle: bool,
+    },
+    Succeeded,
+    Failed,
+}
+
 pub struct PolarVirtualMachine {
     /// Stacks.
     pub goals: GoalStack,
     pub bindings: BindingStack,
     choices: Choices,
     pub queries: Queries,
+    budgets: Budgets,
 
     pub tracing: bool,
     pub trace_stack: TraceStack, // Stack of traces higher up the tree.
@@ -180,6 +300,29 @@ pub struct PolarVirtualMachine {
 
     /// Maximum size of goal stack
     stack_limit: usize,
+    /// Maximum number of goals to run, and the number run so far.
+    goal_limit: Option<u64>,
+    goals_run: u64,
+    /// Maximum estimated size of the bindings, goals and choices, in bytes.
+    memory_limit: Option<usize>,
+
+    /// Which comparisons of numbers are errors.
+    numeric_comparison: NumericComparison,
+
+    /// Whether external instances used as conditions ask the host for
+    /// their truthiness, instead of being errors.
+    instance_truthiness: bool,
+
+    /// Whether calls of rules whose arguments are all bound are proved
+    /// once and then answered from `table`.
+    tabling: bool,
+    table: HashMap<TableKey, TableEntry>,
+
+    /// Set by the host to stop the query.
+    cancelled: Option<Arc<AtomicBool>>,
+
+    /// The goals and rules run, if the host asked for a timeline.
+    timeline: Option<Timeline>,
 
     /// Binding stack constant below here.
     csp: usize,
@@ -193,6 +336,9 @@ pub struct PolarVirtualMachine {
     /// Call ID -> result variable name table.
     call_id_symbols: HashMap<u64, Symbol>,
 
+    /// Rule name -> number of calls, for partial traces.
+    rule_calls: HashMap<Symbol, u64>,
+
     /// Logging flag.
     log: bool,
     polar_log: bool,
@@ -235,9 +381,19 @@ impl PolarVirtualMachine {
             query_start_time: None,
             query_timeout: QUERY_TIMEOUT_S,
             stack_limit: MAX_STACK_SIZE,
+            goal_limit: None,
+            goals_run: 0,
+            memory_limit: None,
+            numeric_comparison: NumericComparison::default(),
+            instance_truthiness: false,
+            tabling: false,
+            table: HashMap::new(),
+            cancelled: None,
+            timeline: None,
             csp: 0,
             choices: vec![],
             queries: vec![],
+            budgets: vec![],
             tracing: trace,
             trace_stack: vec![],
             trace: vec![],
@@ -245,6 +401,7 @@ impl PolarVirtualMachine {
             debugger: Debugger::default(),
             kb,
             call_id_symbols: HashMap::new(),
+            rule_calls: HashMap::new(),
             log: std::env::var("RUST_LOG").is_ok(),
             polar_log: std::env::var("POLAR_LOG").is_ok(),
             polar_log_mute: false,
@@ -258,14 +415,117 @@ impl PolarVirtualMachine {
         PolarVirtualMachine::new(kb, trace, goals, MessageQueue::new())
     }
 
+    /// Reset the machine to run `goals` as if it were new, keeping the
+    /// memory allocated for its stacks.
+    pub fn reset(&mut self, trace: bool, goals: Goals) {
+        let constants = self
+            .kb
+            .read()
+            .expect("cannot acquire KB read lock")
+            .constants
+            .clone();
+        self.goals.clear();
+        self.goals.extend(goals.into_iter().rev().map(Rc::new));
+        self.bindings.clear();
+        self.choices.clear();
+        self.queries.clear();
+        self.budgets.clear();
+        self.tracing = trace;
+        self.trace_stack.clear();
+        self.trace.clear();
+        self.external_error = None;
+        self.query_start_time = None;
+        self.query_timeout = QUERY_TIMEOUT_S;
+        self.stack_limit = MAX_STACK_SIZE;
+        self.goal_limit = None;
+        self.goals_run = 0;
+        self.memory_limit = None;
+        self.numeric_comparison = NumericComparison::default();
+        self.instance_truthiness = false;
+        self.tabling = false;
+        self.table.clear();
+        self.cancelled = None;
+        self.timeline = None;
+        self.csp = 0;
+        self.debugger = Debugger::default();
+        self.call_id_symbols.clear();
+        self.rule_calls.clear();
+        self.polar_log_mute = false;
+        self.bind_constants(constants);
+    }
+
     #[cfg(test)]
     fn set_stack_limit(&mut self, limit: usize) {
         self.stack_limit = limit;
     }
 
-    #[cfg(test)]
-    fn set_query_timeout(&mut self, timeout_s: u64) {
-        self.query_timeout = std::time::Duration::from_secs(timeout_s);
+    /// Fail the query with a `StackOverflow` error once it has more than
+    /// `limits.max_stack_size` goals on its stack, with a `QueryLimit` error
+    /// once it has run more than `limits.max_goals` goals, and with a
+    /// `ResourceExhausted` error once it takes up more than
+    /// `limits.max_memory` bytes.
+    pub fn set_query_limits(&mut self, limits: QueryLimits) {
+        self.stack_limit = limits.max_stack_size;
+        self.goal_limit = limits.max_goals;
+        self.memory_limit = limits.max_memory;
+    }
+
+    /// Fail the query with a `QueryTimeout` error once it has run for
+    /// `timeout`, instead of `QUERY_TIMEOUT_S`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_query_timeout(&mut self, timeout: std::time::Duration) {
+        self.query_timeout = timeout;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn set_query_timeout(&mut self, timeout: std::time::Duration) {
+        self.query_timeout = timeout.as_secs_f64() * 1_000.0;
+    }
+
+    /// Fail the query with a `Cancelled` error once `cancelled` is set,
+    /// e.g. from another thread.
+    pub fn set_cancel_flag(&mut self, cancelled: Arc<AtomicBool>) {
+        self.cancelled = Some(cancelled);
+    }
+
+    /// Record the goals and rules run in a timeline, for the host to take
+    /// with `take_timeline_events`.
+    pub fn set_timeline(&mut self, enabled: bool) {
+        self.timeline = if enabled {
+            Some(Timeline::default())
+        } else {
+            None
+        };
+    }
+
+    /// Take the goals and rules run since the last call. Rules are taken
+    /// once they have been left.
+    pub fn take_timeline_events(&mut self) -> Vec<TimelineEvent> {
+        self.timeline
+            .as_mut()
+            .map(Timeline::take_events)
+            .unwrap_or_default()
+    }
+
+    pub fn set_numeric_comparison(&mut self, numeric_comparison: NumericComparison) {
+        self.numeric_comparison = numeric_comparison;
+    }
+
+    /// Ask the host for the truthiness of external instances used as
+    /// conditions with the `TRUTHINESS_METHOD` method, instead of failing
+    /// with a type error.
+    pub fn set_instance_truthiness(&mut self, enabled: bool) {
+        self.instance_truthiness = enabled;
+    }
+
+    /// Prove each call of a rule whose arguments are all bound at most
+    /// once per query, and answer the same call again from a table, e.g.
+    /// for recursive role hierarchies. A tabled call succeeds at most
+    /// once, however many ways it can be proved, and a call of itself
+    /// while it is being proved fails, so recursive rules that would
+    /// otherwise loop forever terminate.
+    pub fn set_tabling(&mut self, enabled: bool) {
+        self.tabling = enabled;
     }
 
     pub fn new_id(&self) -> u64 {
@@ -289,6 +549,9 @@ impl PolarVirtualMachine {
         }
 
         self.check_timeout()?;
+        if let Some(true) = self.cancelled.as_ref().map(|c| c.load(Ordering::Relaxed)) {
+            return Err(error::RuntimeError::Cancelled.into());
+        }
 
         match goal.as_ref() {
             Goal::Backtrack => self.backtrack()?,
@@ -329,6 +592,9 @@ impl PolarVirtualMachine {
                 return result;
             }
             Goal::PopQuery { .. } => self.pop_query(),
+            Goal::PopBudget => {
+                self.budgets.pop();
+            }
             Goal::FilterRules {
                 applicable_rules,
                 unfiltered_rules,
@@ -340,6 +606,19 @@ impl PolarVirtualMachine {
                 inner,
                 args,
             } => self.sort_rules(rules, args, *outer, *inner)?,
+            Goal::SortList {
+                list,
+                key,
+                result,
+                outer,
+                inner,
+            } => self.sort_list(list, key, result, *outer, *inner)?,
+            Goal::UniqueList { list, kept, result } => self.unique_list(list, kept, result)?,
+            Goal::TableFailed { key } => self.table_failed(key)?,
+            Goal::TableSucceeded { key, choice_index } => {
+                self.table.insert(key.clone(), TableEntry::Succeeded);
+                self.cut(*choice_index);
+            }
             Goal::TracePush => {
                 self.trace_stack.push(Rc::new(self.trace.clone()));
                 self.trace = vec![];
@@ -351,8 +630,14 @@ impl PolarVirtualMachine {
                 let trace = Rc::make_mut(&mut trace);
                 trace.children.append(&mut children);
                 self.trace.push(Rc::new(trace.clone()));
+                if let Some(timeline) = &mut self.timeline {
+                    timeline.leave_rules(self.trace_stack.len());
+                }
             }
             Goal::TraceRule { trace } => {
+                if let (Some(timeline), Node::Rule(rule)) = (&mut self.timeline, &trace.node) {
+                    timeline.enter_rule(rule.name.0.clone(), self.trace_stack.len());
+                }
                 if let Node::Rule(rule) = &trace.node {
                     self.log_with(
                         || {
@@ -384,6 +669,9 @@ impl PolarVirtualMachine {
 
         if self.goals.is_empty() {
             if self.choices.is_empty() {
+                if let Some(timeline) = &mut self.timeline {
+                    timeline.leave_rules(0);
+                }
                 return Ok(QueryEvent::Done);
             } else {
                 self.backtrack()?;
@@ -391,7 +679,20 @@ impl PolarVirtualMachine {
         }
 
         while let Some(goal) = self.goals.pop() {
-            match self.next(goal.clone())? {
+            self.spend_budgets()?;
+            self.count_goal()?;
+            self.check_memory()?;
+            let start = self.timeline.as_ref().map(|_| std::time::Instant::now());
+            let event = self.next(goal.clone());
+            if let (Some(timeline), Some(start)) = (&mut self.timeline, start) {
+                timeline.events.push(TimelineEvent {
+                    name: goal.name().to_string(),
+                    category: "goal",
+                    start,
+                    duration: start.elapsed(),
+                });
+            }
+            match event? {
                 QueryEvent::None => (),
                 event => {
                     self.external_error = None;
@@ -436,6 +737,7 @@ impl PolarVirtualMachine {
         if self.goals.len() >= self.stack_limit {
             return Err(error::RuntimeError::StackOverflow {
                 msg: format!("Goal stack overflow! MAX_GOALS = {}", self.stack_limit),
+                trace: Some(self.partial_trace()),
             }
             .into());
         }
@@ -471,6 +773,7 @@ impl PolarVirtualMachine {
             bsp: self.bsp(),
             goals: self.goals.clone(),
             queries: self.queries.clone(),
+            budgets: self.budgets.clone(),
             trace: self.trace.clone(),
             trace_stack: self.trace_stack.clone(),
         });
@@ -560,6 +863,18 @@ impl PolarVirtualMachine {
         self.csp += bindings.len();
     }
 
+    /// Bind `var` to `value` like a constant. Returns `false`, binding
+    /// nothing, if a goal has already run.
+    pub fn bind_constant(&mut self, var: Symbol, value: Term) -> bool {
+        if self.goals_run > 0 {
+            return false;
+        }
+        let mut bindings = Bindings::new();
+        bindings.insert(var, value);
+        self.bind_constants(bindings);
+        true
+    }
+
     /// Retrieve the current non-constant bindings as a hash map.
     pub fn bindings(&self, include_temps: bool) -> Bindings {
         let mut bindings = HashMap::new();
@@ -717,7 +1032,8 @@ impl PolarVirtualMachine {
             if let Some(line) = lines.first() {
                 let mut msg = format!("[debug] {}{}", &indent, line);
                 if !terms.is_empty() {
-                    let relevant_bindings = self.relevant_bindings(terms);
+                    let relevant_bindings: BTreeMap<_, _> =
+                        self.relevant_bindings(terms).into_iter().collect();
                     msg.push_str(&format!(
                         ", BINDINGS: {{{}}}",
                         relevant_bindings
@@ -794,6 +1110,34 @@ impl PolarVirtualMachine {
         st
     }
 
+    /// The innermost rules being called, and the rules called most often,
+    /// to report where a query that is stopped was spending its time.
+    pub fn partial_trace(&self) -> error::PartialTrace {
+        let mut rules: Vec<Symbol> = self
+            .queries
+            .iter()
+            .filter_map(|query| match query.value() {
+                Value::Call(call) => Some(call.name.clone()),
+                _ => None,
+            })
+            .collect();
+        if rules.len() > MAX_PARTIAL_TRACE_RULES {
+            rules.drain(..rules.len() - MAX_PARTIAL_TRACE_RULES);
+        }
+
+        let mut calls: Vec<(Symbol, u64)> = self
+            .rule_calls
+            .iter()
+            .map(|(rule, calls)| (rule.clone(), *calls))
+            .collect();
+        calls.sort_by(|(a, a_calls), (b, b_calls)| {
+            b_calls.cmp(a_calls).then_with(|| a.0.cmp(&b.0))
+        });
+        calls.truncate(MAX_PARTIAL_TRACE_RULES);
+
+        error::PartialTrace { rules, calls }
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     fn check_timeout(&self) -> PolarResult<()> {
         let now = std::time::Instant::now();
@@ -804,10 +1148,11 @@ impl PolarVirtualMachine {
         if now - start_time > self.query_timeout {
             return Err(error::RuntimeError::QueryTimeout {
                 msg: format!(
-                    "Query running for {}. Exceeded query timeout of {} seconds",
-                    (now - start_time).as_secs(),
-                    self.query_timeout.as_secs()
+                    "Query running for {:?}. Exceeded query timeout of {:?}",
+                    now - start_time,
+                    self.query_timeout
                 ),
+                trace: Some(self.partial_trace()),
             }
             .into());
         }
@@ -829,6 +1174,7 @@ impl PolarVirtualMachine {
                     (now - start_time) / 1_000.0,
                     self.query_timeout / 1_000.0
                 ),
+                trace: Some(self.partial_trace()),
             }
             .into());
         }
@@ -855,6 +1201,7 @@ impl PolarVirtualMachine {
                     bsp,
                     goals,
                     queries,
+                    budgets,
                     trace,
                     trace_stack,
                 }) => {
@@ -863,11 +1210,13 @@ impl PolarVirtualMachine {
                         if alternatives.is_empty() {
                             self.goals = goals;
                             self.queries = queries;
+                            self.budgets = budgets;
                             self.trace = trace;
                             self.trace_stack = trace_stack;
                         } else {
                             self.goals.clone_from(&goals);
                             self.queries.clone_from(&queries);
+                            self.budgets.clone_from(&budgets);
                             self.trace.clone_from(&trace);
                             self.trace_stack.clone_from(&trace_stack);
                             self.choices.push(Choice {
@@ -875,6 +1224,7 @@ impl PolarVirtualMachine {
                                 bsp,
                                 goals,
                                 queries,
+                                budgets,
                                 trace,
                                 trace_stack,
                             })
@@ -898,6 +1248,67 @@ impl PolarVirtualMachine {
         self.queries.pop();
     }
 
+    /// Count a goal against the goal limit of the query.
+    fn count_goal(&mut self) -> PolarResult<()> {
+        self.goals_run += 1;
+        match self.goal_limit {
+            Some(limit) if self.goals_run > limit => Err(error::RuntimeError::QueryLimit {
+                msg: format!("Query ran more than {} goals", limit),
+                trace: Some(self.partial_trace()),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Estimate the bytes taken up by the bindings, goals and choices of the
+    /// query, not counting the values they share.
+    fn memory_used(&self) -> usize {
+        self.bindings.len() * std::mem::size_of::<Binding>()
+            + self.goals.len() * std::mem::size_of::<Goal>()
+            + self.choices.len() * std::mem::size_of::<Choice>()
+            + self.choices.iter().map(|choice| choice.goals.len()).sum::<usize>()
+                * std::mem::size_of::<Rc<Goal>>()
+    }
+
+    /// Check the memory budget every `MEMORY_CHECK_INTERVAL` goals, since
+    /// the estimate counts the goals of every choice.
+    fn check_memory(&self) -> PolarResult<()> {
+        match self.memory_limit {
+            Some(limit) if self.goals_run % MEMORY_CHECK_INTERVAL == 0 => {
+                let used = self.memory_used();
+                if used > limit {
+                    return Err(error::RuntimeError::ResourceExhausted {
+                        used,
+                        limit,
+                        trace: Some(self.partial_trace()),
+                    }
+                    .into());
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a goal against the budget of every rule being called,
+    /// failing with the outermost rule whose budget is spent.
+    fn spend_budgets(&self) -> PolarResult<()> {
+        for budget in &self.budgets {
+            let used = budget.used.get() + 1;
+            budget.used.set(used);
+            if used > budget.goals {
+                return Err(error::RuntimeError::BudgetExceeded {
+                    rule: budget.rule.clone(),
+                    goals: budget.goals,
+                    trace: Some(self.partial_trace()),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
     /// Interact with the debugger.
     fn debug(&mut self, message: &str) -> QueryEvent {
         // Query start time is reset when a debug event occurs.
@@ -958,7 +1369,7 @@ impl PolarVirtualMachine {
                 for (k, v) in right.fields.iter() {
                     let left = left
                         .fields
-                        .get(&k)
+                        .get(k)
                         .expect("left fields should be a superset of right fields")
                         .clone();
                     self.push_goal(Goal::Isa {
@@ -1136,16 +1547,22 @@ impl PolarVirtualMachine {
         field: &Term,
         check_errors: bool,
     ) -> PolarResult<QueryEvent> {
-        let (field_name, args): (Symbol, Option<Vec<Term>>) = match self.deref(field).value() {
-            Value::Call(Call {
-                name,
-                args,
-                kwargs: None,
-            }) => (
+        let (field_name, args, kwargs): (
+            Symbol,
+            Option<Vec<Term>>,
+            Option<BTreeMap<Symbol, Term>>,
+        ) = match self.deref(field).value() {
+            Value::Call(Call { name, args, kwargs }) => (
                 name.clone(),
                 Some(args.iter().map(|arg| self.deep_deref(arg)).collect()),
+                kwargs.as_ref().map(|kwargs| {
+                    kwargs
+                        .iter()
+                        .map(|(name, value)| (name.clone(), self.deep_deref(value)))
+                        .collect()
+                }),
             ),
-            Value::String(field) => (Symbol(field.clone()), None),
+            Value::String(field) => (Symbol(field.clone()), None, None),
             v => {
                 return Err(self.type_error(
                     &field,
@@ -1169,14 +1586,19 @@ impl PolarVirtualMachine {
             || {
                 let mut msg = format!("LOOKUP: {}.{}", instance.to_string(), field_name);
                 if let Some(arguments) = &args {
+                    let mut arguments = arguments
+                        .iter()
+                        .map(|a| a.to_polar())
+                        .collect::<Vec<String>>();
+                    if let Some(kwargs) = &kwargs {
+                        arguments.extend(
+                            kwargs
+                                .iter()
+                                .map(|(name, value)| format!("{}: {}", name, value.to_polar())),
+                        );
+                    }
                     msg.push('(');
-                    msg.push_str(
-                        &arguments
-                            .iter()
-                            .map(|a| a.to_polar())
-                            .collect::<Vec<String>>()
-                            .join(", "),
-                    );
+                    msg.push_str(&arguments.join(", "));
                     msg.push(')');
                 }
                 msg
@@ -1189,6 +1611,7 @@ impl PolarVirtualMachine {
             instance: self.deep_deref(instance),
             attribute: field_name,
             args,
+            kwargs,
         })
     }
 
@@ -1281,6 +1704,12 @@ impl PolarVirtualMachine {
             }
         };
 
+        if let Value::Call(predicate) = term.value() {
+            if !self.table_call(predicate)? {
+                return Ok(QueryEvent::None);
+            }
+        }
+
         self.queries.push(term.clone());
         self.push_goal(Goal::PopQuery { term: term.clone() })?;
         self.trace.push(Rc::new(Trace {
@@ -1303,15 +1732,93 @@ impl PolarVirtualMachine {
         Ok(QueryEvent::None)
     }
 
+    /// Answer the call of `predicate` from the table if it is tabled and
+    /// has been proved or failed before, returning whether it still has to
+    /// be proved. Otherwise, record in the table whether it succeeds: when
+    /// it is first proved, or when its choice is backtracked into.
+    fn table_call(&mut self, predicate: &Call) -> PolarResult<bool> {
+        let key = match self.table_key(predicate) {
+            Some(key) => key,
+            None => return Ok(true),
+        };
+        match self.table.get(&key) {
+            Some(TableEntry::Succeeded) => Ok(false),
+            Some(TableEntry::Failed) => self.backtrack().map(|_| false),
+            Some(TableEntry::InProgress { .. }) => {
+                // Every call being proved may depend on this one, so none
+                // of them has really failed if it fails.
+                for entry in self.table.values_mut() {
+                    if let TableEntry::InProgress { cycle } = entry {
+                        *cycle = true;
+                    }
+                }
+                self.backtrack().map(|_| false)
+            }
+            None => {
+                self.table
+                    .insert(key.clone(), TableEntry::InProgress { cycle: false });
+                // Pushed before the query, so that cuts in the bodies of
+                // the rules called keep it.
+                let choice_index = self.choices.len();
+                self.push_choice(vec![vec![Goal::TableFailed { key: key.clone() }]]);
+                self.push_goal(Goal::TableSucceeded { key, choice_index })?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// The key of the call of `predicate` in the table, if tabling is
+    /// enabled and its arguments are all bound.
+    fn table_key(&self, predicate: &Call) -> Option<TableKey> {
+        fn is_bound(term: &Term) -> bool {
+            match term.value() {
+                Value::Number(_)
+                | Value::String(_)
+                | Value::Boolean(_)
+                | Value::ExternalInstance(_) => true,
+                Value::List(terms) => terms.iter().all(is_bound),
+                Value::Dictionary(Dictionary { fields }) => fields.values().all(is_bound),
+                _ => false,
+            }
+        }
+
+        if !self.tabling || predicate.kwargs.is_some() {
+            return None;
+        }
+        let args: TermList = predicate.args.iter().map(|t| self.deep_deref(t)).collect();
+        if args.iter().all(is_bound) {
+            Some((predicate.name.clone(), args))
+        } else {
+            None
+        }
+    }
+
+    /// Record that the tabled call `key` failed, unless it was cut short by
+    /// a cycle, and backtrack.
+    fn table_failed(&mut self, key: &TableKey) -> PolarResult<()> {
+        match self.table.get(key) {
+            Some(TableEntry::InProgress { cycle: false }) => {
+                self.table.insert(key.clone(), TableEntry::Failed);
+            }
+            Some(TableEntry::InProgress { cycle: true }) => {
+                self.table.remove(key);
+            }
+            _ => (),
+        }
+        self.backtrack()
+    }
+
     /// Select applicable rules for predicate.
     /// Sort applicable rules by specificity.
     /// Create a choice over the applicable rules.
     fn query_for_predicate(&mut self, predicate: Call) -> PolarResult<()> {
         assert!(predicate.kwargs.is_none());
-        let goals = match self.kb.read().unwrap().rules.get(&predicate.name) {
+        let kb = self.kb.read().unwrap();
+        let goals = match kb.rules.get(&predicate.name) {
             None => vec![Goal::Backtrack],
             Some(generic_rule) => {
                 assert_eq!(generic_rule.name, predicate.name);
+                *self.rule_calls.entry(predicate.name.clone()).or_default() += 1;
 
                 // Pre-filter rules.
                 let args = predicate.args.iter().map(|t| self.deep_deref(&t)).collect();
@@ -1320,7 +1827,7 @@ impl PolarVirtualMachine {
                 self.polar_log_mute = true;
 
                 // Filter rules by applicability.
-                vec![
+                let mut goals = vec![
                     Goal::TracePush,
                     Goal::FilterRules {
                         applicable_rules: vec![],
@@ -1328,9 +1835,21 @@ impl PolarVirtualMachine {
                         args: predicate.args,
                     },
                     Goal::TracePop,
-                ]
+                ];
+
+                // Count the goals of this call against the rule's budget.
+                if let Some(&budget) = kb.budgets.get(&predicate.name) {
+                    self.budgets.push(Rc::new(Budget {
+                        rule: predicate.name,
+                        goals: budget,
+                        used: Cell::new(0),
+                    }));
+                    goals.push(Goal::PopBudget);
+                }
+                goals
             }
         };
+        drop(kb);
         self.append_goals(goals)
     }
 
@@ -1433,6 +1952,11 @@ impl PolarVirtualMachine {
                                 .collect::<Vec<Goals>>(),
                         )?;
                     }
+                    // Containment in an external instance is decided by the application.
+                    Value::ExternalInstance(_) => {
+                        let item = self.deref(item);
+                        return self.external_op_helper(Operator::In, vec![item, list.clone()]);
+                    }
                     _ => {
                         return Err(self.type_error(
                             item,
@@ -1491,6 +2015,7 @@ impl PolarVirtualMachine {
                         instance_id,
                         constructor,
                     },
+                    Goal::CheckError,
                 ])?;
             }
             Operator::Cut => {
@@ -1540,25 +2065,95 @@ impl PolarVirtualMachine {
                     term: double_negation,
                 })?;
             }
+            Operator::Sort => {
+                assert_eq!(args.len(), 3);
+                let result = args.pop().unwrap();
+                let key = self.deref(&args.pop().unwrap());
+                let list = self.deref(&args.pop().unwrap());
+                if !matches!(key.value(), Value::String(_)) {
+                    return Err(self.type_error(
+                        &key,
+                        format!("sort expects a string key, got: {}", key.to_polar()),
+                    ));
+                }
+                match list.value() {
+                    Value::List(list) => self.push_goal(Goal::SortList {
+                        list: list.clone(),
+                        key,
+                        result,
+                        outer: 1,
+                        inner: 1,
+                    })?,
+                    _ => {
+                        return Err(self.type_error(
+                            &list,
+                            format!("sort expects a list, got: {}", list.to_polar()),
+                        ))
+                    }
+                }
+            }
+            Operator::Unique => {
+                assert_eq!(args.len(), 2);
+                let result = args.pop().unwrap();
+                let list = self.deref(&args.pop().unwrap());
+                match list.value() {
+                    Value::List(list) => self.push_goal(Goal::UniqueList {
+                        list: list.clone(),
+                        kept: vec![],
+                        result,
+                    })?,
+                    _ => {
+                        return Err(self.type_error(
+                            &list,
+                            format!("unique expects a list, got: {}", list.to_polar()),
+                        ))
+                    }
+                }
+            }
         }
         Ok(QueryEvent::None)
     }
 
     /// Query for a value.  Succeeds if the value is 'truthy' or backtracks.
-    /// Currently only defined for boolean values.
+    /// Currently only defined for boolean values, and for external instances
+    /// if instance truthiness is enabled.
     fn query_for_value(&mut self, term: &Term) -> PolarResult<()> {
-        if let Value::Boolean(value) = term.value() {
-            if !value {
-                // Backtrack if the boolean is false.
-                self.push_goal(Goal::Backtrack)?;
-            }
+        match term.value() {
+            Value::Boolean(value) => {
+                if !value {
+                    // Backtrack if the boolean is false.
+                    self.push_goal(Goal::Backtrack)?;
+                }
 
-            Ok(())
-        } else {
-            Err(self.type_error(
+                Ok(())
+            }
+            Value::ExternalInstance(_) if self.instance_truthiness => {
+                // Look up the truthiness of the instance, and backtrack
+                // unless it is true.
+                let truthy = self.kb.read().unwrap().gensym("truthy");
+                let truthy = Term::new_temporary(Value::Variable(truthy));
+                let method = Term::new_temporary(Value::Call(Call {
+                    name: Symbol(TRUTHINESS_METHOD.to_string()),
+                    args: vec![],
+                    kwargs: None,
+                }));
+                self.append_goals(vec![
+                    Goal::Query {
+                        term: term.clone_with_value(Value::Expression(Operation {
+                            operator: Operator::Dot,
+                            args: vec![term.clone(), method, truthy.clone()],
+                        })),
+                    },
+                    Goal::Unify {
+                        left: truthy,
+                        right: Term::new_temporary(Value::Boolean(true)),
+                    },
+                ])
+            }
+            _ => Err(self.type_error(
                 &term,
                 format!("can't query for: {}", term.value().to_polar()),
-            ))
+            )),
         }
     }
 
@@ -1674,6 +2269,25 @@ impl PolarVirtualMachine {
         Ok(QueryEvent::None)
     }
 
+    /// Ask the application to evaluate an operation on external instances.
+    fn external_op_helper(&mut self, op: Operator, args: Vec<Term>) -> PolarResult<QueryEvent> {
+        // Generate symbol for external op result and bind to `false` (default)
+        let answer = self.kb.read().unwrap().gensym("external_op_result");
+        self.bind(&answer, Term::new_temporary(Value::Boolean(false)));
+
+        // append unify goal to be evaluated after external op result is returned & bound
+        self.append_goals(vec![Goal::Unify {
+            left: Term::new_temporary(Value::Variable(answer.clone())),
+            right: Term::new_temporary(Value::Boolean(true)),
+        }])?;
+        let call_id = self.new_call_id(&answer);
+        Ok(QueryEvent::ExternalOp {
+            call_id,
+            operator: op,
+            args,
+        })
+    }
+
     /// Evaluate comparisons.
     fn comparison_op_helper(
         &mut self,
@@ -1715,6 +2329,18 @@ impl PolarVirtualMachine {
         // Do the comparison.
         match (left_term.value(), right_term.value()) {
             (Value::Number(left), Value::Number(right)) => {
+                if let Some(msg) = self.numeric_comparison.check(*left, *right) {
+                    let error = error::RuntimeError::NumericComparison {
+                        msg: format!(
+                            "{}: {} {} {}",
+                            msg,
+                            left_term.to_polar(),
+                            op.to_polar(),
+                            right_term.to_polar()
+                        ),
+                    };
+                    return Err(self.set_error_context(term, error));
+                }
                 if !match op {
                     Operator::Lt => left < right,
                     Operator::Leq => left <= right,
@@ -1743,21 +2369,7 @@ impl PolarVirtualMachine {
                 Ok(QueryEvent::None)
             }
             (Value::ExternalInstance(_), Value::ExternalInstance(_)) => {
-                // Generate symbol for external op result and bind to `false` (default)
-                let answer = self.kb.read().unwrap().gensym("external_op_result");
-                self.bind(&answer, Term::new_temporary(Value::Boolean(false)));
-
-                // append unify goal to be evaluated after external op result is returned & bound
-                self.append_goals(vec![Goal::Unify {
-                    left: Term::new_temporary(Value::Variable(answer.clone())),
-                    right: Term::new_temporary(Value::Boolean(true)),
-                }])?;
-                let call_id = self.new_call_id(&answer);
-                Ok(QueryEvent::ExternalOp {
-                    call_id,
-                    operator: op,
-                    args: vec![left_term, right_term],
-                })
+                self.external_op_helper(op, vec![left_term, right_term])
             }
             (left, right) => Err(self.type_error(
                 term,
@@ -1868,7 +2480,7 @@ impl PolarVirtualMachine {
                 for (k, v) in left.fields.iter() {
                     let right = right
                         .fields
-                        .get(&k)
+                        .get(k)
                         .expect("fields should be equal")
                         .clone();
                     self.push_goal(Goal::Unify {
@@ -2272,6 +2884,140 @@ impl PolarVirtualMachine {
         Ok(())
     }
 
+    /// Insertion sort `list` by comparing its elements, or their `key` fields
+    /// if `key` is not empty, with `<`. Comparisons of external instances are
+    /// answered by the application. Unifies the sorted list with `result`.
+    fn sort_list(
+        &mut self,
+        list: &[Term],
+        key: &Term,
+        result: &Term,
+        outer: usize,
+        inner: usize,
+    ) -> PolarResult<()> {
+        if outer >= list.len() {
+            // We're done; the list is sorted.
+            return self.push_goal(Goal::Unify {
+                left: result.clone_with_value(Value::List(list.to_vec())),
+                right: result.clone(),
+            });
+        }
+
+        let next_outer = Goal::SortList {
+            list: list.to_vec(),
+            key: key.clone(),
+            result: result.clone(),
+            outer: outer + 1,
+            inner: outer + 1,
+        };
+        if inner == 0 {
+            return self.push_goal(next_outer);
+        }
+
+        let mut left = list[inner].clone();
+        let mut right = list[inner - 1].clone();
+        let mut compare = vec![];
+        if !matches!(key.value(), Value::String(key) if key.is_empty()) {
+            // Compare the fields, looked up as with `.`.
+            let lookup = |vm: &mut Self, object: Term| {
+                let value = object
+                    .clone_with_value(Value::Variable(vm.kb.read().unwrap().gensym("sort_key")));
+                let lookup = Goal::Query {
+                    term: object.clone_with_value(Value::Expression(Operation {
+                        operator: Operator::Dot,
+                        args: vec![object.clone(), key.clone(), value.clone()],
+                    })),
+                };
+                (lookup, value)
+            };
+            let (lookup_left, left_value) = lookup(self, left);
+            let (lookup_right, right_value) = lookup(self, right);
+            compare.push(lookup_left);
+            compare.push(lookup_right);
+            left = left_value;
+            right = right_value;
+        }
+        compare.push(Goal::Query {
+            term: result.clone_with_value(Value::Expression(Operation {
+                operator: Operator::Lt,
+                args: vec![left, right],
+            })),
+        });
+
+        let mut swapped = list.to_vec();
+        swapped.swap(inner - 1, inner);
+        let next_inner = Goal::SortList {
+            list: swapped,
+            key: key.clone(),
+            result: result.clone(),
+            outer,
+            inner: inner - 1,
+        };
+
+        // If the element is less than the one before it, swap them and
+        // continue the inner loop, otherwise break out of it.
+        self.choose_conditional(compare, vec![next_inner], vec![next_outer])
+    }
+
+    /// Keep the first of each group of equal elements of `list`, in order.
+    /// Equality of external instances is decided by the application.
+    /// Unifies the kept elements with `result`.
+    fn unique_list(&mut self, list: &[Term], kept: &[Term], result: &Term) -> PolarResult<()> {
+        let (first, rest) = match list.split_first() {
+            Some(split) => split,
+            None => {
+                return self.push_goal(Goal::Unify {
+                    left: result.clone_with_value(Value::List(kept.to_vec())),
+                    right: result.clone(),
+                })
+            }
+        };
+        let first = self.deep_deref(first);
+        let mut with_first = kept.to_vec();
+        with_first.push(first.clone());
+        let keep = Goal::UniqueList {
+            list: rest.to_vec(),
+            kept: with_first,
+            result: result.clone(),
+        };
+        let skip = Goal::UniqueList {
+            list: rest.to_vec(),
+            kept: kept.to_vec(),
+            result: result.clone(),
+        };
+
+        if kept.iter().any(|term| term.value() == first.value()) {
+            return self.push_goal(skip);
+        }
+
+        // Ask the application whether other instances are equal.
+        let compare = kept
+            .iter()
+            .filter(|term| {
+                matches!(
+                    (term.value(), first.value()),
+                    (Value::ExternalInstance(_), Value::ExternalInstance(_))
+                )
+            })
+            .map(|term| {
+                first.clone_with_value(Value::Expression(Operation {
+                    operator: Operator::Eq,
+                    args: vec![term.clone(), first.clone()],
+                }))
+            })
+            .collect::<TermList>();
+        if compare.is_empty() {
+            return self.push_goal(keep);
+        }
+        let any_equal = Goal::Query {
+            term: first.clone_with_value(Value::Expression(Operation {
+                operator: Operator::Or,
+                args: compare,
+            })),
+        };
+        self.choose_conditional(vec![any_equal], vec![skip], vec![keep])
+    }
+
     /// Succeed if `left` is more specific than `right` with respect to `args`.
     #[allow(clippy::ptr_arg)]
     fn is_more_specific(&mut self, left: &Rule, right: &Rule, args: &TermList) -> PolarResult<()> {
@@ -3236,7 +3982,7 @@ mod tests {
     #[test]
     fn test_timeout() {
         let mut vm = PolarVirtualMachine::default();
-        vm.set_query_timeout(1);
+        vm.set_query_timeout(std::time::Duration::from_secs(1));
         // Turn this off so we don't hit it.
         vm.set_stack_limit(std::usize::MAX);
 
@@ -3263,6 +4009,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_goal_limit() {
+        let mut vm = PolarVirtualMachine::default();
+        vm.set_query_limits(QueryLimits {
+            max_goals: Some(2),
+            ..QueryLimits::default()
+        });
+        for _ in 0..3 {
+            vm.push_goal(Goal::Noop).unwrap();
+        }
+        assert!(matches!(
+            vm.run(),
+            Err(error::PolarError {
+                kind: error::ErrorKind::Runtime(error::RuntimeError::QueryLimit { .. }),
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_prefiltering() {
         let bar_rule = GenericRule::new(
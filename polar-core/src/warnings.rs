@@ -98,3 +98,49 @@ pub fn check_singletons(rule: &Rule, kb: &KnowledgeBase) -> Vec<String> {
     }
     warnings
 }
+
+/// Find the first reference in `terms`, the terms of a rule or a query, to
+/// a class or constant that is not registered: the class of a specializer
+/// or of a `new` expression, or a capitalized variable that is only used
+/// as the receiver of a lookup, e.g. `Roles.ADMIN`.
+pub fn find_unknown_reference(terms: &[&Term], kb: &KnowledgeBase) -> Option<(String, Term)> {
+    let mut occurrences = HashMap::<Symbol, usize>::new();
+    let mut references = vec![];
+    let mut check_term = |term: &Term| {
+        match term.value() {
+            Value::Variable(sym) => *occurrences.entry(sym.clone()).or_insert(0) += 1,
+            Value::Pattern(Pattern::Instance(InstanceLiteral { tag, .. })) => {
+                references.push(("class", tag.clone(), term.clone()))
+            }
+            Value::Expression(Operation {
+                operator: Operator::New,
+                args,
+            }) => {
+                if let Some(Value::Call(Call { name, .. })) = args.first().map(Term::value) {
+                    references.push(("class", name.clone(), term.clone()))
+                }
+            }
+            Value::Expression(Operation {
+                operator: Operator::Dot,
+                args,
+            }) => match args.first().map(Term::value) {
+                Some(Value::Variable(sym)) if sym.0.starts_with(char::is_uppercase) => {
+                    references.push(("constant", sym.clone(), args[0].clone()))
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        term.clone()
+    };
+    for term in terms {
+        (*term).clone().map_replace(&mut check_term);
+    }
+
+    references
+        .into_iter()
+        .find(|(kind, sym, _)| {
+            !kb.is_constant(sym) && (*kind == "class" || occurrences.get(sym) == Some(&1))
+        })
+        .map(|(kind, sym, term)| (format!("unknown {} {}", kind, sym), term))
+}
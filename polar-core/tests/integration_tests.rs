@@ -83,6 +83,7 @@ where
                 instance,
                 attribute,
                 args,
+                ..
             } => {
                 query
                     .call_result(
@@ -1340,6 +1341,64 @@ fn test_cut() {
     assert!(qeval(&mut polar, "f(2)"));
 }
 
+#[test]
+fn test_sort_and_unique() {
+    let mut polar = Polar::new();
+    assert_eq!(
+        qvar(&mut polar, "x = sort([3, 1, 2])", "x"),
+        vec![value!([1, 2, 3])]
+    );
+    assert_eq!(
+        qvar(&mut polar, r#"x = sort(["b", "c", "a"])"#, "x"),
+        vec![value!(["a", "b", "c"])]
+    );
+    assert_eq!(qvar(&mut polar, "x = sort([])", "x"), vec![value!([])]);
+    // Sorting by a field is stable.
+    assert_eq!(
+        qvar(
+            &mut polar,
+            r#"[a, b, c] = sort([{n: 2, v: "a"}, {n: 1, v: "b"}, {n: 2, v: "c"}], "n")
+               and x = [a.v, b.v, c.v]"#,
+            "x"
+        ),
+        vec![value!(["b", "a", "c"])]
+    );
+    assert_eq!(
+        qvar(&mut polar, "x = unique([1, 2, 1, 3, 2])", "x"),
+        vec![value!([1, 2, 3])]
+    );
+    assert_eq!(
+        qvar(&mut polar, "x = sort(unique([2, 1, 2]))", "x"),
+        vec![value!([1, 2])]
+    );
+
+    polar
+        .load_str("first_sorted(l, x) if [x, *_] = sort(l);")
+        .unwrap();
+    assert_eq!(
+        qvar(&mut polar, "first_sorted([5, 4, 6], x)", "x"),
+        vec![value!(4)]
+    );
+
+    // `sort` and `unique` are not keywords: rules, variables and fields
+    // may have the same names, and calls in goal position call rules.
+    polar
+        .load_str(
+            r#"sort(x) if x = 1;
+               unique(d) if d.unique = true;
+               same(sort, unique) if sort = unique;"#,
+        )
+        .unwrap();
+    assert!(qeval(&mut polar, "sort(1)"));
+    assert!(qnull(&mut polar, "sort(2)"));
+    assert!(qeval(&mut polar, "unique({unique: true})"));
+    assert!(qeval(&mut polar, "same(1, 1)"));
+    assert_eq!(
+        qvar(&mut polar, "x = sort([3, 1, 2])", "x"),
+        vec![value!([1, 2, 3])]
+    );
+}
+
 #[test]
 fn test_forall() {
     let mut polar = Polar::new();
@@ -1689,3 +1748,75 @@ fn test_list_matches() {
         vec![value!([3, Value::RestVariable(Symbol::new("ys"))])]
     );
 }
+
+#[test]
+fn test_rule_budgets() {
+    let mut polar = Polar::new();
+    polar
+        .load_str(indoc!(
+            r#"
+            @budget(goals=1000)
+            count(0);
+            count(n) if n > 0 and count(n - 1);
+
+            check(n) if count(n);
+            "#
+        ))
+        .unwrap();
+
+    assert!(qeval(&mut polar, "check(10)"));
+    assert!(qeval(&mut polar, "check(10) and check(10)"));
+
+    let mut query = polar.new_query("check(1000)", false).unwrap();
+    let error = query.next_event().unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("rules being called: count > count > "));
+    assert!(message.contains("most called rules: count ("));
+    assert!(message.contains(", check (1 calls)"));
+    match error.kind {
+        ErrorKind::Runtime(RuntimeError::BudgetExceeded { rule, goals, trace }) => {
+            assert_eq!(rule, sym!("count"));
+            assert_eq!(goals, 1000);
+            let trace = trace.unwrap();
+            assert_eq!(trace.rules.len(), 10);
+            assert!(trace.rules.iter().all(|rule| rule == &sym!("count")));
+            assert_eq!(trace.calls[0].0, sym!("count"));
+            assert_eq!(trace.calls[1], (sym!("check"), 1));
+        }
+        _ => panic!("unexpected error: {}", error),
+    }
+
+    let error = polar.load_str("@cache(goals=1) f(1);").unwrap_err();
+    assert!(matches!(
+        error.kind,
+        ErrorKind::Parse(ParseError::UnrecognizedToken { .. })
+    ));
+}
+
+#[test]
+fn test_reset_query() {
+    let polar = Polar::new();
+    polar.load_str("f(1); f(2); g(x) if f(x) and x > 1;").unwrap();
+
+    let next_x = |query: &mut Query| match query.next_event().unwrap() {
+        QueryEvent::Result { bindings, .. } => Some(bindings[&sym!("x")].value().clone()),
+        QueryEvent::Done => None,
+        event => panic!("unexpected event: {:?}", event),
+    };
+
+    // Reset a query in the middle of its results.
+    let mut query = polar.new_query("f(x)", false).unwrap();
+    assert_eq!(next_x(&mut query), Some(value!(1)));
+    let term = polar_core::parser::parse_term("g(x)").unwrap();
+    polar.reset_query(&mut query, term, false);
+    assert_eq!(next_x(&mut query), Some(value!(2)));
+    assert_eq!(next_x(&mut query), None);
+
+    // Reset a query made by another `Polar`.
+    let other = Polar::new();
+    other.load_str("f(3);").unwrap();
+    let term = polar_core::parser::parse_term("f(x)").unwrap();
+    other.reset_query(&mut query, term, false);
+    assert_eq!(next_x(&mut query), Some(value!(3)));
+    assert_eq!(next_x(&mut query), None);
+}
@@ -25,6 +25,7 @@ mod tests {
                 Term::new_from_test(value!(0)),
                 Term::new_from_test(value!("hello")),
             ]),
+            kwargs: None,
         };
         eprintln!("{}", serde_json::to_string(&event).unwrap());
         let term = Term::new_from_test(value!(1));
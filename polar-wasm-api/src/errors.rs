@@ -33,8 +33,14 @@ fn kind(err: &PolarError) -> String {
         Parse(ReservedWord { .. }) => "ParseError::ReservedWord",
         Parse(InvalidFloat { .. }) => "ParseError::InvalidFloat",
         Runtime(Application { .. }) => "RuntimeError::Application",
+        Runtime(BudgetExceeded { .. }) => "RuntimeError::BudgetExceeded",
+        Runtime(Cancelled) => "RuntimeError::Cancelled",
+        Runtime(ResourceExhausted { .. }) => "RuntimeError::ResourceExhausted",
         Runtime(ArithmeticError { .. }) => "RuntimeError::ArithmeticError",
         Runtime(FileLoading { .. }) => "RuntimeError::FileLoading",
+        Runtime(NumericComparison { .. }) => "RuntimeError::NumericComparison",
+        Runtime(UnknownReference { .. }) => "RuntimeError::UnknownReference",
+        Runtime(QueryLimit { .. }) => "RuntimeError::QueryLimit",
         Runtime(QueryTimeout { .. }) => "RuntimeError::QueryTimeout",
         Runtime(Serialization { .. }) => "RuntimeError::Serialization",
         Runtime(StackOverflow { .. }) => "RuntimeError::StackOverflow",